@@ -5,7 +5,7 @@ use criterion::{criterion_group, criterion_main, Criterion};
 use uuid::Uuid;
 
 use eth_trie::MemoryDB;
-use eth_trie::{EthTrie, Trie};
+use eth_trie::{EthTrie, TrieRead, TrieWrite};
 
 fn insert_worse_case_benchmark(c: &mut Criterion) {
     c.bench_function("insert one", |b| {
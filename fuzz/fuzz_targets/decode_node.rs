@@ -0,0 +1,15 @@
+#![no_main]
+
+use eth_trie::{decode_node, NodeCodec};
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    // Decoding arbitrary bytes may fail but must not panic
+    let _ = decode_node(&mut &data[..]);
+
+    // A node accepted in its canonical encoding encodes back to the same bytes
+    if let Ok(node) = NodeCodec::decode_strict(data) {
+        assert_eq!(NodeCodec::encode(&node), data);
+        assert_eq!(NodeCodec::encoded_len(&node), data.len());
+    }
+});
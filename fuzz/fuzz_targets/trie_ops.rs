@@ -0,0 +1,62 @@
+#![no_main]
+
+use std::collections::BTreeMap;
+use std::sync::Arc;
+
+use arbitrary::Arbitrary;
+use eth_trie::{EthTrie, MemoryDB, TrieRead, TrieWrite};
+use libfuzzer_sys::fuzz_target;
+
+#[derive(Debug, Arbitrary)]
+enum Op {
+    Insert(Vec<u8>, Vec<u8>),
+    Remove(Vec<u8>),
+    Commit,
+}
+
+// Checks a trie against a map taking the same inserts and removals.
+fuzz_target!(|ops: Vec<Op>| {
+    let memdb = Arc::new(MemoryDB::new(true));
+    let mut trie = EthTrie::new(memdb.clone());
+    let mut model = BTreeMap::new();
+    for op in ops {
+        match op {
+            Op::Insert(key, value) => {
+                trie.insert(&key, &value).unwrap();
+                // Inserting an empty value removes the key
+                if value.is_empty() {
+                    model.remove(&key);
+                } else {
+                    assert_eq!(trie.get(&key).unwrap().as_ref(), Some(&value));
+                    model.insert(key, value);
+                }
+            }
+            Op::Remove(key) => {
+                let removed = trie.remove(&key).unwrap();
+                assert_eq!(removed, model.remove(&key).is_some());
+                assert_eq!(trie.get(&key).unwrap(), None);
+            }
+            Op::Commit => {
+                trie.root_hash().unwrap();
+            }
+        }
+    }
+
+    let entries: Vec<_> = trie.iter().collect::<Result<_, _>>().unwrap();
+    let expected: Vec<_> = model.clone().into_iter().collect();
+    assert_eq!(entries, expected);
+
+    // The root depends only on the entries, not on how the trie got there
+    let root = trie.root_hash().unwrap();
+    let mut fresh = EthTrie::new(Arc::new(MemoryDB::new(true)));
+    for (key, value) in &model {
+        fresh.insert(key, value).unwrap();
+    }
+    assert_eq!(fresh.root_hash().unwrap(), root);
+
+    // And the committed trie reads back the same from the database
+    let reopened = EthTrie::from(memdb, root).unwrap();
+    for (key, value) in &model {
+        assert_eq!(reopened.get(key).unwrap().as_ref(), Some(value));
+    }
+});
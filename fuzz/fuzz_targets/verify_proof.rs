@@ -0,0 +1,48 @@
+#![no_main]
+
+use std::sync::Arc;
+
+use arbitrary::Arbitrary;
+use eth_trie::{EthTrie, MemoryDB, TrieRead, TrieWrite};
+use libfuzzer_sys::fuzz_target;
+
+#[derive(Debug, Arbitrary)]
+struct Input {
+    entries: Vec<(Vec<u8>, Vec<u8>)>,
+    key: Vec<u8>,
+    /// Bytes to overwrite in the proof, as (node, offset, byte).
+    corruptions: Vec<(usize, usize, u8)>,
+}
+
+fuzz_target!(|input: Input| {
+    let mut trie = EthTrie::new(Arc::new(MemoryDB::new(true)));
+    for (key, value) in &input.entries {
+        trie.insert(key, value).unwrap();
+    }
+    let root = trie.root_hash().unwrap();
+    let expected = trie.get(&input.key).unwrap();
+
+    // An untouched proof proves what the trie holds
+    let mut proof = trie.get_proof(&input.key).unwrap();
+    assert_eq!(
+        trie.verify_proof(root, &input.key, proof.clone()).unwrap(),
+        expected
+    );
+
+    // A corrupted one may be rejected, but must not panic or prove another value
+    for (node, offset, byte) in input.corruptions {
+        if proof.is_empty() {
+            break;
+        }
+        let len = proof.len();
+        let node = &mut proof[node % len];
+        if node.is_empty() {
+            continue;
+        }
+        let len = node.len();
+        node[offset % len] = byte;
+    }
+    if let Ok(Some(value)) = trie.verify_proof(root, &input.key, proof) {
+        assert_eq!(Some(value), expected);
+    }
+});
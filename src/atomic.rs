@@ -0,0 +1,207 @@
+use std::sync::Arc;
+
+use alloy_primitives::B256;
+
+use crate::db::DB;
+use crate::errors::TrieError;
+use crate::trie::{EthTrie, TrieResult};
+
+/// Commits several tries sharing a database, such as an account trie and the storage
+/// tries it refers to, with a single batch write.
+///
+/// The nodes of every trie, their leaf counts and any entries added with `put` are
+/// written in one `insert_batch` call, so with a database that writes batches
+/// atomically, a crash leaves either all the new roots or none of them. Stale nodes are
+/// removed afterwards, one trie at a time; a crash in between only leaves some of them
+/// behind. The roots recorded with an attached `RootManager` are also written after the
+/// batch.
+#[derive(Debug)]
+pub struct AtomicCommit<'a, D>
+where
+    D: DB,
+{
+    tries: Vec<&'a mut EthTrie<D>>,
+    keys: Vec<Vec<u8>>,
+    values: Vec<Vec<u8>>,
+}
+
+impl<D> Default for AtomicCommit<'_, D>
+where
+    D: DB,
+{
+    fn default() -> Self {
+        Self {
+            tries: vec![],
+            keys: vec![],
+            values: vec![],
+        }
+    }
+}
+
+impl<'a, D> AtomicCommit<'a, D>
+where
+    D: DB,
+{
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a trie to commit. Every trie must share the database of the first one.
+    pub fn trie(mut self, trie: &'a mut EthTrie<D>) -> Self {
+        if let Some(first) = self.tries.first() {
+            assert!(
+                Arc::ptr_eq(&first.db, &trie.db),
+                "Tries committed together must share a database"
+            );
+        }
+        self.tries.push(trie);
+        self
+    }
+
+    /// Adds an entry to write in the same batch as the nodes, such as a record of the
+    /// new roots.
+    pub fn put(mut self, key: &[u8], value: Vec<u8>) -> Self {
+        self.keys.push(key.to_vec());
+        self.values.push(value);
+        self
+    }
+
+    /// Commits the tries and returns their new roots, in the order they were added.
+    /// Entries added with `put` are only written if there is at least one trie.
+    pub fn commit(self) -> TrieResult<Vec<B256>> {
+        let Self {
+            tries,
+            mut keys,
+            mut values,
+        } = self;
+        let Some(db) = tries.first().map(|trie| trie.db.clone()) else {
+            return Ok(vec![]);
+        };
+
+        let mut prepared = Vec::with_capacity(tries.len());
+        for trie in tries {
+            let mut commit = trie.prepare_commit(false)?;
+            keys.append(&mut commit.keys);
+            values.append(&mut commit.values);
+            prepared.push((trie, commit));
+        }
+        db.insert_batch(keys, values).map_err(TrieError::db)?;
+
+        let mut roots = Vec::with_capacity(prepared.len());
+        for (trie, commit) in prepared {
+            roots.push(commit.root_hash);
+            let registry = trie.config.roots.clone();
+            let mut registry = registry.as_ref().map(|roots| roots.write());
+            if let Some(registry) = registry.as_mut() {
+                if let Some(pending) = registry.record(&*db, commit.root_hash)? {
+                    let (keys, values) = pending.entries().into_iter().unzip();
+                    db.insert_batch(keys, values).map_err(TrieError::db)?;
+                    registry.recorded(pending);
+                }
+            }
+            trie.finish_commit(commit, registry)?;
+        }
+        Ok(roots)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::AtomicCommit;
+    use crate::db::{MemoryDB, DB};
+    use crate::trie::{EthTrie, TrieWrite};
+
+    // Records the batches written to a `MemoryDB`.
+    struct BatchDB {
+        inner: MemoryDB,
+        batches: parking_lot::Mutex<Vec<usize>>,
+    }
+
+    impl DB for BatchDB {
+        type Error = <MemoryDB as DB>::Error;
+
+        fn get(&self, key: &[u8]) -> Result<Option<Vec<u8>>, Self::Error> {
+            self.inner.get(key)
+        }
+
+        fn insert(&self, key: &[u8], value: Vec<u8>) -> Result<(), Self::Error> {
+            self.inner.insert(key, value)
+        }
+
+        fn remove(&self, key: &[u8]) -> Result<(), Self::Error> {
+            self.inner.remove(key)
+        }
+
+        fn insert_batch(
+            &self,
+            keys: Vec<Vec<u8>>,
+            values: Vec<Vec<u8>>,
+        ) -> Result<(), Self::Error> {
+            self.batches.lock().push(keys.len());
+            self.inner.insert_batch(keys, values)
+        }
+
+        fn flush(&self) -> Result<(), Self::Error> {
+            self.inner.flush()
+        }
+
+        fn len(&self) -> Result<usize, Self::Error> {
+            self.inner.len()
+        }
+
+        fn is_empty(&self) -> Result<bool, Self::Error> {
+            self.inner.is_empty()
+        }
+    }
+
+    #[test]
+    fn test_atomic_commit() {
+        let db = Arc::new(BatchDB {
+            inner: MemoryDB::new(true),
+            batches: Default::default(),
+        });
+        let mut accounts = EthTrie::new(db.clone());
+        let mut storage = EthTrie::new(db.clone());
+        for i in 0..30u8 {
+            accounts.insert(&[i], &[i; 40]).unwrap();
+            storage.insert(&[i, i], &[i; 50]).unwrap();
+        }
+
+        let roots = AtomicCommit::new()
+            .trie(&mut accounts)
+            .trie(&mut storage)
+            .put(b"head", b"block 1".to_vec())
+            .commit()
+            .unwrap();
+        assert_eq!(db.batches.lock().len(), 1);
+        assert_eq!(roots, vec![accounts.root_hash, storage.root_hash]);
+        assert!(!accounts.is_dirty() && !storage.is_dirty());
+        assert_eq!(db.get(b"head").unwrap(), Some(b"block 1".to_vec()));
+
+        for root in roots {
+            let trie = EthTrie::from(db.clone(), root).unwrap();
+            assert_eq!(trie.len().unwrap(), 30);
+        }
+
+        // Stale nodes are removed as with separate commits.
+        let separate_db = Arc::new(MemoryDB::new(true));
+        let mut separate = EthTrie::new(separate_db.clone());
+        for i in 0..30u8 {
+            separate.insert(&[i, i], &[i; 50]).unwrap();
+        }
+        separate.root_hash().unwrap();
+        let before = db.len().unwrap();
+        let separate_before = separate_db.len().unwrap();
+        storage.insert(&[3, 3], b"changed").unwrap();
+        separate.insert(&[3, 3], b"changed").unwrap();
+        AtomicCommit::new().trie(&mut storage).commit().unwrap();
+        separate.root_hash().unwrap();
+        assert_eq!(storage.root_hash, separate.root_hash);
+        assert_eq!(
+            db.len().unwrap() as isize - before as isize,
+            separate_db.len().unwrap() as isize - separate_before as isize
+        );
+    }
+}
@@ -0,0 +1,111 @@
+use std::thread::JoinHandle;
+
+use alloy_primitives::B256;
+
+use crate::db::DB;
+use crate::reader::TrieReader;
+use crate::trie::{EthTrie, TrieResult, TrieWrite};
+
+/// A commit running on another thread, started by `EthTrie::commit_in_background`.
+///
+/// Until the commit is done, its reader keeps returning the previous committed root; it
+/// moves to the new root at once when the commit finishes.
+#[derive(Debug)]
+pub struct BackgroundCommit<D>
+where
+    D: DB,
+{
+    reader: TrieReader<D>,
+    worker: JoinHandle<(EthTrie<D>, TrieResult<B256>)>,
+}
+
+impl<D> BackgroundCommit<D>
+where
+    D: DB,
+{
+    /// Returns a reader of the committed roots of the trie being committed.
+    pub fn reader(&self) -> &TrieReader<D> {
+        &self.reader
+    }
+
+    /// Returns true once the commit is done, so that `wait` returns without blocking.
+    pub fn is_finished(&self) -> bool {
+        self.worker.is_finished()
+    }
+
+    /// Waits for the commit to finish, and returns the trie along with the new root or
+    /// the error the commit failed with.
+    pub fn wait(self) -> (EthTrie<D>, TrieResult<B256>) {
+        match self.worker.join() {
+            Ok(result) => result,
+            Err(panic) => std::panic::resume_unwind(panic),
+        }
+    }
+}
+
+impl<D> EthTrie<D>
+where
+    D: DB + 'static,
+{
+    /// Commits the trie on another thread, which encodes and hashes the pending changes
+    /// and writes them to the database, and returns a handle to the commit. The trie
+    /// comes back from `BackgroundCommit::wait`; meanwhile, the previous committed root
+    /// can still be read through `BackgroundCommit::reader`, or the readers and snapshots
+    /// taken from the trie before.
+    pub fn commit_in_background(mut self) -> BackgroundCommit<D> {
+        let reader = self.trie_reader();
+        let worker = std::thread::spawn(move || {
+            let root = self.root_hash();
+            (self, root)
+        });
+        BackgroundCommit { reader, worker }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use crate::db::MemoryDB;
+    use crate::trie::{EthTrie, TrieRead, TrieWrite};
+
+    #[test]
+    fn test_commit_in_background() {
+        // Stale nodes are kept, so that the snapshots of the previous root stay readable
+        let mut trie = EthTrie::builder(Arc::new(MemoryDB::new(true)))
+            .retain_stale_nodes(true)
+            .build()
+            .unwrap();
+        let mut expected = EthTrie::new(Arc::new(MemoryDB::new(true)));
+        for i in 0..100u8 {
+            trie.insert(&[i], &[i; 40]).unwrap();
+            expected.insert(&[i], &[i; 40]).unwrap();
+        }
+        let previous = trie.root_hash().unwrap();
+        let snapshot = trie.snapshot();
+        for i in 0..100u8 {
+            trie.insert(&[i], &[0xff; 40]).unwrap();
+            expected.insert(&[i], &[0xff; 40]).unwrap();
+        }
+
+        let commit = trie.commit_in_background();
+        // The reader sees either root, never the changes before they are committed
+        let view = commit.reader().snapshot();
+        let value = view.get(&[5]).unwrap();
+        match view.root_hash() == previous {
+            true => assert_eq!(value, Some(vec![5; 40])),
+            false => assert_eq!(value, Some(vec![0xff; 40])),
+        }
+        assert_eq!(snapshot.get(&[5]).unwrap(), Some(vec![5; 40]));
+
+        let reader = commit.reader().clone();
+        let (mut trie, root) = commit.wait();
+        let root = root.unwrap();
+        assert_eq!(root, expected.root_hash().unwrap());
+        assert_eq!(reader.root_hash(), root);
+        assert!(!trie.is_dirty());
+
+        trie.insert(b"test", b"test").unwrap();
+        assert_ne!(trie.root_hash().unwrap(), root);
+    }
+}
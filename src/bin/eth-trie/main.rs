@@ -0,0 +1,542 @@
+//! Inspects a trie stored in a sled database, to answer questions about a state
+//! without writing a program for each. Built with the `cli` feature.
+//!
+//! ```text
+//! eth-trie --db <DIR> --root <HASH> [--secure] <get|proof|verify|stats|iter|check>
+//! eth-trie --db <DIR> --root <HASH> dump (--to-db <DIR> | --to-file <FILE>)
+//! eth-trie --db <DIR> --root <HASH> restore (--from-db <DIR> | --from-file <FILE>)
+//! ```
+//!
+//! The trie is read from the default tree of the database, or from the one named by
+//! `--tree`, which is also used in the databases `dump` and `restore` copy to and from.
+
+mod sled_db;
+
+use std::error::Error;
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::ops::Bound;
+use std::path::PathBuf;
+use std::process::ExitCode;
+use std::sync::Arc;
+
+use alloy_primitives::{hex, B256};
+use clap::{value_parser, Arg, ArgAction, ArgGroup, ArgMatches, Command};
+use eth_trie::{EthTrie, MemoryDB, TrieRead, DB};
+use keccak_hash::{keccak, KECCAK_NULL_RLP};
+
+use sled_db::SledDB;
+
+type CliResult<T> = Result<T, Box<dyn Error>>;
+
+fn cli() -> Command {
+    let key = || {
+        Arg::new("key")
+            .value_name("KEY")
+            .required(true)
+            .help("The key, in hex")
+    };
+    let path = |name: &'static str, value_name: &'static str, help: &'static str| {
+        Arg::new(name)
+            .long(name)
+            .value_name(value_name)
+            .value_parser(value_parser!(PathBuf))
+            .help(help)
+    };
+    Command::new("eth-trie")
+        .version(env!("CARGO_PKG_VERSION"))
+        .about("Inspects a trie stored in a sled database")
+        .arg(
+            Arg::new("db")
+                .long("db")
+                .value_name("DIR")
+                .required(true)
+                .value_parser(value_parser!(PathBuf))
+                .help("The sled database directory"),
+        )
+        .arg(
+            Arg::new("tree")
+                .long("tree")
+                .value_name("NAME")
+                .help("The sled tree holding the trie, rather than the default tree"),
+        )
+        .arg(
+            Arg::new("root")
+                .long("root")
+                .value_name("HASH")
+                .required(true)
+                .help("The root of the trie"),
+        )
+        .arg(
+            Arg::new("secure")
+                .long("secure")
+                .action(ArgAction::SetTrue)
+                .help("Hashes keys before looking them up, as the state and storage tries do"),
+        )
+        .subcommand_required(true)
+        .subcommand(
+            Command::new("get")
+                .about("Prints the value of a key")
+                .arg(key()),
+        )
+        .subcommand(
+            Command::new("proof")
+                .about("Prints the proof of a key, one node per line")
+                .arg(key()),
+        )
+        .subcommand(
+            Command::new("verify")
+                .about("Checks a proof of a key against the root and prints the proven value")
+                .arg(key())
+                .arg(
+                    Arg::new("nodes")
+                        .value_name("NODE")
+                        .num_args(0..)
+                        .help("The nodes of the proof, in hex"),
+                ),
+        )
+        .subcommand(Command::new("stats").about("Prints the shape and size of the trie"))
+        .subcommand(
+            Command::new("iter")
+                .about("Prints the entries of the trie in key order")
+                .arg(
+                    Arg::new("prefix")
+                        .long("prefix")
+                        .value_name("HEX")
+                        .help("Only prints the keys starting with these bytes"),
+                )
+                .arg(
+                    Arg::new("limit")
+                        .long("limit")
+                        .value_name("N")
+                        .value_parser(value_parser!(usize))
+                        .help("Prints at most this many entries"),
+                ),
+        )
+        .subcommand(
+            Command::new("check")
+                .about("Reads every node and entry of the trie, checking the node hashes"),
+        )
+        .subcommand(
+            Command::new("dump")
+                .about("Copies the trie to another database or to a file, then checks the copy")
+                .arg(path(
+                    "to-db",
+                    "DIR",
+                    "The sled database directory to copy to",
+                ))
+                .arg(path("to-file", "FILE", "The file to write the trie to"))
+                .group(
+                    ArgGroup::new("target")
+                        .args(["to-db", "to-file"])
+                        .required(true),
+                ),
+        )
+        .subcommand(
+            Command::new("restore")
+                .about("Copies the trie from another database or from a file, then checks it")
+                .arg(path(
+                    "from-db",
+                    "DIR",
+                    "The sled database directory to copy from",
+                ))
+                .arg(path(
+                    "from-file",
+                    "FILE",
+                    "The file written by dump to read",
+                ))
+                .group(
+                    ArgGroup::new("source")
+                        .args(["from-db", "from-file"])
+                        .required(true),
+                ),
+        )
+}
+
+fn main() -> ExitCode {
+    let matches = cli().get_matches();
+    match run(&matches, &mut io::stdout().lock(), &mut io::stderr()) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(err) => {
+            eprintln!("error: {}", err);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+// Runs a command, writing its output to `out` and its progress to `progress`.
+fn run(matches: &ArgMatches, out: &mut dyn Write, progress: &mut dyn Write) -> CliResult<()> {
+    let tree = matches.get_one::<String>("tree").map(String::as_str);
+    let db = Arc::new(SledDB::open(
+        matches.get_one::<PathBuf>("db").unwrap(),
+        tree,
+    )?);
+    let root: B256 = matches.get_one::<String>("root").unwrap().parse()?;
+    if let Some(("restore", args)) = matches.subcommand() {
+        return restore(db, tree, root, args, out, progress);
+    }
+    let trie = open(db, root)?;
+    let secure = matches.get_flag("secure");
+    let key = |args: &ArgMatches| -> CliResult<Vec<u8>> {
+        let key = hex::decode(args.get_one::<String>("key").unwrap())?;
+        Ok(match secure {
+            true => keccak(key).as_bytes().to_vec(),
+            false => key,
+        })
+    };
+
+    match matches.subcommand() {
+        Some(("get", args)) => match trie.get(&key(args)?)? {
+            Some(value) => writeln!(out, "{}", hex::encode_prefixed(value))?,
+            None => return Err("key not found".into()),
+        },
+        Some(("proof", args)) => {
+            for node in trie.get_proof(&key(args)?)? {
+                writeln!(out, "{}", hex::encode_prefixed(node))?;
+            }
+        }
+        Some(("verify", args)) => {
+            let proof = args
+                .get_many::<String>("nodes")
+                .unwrap_or_default()
+                .map(hex::decode)
+                .collect::<Result<Vec<_>, _>>()?;
+            match trie.verify_proof(root, &key(args)?, proof)? {
+                Some(value) => writeln!(out, "{}", hex::encode_prefixed(value))?,
+                None => writeln!(out, "proven absent")?,
+            }
+        }
+        Some(("stats", _)) => {
+            let stats = trie.stats()?;
+            writeln!(out, "branch nodes:      {}", stats.branch_nodes)?;
+            writeln!(out, "extension nodes:   {}", stats.extension_nodes)?;
+            writeln!(out, "leaf nodes:        {}", stats.leaf_nodes)?;
+            writeln!(out, "hashed nodes:      {}", stats.hashed_nodes)?;
+            writeln!(out, "inline nodes:      {}", stats.inline_nodes)?;
+            writeln!(out, "encoded bytes:     {}", stats.encoded_bytes)?;
+            writeln!(out, "max depth:         {:?}", stats.max_depth())?;
+            writeln!(
+                out,
+                "branching factor:  {:.2}",
+                stats.average_branching_factor()
+            )?;
+            writeln!(out, "nodes by depth:    {:?}", stats.depth_histogram)?;
+        }
+        Some(("iter", args)) => {
+            let prefix = match args.get_one::<String>("prefix") {
+                Some(prefix) => hex::decode(prefix)?,
+                None => vec![],
+            };
+            let limit = args
+                .get_one::<usize>("limit")
+                .copied()
+                .unwrap_or(usize::MAX);
+            let bounds = (Bound::Included(prefix.clone()), Bound::Unbounded);
+            for entry in trie.range(bounds).take(limit) {
+                let (key, value) = entry?;
+                if !key.starts_with(&prefix) {
+                    break;
+                }
+                writeln!(
+                    out,
+                    "{} {}",
+                    hex::encode_prefixed(key),
+                    hex::encode_prefixed(value)
+                )?;
+            }
+        }
+        Some(("check", _)) => check(&trie, out)?,
+        Some(("dump", args)) => dump(&trie, tree, root, args, out, progress)?,
+        _ => unreachable!("a subcommand is required"),
+    }
+    Ok(())
+}
+
+fn open<D>(db: Arc<D>, root: B256) -> CliResult<EthTrie<D>>
+where
+    D: DB,
+{
+    let empty_root: B256 = KECCAK_NULL_RLP.as_fixed_bytes().into();
+    match root == empty_root {
+        true => Ok(EthTrie::new(db)),
+        false => Ok(EthTrie::from(db, root)?),
+    }
+}
+
+// Copies the trie to the target of `dump`, then checks the copy.
+fn dump<D>(
+    trie: &EthTrie<D>,
+    tree: Option<&str>,
+    root: B256,
+    args: &ArgMatches,
+    out: &mut dyn Write,
+    progress: &mut dyn Write,
+) -> CliResult<()>
+where
+    D: DB,
+{
+    if let Some(dir) = args.get_one::<PathBuf>("to-db") {
+        let target = Arc::new(SledDB::open(dir, tree)?);
+        trie.copy_to(target.clone(), |copied| {
+            let _ = write!(progress, "\rcopied {} nodes", copied);
+        })?;
+        let _ = writeln!(progress);
+        return verify(&open(target, root)?, root, out);
+    }
+
+    let path = args.get_one::<PathBuf>("to-file").unwrap();
+    let writer = Progress::new(BufWriter::new(File::create(path)?), progress);
+    trie.export_to_writer(writer)?;
+    let _ = writeln!(progress);
+    // Read back, to catch a file that doesn't hold what was written
+    let reader = BufReader::new(File::open(path)?);
+    let copy = EthTrie::import_from_reader(Arc::new(MemoryDB::new(true)), reader)?;
+    verify(&copy, root, out)
+}
+
+// Copies the trie from the source of `restore` into `db`, then checks it.
+fn restore(
+    db: Arc<SledDB>,
+    tree: Option<&str>,
+    root: B256,
+    args: &ArgMatches,
+    out: &mut dyn Write,
+    progress: &mut dyn Write,
+) -> CliResult<()> {
+    let restored = match args.get_one::<PathBuf>("from-db") {
+        Some(dir) => {
+            let source = open(Arc::new(SledDB::open(dir, tree)?), root)?;
+            source.copy_to(db.clone(), |copied| {
+                let _ = write!(progress, "\rcopied {} nodes", copied);
+            })?;
+            open(db, root)?
+        }
+        None => {
+            let path = args.get_one::<PathBuf>("from-file").unwrap();
+            let reader = Progress::new(BufReader::new(File::open(path)?), progress);
+            EthTrie::import_from_reader(db, reader)?
+        }
+    };
+    let _ = writeln!(progress);
+    verify(&restored, root, out)
+}
+
+// Checks that a copy of the trie is at `root` and holds all its nodes.
+fn verify<D>(copy: &EthTrie<D>, root: B256, out: &mut dyn Write) -> CliResult<()>
+where
+    D: DB,
+{
+    let copied = copy.snapshot().root_hash();
+    if copied != root {
+        return Err(format!("the copy is at root {}, not {}", copied, root).into());
+    }
+    check(copy, out)
+}
+
+// Passes reads or writes through, reporting the bytes moved every MiB.
+struct Progress<'a, T> {
+    inner: T,
+    progress: &'a mut dyn Write,
+    bytes: u64,
+}
+
+impl<'a, T> Progress<'a, T> {
+    const STEP: u64 = 1 << 20;
+
+    fn new(inner: T, progress: &'a mut dyn Write) -> Self {
+        Self {
+            inner,
+            progress,
+            bytes: 0,
+        }
+    }
+
+    fn moved(&mut self, bytes: usize) {
+        let before = self.bytes / Self::STEP;
+        self.bytes += bytes as u64;
+        if self.bytes / Self::STEP != before {
+            let _ = write!(self.progress, "\r{} MiB", self.bytes / Self::STEP);
+        }
+    }
+}
+
+impl<T: Read> Read for Progress<'_, T> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = self.inner.read(buf)?;
+        self.moved(read);
+        Ok(read)
+    }
+}
+
+impl<T: Write> Write for Progress<'_, T> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = self.inner.write(buf)?;
+        self.moved(written);
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+// Walks every node and entry of the trie, failing at the first missing or corrupt node.
+fn check<D>(trie: &EthTrie<D>, out: &mut dyn Write) -> CliResult<()>
+where
+    D: DB,
+{
+    let report = trie.health_check()?.into_result()?;
+    writeln!(out, "{}", report)?;
+
+    let mut nodes = 0;
+    for node in trie.iter_nodes() {
+        let node = node?;
+        if let Some(hash) = node.hash {
+            if keccak(&node.encoded).as_bytes() != hash.as_slice() {
+                return Err(format!("node {} at {:?} is corrupt", hash, node.path).into());
+            }
+        }
+        nodes += 1;
+    }
+    let mut entries = 0;
+    for entry in trie.iter() {
+        entry?;
+        entries += 1;
+    }
+    writeln!(out, "ok: {} nodes, {} entries", nodes, entries)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io;
+    use std::path::PathBuf;
+    use std::sync::Arc;
+
+    use eth_trie::{EthTrie, TrieWrite, DB};
+
+    use super::{cli, run, SledDB};
+
+    fn eth_trie(dir: &std::path::Path, args: &[&str]) -> Result<String, String> {
+        let mut argv = vec!["eth-trie", "--db", dir.to_str().unwrap()];
+        argv.extend(args);
+        let matches = cli().try_get_matches_from(argv).unwrap();
+        let mut out = vec![];
+        run(&matches, &mut out, &mut io::sink()).map_err(|err| err.to_string())?;
+        Ok(String::from_utf8(out).unwrap())
+    }
+
+    #[test]
+    fn test_cli_commands() {
+        let dir = std::env::temp_dir().join(format!("eth-trie-cli-{}", uuid::Uuid::new_v4()));
+        let mut trie = EthTrie::new(Arc::new(SledDB::open(&dir, None).unwrap()));
+        for i in 0..=255u8 {
+            trie.insert(&[i >> 4, i], &[i; 40]).unwrap();
+        }
+        let root = format!("{}", trie.root_hash().unwrap());
+        let child = trie
+            .iter_nodes()
+            .filter_map(|node| node.unwrap().hash)
+            .nth(3)
+            .unwrap();
+        // sled locks its directory, for as long as it's open
+        drop(trie);
+        let run = |args: &[&str]| {
+            let mut argv = vec!["--root", root.as_str()];
+            argv.extend(args);
+            eth_trie(&dir, &argv)
+        };
+
+        assert_eq!(
+            run(&["get", "0x0442"]).unwrap().trim(),
+            format!("0x{}", "42".repeat(40))
+        );
+        assert_eq!(run(&["get", "0x42"]).unwrap_err(), "key not found");
+
+        let proof = run(&["proof", "0442"]).unwrap();
+        let mut verify = vec!["verify", "0442"];
+        verify.extend(proof.lines());
+        assert_eq!(run(&verify).unwrap(), run(&["get", "0442"]).unwrap());
+        let proof = run(&["proof", "0443ff"]).unwrap();
+        let mut absent = vec!["verify", "0443ff"];
+        absent.extend(proof.lines());
+        assert_eq!(run(&absent).unwrap().trim(), "proven absent");
+
+        assert!(run(&["stats"]).unwrap().contains("leaf nodes:        256"));
+        assert_eq!(
+            run(&["iter", "--prefix", "0x04"]).unwrap().lines().count(),
+            16
+        );
+        assert_eq!(run(&["iter", "--limit", "3"]).unwrap().lines().count(), 3);
+        assert!(run(&["check"]).unwrap().contains("ok: "));
+
+        // A changed node fails the check
+        let db = SledDB::open(&dir, None).unwrap();
+        db.insert(child.as_slice(), vec![0xc0]).unwrap();
+        drop(db);
+        assert!(run(&["check"]).is_err());
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    fn temp_dir(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("eth-trie-{}-{}", name, uuid::Uuid::new_v4()))
+    }
+
+    #[test]
+    fn test_cli_dump_restore() {
+        let dir = temp_dir("source");
+        let mut trie = EthTrie::new(Arc::new(SledDB::open(&dir, None).unwrap()));
+        for i in 0..=255u8 {
+            trie.insert(&[i, i], &[i; 40]).unwrap();
+        }
+        let root = format!("{}", trie.root_hash().unwrap());
+        drop(trie);
+        let run = |dir: &PathBuf, args: &[&str]| {
+            let mut argv = vec!["--root", root.as_str()];
+            argv.extend(args);
+            eth_trie(dir, &argv)
+        };
+        let entries = run(&dir, &["iter"]).unwrap();
+
+        // Through another database and through a file, and back
+        let (copy, file) = (temp_dir("copy"), temp_dir("file"));
+        assert!(run(&dir, &["dump", "--to-db", copy.to_str().unwrap()])
+            .unwrap()
+            .contains("ok: "));
+        run(&dir, &["dump", "--to-file", file.to_str().unwrap()]).unwrap();
+        for (from, source) in [("--from-db", &copy), ("--from-file", &file)] {
+            let restored = temp_dir("restored");
+            run(&restored, &["restore", from, source.to_str().unwrap()]).unwrap();
+            assert_eq!(run(&restored, &["iter"]).unwrap(), entries);
+            std::fs::remove_dir_all(restored).unwrap();
+        }
+        // Into another tree of the same database
+        let file_arg = file.to_str().unwrap();
+        run(
+            &dir,
+            &["--tree", "copy", "restore", "--from-file", file_arg],
+        )
+        .unwrap();
+        assert_eq!(run(&dir, &["--tree", "copy", "iter"]).unwrap(), entries);
+
+        // A file of another root is refused
+        let other = format!("{}", alloy_primitives::B256::repeat_byte(1));
+        let restored = temp_dir("restored");
+        let argv = [
+            "--root",
+            &other,
+            "restore",
+            "--from-file",
+            file.to_str().unwrap(),
+        ];
+        assert!(eth_trie(&restored, &argv)
+            .unwrap_err()
+            .contains("not 0x0101"));
+
+        for path in [dir, copy, restored] {
+            std::fs::remove_dir_all(path).unwrap();
+        }
+        std::fs::remove_file(file).unwrap();
+    }
+}
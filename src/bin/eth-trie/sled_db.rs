@@ -0,0 +1,62 @@
+use std::path::Path;
+
+use eth_trie::DB;
+
+/// A database in a sled directory, keeping the entries in one of its trees.
+#[derive(Debug)]
+pub struct SledDB {
+    db: sled::Db,
+    tree: sled::Tree,
+}
+
+impl SledDB {
+    /// Opens the database in `dir`, creating it if it doesn't exist, and reads and writes
+    /// the tree named `tree`, or the default tree.
+    pub fn open(dir: &Path, tree: Option<&str>) -> sled::Result<Self> {
+        let db = sled::open(dir)?;
+        let tree = match tree {
+            Some(name) => db.open_tree(name)?,
+            None => (*db).clone(),
+        };
+        Ok(Self { db, tree })
+    }
+}
+
+impl DB for SledDB {
+    type Error = sled::Error;
+
+    fn get(&self, key: &[u8]) -> Result<Option<Vec<u8>>, Self::Error> {
+        Ok(self.tree.get(key)?.map(|value| value.to_vec()))
+    }
+
+    fn insert(&self, key: &[u8], value: Vec<u8>) -> Result<(), Self::Error> {
+        self.tree.insert(key, value)?;
+        Ok(())
+    }
+
+    fn remove(&self, key: &[u8]) -> Result<(), Self::Error> {
+        self.tree.remove(key)?;
+        Ok(())
+    }
+
+    fn insert_batch(&self, keys: Vec<Vec<u8>>, values: Vec<Vec<u8>>) -> Result<(), Self::Error> {
+        let mut batch = sled::Batch::default();
+        for (key, value) in keys.into_iter().zip(values) {
+            batch.insert(key, value);
+        }
+        self.tree.apply_batch(batch)
+    }
+
+    fn remove_batch(&self, keys: &[Vec<u8>]) -> Result<(), Self::Error> {
+        let mut batch = sled::Batch::default();
+        for key in keys {
+            batch.remove(key.as_slice());
+        }
+        self.tree.apply_batch(batch)
+    }
+
+    fn flush(&self) -> Result<(), Self::Error> {
+        self.db.flush()?;
+        Ok(())
+    }
+}
@@ -0,0 +1,759 @@
+//! An experimental binary Merkle-Patricia trie, in the spirit of EIP-3102.
+//!
+//! Keys are split into bits instead of nibbles, so every internal node has two children.
+//! Runs of single-child nodes are compressed into a prefix on the branch below them, which
+//! plays the part of the hexary extension node. Every node is stored by hash, there is no
+//! inlining of small nodes.
+//!
+//! The trie works over the same `DB` as `EthTrie` and implements the same `TrieRead` and
+//! `TrieWrite` traits, so the two layouts can be compared over one storage, by the same
+//! generic code. The encoding is not part of any
+//! specification and may change.
+
+use std::sync::Arc;
+
+use alloy_primitives::B256;
+use alloy_rlp::{Decodable, Encodable, Header, EMPTY_STRING_CODE};
+use hashbrown::{HashMap, HashSet};
+use keccak_hash::{keccak, KECCAK_NULL_RLP};
+
+use crate::db::{MemoryDB, DB};
+use crate::errors::TrieError;
+use crate::nibbles::Nibbles;
+use crate::trie::{RootWithTrieDiff, TrieRead, TrieResult, TrieWrite};
+
+#[derive(Debug, Clone)]
+enum BinaryNode {
+    Empty,
+    // The bits of the key left below the parent.
+    Leaf {
+        key: Nibbles,
+        value: Vec<u8>,
+    },
+    // The bits shared by every key below the branch, then one child per bit value. The
+    // value is that of the key ending right after the prefix.
+    Branch {
+        prefix: Nibbles,
+        children: Box<[BinaryNode; 2]>,
+        value: Option<Vec<u8>>,
+    },
+    Hash(B256),
+}
+
+/// A binary Merkle-Patricia trie, read and written through `TrieRead` and `TrieWrite`.
+#[derive(Debug)]
+pub struct BinaryTrie<D>
+where
+    D: DB,
+{
+    root: BinaryNode,
+    root_hash: B256,
+    db: Arc<D>,
+}
+
+impl<D> BinaryTrie<D>
+where
+    D: DB,
+{
+    pub fn new(db: Arc<D>) -> Self {
+        Self {
+            root: BinaryNode::Empty,
+            root_hash: KECCAK_NULL_RLP.as_fixed_bytes().into(),
+            db,
+        }
+    }
+
+    /// Opens the trie at `root_hash`.
+    pub fn from(db: Arc<D>, root_hash: B256) -> TrieResult<Self> {
+        let mut trie = Self::new(db);
+        if root_hash != trie.root_hash {
+            trie.root = trie.load(root_hash)?.ok_or(TrieError::InvalidStateRoot)?;
+            trie.root_hash = root_hash;
+        }
+        Ok(trie)
+    }
+
+    // Writes the changed nodes to the database and moves the trie to the new root.
+    fn commit(&mut self) -> TrieResult<RootWithTrieDiff> {
+        let previous_root = self.root_hash;
+        let mut nodes = HashMap::new();
+        let root_hash = match &self.root {
+            BinaryNode::Hash(hash) => *hash,
+            root => {
+                let encoded = encode(root, &mut |hash, encoded| {
+                    nodes.insert(hash, encoded);
+                });
+                let hash = hash(&encoded);
+                nodes.insert(hash, encoded);
+                hash
+            }
+        };
+
+        let (keys, values) = nodes
+            .iter()
+            .map(|(hash, encoded)| (hash.to_vec(), encoded.clone()))
+            .unzip();
+        self.db.insert_batch(keys, values).map_err(TrieError::db)?;
+        self.root_hash = root_hash;
+        if !matches!(self.root, BinaryNode::Empty) {
+            self.root = BinaryNode::Hash(root_hash);
+        }
+        Ok(RootWithTrieDiff {
+            root: root_hash,
+            previous_root,
+            trie_diff: nodes,
+            stale_nodes: HashSet::new(),
+        })
+    }
+
+    fn insert_at(&self, node: BinaryNode, bits: Nibbles, value: Vec<u8>) -> TrieResult<BinaryNode> {
+        match node {
+            BinaryNode::Empty => Ok(BinaryNode::Leaf { key: bits, value }),
+            BinaryNode::Leaf {
+                key,
+                value: old_value,
+            } => {
+                if key == bits {
+                    return Ok(BinaryNode::Leaf { key, value });
+                }
+                let common = key.common_prefix(&bits);
+                let branch = BinaryNode::Branch {
+                    prefix: key.slice(0, common),
+                    children: Box::new([BinaryNode::Empty, BinaryNode::Empty]),
+                    value: None,
+                };
+                let branch = self.insert_at(branch, key, old_value)?;
+                self.insert_at(branch, bits, value)
+            }
+            BinaryNode::Branch {
+                prefix,
+                mut children,
+                value: branch_value,
+            } => {
+                let common = prefix.common_prefix(&bits);
+                if common < prefix.len() {
+                    // Split the prefix, moving the branch one level down
+                    let mut split: [BinaryNode; 2] = [BinaryNode::Empty, BinaryNode::Empty];
+                    split[prefix.at(common)] = BinaryNode::Branch {
+                        prefix: prefix.offset(common + 1),
+                        children,
+                        value: branch_value,
+                    };
+                    let branch = BinaryNode::Branch {
+                        prefix: prefix.slice(0, common),
+                        children: Box::new(split),
+                        value: None,
+                    };
+                    return self.insert_at(branch, bits, value);
+                }
+
+                if bits.len() == prefix.len() {
+                    return Ok(BinaryNode::Branch {
+                        prefix,
+                        children,
+                        value: Some(value),
+                    });
+                }
+                let bit = bits.at(prefix.len());
+                let child = std::mem::replace(&mut children[bit], BinaryNode::Empty);
+                children[bit] = self.insert_at(child, bits.offset(prefix.len() + 1), value)?;
+                Ok(BinaryNode::Branch {
+                    prefix,
+                    children,
+                    value: branch_value,
+                })
+            }
+            BinaryNode::Hash(hash) => self.insert_at(self.load_existing(hash)?, bits, value),
+        }
+    }
+
+    fn remove_at(&self, node: BinaryNode, bits: Nibbles) -> TrieResult<(BinaryNode, bool)> {
+        match node {
+            BinaryNode::Empty => Ok((BinaryNode::Empty, false)),
+            BinaryNode::Leaf { ref key, .. } => {
+                if *key == bits {
+                    Ok((BinaryNode::Empty, true))
+                } else {
+                    Ok((node, false))
+                }
+            }
+            BinaryNode::Branch {
+                prefix,
+                mut children,
+                mut value,
+            } => {
+                let removed = if prefix.common_prefix(&bits) < prefix.len() {
+                    false
+                } else if bits.len() == prefix.len() {
+                    value.take().is_some()
+                } else {
+                    let bit = bits.at(prefix.len());
+                    let child = std::mem::replace(&mut children[bit], BinaryNode::Empty);
+                    let (child, removed) = self.remove_at(child, bits.offset(prefix.len() + 1))?;
+                    children[bit] = child;
+                    removed
+                };
+                let branch = BinaryNode::Branch {
+                    prefix,
+                    children,
+                    value,
+                };
+                if removed {
+                    Ok((self.normalize(branch)?, true))
+                } else {
+                    Ok((branch, false))
+                }
+            }
+            BinaryNode::Hash(hash) => self.remove_at(self.load_existing(hash)?, bits),
+        }
+    }
+
+    // Collapses a branch left with fewer than two entries into its only child or value.
+    fn normalize(&self, node: BinaryNode) -> TrieResult<BinaryNode> {
+        let BinaryNode::Branch {
+            prefix,
+            children,
+            value,
+        } = node
+        else {
+            return Ok(node);
+        };
+        let [left, right] = *children;
+        let only_child = match (&left, &right, &value) {
+            (BinaryNode::Empty, BinaryNode::Empty, None) => return Ok(BinaryNode::Empty),
+            (BinaryNode::Empty, BinaryNode::Empty, Some(_)) => {
+                return Ok(BinaryNode::Leaf {
+                    key: prefix,
+                    value: value.unwrap(),
+                })
+            }
+            (child, BinaryNode::Empty, None) => Some((0, child.clone())),
+            (BinaryNode::Empty, child, None) => Some((1, child.clone())),
+            _ => None,
+        };
+
+        match only_child {
+            Some((bit, child)) => {
+                let mut path = prefix;
+                path.push(bit);
+                let child = match child {
+                    BinaryNode::Hash(hash) => self.load_existing(hash)?,
+                    child => child,
+                };
+                Ok(match child {
+                    BinaryNode::Leaf { key, value } => BinaryNode::Leaf {
+                        key: path.join(&key),
+                        value,
+                    },
+                    BinaryNode::Branch {
+                        prefix,
+                        children,
+                        value,
+                    } => BinaryNode::Branch {
+                        prefix: path.join(&prefix),
+                        children,
+                        value,
+                    },
+                    _ => unreachable!(),
+                })
+            }
+            None => Ok(BinaryNode::Branch {
+                prefix,
+                children: Box::new([left, right]),
+                value,
+            }),
+        }
+    }
+
+    fn load(&self, hash: B256) -> TrieResult<Option<BinaryNode>> {
+        match self.db.get(hash.as_slice()).map_err(TrieError::db)? {
+            Some(encoded) => Ok(Some(decode(&encoded)?)),
+            None => Ok(None),
+        }
+    }
+
+    fn load_existing(&self, hash: B256) -> TrieResult<BinaryNode> {
+        self.load(hash)?.ok_or(TrieError::MissingTrieNode {
+            node_hash: hash,
+            traversed: None,
+            root_hash: Some(self.root_hash),
+            err_key: None,
+        })
+    }
+}
+
+impl<D> TrieRead<D> for BinaryTrie<D>
+where
+    D: DB,
+{
+    type Iter<'a>
+        = BinaryTrieIterator<'a, D>
+    where
+        Self: 'a;
+
+    /// Returns the value for key stored in the trie.
+    fn get(&self, key: &[u8]) -> TrieResult<Option<Vec<u8>>> {
+        let bits = key_bits(key);
+        let mut node = self.root.clone();
+        let mut depth = 0;
+        loop {
+            node = match node {
+                BinaryNode::Empty => return Ok(None),
+                BinaryNode::Leaf { key, value } => {
+                    return Ok((key == bits.offset(depth)).then_some(value))
+                }
+                BinaryNode::Branch {
+                    prefix,
+                    children,
+                    value,
+                } => {
+                    let rest = bits.offset(depth);
+                    if rest.common_prefix(&prefix) < prefix.len() {
+                        return Ok(None);
+                    }
+                    depth += prefix.len();
+                    if depth == bits.len() {
+                        return Ok(value);
+                    }
+                    let [left, right] = *children;
+                    depth += 1;
+                    if bits.at(depth - 1) == 0 {
+                        left
+                    } else {
+                        right
+                    }
+                }
+                BinaryNode::Hash(hash) => self.load_existing(hash)?,
+            };
+        }
+    }
+
+    /// Checks that the key is present in the trie.
+    fn contains(&self, key: &[u8]) -> TrieResult<bool> {
+        Ok(self.get(key)?.is_some())
+    }
+
+    /// Returns the encoded nodes on the path to key, from the root down. Uncommitted
+    /// changes are not part of the proof.
+    fn get_proof(&self, key: &[u8]) -> TrieResult<Vec<Vec<u8>>> {
+        let bits = key_bits(key);
+        let mut proof = Vec::new();
+        let mut next = Some(self.root_hash);
+        let mut depth = 0;
+        while let Some(hash) = next.take() {
+            let Some(encoded) = self.db.get(hash.as_slice()).map_err(TrieError::db)? else {
+                break;
+            };
+            if let BinaryNode::Branch {
+                prefix, children, ..
+            } = decode(&encoded)?
+            {
+                let rest = bits.offset(depth);
+                if rest.common_prefix(&prefix) == prefix.len() && rest.len() > prefix.len() {
+                    depth += prefix.len() + 1;
+                    if let BinaryNode::Hash(child) = &children[bits.at(depth - 1)] {
+                        next = Some(*child);
+                    }
+                }
+            }
+            proof.push(encoded);
+        }
+        Ok(proof)
+    }
+
+    /// Returns the value for key if the proof holds, None if it proves the key absent, or
+    /// `TrieError::InvalidProof` if the proof does not lead to `root_hash`.
+    fn verify_proof(
+        &self,
+        root_hash: B256,
+        key: &[u8],
+        proof: Vec<Vec<u8>>,
+    ) -> TrieResult<Option<Vec<u8>>> {
+        verify_binary_proof(root_hash, key, proof)
+    }
+
+    /// Returns an iterator over all the entries of the trie, in key order.
+    fn iter(&self) -> Self::Iter<'_> {
+        BinaryTrieIterator {
+            trie: self,
+            stack: vec![(Nibbles::from_hex(&[]), self.root.clone())],
+        }
+    }
+}
+
+impl<D> TrieWrite<D> for BinaryTrie<D>
+where
+    D: DB,
+{
+    /// Inserts value into trie and modifies it if it exists. An empty value removes the
+    /// key, as with `EthTrie`.
+    fn insert(&mut self, key: &[u8], value: &[u8]) -> TrieResult<()> {
+        if value.is_empty() {
+            self.remove(key)?;
+            return Ok(());
+        }
+        self.root = self.insert_at(self.root.clone(), key_bits(key), value.to_vec())?;
+        Ok(())
+    }
+
+    /// Removes any existing value for key from the trie.
+    fn remove(&mut self, key: &[u8]) -> TrieResult<bool> {
+        let (root, removed) = self.remove_at(self.root.clone(), key_bits(key))?;
+        self.root = root;
+        Ok(removed)
+    }
+
+    /// Writes the changed nodes to the database and returns the new root hash. Nodes
+    /// that are no longer used are left in the database.
+    fn root_hash(&mut self) -> TrieResult<B256> {
+        Ok(self.commit()?.root)
+    }
+
+    /// Writes the changed nodes to the database and returns them with the new root hash.
+    /// Stale nodes are not tracked, so `stale_nodes` is always empty.
+    fn root_hash_with_changed_nodes(&mut self) -> TrieResult<RootWithTrieDiff> {
+        self.commit()
+    }
+
+    /// Removes every node of the committed trie from the database, and empties the trie.
+    fn clear_trie_from_db(&mut self) -> TrieResult<()> {
+        let mut stack = vec![self.root_hash];
+        let mut visited = HashSet::new();
+        while let Some(hash) = stack.pop() {
+            if !visited.insert(hash) {
+                continue;
+            }
+            let Some(node) = self.load(hash)? else {
+                continue;
+            };
+            self.db.remove(hash.as_slice()).map_err(TrieError::db)?;
+            if let BinaryNode::Branch { children, .. } = node {
+                stack.extend(children.iter().filter_map(|child| match child {
+                    BinaryNode::Hash(hash) => Some(*hash),
+                    _ => None,
+                }));
+            }
+        }
+        self.root = BinaryNode::Empty;
+        self.root_hash = KECCAK_NULL_RLP.as_fixed_bytes().into();
+        Ok(())
+    }
+}
+
+/// Iterates over the entries of a `BinaryTrie` in key order.
+pub struct BinaryTrieIterator<'a, D>
+where
+    D: DB,
+{
+    trie: &'a BinaryTrie<D>,
+    // The nodes left to visit with the bits leading to them, next one last.
+    stack: Vec<(Nibbles, BinaryNode)>,
+}
+
+impl<D> Iterator for BinaryTrieIterator<'_, D>
+where
+    D: DB,
+{
+    type Item = TrieResult<(Vec<u8>, Vec<u8>)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while let Some((path, node)) = self.stack.pop() {
+            match node {
+                BinaryNode::Empty => {}
+                BinaryNode::Leaf { key, value } => {
+                    return Some(Ok((bits_key(&path.join(&key)), value)));
+                }
+                BinaryNode::Branch {
+                    prefix,
+                    children,
+                    value,
+                } => {
+                    let path = path.join(&prefix);
+                    let [left, right] = *children;
+                    for (bit, child) in [(1, right), (0, left)] {
+                        let mut child_path = path.clone();
+                        child_path.push(bit);
+                        self.stack.push((child_path, child));
+                    }
+                    if let Some(value) = value {
+                        return Some(Ok((bits_key(&path), value)));
+                    }
+                }
+                BinaryNode::Hash(hash) => match self.trie.load_existing(hash) {
+                    Ok(node) => self.stack.push((path, node)),
+                    Err(e) => {
+                        self.stack.clear();
+                        return Some(Err(e));
+                    }
+                },
+            }
+        }
+        None
+    }
+}
+
+fn verify_binary_proof(
+    root_hash: B256,
+    key: &[u8],
+    proof: Vec<Vec<u8>>,
+) -> TrieResult<Option<Vec<u8>>> {
+    let proof_db = Arc::new(MemoryDB::new(true));
+    let (keys, values) = proof
+        .into_iter()
+        .map(|encoded| (hash(&encoded).to_vec(), encoded))
+        .unzip();
+    proof_db.insert_batch(keys, values).unwrap();
+    let trie = BinaryTrie::from(proof_db, root_hash).or(Err(TrieError::InvalidProof))?;
+    trie.get(key).or(Err(TrieError::InvalidProof))
+}
+
+fn key_bits(key: &[u8]) -> Nibbles {
+    let bits: Vec<u8> = key
+        .iter()
+        .flat_map(|byte| (0..8).rev().map(move |i| (byte >> i) & 1))
+        .collect();
+    Nibbles::from_hex(&bits)
+}
+
+fn bits_key(bits: &Nibbles) -> Vec<u8> {
+    pack_bits(bits.get_data())
+}
+
+fn pack_bits(bits: &[u8]) -> Vec<u8> {
+    bits.chunks(8)
+        .map(|chunk| {
+            chunk
+                .iter()
+                .enumerate()
+                .fold(0, |byte, (i, bit)| byte | (bit << (7 - i)))
+        })
+        .collect()
+}
+
+fn unpack_bits(packed: &[u8], len: usize) -> TrieResult<Nibbles> {
+    if packed.len() != len.div_ceil(8) {
+        return Err(TrieError::InvalidData);
+    }
+    let mut bits = key_bits(packed);
+    bits.truncate(len);
+    Ok(bits)
+}
+
+fn hash(encoded: &[u8]) -> B256 {
+    keccak(encoded).as_fixed_bytes().into()
+}
+
+// Encodes a node, passing every child to `store` with its hash. A leaf is the list
+// `[bit count, packed bits, value]` and a branch `[bit count, packed bits, left, right,
+// value]`, where children are referred to by hash, or by an empty string if absent.
+fn encode<F>(node: &BinaryNode, store: &mut F) -> Vec<u8>
+where
+    F: FnMut(B256, Vec<u8>),
+{
+    let mut payload = Vec::new();
+    match node {
+        BinaryNode::Empty => return vec![EMPTY_STRING_CODE],
+        BinaryNode::Leaf { key, value } => {
+            encode_bits(key, &mut payload);
+            value.as_slice().encode(&mut payload);
+        }
+        BinaryNode::Branch {
+            prefix,
+            children,
+            value,
+        } => {
+            encode_bits(prefix, &mut payload);
+            for child in children.iter() {
+                match child {
+                    BinaryNode::Empty => payload.push(EMPTY_STRING_CODE),
+                    BinaryNode::Hash(hash) => hash.encode(&mut payload),
+                    child => {
+                        let encoded = encode(child, store);
+                        let child_hash = hash(&encoded);
+                        store(child_hash, encoded);
+                        child_hash.encode(&mut payload);
+                    }
+                }
+            }
+            value.as_deref().unwrap_or_default().encode(&mut payload);
+        }
+        BinaryNode::Hash(_) => unreachable!(),
+    }
+
+    let mut encoded = Vec::with_capacity(payload.len() + 3);
+    Header {
+        list: true,
+        payload_length: payload.len(),
+    }
+    .encode(&mut encoded);
+    encoded.extend_from_slice(&payload);
+    encoded
+}
+
+fn encode_bits(bits: &Nibbles, out: &mut Vec<u8>) {
+    (bits.len() as u64).encode(out);
+    pack_bits(bits.get_data()).as_slice().encode(out);
+}
+
+fn decode(encoded: &[u8]) -> TrieResult<BinaryNode> {
+    let mut buf = encoded;
+    let mut payload = Header::decode_bytes(&mut buf, true)?;
+    let len = u64::decode(&mut payload)? as usize;
+    let path = unpack_bits(Header::decode_bytes(&mut payload, false)?, len)?;
+
+    let mut items = Vec::with_capacity(3);
+    while !payload.is_empty() {
+        items.push(Header::decode_bytes(&mut payload, false)?);
+    }
+    let child = |item: &[u8]| match item.len() {
+        0 => Ok(BinaryNode::Empty),
+        32 => Ok(BinaryNode::Hash(B256::from_slice(item))),
+        _ => Err(TrieError::InvalidData),
+    };
+    match items.as_slice() {
+        [value] => Ok(BinaryNode::Leaf {
+            key: path,
+            value: value.to_vec(),
+        }),
+        [left, right, value] => Ok(BinaryNode::Branch {
+            prefix: path,
+            children: Box::new([child(left)?, child(right)?]),
+            value: (!value.is_empty()).then(|| value.to_vec()),
+        }),
+        _ => Err(TrieError::InvalidData),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+    use std::sync::Arc;
+
+    use keccak_hash::KECCAK_NULL_RLP;
+    use rand::{thread_rng, Rng};
+
+    use super::BinaryTrie;
+    use crate::db::{MemoryDB, DB};
+    use crate::errors::TrieError;
+    use crate::trie::{EthTrie, Trie, TrieRead, TrieWrite};
+
+    fn random_entries(count: usize) -> BTreeMap<Vec<u8>, Vec<u8>> {
+        let mut rng = thread_rng();
+        (0..count)
+            .map(|_| {
+                let key_len = rng.gen_range(1..6);
+                let key: Vec<u8> = (0..key_len).map(|_| rng.gen_range(0..4)).collect();
+                (key, rng.gen::<[u8; 8]>().to_vec())
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_binary_trie_insert_get_remove() {
+        let memdb = Arc::new(MemoryDB::new(false));
+        let mut trie = BinaryTrie::new(memdb.clone());
+        let entries = random_entries(300);
+        for (key, value) in entries.iter() {
+            trie.insert(key, value).unwrap();
+        }
+        for (key, value) in entries.iter() {
+            assert_eq!(trie.get(key).unwrap().as_ref(), Some(value));
+        }
+
+        let root = trie.root_hash().unwrap();
+        let iterated: BTreeMap<_, _> = trie.iter().map(|item| item.unwrap()).collect();
+        assert_eq!(iterated, entries);
+        let keys: Vec<_> = trie.iter().map(|item| item.unwrap().0).collect();
+        assert!(keys.windows(2).all(|pair| pair[0] < pair[1]));
+
+        // The root depends on the entries only, not on the order of insertion
+        let mut reversed = BinaryTrie::new(Arc::new(MemoryDB::new(false)));
+        for (key, value) in entries.iter().rev() {
+            reversed.insert(key, value).unwrap();
+        }
+        assert_eq!(reversed.root_hash().unwrap(), root);
+
+        let mut trie = BinaryTrie::from(memdb, root).unwrap();
+        for key in entries.keys() {
+            assert!(trie.remove(key).unwrap());
+            assert!(!trie.contains(key).unwrap());
+        }
+        assert_eq!(
+            trie.root_hash().unwrap().as_slice(),
+            KECCAK_NULL_RLP.as_bytes()
+        );
+    }
+
+    #[test]
+    fn test_binary_trie_proof() {
+        let mut trie = BinaryTrie::new(Arc::new(MemoryDB::new(false)));
+        let entries = random_entries(100);
+        for (key, value) in entries.iter() {
+            trie.insert(key, value).unwrap();
+        }
+        let root = trie.root_hash().unwrap();
+
+        for (key, value) in entries.iter() {
+            let proof = trie.get_proof(key).unwrap();
+            assert_eq!(
+                trie.verify_proof(root, key, proof).unwrap().as_ref(),
+                Some(value)
+            );
+        }
+        let proof = trie.get_proof(b"missing").unwrap();
+        assert_eq!(trie.verify_proof(root, b"missing", proof).unwrap(), None);
+
+        let mut proof = trie.get_proof(entries.keys().next().unwrap()).unwrap();
+        proof.pop();
+        assert!(proof.len() > 1);
+        assert_eq!(
+            trie.verify_proof(root, entries.keys().next().unwrap(), proof),
+            Err(TrieError::InvalidProof)
+        );
+    }
+
+    // Fills a trie of either layout, then reads a range of it back.
+    fn fill_and_range<T, D>(trie: &mut T, entries: &BTreeMap<Vec<u8>, Vec<u8>>) -> Vec<Vec<u8>>
+    where
+        T: Trie<D>,
+        D: DB,
+    {
+        for (key, value) in entries.iter() {
+            trie.insert(key, value).unwrap();
+        }
+        trie.root_hash().unwrap();
+        trie.range(vec![1]..vec![2, 2])
+            .map(|item| item.unwrap().0)
+            .collect()
+    }
+
+    #[test]
+    fn test_binary_trie_traits() {
+        let entries = random_entries(200);
+        let expected: Vec<_> = entries
+            .keys()
+            .filter(|key| key.as_slice() >= [1].as_slice() && key.as_slice() < [2, 2].as_slice())
+            .cloned()
+            .collect();
+        let mut binary = BinaryTrie::new(Arc::new(MemoryDB::new(true)));
+        let mut hexary = EthTrie::new(Arc::new(MemoryDB::new(false)));
+        assert_eq!(fill_and_range(&mut binary, &entries), expected);
+        assert_eq!(fill_and_range(&mut hexary, &entries), expected);
+
+        // The changed nodes are those written under the new root
+        let (key, _) = entries.iter().next().unwrap();
+        let previous_root = binary.root_hash().unwrap();
+        binary.insert(key, b"changed").unwrap();
+        let diff = binary.root_hash_with_changed_nodes().unwrap();
+        assert_eq!(diff.previous_root, previous_root);
+        assert!(diff.trie_diff.contains_key(&diff.root));
+        let reopened = BinaryTrie::from(binary.db.clone(), diff.root).unwrap();
+        assert_eq!(reopened.get(key).unwrap(), Some(b"changed".to_vec()));
+
+        let db = binary.db.clone();
+        binary.clear_trie_from_db().unwrap();
+        assert!(binary.iter().next().is_none());
+        assert!(db.get(diff.root.as_slice()).unwrap().is_none());
+        assert!(BinaryTrie::from(db, diff.root).is_err());
+    }
+}
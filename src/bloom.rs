@@ -0,0 +1,265 @@
+use std::f64::consts::LN_2;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use keccak_hash::keccak;
+
+use crate::db::{IterableDB, DB};
+use crate::trie::HASHED_LENGTH;
+
+/// A database wrapper that keeps a bloom filter over the keys written through it, so
+/// lookups of keys that were never written skip the inner database.
+///
+/// Sync and healing mostly ask for nodes the database doesn't have yet; with the filter,
+/// all but a false positive rate of those lookups return `None` without a read. Removed
+/// keys stay in the filter, which costs reads but never correctness. The filter only
+/// knows the keys written through the wrapper, so a database that already has data must
+/// be opened with `BloomDB::with_keys`.
+#[derive(Debug)]
+pub struct BloomDB<D>
+where
+    D: DB,
+{
+    db: D,
+    filter: BloomFilter,
+    skipped_reads: AtomicU64,
+}
+
+impl<D> BloomDB<D>
+where
+    D: DB,
+{
+    /// Wraps an empty database with a filter sized for `capacity` keys at the given false
+    /// positive rate. The rate grows past `capacity` keys.
+    pub fn new(db: D, capacity: usize, false_positive_rate: f64) -> Self {
+        Self {
+            db,
+            filter: BloomFilter::new(capacity, false_positive_rate),
+            skipped_reads: AtomicU64::new(0),
+        }
+    }
+
+    /// Returns false if `key` was never written, true if it may have been.
+    pub fn may_contain(&self, key: &[u8]) -> bool {
+        self.filter.contains(key)
+    }
+
+    /// The number of lookups answered by the filter alone.
+    pub fn skipped_reads(&self) -> u64 {
+        self.skipped_reads.load(Ordering::Relaxed)
+    }
+
+    pub fn inner(&self) -> &D {
+        &self.db
+    }
+
+    pub fn into_inner(self) -> D {
+        self.db
+    }
+
+    fn skip(&self, key: &[u8]) -> bool {
+        let skip = !self.filter.contains(key);
+        if skip {
+            self.skipped_reads.fetch_add(1, Ordering::Relaxed);
+        }
+        skip
+    }
+}
+
+impl<D> BloomDB<D>
+where
+    D: IterableDB,
+{
+    /// Wraps a database that may already have data, adding its keys to the filter.
+    /// The filter is sized for `capacity` keys or the current number of keys, whichever
+    /// is larger.
+    pub fn with_keys(db: D, capacity: usize, false_positive_rate: f64) -> Result<Self, D::Error> {
+        let keys = db.keys()?;
+        let bloom = Self::new(db, capacity.max(keys.len()), false_positive_rate);
+        for key in keys.iter() {
+            bloom.filter.insert(key);
+        }
+        Ok(bloom)
+    }
+}
+
+impl<D> DB for BloomDB<D>
+where
+    D: DB,
+{
+    type Error = D::Error;
+
+    fn get(&self, key: &[u8]) -> Result<Option<Vec<u8>>, Self::Error> {
+        if self.skip(key) {
+            return Ok(None);
+        }
+        self.db.get(key)
+    }
+
+    fn insert(&self, key: &[u8], value: Vec<u8>) -> Result<(), Self::Error> {
+        // The filter must know the key before the database holds it: the other way round,
+        // a concurrent read in between would be skipped, and miss a key that is stored. A
+        // failed write only leaves an extra key in the filter.
+        self.filter.insert(key);
+        self.db.insert(key, value)
+    }
+
+    fn remove(&self, key: &[u8]) -> Result<(), Self::Error> {
+        self.db.remove(key)
+    }
+
+    fn get_batch(&self, keys: &[Vec<u8>]) -> Result<Vec<Option<Vec<u8>>>, Self::Error> {
+        let maybe: Vec<bool> = keys.iter().map(|k| !self.skip(k)).collect();
+        if maybe.iter().all(|m| *m) {
+            return self.db.get_batch(keys);
+        }
+
+        let present: Vec<Vec<u8>> = keys
+            .iter()
+            .zip(maybe.iter())
+            .filter(|(_, m)| **m)
+            .map(|(k, _)| k.clone())
+            .collect();
+        let mut values = self.db.get_batch(&present)?.into_iter();
+        Ok(maybe
+            .into_iter()
+            .map(|m| match m {
+                true => values.next().flatten(),
+                false => None,
+            })
+            .collect())
+    }
+
+    fn insert_batch(&self, keys: Vec<Vec<u8>>, values: Vec<Vec<u8>>) -> Result<(), Self::Error> {
+        // Filter first, as in `insert`
+        for key in keys.iter() {
+            self.filter.insert(key);
+        }
+        self.db.insert_batch(keys, values)
+    }
+
+    fn remove_batch(&self, keys: &[Vec<u8>]) -> Result<(), Self::Error> {
+        self.db.remove_batch(keys)
+    }
+
+    fn flush(&self) -> Result<(), Self::Error> {
+        self.db.flush()
+    }
+
+    #[cfg(test)]
+    fn len(&self) -> Result<usize, Self::Error> {
+        self.db.len()
+    }
+    #[cfg(test)]
+    fn is_empty(&self) -> Result<bool, Self::Error> {
+        self.db.is_empty()
+    }
+}
+
+impl<D> IterableDB for BloomDB<D>
+where
+    D: IterableDB,
+{
+    fn keys(&self) -> Result<Vec<Vec<u8>>, Self::Error> {
+        self.db.keys()
+    }
+}
+
+// A bloom filter that can be added to through a shared reference.
+#[derive(Debug)]
+struct BloomFilter {
+    bits: Vec<AtomicU64>,
+    hashes: u32,
+}
+
+impl BloomFilter {
+    fn new(capacity: usize, false_positive_rate: f64) -> Self {
+        let capacity = capacity.max(1) as f64;
+        let rate = false_positive_rate.clamp(1e-9, 0.5);
+        let bits = (-capacity * rate.ln() / (LN_2 * LN_2)).ceil().max(64.0);
+        let hashes = ((bits / capacity) * LN_2).round().clamp(1.0, 32.0) as u32;
+        let words = (bits as usize).div_ceil(64);
+        Self {
+            bits: (0..words).map(|_| AtomicU64::new(0)).collect(),
+            hashes,
+        }
+    }
+
+    fn insert(&self, key: &[u8]) {
+        for (word, mask) in self.positions(key) {
+            self.bits[word].fetch_or(mask, Ordering::Relaxed);
+        }
+    }
+
+    fn contains(&self, key: &[u8]) -> bool {
+        self.positions(key)
+            .all(|(word, mask)| self.bits[word].load(Ordering::Relaxed) & mask != 0)
+    }
+
+    // Double hashing over two 64 bit halves of the key's hash. Node keys are already
+    // hashes, so only other keys are hashed here.
+    fn positions(&self, key: &[u8]) -> impl Iterator<Item = (usize, u64)> {
+        let hash = match key.len() {
+            HASHED_LENGTH => {
+                let mut hash = [0u8; HASHED_LENGTH];
+                hash.copy_from_slice(key);
+                hash
+            }
+            _ => keccak(key).0,
+        };
+        let h1 = u64::from_le_bytes(hash[..8].try_into().unwrap());
+        let h2 = u64::from_le_bytes(hash[8..16].try_into().unwrap()) | 1;
+        let bits = self.bits.len() as u64 * 64;
+        (0..self.hashes as u64).map(move |i| {
+            let bit = h1.wrapping_add(i.wrapping_mul(h2)) % bits;
+            ((bit / 64) as usize, 1 << (bit % 64))
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use keccak_hash::keccak;
+
+    use super::BloomDB;
+    use crate::db::{MemoryDB, DB};
+    use crate::trie::{EthTrie, TrieRead, TrieWrite};
+
+    #[test]
+    fn test_bloom_db_skips_missing_keys() {
+        let db = Arc::new(BloomDB::new(MemoryDB::new(true), 1000, 0.01));
+        let mut trie = EthTrie::new(db.clone());
+        for i in 0..200u32 {
+            trie.insert(&i.to_be_bytes(), &[1; 40]).unwrap();
+        }
+        let root = trie.root_hash().unwrap();
+
+        let trie = EthTrie::from(db.clone(), root).unwrap();
+        for i in 0..200u32 {
+            assert_eq!(trie.get(&i.to_be_bytes()).unwrap(), Some(vec![1; 40]));
+        }
+        assert_eq!(db.skipped_reads(), 0);
+
+        let missing: Vec<Vec<u8>> = (0..1000u32)
+            .map(|i| keccak(i.to_be_bytes()).as_bytes().to_vec())
+            .collect();
+        let values = db.get_batch(&missing).unwrap();
+        assert!(values.iter().all(Option::is_none));
+        assert!(db.skipped_reads() > 950);
+    }
+
+    #[test]
+    fn test_bloom_db_with_keys() {
+        let memdb = MemoryDB::new(true);
+        memdb.insert(b"eth-trie:epoch", vec![1]).unwrap();
+        let db = BloomDB::with_keys(memdb, 10, 0.01).unwrap();
+        assert!(db.may_contain(b"eth-trie:epoch"));
+        assert_eq!(db.get(b"eth-trie:epoch").unwrap(), Some(vec![1]));
+        assert_eq!(
+            db.get_batch(&[b"eth-trie:epoch".to_vec(), b"missing".to_vec()])
+                .unwrap(),
+            vec![Some(vec![1]), None]
+        );
+    }
+}
@@ -0,0 +1,242 @@
+use std::sync::Arc;
+
+use alloy_primitives::B256;
+
+use crate::db::DB;
+use crate::observer::CommitObserver;
+use crate::resolver::NodeResolver;
+use crate::root_manager::RootManager;
+use crate::trie::{EthTrie, NodeReader, TrieConfig, TrieResult};
+
+/// Configures an `EthTrie` before opening it.
+///
+/// ```
+/// use std::sync::Arc;
+///
+/// use eth_trie::{EthTrie, MemoryDB, TrieWrite};
+///
+/// let memdb = Arc::new(MemoryDB::new(true));
+/// let mut trie = EthTrie::builder(memdb).auto_flush(1000).strict(true).build().unwrap();
+/// trie.insert(b"test", b"test").unwrap();
+/// ```
+#[derive(Debug)]
+pub struct EthTrieBuilder<D>
+where
+    D: DB,
+{
+    db: Arc<D>,
+    root: Option<B256>,
+    health_check: bool,
+    config: TrieConfig,
+}
+
+impl<D> EthTrieBuilder<D>
+where
+    D: DB,
+{
+    pub fn new(db: Arc<D>) -> Self {
+        Self {
+            db,
+            root: None,
+            health_check: false,
+            config: TrieConfig::default(),
+        }
+    }
+
+    /// Opens the trie at an existing root instead of starting an empty one.
+    pub fn root(mut self, root: B256) -> Self {
+        self.root = Some(root);
+        self
+    }
+
+    /// Runs `EthTrie::health_check` on the root the trie is opened at, and fails to build
+    /// with the error for the first node found missing or corrupt, so that a database
+    /// that doesn't hold the trie is caught when it is opened.
+    pub fn health_check(mut self, check: bool) -> Self {
+        self.health_check = check;
+        self
+    }
+
+    /// Commits the trie automatically once `threshold` inserts and removals have been
+    /// made since the last commit. Like any commit, this drops outstanding checkpoints.
+    pub fn auto_flush(mut self, threshold: usize) -> Self {
+        self.config.auto_flush = Some(threshold);
+        self
+    }
+
+    /// Keeps the nodes a commit makes unreachable in the database, so that earlier roots
+    /// stay readable, whatever the database does with removals.
+    pub fn retain_stale_nodes(mut self, retain: bool) -> Self {
+        self.config.retain_stale_nodes = retain;
+        self
+    }
+
+    /// Defers the removal of the nodes a commit makes stale until `commits` more commits
+    /// have been made, so that the last `commits` roots before the current one stay
+    /// readable. Deferred removals are held in memory and are dropped, never made, if the
+    /// trie is dropped first. Has no effect when stale nodes are retained.
+    pub fn retention_window(mut self, commits: usize) -> Self {
+        self.config.retention_window = Some(commits);
+        self
+    }
+
+    /// Numbers each commit to the database as an epoch, tags the nodes it writes with it,
+    /// and removes the nodes a commit makes stale once `keep` more epochs have passed,
+    /// unless they have been written again since. The last `keep` roots before the
+    /// current one stay readable, and the bookkeeping is stored in the database, so it
+    /// carries over to tries opened later. Takes precedence over `retention_window`, and
+    /// has no effect when stale nodes are retained.
+    pub fn epoch_pruning(mut self, keep: u64) -> Self {
+        self.config.epoch_pruning = Some(keep);
+        self
+    }
+
+    /// Writes the nodes of a commit in batches of `batch_size` from a separate thread as
+    /// they are encoded, so that large commits overlap their writes to the database with
+    /// hashing the rest of the trie. The batch holding the root node is written last, so
+    /// the new root only becomes readable once all its nodes are, but a failed commit can
+    /// leave some of its nodes behind.
+    pub fn pipelined_commit(mut self, batch_size: usize) -> Self {
+        self.config.commit_batch_size = Some(batch_size.max(1));
+        self
+    }
+
+    /// Attaches an observer to the commits of the trie. Observers are called in the order
+    /// they were attached.
+    pub fn observer(mut self, observer: Arc<dyn CommitObserver>) -> Self {
+        self.config.observers.0.push(observer);
+        self
+    }
+
+    /// Sets the resolver asked for the nodes missing from the database, including the
+    /// root node.
+    pub fn resolver(mut self, resolver: Arc<dyn NodeResolver>) -> Self {
+        self.config.resolver.0 = Some(resolver);
+        self
+    }
+
+    /// Rejects inserting an empty value with `TrieError::InvalidData`, instead of taking
+    /// it as a removal of the key.
+    pub fn strict(mut self, strict: bool) -> Self {
+        self.config.strict = strict;
+        self
+    }
+
+    /// Attaches a `RootManager`, which records each committed root and keeps the nodes
+    /// reachable from its pinned roots when a commit makes them stale.
+    pub fn root_manager(mut self, manager: &RootManager<D>) -> Self {
+        self.config.roots = Some(manager.registry.clone());
+        self
+    }
+
+    pub fn build(self) -> TrieResult<EthTrie<D>> {
+        if let (Some(root), Some(_)) = (self.root, &self.config.resolver.0) {
+            NodeReader::new(&*self.db, root)
+                .with_resolver(self.config.resolver.0.clone())
+                .recover(root)?;
+        }
+        let mut trie = match self.root {
+            Some(root) => EthTrie::from(self.db, root)?,
+            None => EthTrie::new(self.db),
+        };
+        trie.config = self.config;
+        if self.health_check {
+            trie.health_check()?.into_result()?;
+        }
+        Ok(trie)
+    }
+}
+
+impl<D> EthTrie<D>
+where
+    D: DB,
+{
+    pub fn builder(db: Arc<D>) -> EthTrieBuilder<D> {
+        EthTrieBuilder::new(db)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use crate::db::MemoryDB;
+    use crate::errors::TrieError;
+    use crate::trie::{EthTrie, TrieRead, TrieWrite};
+
+    #[test]
+    fn test_builder_auto_flush() {
+        let memdb = Arc::new(MemoryDB::new(true));
+        let mut trie = EthTrie::builder(memdb.clone())
+            .auto_flush(2)
+            .build()
+            .unwrap();
+        trie.insert(b"test", b"test").unwrap();
+        assert!(trie.is_dirty());
+        trie.insert(b"test1", b"test1").unwrap();
+        assert!(!trie.is_dirty());
+
+        let root = trie.root_hash().unwrap();
+        let trie = EthTrie::builder(memdb).root(root).build().unwrap();
+        assert_eq!(trie.get(b"test1").unwrap(), Some(b"test1".to_vec()));
+    }
+
+    #[test]
+    fn test_builder_retain_stale_nodes() {
+        let memdb = Arc::new(MemoryDB::new(true));
+        let mut trie = EthTrie::builder(memdb.clone())
+            .retain_stale_nodes(true)
+            .build()
+            .unwrap();
+        trie.insert(b"test", &[1; 40]).unwrap();
+        trie.insert(b"test1", &[2; 40]).unwrap();
+        let old_root = trie.root_hash().unwrap();
+        trie.insert(b"test1", &[3; 40]).unwrap();
+        trie.root_hash().unwrap();
+
+        let old = EthTrie::from(memdb, old_root).unwrap();
+        assert_eq!(old.get(b"test1").unwrap(), Some(vec![2; 40]));
+    }
+
+    #[test]
+    fn test_builder_retention_window() {
+        let memdb = Arc::new(MemoryDB::new(true));
+        let mut trie = EthTrie::builder(memdb.clone())
+            .retention_window(2)
+            .build()
+            .unwrap();
+        let mut roots = vec![];
+        for round in 0..5u8 {
+            trie.insert(b"test", &[round; 40]).unwrap();
+            trie.insert(b"test1", &[round; 40]).unwrap();
+            roots.push(trie.root_hash().unwrap());
+        }
+        // The two roots before the current one are still readable
+        for (round, root) in roots.iter().enumerate() {
+            let old = EthTrie::from(memdb.clone(), *root).unwrap();
+            if round >= 2 {
+                assert_eq!(old.get(b"test1").unwrap(), Some(vec![round as u8; 40]));
+            } else {
+                assert!(old.get(b"test1").is_err());
+            }
+        }
+
+        // A node made stale and then written again is kept
+        trie.insert(b"test1", &[3; 40]).unwrap();
+        trie.root_hash().unwrap();
+        trie.insert(b"test1", &[4; 40]).unwrap();
+        trie.root_hash().unwrap();
+        trie.insert(b"test2", &[5; 40]).unwrap();
+        trie.root_hash().unwrap();
+        assert_eq!(trie.get(b"test1").unwrap(), Some(vec![4; 40]));
+    }
+
+    #[test]
+    fn test_builder_strict() {
+        let memdb = Arc::new(MemoryDB::new(true));
+        let mut trie = EthTrie::builder(memdb).strict(true).build().unwrap();
+        trie.insert(b"test", b"test").unwrap();
+        assert_eq!(trie.insert(b"test", b""), Err(TrieError::InvalidData));
+        assert_eq!(trie.get(b"test").unwrap(), Some(b"test".to_vec()));
+    }
+}
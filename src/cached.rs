@@ -0,0 +1,315 @@
+use std::collections::BTreeMap;
+use std::marker::PhantomData;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use alloy_primitives::B256;
+use hashbrown::HashMap;
+use parking_lot::Mutex;
+
+use crate::db::DB;
+use crate::diff::KeyChange;
+use crate::trie::{RootWithTrieDiff, TrieRead, TrieResult, TrieWrite};
+
+/// Wraps a trie with a cache of the values last read, so that repeated reads of the same
+/// keys are answered without walking the trie. The least recently read key is dropped
+/// once the cache is full. Keys found missing are cached as well.
+///
+/// Writes made through the wrapper drop the keys they write, so the cache always matches
+/// the trie, committed or not. If the trie changes some other way, such as a view moved
+/// to a newer root, the keys that changed must be dropped with `invalidate_changes`, or
+/// the whole cache with `clear`.
+#[derive(Debug)]
+pub struct CachedTrie<D, T>
+where
+    D: DB,
+    T: TrieRead<D>,
+{
+    trie: T,
+    cache: Mutex<Lru>,
+    hits: AtomicU64,
+    misses: AtomicU64,
+    _db: PhantomData<fn() -> D>,
+}
+
+// A map from keys to values that drops the least recently used key past its capacity.
+#[derive(Debug)]
+struct Lru {
+    capacity: usize,
+    // The value of each key, and when it was last used.
+    entries: HashMap<Vec<u8>, (Option<Vec<u8>>, u64)>,
+    // The keys by when they were last used.
+    order: BTreeMap<u64, Vec<u8>>,
+    tick: u64,
+}
+
+impl Lru {
+    fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            entries: HashMap::new(),
+            order: BTreeMap::new(),
+            tick: 0,
+        }
+    }
+
+    fn get(&mut self, key: &[u8]) -> Option<Option<Vec<u8>>> {
+        self.tick += 1;
+        let (value, used) = self.entries.get_mut(key)?;
+        let key = self.order.remove(used).expect("every entry is ordered");
+        *used = self.tick;
+        self.order.insert(self.tick, key);
+        Some(value.clone())
+    }
+
+    fn insert(&mut self, key: Vec<u8>, value: Option<Vec<u8>>) {
+        if self.capacity == 0 {
+            return;
+        }
+        self.remove(&key);
+        while self.entries.len() >= self.capacity {
+            let Some((_, oldest)) = self.order.pop_first() else {
+                break;
+            };
+            self.entries.remove(&oldest);
+        }
+        self.tick += 1;
+        self.order.insert(self.tick, key.clone());
+        self.entries.insert(key, (value, self.tick));
+    }
+
+    fn remove(&mut self, key: &[u8]) {
+        if let Some((_, used)) = self.entries.remove(key) {
+            self.order.remove(&used);
+        }
+    }
+
+    fn clear(&mut self) {
+        self.entries.clear();
+        self.order.clear();
+    }
+}
+
+impl<D, T> CachedTrie<D, T>
+where
+    D: DB,
+    T: TrieRead<D>,
+{
+    /// Wraps a trie with a cache of up to `capacity` keys.
+    pub fn new(trie: T, capacity: usize) -> Self {
+        Self {
+            trie,
+            cache: Mutex::new(Lru::new(capacity)),
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+            _db: PhantomData,
+        }
+    }
+
+    /// Drops a key from the cache.
+    pub fn invalidate(&self, key: &[u8]) {
+        self.cache.lock().remove(key);
+    }
+
+    /// Drops the keys of a list of changes from the cache, such as those `diff` returns
+    /// between the root the trie was at and the one it is at now.
+    pub fn invalidate_changes<I>(&self, changes: I) -> TrieResult<()>
+    where
+        I: IntoIterator<Item = TrieResult<KeyChange>>,
+    {
+        for change in changes {
+            let (key, _, _) = change?;
+            self.invalidate(&key);
+        }
+        Ok(())
+    }
+
+    /// Moves the cache to another trie, such as a view of a newer root, dropping the keys
+    /// of the changes between the two. Returns the trie it was on. If reading the changes
+    /// fails, the whole cache is dropped.
+    pub fn update<I>(&mut self, trie: T, changes: I) -> TrieResult<T>
+    where
+        I: IntoIterator<Item = TrieResult<KeyChange>>,
+    {
+        if let Err(err) = self.invalidate_changes(changes) {
+            self.clear();
+            return Err(err);
+        }
+        Ok(std::mem::replace(&mut self.trie, trie))
+    }
+
+    pub fn clear(&self) {
+        self.cache.lock().clear();
+    }
+
+    /// The number of keys in the cache.
+    pub fn len(&self) -> usize {
+        self.cache.lock().entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// The number of reads answered from the cache so far.
+    pub fn hits(&self) -> u64 {
+        self.hits.load(Ordering::Relaxed)
+    }
+
+    /// The number of reads that walked the trie so far.
+    pub fn misses(&self) -> u64 {
+        self.misses.load(Ordering::Relaxed)
+    }
+
+    pub fn inner(&self) -> &T {
+        &self.trie
+    }
+
+    pub fn into_inner(self) -> T {
+        self.trie
+    }
+}
+
+impl<D, T> TrieRead<D> for CachedTrie<D, T>
+where
+    D: DB,
+    T: TrieRead<D>,
+{
+    type Iter<'a>
+        = T::Iter<'a>
+    where
+        Self: 'a;
+
+    fn get(&self, key: &[u8]) -> TrieResult<Option<Vec<u8>>> {
+        if let Some(value) = self.cache.lock().get(key) {
+            self.hits.fetch_add(1, Ordering::Relaxed);
+            return Ok(value);
+        }
+        self.misses.fetch_add(1, Ordering::Relaxed);
+        let value = self.trie.get(key)?;
+        self.cache.lock().insert(key.to_vec(), value.clone());
+        Ok(value)
+    }
+
+    fn get_with<R, G>(&self, key: &[u8], f: G) -> TrieResult<Option<R>>
+    where
+        G: FnOnce(&[u8]) -> R,
+    {
+        Ok(self.get(key)?.map(|value| f(&value)))
+    }
+
+    fn contains(&self, key: &[u8]) -> TrieResult<bool> {
+        Ok(self.get(key)?.is_some())
+    }
+
+    fn get_proof(&self, key: &[u8]) -> TrieResult<Vec<Vec<u8>>> {
+        self.trie.get_proof(key)
+    }
+
+    fn verify_proof(
+        &self,
+        root_hash: B256,
+        key: &[u8],
+        proof: Vec<Vec<u8>>,
+    ) -> TrieResult<Option<Vec<u8>>> {
+        self.trie.verify_proof(root_hash, key, proof)
+    }
+
+    fn iter(&self) -> Self::Iter<'_> {
+        self.trie.iter()
+    }
+
+    fn seek_iter<'a>(&'a self, iter: &mut Self::Iter<'a>, key: &[u8]) -> TrieResult<()> {
+        self.trie.seek_iter(iter, key)
+    }
+}
+
+impl<D, T> TrieWrite<D> for CachedTrie<D, T>
+where
+    D: DB,
+    T: TrieWrite<D>,
+{
+    fn insert(&mut self, key: &[u8], value: &[u8]) -> TrieResult<()> {
+        self.invalidate(key);
+        self.trie.insert(key, value)
+    }
+
+    fn remove(&mut self, key: &[u8]) -> TrieResult<bool> {
+        self.invalidate(key);
+        self.trie.remove(key)
+    }
+
+    fn root_hash(&mut self) -> TrieResult<B256> {
+        self.trie.root_hash()
+    }
+
+    fn root_hash_with_changed_nodes(&mut self) -> TrieResult<RootWithTrieDiff> {
+        self.trie.root_hash_with_changed_nodes()
+    }
+
+    fn clear_trie_from_db(&mut self) -> TrieResult<()> {
+        self.clear();
+        self.trie.clear_trie_from_db()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::CachedTrie;
+    use crate::db::MemoryDB;
+    use crate::diff::diff;
+    use crate::trie::tests::random_trie;
+    use crate::trie::{EthTrie, TrieRead, TrieWrite};
+
+    #[test]
+    fn test_cached_trie_reads() {
+        let (trie, kv) = random_trie(100);
+        let mut trie = CachedTrie::new(trie, 10);
+        let (key, value) = kv.iter().next().unwrap();
+        for _ in 0..5 {
+            assert_eq!(trie.get(key).unwrap().as_ref(), Some(value));
+            assert_eq!(trie.get(b"missing").unwrap(), None);
+        }
+        assert_eq!((trie.hits(), trie.misses()), (8, 2));
+
+        // Writes through the wrapper are never hidden by the cache
+        trie.insert(key, b"new").unwrap();
+        trie.insert(b"missing", b"found").unwrap();
+        assert_eq!(trie.get(key).unwrap(), Some(b"new".to_vec()));
+        assert_eq!(trie.get(b"missing").unwrap(), Some(b"found".to_vec()));
+
+        // Only the most recently read keys are kept
+        for key in kv.keys() {
+            trie.get(key).unwrap();
+        }
+        assert_eq!(trie.len(), 10);
+        let misses = trie.misses();
+        trie.get(kv.keys().last().unwrap()).unwrap();
+        assert_eq!(trie.misses(), misses);
+    }
+
+    #[test]
+    fn test_cached_trie_invalidate_changes() {
+        let db = Arc::new(MemoryDB::new(true));
+        let mut trie = EthTrie::builder(db.clone())
+            .retain_stale_nodes(true)
+            .build()
+            .unwrap();
+        trie.insert(b"key", b"old").unwrap();
+        let old_root = trie.root_hash().unwrap();
+        let mut cached = CachedTrie::new(EthTrie::from(db.clone(), old_root).unwrap(), 16);
+        assert_eq!(cached.get(b"key").unwrap(), Some(b"old".to_vec()));
+        assert_eq!(cached.get(b"other").unwrap(), None);
+
+        // Moved to the trie at a newer root, only the keys that changed are read again
+        trie.insert(b"key", b"new").unwrap();
+        let new_root = trie.root_hash().unwrap();
+        let changes = diff(&db, old_root, new_root);
+        cached
+            .update(EthTrie::from(db, new_root).unwrap(), changes)
+            .unwrap();
+        assert_eq!(cached.len(), 1);
+        assert_eq!(cached.get(b"key").unwrap(), Some(b"new".to_vec()));
+    }
+}
@@ -0,0 +1,196 @@
+use alloy_primitives::B256;
+use alloy_rlp::{BufMut, Decodable, Encodable, Header};
+use keccak_hash::keccak;
+
+use crate::errors::{NodeDecodeError, NodeDecodeErrorKind, TrieError};
+use crate::node::Node;
+use crate::trie::{
+    decode_node, decode_node_strict, encode_node, encoded_len, TrieResult, HASHED_LENGTH,
+};
+
+/// Encodes and decodes trie nodes in the format they are stored and hashed in.
+///
+/// Children whose encoding is shorter than 32 bytes are embedded in their parent, larger
+/// ones are referred to by hash. Decoding leaves those references as `Node::Hash`.
+pub struct NodeCodec;
+
+impl NodeCodec {
+    /// Returns the encoding of `node`. A `Node::Hash` encodes as the reference a parent
+    /// holds, the 32 byte hash as an RLP string.
+    pub fn encode(node: &Node) -> Vec<u8> {
+        match node {
+            Node::Hash(hash_node) => alloy_rlp::encode(hash_node.hash),
+            _ => encode_node(node, &mut |_, _| {}),
+        }
+    }
+
+    /// Decodes a single node, which must take up the whole of `data`.
+    pub fn decode(data: &[u8]) -> TrieResult<Node> {
+        let len = item_len(data)?;
+        if len != data.len() {
+            return Err(TrieError::InvalidNode(NodeDecodeError {
+                kind: NodeDecodeErrorKind::TrailingBytes,
+                offset: len,
+            }));
+        }
+        decode_node(&mut &data[..])
+    }
+
+    /// Decodes a single node, accepting only its canonical encoding. See
+    /// `decode_node_strict`.
+    pub fn decode_strict(data: &[u8]) -> TrieResult<Node> {
+        decode_node_strict(data)
+    }
+
+    /// Returns the hash of the node's encoding, which is how it is stored.
+    pub fn hash(node: &Node) -> B256 {
+        match node {
+            Node::Hash(hash_node) => hash_node.hash,
+            _ => keccak(Self::encode(node)).as_fixed_bytes().into(),
+        }
+    }
+
+    /// Returns the length of the node's encoding, computed without encoding it.
+    pub fn encoded_len(node: &Node) -> usize {
+        encoded_len(node)
+    }
+
+    /// Returns true if the node is small enough to be embedded in its parent.
+    pub fn is_inline(node: &Node) -> bool {
+        encoded_len(node) < HASHED_LENGTH
+    }
+}
+
+impl Encodable for Node {
+    fn encode(&self, out: &mut dyn BufMut) {
+        out.put_slice(&NodeCodec::encode(self));
+    }
+
+    fn length(&self) -> usize {
+        encoded_len(self)
+    }
+}
+
+impl Decodable for Node {
+    fn decode(buf: &mut &[u8]) -> alloy_rlp::Result<Self> {
+        let len = item_len(buf)?;
+        let node = decode_node(&mut &buf[..len]).map_err(|e| match e {
+            TrieError::Decoder(e) => e,
+            TrieError::InvalidNode(NodeDecodeError {
+                kind: NodeDecodeErrorKind::Rlp(e),
+                ..
+            }) => e,
+            _ => alloy_rlp::Error::Custom("invalid trie node"),
+        })?;
+        *buf = &buf[len..];
+        Ok(node)
+    }
+}
+
+// Returns the length of the RLP item at the start of `buf`, header included.
+fn item_len(buf: &[u8]) -> alloy_rlp::Result<usize> {
+    let mut payload = buf;
+    let header = Header::decode(&mut payload)?;
+    let len = buf.len() - payload.len() + header.payload_length;
+    if len > buf.len() {
+        return Err(alloy_rlp::Error::InputTooShort);
+    }
+    Ok(len)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use alloy_rlp::{Decodable, Encodable};
+
+    use super::NodeCodec;
+    use crate::db::{MemoryDB, DB};
+    use crate::errors::{NodeDecodeError, NodeDecodeErrorKind, TrieError};
+    use crate::nibbles::Nibbles;
+    use crate::node::{empty_children, Node};
+    use crate::trie::{EthTrie, TrieWrite};
+
+    #[test]
+    fn test_codec_round_trip_stored_nodes() {
+        let memdb = Arc::new(MemoryDB::new(true));
+        let mut trie = EthTrie::new(memdb.clone());
+        for i in 0..100u8 {
+            trie.insert(&[i, i / 3], &[i; 20]).unwrap();
+        }
+        let root = trie.root_hash().unwrap();
+
+        let export = trie.export().unwrap();
+        assert_eq!(export.root, root);
+        for encoded in export.nodes.iter() {
+            let node = NodeCodec::decode(encoded).unwrap();
+            assert_eq!(NodeCodec::encode(&node), encoded.to_vec());
+            assert_eq!(node.length(), encoded.len());
+            assert_eq!(NodeCodec::encoded_len(&node), encoded.len());
+            assert_eq!(
+                memdb
+                    .get(NodeCodec::hash(&node).as_slice())
+                    .unwrap()
+                    .unwrap(),
+                encoded.to_vec()
+            );
+        }
+    }
+
+    #[test]
+    fn test_codec_encoded_len() {
+        // Values around the inline limit and the long string header
+        for len in [0, 1, 2, 20, 29, 30, 31, 32, 54, 55, 56, 300] {
+            for key_len in [0, 1, 2, 40] {
+                let key = Nibbles::from_raw(&vec![0xab; key_len], true);
+                let leaf = Node::from_leaf(key, vec![0x99; len]);
+                assert_eq!(
+                    NodeCodec::encoded_len(&leaf),
+                    NodeCodec::encode(&leaf).len()
+                );
+
+                let mut children = empty_children();
+                children[1] = leaf.clone();
+                children[9] = leaf.clone();
+                let branch = Node::from_branch(children, Some(vec![0x99; len]));
+                assert_eq!(
+                    NodeCodec::encoded_len(&branch),
+                    NodeCodec::encode(&branch).len()
+                );
+
+                let ext = Node::from_extension(Nibbles::from_hex(&[1, 2, 3]), branch);
+                assert_eq!(NodeCodec::encoded_len(&ext), NodeCodec::encode(&ext).len());
+                assert_eq!(
+                    NodeCodec::is_inline(&ext),
+                    NodeCodec::encode(&ext).len() < 32
+                );
+            }
+        }
+        assert_eq!(NodeCodec::encoded_len(&Node::Empty), 1);
+    }
+
+    #[test]
+    fn test_codec_rlp_traits() {
+        let leaf = Node::from_leaf(Nibbles::from_raw(b"key", true), b"value".to_vec());
+        assert!(NodeCodec::is_inline(&leaf));
+
+        // Nodes decode one after another from the same buffer
+        let mut buf = vec![];
+        leaf.encode(&mut buf);
+        Node::Empty.encode(&mut buf);
+        let mut data = buf.as_slice();
+        let decoded = Node::decode(&mut data).unwrap();
+        assert_eq!(NodeCodec::encode(&decoded), NodeCodec::encode(&leaf));
+        assert!(matches!(Node::decode(&mut data).unwrap(), Node::Empty));
+        assert!(data.is_empty());
+
+        assert_eq!(
+            NodeCodec::decode(&buf).unwrap_err(),
+            TrieError::InvalidNode(NodeDecodeError {
+                kind: NodeDecodeErrorKind::TrailingBytes,
+                offset: buf.len() - 1,
+            })
+        );
+        assert!(Node::decode(&mut &buf[..3]).is_err());
+    }
+}
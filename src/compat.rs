@@ -0,0 +1,81 @@
+//! Interop with `primitive_types::H256`, for projects that haven't moved to alloy types.
+//!
+//! `H256` here is the type from `primitive-types` 0.12, which the crate already depends on
+//! through `keccak-hash`.
+
+use std::sync::Arc;
+
+use alloy_primitives::B256;
+pub use keccak_hash::H256;
+
+use crate::db::DB;
+use crate::key::TrieKey;
+use crate::trie::{EthTrie, TrieRead, TrieResult, TrieWrite};
+
+pub fn h256_to_b256(hash: H256) -> B256 {
+    B256::from(hash.to_fixed_bytes())
+}
+
+pub fn b256_to_h256(hash: B256) -> H256 {
+    H256::from(hash.0)
+}
+
+impl TrieKey for H256 {
+    fn trie_key(&self) -> Vec<u8> {
+        self.as_bytes().to_vec()
+    }
+}
+
+impl<D> EthTrie<D>
+where
+    D: DB,
+{
+    /// Same as `from`, taking the root as an `H256`.
+    pub fn from_h256(db: Arc<D>, root: H256) -> TrieResult<Self> {
+        Self::from(db, h256_to_b256(root))
+    }
+
+    /// Same as `root_hash`, returning the root as an `H256`.
+    pub fn root_hash_h256(&mut self) -> TrieResult<H256> {
+        self.root_hash().map(b256_to_h256)
+    }
+
+    /// Same as `verify_proof`, taking the root as an `H256`.
+    pub fn verify_proof_h256(
+        &self,
+        root_hash: H256,
+        key: &[u8],
+        proof: Vec<Vec<u8>>,
+    ) -> TrieResult<Option<Vec<u8>>> {
+        self.verify_proof(h256_to_b256(root_hash), key, proof)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use keccak_hash::H256;
+
+    use super::{b256_to_h256, h256_to_b256};
+    use crate::db::MemoryDB;
+    use crate::key::TrieKey;
+    use crate::trie::{EthTrie, TrieRead, TrieWrite};
+
+    #[test]
+    fn test_h256_round_trip() {
+        let memdb = Arc::new(MemoryDB::new(true));
+        let mut trie = EthTrie::new(memdb.clone());
+        let key = H256::repeat_byte(7);
+        trie.insert(&key.trie_key(), b"value").unwrap();
+
+        let root = trie.root_hash_h256().unwrap();
+        assert_eq!(h256_to_b256(root), trie.root_hash().unwrap());
+        assert_eq!(b256_to_h256(h256_to_b256(root)), root);
+
+        let trie = EthTrie::from_h256(memdb, root).unwrap();
+        let proof = trie.get_proof(key.as_bytes()).unwrap();
+        let value = trie.verify_proof_h256(root, key.as_bytes(), proof).unwrap();
+        assert_eq!(value, Some(b"value".to_vec()));
+    }
+}
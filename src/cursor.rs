@@ -0,0 +1,319 @@
+use alloy_primitives::B256;
+use keccak_hash::keccak;
+
+use crate::db::DB;
+use crate::errors::TrieError;
+use crate::nibbles::Nibbles;
+use crate::node::{Node, NodeKind};
+use crate::trie::{encode_node, EthTrie, NodeReader, TrieResult};
+use crate::view::TrieView;
+
+/// Walks a trie node by node, for callers that need to step through its structure rather
+/// than its entries.
+///
+/// The cursor starts at the root and keeps the nodes between the root and its position,
+/// so it can always move back up. Stored nodes are loaded from the database as the cursor
+/// reaches them.
+pub struct TrieCursor<'a, D>
+where
+    D: DB,
+{
+    reader: NodeReader<'a, D>,
+    root: Node,
+    // The nodes from the root down to the current one, each with the path leading to it.
+    stack: Vec<(Nibbles, Node)>,
+}
+
+impl<'a, D> TrieCursor<'a, D>
+where
+    D: DB,
+{
+    pub(crate) fn new(reader: NodeReader<'a, D>, root: Node) -> TrieResult<Self> {
+        let mut cursor = Self {
+            reader,
+            root,
+            stack: Vec::new(),
+        };
+        cursor.reset()?;
+        Ok(cursor)
+    }
+
+    /// Returns the nibble path from the root to the current node.
+    pub fn path(&self) -> &Nibbles {
+        &self.current().0
+    }
+
+    /// Returns the number of nodes above the current one.
+    pub fn depth(&self) -> usize {
+        self.stack.len() - 1
+    }
+
+    /// Returns the kind of the current node, which is never `NodeKind::Hash` since the
+    /// cursor loads stored nodes as it reaches them.
+    pub fn kind(&self) -> NodeKind {
+        self.node().kind()
+    }
+
+    pub fn node(&self) -> &Node {
+        &self.current().1
+    }
+
+    /// Returns the RLP encoding of the current node.
+    pub fn encoded(&self) -> Vec<u8> {
+        encode_node(self.node(), &mut |_, _| {})
+    }
+
+    /// Returns the hash of the current node's encoding.
+    pub fn hash(&self) -> B256 {
+        keccak(self.encoded()).as_fixed_bytes().into()
+    }
+
+    /// Returns the value stored at the current node, if it holds one.
+    pub fn value(&self) -> Option<Vec<u8>> {
+        self.node().value()
+    }
+
+    /// Moves to the child reached through `nibble`. For an extension node, that is its
+    /// only child, if its prefix starts with `nibble`. Returns false, without moving, if
+    /// there is no such child.
+    pub fn child(&mut self, nibble: u8) -> TrieResult<bool> {
+        let (path, node) = self.current().clone();
+        let child = match node {
+            Node::Branch(branch) => {
+                let branch = branch.read();
+                match branch.children.get(nibble as usize) {
+                    Some(Node::Empty) | None => return Ok(false),
+                    Some(child) => {
+                        let mut child_path = path;
+                        child_path.push(nibble);
+                        (child_path, child.clone())
+                    }
+                }
+            }
+            Node::Extension(ext) => {
+                let ext = ext.read();
+                if ext.prefix.at(0) != nibble as usize {
+                    return Ok(false);
+                }
+                (path.join(&ext.prefix), ext.node.clone())
+            }
+            _ => return Ok(false),
+        };
+        self.push(child)?;
+        Ok(true)
+    }
+
+    /// Moves to the parent node. Returns false if the cursor is at the root.
+    pub fn parent(&mut self) -> bool {
+        if self.stack.len() == 1 {
+            return false;
+        }
+        self.stack.pop();
+        true
+    }
+
+    /// Moves to the next child of the parent branch node. Returns false, without moving,
+    /// if there is none.
+    pub fn next_sibling(&mut self) -> TrieResult<bool> {
+        if self.stack.len() == 1 {
+            return Ok(false);
+        }
+        let (parent_path, parent) = &self.stack[self.stack.len() - 2];
+        let Node::Branch(branch) = parent else {
+            return Ok(false);
+        };
+        let index = self.path().at(parent_path.len());
+
+        let sibling = {
+            let branch = branch.read();
+            branch.children[index + 1..]
+                .iter()
+                .enumerate()
+                .find(|(_, child)| !matches!(child, Node::Empty))
+                .map(|(i, child)| {
+                    let mut path = parent_path.clone();
+                    path.push((index + 1 + i) as u8);
+                    (path, child.clone())
+                })
+        };
+        match sibling {
+            Some(sibling) => {
+                self.stack.pop();
+                self.push(sibling)?;
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+
+    /// Moves down from the root along the path of `key`, as far as the trie goes.
+    /// Returns true if the cursor ends up at the node holding the value for `key`.
+    pub fn move_to(&mut self, key: &[u8]) -> TrieResult<bool> {
+        self.reset()?;
+        let target = Nibbles::from_raw(key, false);
+
+        loop {
+            let depth = self.path().len();
+            let partial = target.offset(depth);
+            let moved = match self.node() {
+                Node::Leaf(leaf) => {
+                    let mut leaf_key = leaf.key.clone();
+                    leaf_key.pop();
+                    return Ok(leaf_key == partial);
+                }
+                Node::Branch(branch) => {
+                    if partial.is_empty() {
+                        return Ok(branch.read().value.is_some());
+                    }
+                    self.child(partial.at(0) as u8)?
+                }
+                Node::Extension(ext) => {
+                    let prefix_len = ext.read().prefix.len();
+                    if partial.common_prefix(&ext.read().prefix) < prefix_len {
+                        return Ok(false);
+                    }
+                    self.child(partial.at(0) as u8)?
+                }
+                _ => false,
+            };
+            if !moved {
+                return Ok(false);
+            }
+        }
+    }
+
+    /// Moves back to the root.
+    pub fn reset(&mut self) -> TrieResult<()> {
+        self.stack.clear();
+        let root = self.root.clone();
+        self.push((Nibbles::from_hex(&[]), root))
+    }
+
+    fn current(&self) -> &(Nibbles, Node) {
+        self.stack.last().unwrap()
+    }
+
+    fn push(&mut self, (path, node): (Nibbles, Node)) -> TrieResult<()> {
+        let node = match node {
+            Node::Hash(hash_node) => match self.reader.recover(hash_node.hash)? {
+                Some(node) => node,
+                None => {
+                    return Err(TrieError::MissingTrieNode {
+                        node_hash: hash_node.hash,
+                        traversed: Some(path),
+                        root_hash: Some(self.reader.root_hash()),
+                        err_key: None,
+                    })
+                }
+            },
+            node => node,
+        };
+        self.stack.push((path, node));
+        Ok(())
+    }
+}
+
+impl<D> EthTrie<D>
+where
+    D: DB,
+{
+    /// Returns a cursor at the root of the trie, including uncommitted changes.
+    pub fn cursor(&self) -> TrieResult<TrieCursor<'_, D>> {
+        TrieCursor::new(self.reader(), self.root.clone())
+    }
+}
+
+impl<D> TrieView<D>
+where
+    D: DB,
+{
+    /// Returns a cursor at the root of the trie.
+    pub fn cursor(&self) -> TrieResult<TrieCursor<'_, D>> {
+        TrieCursor::new(self.reader(), self.root.clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use crate::db::MemoryDB;
+    use crate::nibbles::Nibbles;
+    use crate::node::NodeKind;
+    use crate::trie::{EthTrie, TrieWrite};
+    use crate::view::TrieView;
+
+    // An extension over a branch, holding "do" and "dog" under one child and "horse"
+    // under the other.
+    fn sample_view() -> TrieView<MemoryDB> {
+        let memdb = Arc::new(MemoryDB::new(true));
+        let mut trie = EthTrie::new(memdb.clone());
+        trie.insert(b"do", b"verb").unwrap();
+        trie.insert(b"dog", b"puppy").unwrap();
+        trie.insert(b"horse", &[0xaa; 40]).unwrap();
+        let root = trie.root_hash().unwrap();
+        TrieView::new(memdb, root).unwrap()
+    }
+
+    #[test]
+    fn test_cursor_navigation() {
+        let view = sample_view();
+        let mut cursor = view.cursor().unwrap();
+        assert_eq!(cursor.kind(), NodeKind::Extension);
+        assert_eq!(cursor.hash(), view.root_hash());
+        assert!(!cursor.parent());
+
+        assert!(!cursor.child(5).unwrap());
+        assert!(cursor.child(6).unwrap());
+        assert_eq!(cursor.kind(), NodeKind::Branch);
+        assert_eq!(cursor.path(), &Nibbles::from_hex(&[6]));
+
+        assert!(cursor.child(4).unwrap());
+        assert_eq!(cursor.kind(), NodeKind::Extension);
+        assert_eq!(cursor.path(), &Nibbles::from_hex(&[6, 4]));
+        assert_eq!(cursor.depth(), 2);
+
+        assert!(cursor.next_sibling().unwrap());
+        assert_eq!(cursor.kind(), NodeKind::Leaf);
+        assert_eq!(cursor.path(), &Nibbles::from_hex(&[6, 8]));
+        assert_eq!(cursor.value(), Some(vec![0xaa; 40]));
+        assert!(!cursor.next_sibling().unwrap());
+
+        assert!(cursor.parent());
+        assert_eq!(cursor.kind(), NodeKind::Branch);
+        assert!(cursor.parent());
+        assert_eq!(cursor.depth(), 0);
+    }
+
+    #[test]
+    fn test_cursor_move_to() {
+        let view = sample_view();
+        let mut cursor = view.cursor().unwrap();
+
+        assert!(cursor.move_to(b"do").unwrap());
+        assert_eq!(cursor.kind(), NodeKind::Branch);
+        assert_eq!(cursor.value(), Some(b"verb".to_vec()));
+
+        assert!(cursor.move_to(b"dog").unwrap());
+        assert_eq!(cursor.kind(), NodeKind::Leaf);
+        assert_eq!(
+            cursor.path(),
+            &Nibbles::from_raw(b"do", false).join(&Nibbles::from_hex(&[6]))
+        );
+
+        assert!(!cursor.move_to(b"doge").unwrap());
+        assert_eq!(cursor.kind(), NodeKind::Leaf);
+
+        assert!(!cursor.move_to(b"cat").unwrap());
+        assert_eq!(cursor.depth(), 1);
+    }
+
+    #[test]
+    fn test_cursor_empty_trie() {
+        let trie = EthTrie::new(Arc::new(MemoryDB::new(true)));
+        let mut cursor = trie.cursor().unwrap();
+        assert_eq!(cursor.kind(), NodeKind::Empty);
+        assert!(!cursor.child(0).unwrap());
+        assert!(!cursor.move_to(b"key").unwrap());
+    }
+}
@@ -10,7 +10,7 @@ use crate::errors::MemDBError;
 /// You should first write the data to the cache and write the data
 /// to the database in bulk after the end of a set of operations.
 pub trait DB: Send + Sync {
-    type Error: Error;
+    type Error: Error + Send + Sync + 'static;
 
     fn get(&self, key: &[u8]) -> Result<Option<Vec<u8>>, Self::Error>;
 
@@ -20,6 +20,11 @@ pub trait DB: Send + Sync {
     /// Remove data with given key.
     fn remove(&self, key: &[u8]) -> Result<(), Self::Error>;
 
+    /// Get the values of a batch of keys, in the same order.
+    fn get_batch(&self, keys: &[Vec<u8>]) -> Result<Vec<Option<Vec<u8>>>, Self::Error> {
+        keys.iter().map(|key| self.get(key)).collect()
+    }
+
     /// Insert a batch of data into the cache.
     fn insert_batch(&self, keys: Vec<Vec<u8>>, values: Vec<Vec<u8>>) -> Result<(), Self::Error> {
         for i in 0..keys.len() {
@@ -47,6 +52,12 @@ pub trait DB: Send + Sync {
     fn is_empty(&self) -> Result<bool, Self::Error>;
 }
 
+/// A database whose keys can be listed, which `Pruner` needs to find unreachable nodes.
+pub trait IterableDB: DB {
+    /// Returns every key in the database.
+    fn keys(&self) -> Result<Vec<Vec<u8>>, Self::Error>;
+}
+
 #[derive(Default, Debug)]
 pub struct MemoryDB {
     // If "light" is true, the data is deleted from the database at the time of submission.
@@ -100,6 +111,12 @@ impl DB for MemoryDB {
     }
 }
 
+impl IterableDB for MemoryDB {
+    fn keys(&self) -> Result<Vec<Vec<u8>>, Self::Error> {
+        Ok(self.storage.read().keys().cloned().collect())
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
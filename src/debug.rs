@@ -0,0 +1,306 @@
+use alloy_primitives::{hex, B256};
+use alloy_rlp::EMPTY_STRING_CODE;
+use keccak_hash::keccak;
+
+use crate::db::DB;
+use crate::errors::TrieError;
+use crate::nibbles::Nibbles;
+use crate::node::Node;
+use crate::trie::{encode_node, EthTrie, NodeReader, TrieResult, HASHED_LENGTH};
+
+// Values longer than this are cut short in debug output.
+const MAX_VALUE_BYTES: usize = 32;
+
+impl<D> EthTrie<D>
+where
+    D: DB,
+{
+    /// Renders the trie, including uncommitted changes, as pretty-printed JSON.
+    ///
+    /// Each node is listed with its type, its key or prefix in nibbles, and its hash when
+    /// it is stored apart from its parent. Values are cut to their first 32 bytes.
+    pub fn to_debug_json(&self) -> TrieResult<String> {
+        let mut out = render_json(&self.reader(), &self.root, Nibbles::from_hex(&[]), 0)?;
+        out.push('\n');
+        Ok(out)
+    }
+
+    /// Renders the trie, including uncommitted changes, as a Graphviz graph.
+    ///
+    /// Edges out of branch nodes are labelled with their nibble. Nodes stored apart from
+    /// their parent show the start of their hash, inline nodes are drawn dashed.
+    pub fn to_dot(&self) -> TrieResult<String> {
+        let mut out = String::from("digraph trie {\n  node [shape=box, fontname=monospace];\n");
+        let mut next_id = 0;
+        render_dot(
+            &self.reader(),
+            &self.root,
+            Nibbles::from_hex(&[]),
+            &mut out,
+            &mut next_id,
+        )?;
+        out.push_str("}\n");
+        Ok(out)
+    }
+}
+
+fn render_json<D: DB>(
+    reader: &NodeReader<'_, D>,
+    node: &Node,
+    path: Nibbles,
+    indent: usize,
+) -> TrieResult<String> {
+    let (node, _, hash) = resolve(reader, node, &path, indent == 0)?;
+
+    let mut fields = vec![];
+    match &node {
+        Node::Empty => fields.push(("type".to_string(), quote("empty"))),
+        Node::Leaf(leaf) => {
+            fields.push(("type".to_string(), quote("leaf")));
+            fields.push(("key".to_string(), quote(&nibbles_to_string(&leaf.key))));
+            fields.push(("value".to_string(), quote(&value_to_string(&leaf.value))));
+        }
+        Node::Extension(ext) => {
+            let ext = ext.read().clone();
+            fields.push(("type".to_string(), quote("extension")));
+            fields.push(("prefix".to_string(), quote(&nibbles_to_string(&ext.prefix))));
+            let child = render_json(reader, &ext.node, path.join(&ext.prefix), indent + 1)?;
+            fields.push(("child".to_string(), child));
+        }
+        Node::Branch(branch) => {
+            let branch = branch.read().clone();
+            fields.push(("type".to_string(), quote("branch")));
+            let mut children = vec![];
+            for (i, child) in branch.children.iter().enumerate() {
+                if matches!(child, Node::Empty) {
+                    continue;
+                }
+                let mut child_path = path.clone();
+                child_path.push(i as u8);
+                let child = render_json(reader, child, child_path, indent + 2)?;
+                children.push((format!("{:x}", i), child));
+            }
+            fields.push(("children".to_string(), object(children, indent + 1)));
+            if let Some(value) = &branch.value {
+                fields.push(("value".to_string(), quote(&value_to_string(value))));
+            }
+        }
+        Node::Hash(_) => unreachable!(),
+    }
+    if let Some(hash) = hash {
+        fields.insert(1, ("hash".to_string(), quote(&hash.to_string())));
+    }
+
+    Ok(object(fields, indent))
+}
+
+// Writes the node and everything below it, returning the id the node was given.
+fn render_dot<D: DB>(
+    reader: &NodeReader<'_, D>,
+    node: &Node,
+    path: Nibbles,
+    out: &mut String,
+    next_id: &mut usize,
+) -> TrieResult<usize> {
+    let (node, _, hash) = resolve(reader, node, &path, *next_id == 0)?;
+    let id = *next_id;
+    *next_id += 1;
+
+    let mut label = vec![];
+    let mut edges = vec![];
+    match &node {
+        Node::Empty => label.push("empty".to_string()),
+        Node::Leaf(leaf) => {
+            label.push("leaf".to_string());
+            label.push(format!("key: {}", nibbles_to_string(&leaf.key)));
+            label.push(format!("value: {}", value_to_string(&leaf.value)));
+        }
+        Node::Extension(ext) => {
+            let ext = ext.read().clone();
+            label.push("extension".to_string());
+            label.push(format!("prefix: {}", nibbles_to_string(&ext.prefix)));
+            let child = render_dot(reader, &ext.node, path.join(&ext.prefix), out, next_id)?;
+            edges.push((child, None));
+        }
+        Node::Branch(branch) => {
+            let branch = branch.read().clone();
+            label.push("branch".to_string());
+            if let Some(value) = &branch.value {
+                label.push(format!("value: {}", value_to_string(value)));
+            }
+            for (i, child) in branch.children.iter().enumerate() {
+                if matches!(child, Node::Empty) {
+                    continue;
+                }
+                let mut child_path = path.clone();
+                child_path.push(i as u8);
+                let child = render_dot(reader, child, child_path, out, next_id)?;
+                edges.push((child, Some(format!("{:x}", i))));
+            }
+        }
+        Node::Hash(_) => unreachable!(),
+    }
+
+    let style = match hash {
+        Some(hash) => {
+            label.insert(1, format!("hash: 0x{}", hex::encode(&hash[..4])));
+            ""
+        }
+        None => ", style=dashed",
+    };
+    out.push_str(&format!(
+        "  n{} [label=\"{}\"{}];\n",
+        id,
+        label.join("\\n"),
+        style
+    ));
+    for (child, nibble) in edges {
+        match nibble {
+            Some(nibble) => out.push_str(&format!(
+                "  n{} -> n{} [label=\"{}\"];\n",
+                id, child, nibble
+            )),
+            None => out.push_str(&format!("  n{} -> n{};\n", id, child)),
+        }
+    }
+
+    Ok(id)
+}
+
+// Loads a hash node from the database, and encodes the node to work out its hash if it
+// is stored apart from its parent. The root is always stored by hash.
+pub(crate) fn resolve<D: DB>(
+    reader: &NodeReader<'_, D>,
+    node: &Node,
+    path: &Nibbles,
+    is_root: bool,
+) -> TrieResult<(Node, Vec<u8>, Option<B256>)> {
+    match node {
+        Node::Hash(hash_node) => match reader.recover(hash_node.hash)? {
+            Some(resolved) => {
+                let encoded = encode_node(&resolved, &mut |_, _| {});
+                Ok((resolved, encoded, Some(hash_node.hash)))
+            }
+            None => Err(TrieError::MissingTrieNode {
+                node_hash: hash_node.hash,
+                traversed: Some(path.clone()),
+                root_hash: Some(reader.root_hash()),
+                err_key: None,
+            }),
+        },
+        Node::Empty => Ok((Node::Empty, vec![EMPTY_STRING_CODE], None)),
+        _ => {
+            let encoded = encode_node(node, &mut |_, _| {});
+            let hash = (is_root || encoded.len() >= HASHED_LENGTH)
+                .then(|| keccak(&encoded).as_fixed_bytes().into());
+            Ok((node.clone(), encoded, hash))
+        }
+    }
+}
+
+pub(crate) fn nibbles_to_string(nibbles: &Nibbles) -> String {
+    nibbles
+        .get_data()
+        .iter()
+        .filter(|nibble| **nibble < 16)
+        .map(|nibble| format!("{:x}", nibble))
+        .collect()
+}
+
+pub(crate) fn value_to_string(value: &[u8]) -> String {
+    if value.len() > MAX_VALUE_BYTES {
+        format!("0x{}...", hex::encode(&value[..MAX_VALUE_BYTES]))
+    } else {
+        format!("0x{}", hex::encode(value))
+    }
+}
+
+fn quote(s: &str) -> String {
+    format!("\"{}\"", s)
+}
+
+fn object(fields: Vec<(String, String)>, indent: usize) -> String {
+    if fields.is_empty() {
+        return "{}".to_string();
+    }
+    let pad = "  ".repeat(indent);
+    let body = fields
+        .iter()
+        .map(|(name, value)| format!("{}  \"{}\": {}", pad, name, value))
+        .collect::<Vec<_>>()
+        .join(",\n");
+    format!("{{\n{}\n{}}}", body, pad)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use crate::db::MemoryDB;
+    use crate::trie::{EthTrie, TrieWrite};
+
+    #[test]
+    fn test_debug_json_single_leaf() {
+        let memdb = Arc::new(MemoryDB::new(true));
+        let mut trie = EthTrie::new(memdb);
+        trie.insert(b"do", b"verb").unwrap();
+        let root = trie.root_hash().unwrap();
+
+        let expected = format!(
+            "{{\n  \"type\": \"leaf\",\n  \"hash\": \"{}\",\n  \"key\": \"646f\",\n  \"value\": \"0x76657262\"\n}}\n",
+            root
+        );
+        assert_eq!(trie.to_debug_json().unwrap(), expected);
+    }
+
+    #[test]
+    fn test_debug_json_nested() {
+        let memdb = Arc::new(MemoryDB::new(true));
+        let mut trie = EthTrie::new(memdb.clone());
+        trie.insert(b"do", b"verb").unwrap();
+        trie.insert(b"dog", b"puppy").unwrap();
+        trie.insert(b"horse", &[0xaa; 40]).unwrap();
+        let root = trie.root_hash().unwrap();
+
+        // Reopened from the database, so that stored children have to be loaded
+        let trie = EthTrie::from(memdb, root).unwrap();
+        let json = trie.to_debug_json().unwrap();
+        assert!(json.starts_with("{\n  \"type\": \"extension\",\n  \"hash\""));
+        assert!(json.contains("\"type\": \"branch\""));
+        assert!(json.contains("\"value\": \"0x76657262\""));
+        assert!(json.contains(&format!("\"0x{}...\"", "aa".repeat(32))));
+    }
+
+    #[test]
+    fn test_dot() {
+        let memdb = Arc::new(MemoryDB::new(true));
+        let mut trie = EthTrie::new(memdb);
+        trie.insert(b"do", b"verb").unwrap();
+        trie.insert(b"dog", b"puppy").unwrap();
+        let root = trie.root_hash().unwrap();
+
+        let expected = format!(
+            concat!(
+                "digraph trie {{\n",
+                "  node [shape=box, fontname=monospace];\n",
+                "  n2 [label=\"leaf\\nkey: 7\\nvalue: 0x7075707079\", style=dashed];\n",
+                "  n1 [label=\"branch\\nvalue: 0x76657262\", style=dashed];\n",
+                "  n1 -> n2 [label=\"6\"];\n",
+                "  n0 [label=\"extension\\nhash: 0x{}\\nprefix: 646f\"];\n",
+                "  n0 -> n1;\n",
+                "}}\n"
+            ),
+            alloy_primitives::hex::encode(&root[..4])
+        );
+        assert_eq!(trie.to_dot().unwrap(), expected);
+    }
+
+    #[test]
+    fn test_debug_json_empty() {
+        let trie = EthTrie::new(Arc::new(MemoryDB::new(true)));
+        assert_eq!(
+            trie.to_debug_json().unwrap(),
+            "{\n  \"type\": \"empty\"\n}\n"
+        );
+    }
+}
@@ -0,0 +1,328 @@
+use std::sync::Arc;
+
+use alloy_primitives::B256;
+use hashbrown::HashMap;
+use keccak_hash::keccak;
+
+use crate::db::DB;
+use crate::errors::TrieError;
+use crate::trie::{EthTrie, TrieIterator, TrieRead, TrieResult, TrieWrite, HASHED_LENGTH};
+
+// Values are stored in the trie after one of these bytes: the value itself, or the hash
+// of a value held in the value store.
+const INLINE_VALUE: u8 = 0;
+const STORED_VALUE: u8 = 1;
+// Stored values are kept under this byte followed by their hash, and the number of
+// entries referring to them under the other.
+const VALUE_KEY_PREFIX: u8 = b'v';
+const REFS_KEY_PREFIX: u8 = b'r';
+
+/// Wraps an `EthTrie`, storing values of at least a minimum size once each in another
+/// database, under their hash, with the trie holding only the hash. Suits tries where
+/// many keys hold the same large value, such as the code of contracts deployed many
+/// times.
+///
+/// Every value is stored in the trie behind a byte telling whether it is the value or
+/// its hash, so the root is not that of a trie of the values themselves, and a trie can
+/// only be wrapped if it was written through a `DedupTrie`. Each stored value has a count
+/// of the entries referring to it, and is removed when that reaches zero. Commits write
+/// new values and added references before the trie, and remove references after it, so
+/// a failure in between can leave a value that is no longer needed but never loses one.
+/// Only one `DedupTrie` should write to a value store at a time.
+#[derive(Debug)]
+pub struct DedupTrie<D, S>
+where
+    D: DB,
+    S: DB,
+{
+    trie: EthTrie<D>,
+    store: Arc<S>,
+    min_size: usize,
+    // The references added and removed since the last commit, by hash.
+    refs: HashMap<B256, i64>,
+    // The values referred to since the last commit, which may not be stored yet.
+    values: HashMap<B256, Vec<u8>>,
+}
+
+impl<D, S> DedupTrie<D, S>
+where
+    D: DB,
+    S: DB,
+{
+    /// Wraps a trie written through a `DedupTrie`, or an empty one, storing values of 256
+    /// bytes or more in `store`.
+    pub fn new(trie: EthTrie<D>, store: Arc<S>) -> Self {
+        Self {
+            trie,
+            store,
+            min_size: 256,
+            refs: HashMap::new(),
+            values: HashMap::new(),
+        }
+    }
+
+    /// Sets the size from which values are stored apart from the trie. Values already
+    /// written stay as they are.
+    pub fn min_size(mut self, bytes: usize) -> Self {
+        self.min_size = bytes;
+        self
+    }
+
+    pub fn get(&self, key: &[u8]) -> TrieResult<Option<Vec<u8>>> {
+        match self.trie.get(key)? {
+            Some(value) => self.resolve(&value).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    pub fn contains(&self, key: &[u8]) -> TrieResult<bool> {
+        self.trie.contains(key)
+    }
+
+    /// Inserts a value, replacing any existing value for key. An empty value removes the
+    /// key, as with `EthTrie`.
+    pub fn insert(&mut self, key: &[u8], value: &[u8]) -> TrieResult<()> {
+        if value.is_empty() {
+            return self.remove(key).map(|_| ());
+        }
+        self.release(key)?;
+        let encoded = match value.len() >= self.min_size {
+            true => {
+                let hash: B256 = keccak(value).as_fixed_bytes().into();
+                *self.refs.entry(hash).or_default() += 1;
+                self.values.insert(hash, value.to_vec());
+                [&[STORED_VALUE], hash.as_slice()].concat()
+            }
+            false => [&[INLINE_VALUE], value].concat(),
+        };
+        self.trie.insert(key, &encoded)
+    }
+
+    pub fn remove(&mut self, key: &[u8]) -> TrieResult<bool> {
+        self.release(key)?;
+        self.trie.remove(key)
+    }
+
+    /// Commits the trie, along with the values and references changed since the last
+    /// commit.
+    pub fn root_hash(&mut self) -> TrieResult<B256> {
+        let mut keys = vec![];
+        let mut values = vec![];
+        for (hash, added) in self.refs.iter().filter(|(_, refs)| **refs > 0) {
+            let refs = self.stored_refs(hash)?;
+            if refs == 0 {
+                let value = self.values.get(hash).ok_or(TrieError::InvalidData)?;
+                keys.push(value_key(hash));
+                values.push(value.clone());
+            }
+            keys.push(refs_key(hash));
+            values.push((refs + *added as u64).to_be_bytes().to_vec());
+        }
+        self.store
+            .insert_batch(keys, values)
+            .map_err(TrieError::db)?;
+        self.refs.retain(|_, refs| *refs < 0);
+        self.values.clear();
+
+        let root = self.trie.root_hash()?;
+
+        let mut keys = vec![];
+        let mut values = vec![];
+        let mut removed = vec![];
+        for (hash, released) in self.refs.iter() {
+            match self.stored_refs(hash)?.checked_sub(released.unsigned_abs()) {
+                Some(refs) if refs > 0 => {
+                    keys.push(refs_key(hash));
+                    values.push(refs.to_be_bytes().to_vec());
+                }
+                _ => removed.extend([value_key(hash), refs_key(hash)]),
+            }
+        }
+        self.store
+            .insert_batch(keys, values)
+            .map_err(TrieError::db)?;
+        self.store.remove_batch(&removed).map_err(TrieError::db)?;
+        self.refs.clear();
+        Ok(root)
+    }
+
+    /// Iterates over the entries in key order, reading stored values from the value
+    /// store.
+    pub fn iter(&self) -> DedupIterator<'_, D, S> {
+        DedupIterator {
+            trie: self,
+            inner: self.trie.iter(),
+        }
+    }
+
+    /// Returns the number of entries referring to a stored value, as of the last commit.
+    pub fn refs(&self, hash: &B256) -> TrieResult<u64> {
+        self.stored_refs(hash)
+    }
+
+    pub fn inner(&self) -> &EthTrie<D> {
+        &self.trie
+    }
+
+    pub fn into_inner(self) -> EthTrie<D> {
+        self.trie
+    }
+
+    // Removes the reference the value of `key` holds, if any.
+    fn release(&mut self, key: &[u8]) -> TrieResult<()> {
+        if let Some(hash) = self.trie.get(key)?.as_deref().and_then(stored_hash) {
+            *self.refs.entry(hash).or_default() -= 1;
+        }
+        Ok(())
+    }
+
+    // Returns the value a value of the trie stands for.
+    fn resolve(&self, value: &[u8]) -> TrieResult<Vec<u8>> {
+        match value.split_first() {
+            Some((&INLINE_VALUE, value)) => Ok(value.to_vec()),
+            Some((&STORED_VALUE, _)) => {
+                let hash = stored_hash(value).ok_or(TrieError::InvalidData)?;
+                if let Some(value) = self.values.get(&hash) {
+                    return Ok(value.clone());
+                }
+                let value = self.store.get(&value_key(&hash)).map_err(TrieError::db)?;
+                value.ok_or(TrieError::InvalidData)
+            }
+            _ => Err(TrieError::InvalidData),
+        }
+    }
+
+    fn stored_refs(&self, hash: &B256) -> TrieResult<u64> {
+        match self.store.get(&refs_key(hash)).map_err(TrieError::db)? {
+            Some(refs) => {
+                let refs: [u8; 8] = refs.try_into().map_err(|_| TrieError::InvalidData)?;
+                Ok(u64::from_be_bytes(refs))
+            }
+            None => Ok(0),
+        }
+    }
+}
+
+/// Iterates over the entries of a `DedupTrie` in key order. Created by `DedupTrie::iter`.
+pub struct DedupIterator<'a, D, S>
+where
+    D: DB,
+    S: DB,
+{
+    trie: &'a DedupTrie<D, S>,
+    inner: TrieIterator<'a, D>,
+}
+
+impl<D, S> DedupIterator<'_, D, S>
+where
+    D: DB,
+    S: DB,
+{
+    /// Repositions the iterator so that the next item returned is the first entry
+    /// whose key is greater than or equal to `key`.
+    pub fn seek(&mut self, key: &[u8]) -> TrieResult<()> {
+        self.inner.seek(key)
+    }
+}
+
+impl<D, S> Iterator for DedupIterator<'_, D, S>
+where
+    D: DB,
+    S: DB,
+{
+    type Item = TrieResult<(Vec<u8>, Vec<u8>)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let entry = self.inner.next()?;
+        Some(entry.and_then(|(key, value)| Ok((key, self.trie.resolve(&value)?))))
+    }
+}
+
+// Returns the hash a value of the trie refers to, if it refers to a stored value.
+fn stored_hash(value: &[u8]) -> Option<B256> {
+    match value.split_first() {
+        Some((&STORED_VALUE, hash)) if hash.len() == HASHED_LENGTH => Some(B256::from_slice(hash)),
+        _ => None,
+    }
+}
+
+fn value_key(hash: &B256) -> Vec<u8> {
+    [&[VALUE_KEY_PREFIX], hash.as_slice()].concat()
+}
+
+fn refs_key(hash: &B256) -> Vec<u8> {
+    [&[REFS_KEY_PREFIX], hash.as_slice()].concat()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use alloy_primitives::B256;
+    use keccak_hash::keccak;
+
+    use super::DedupTrie;
+    use crate::db::{MemoryDB, DB};
+    use crate::trie::{EthTrie, TrieRead};
+
+    #[test]
+    fn test_dedup_trie_counts_references() {
+        let store = Arc::new(MemoryDB::new(true));
+        let trie = EthTrie::new(Arc::new(MemoryDB::new(true)));
+        let mut trie = DedupTrie::new(trie, store.clone()).min_size(32);
+        let code = vec![0x60; 1000];
+        let hash: B256 = keccak(&code).as_fixed_bytes().into();
+        for i in 0..10u8 {
+            trie.insert(&[i; 20], &code).unwrap();
+        }
+        trie.insert(b"small", b"value").unwrap();
+        assert_eq!(trie.get(&[3; 20]).unwrap(), Some(code.clone()));
+        let root = trie.root_hash().unwrap();
+
+        // One copy of the value, and the count of its references
+        assert_eq!(store.len().unwrap(), 2);
+        assert_eq!(trie.refs(&hash).unwrap(), 10);
+        assert_eq!(trie.inner().get(&[3; 20]).unwrap().unwrap().len(), 33);
+
+        let mut trie = DedupTrie::new(
+            EthTrie::from(trie.inner().db.clone(), root).unwrap(),
+            store.clone(),
+        );
+        for i in 0..5u8 {
+            assert!(trie.remove(&[i; 20]).unwrap());
+        }
+        trie.insert(&[5; 20], b"replaced").unwrap();
+        trie.root_hash().unwrap();
+        assert_eq!(trie.refs(&hash).unwrap(), 4);
+        let entries: Vec<_> = trie.iter().map(Result::unwrap).collect();
+        assert_eq!(entries.len(), 6);
+        assert_eq!(entries[0], (vec![5; 20], b"replaced".to_vec()));
+        assert_eq!(entries[1], (vec![6; 20], code.clone()));
+
+        // The value goes once nothing refers to it
+        for i in 6..10u8 {
+            trie.remove(&[i; 20]).unwrap();
+        }
+        trie.root_hash().unwrap();
+        assert_eq!(trie.refs(&hash).unwrap(), 0);
+        assert!(store.is_empty().unwrap());
+    }
+
+    #[test]
+    fn test_dedup_trie_uncommitted_values() {
+        let store = Arc::new(MemoryDB::new(true));
+        let trie = EthTrie::new(Arc::new(MemoryDB::new(true)));
+        let mut trie = DedupTrie::new(trie, store.clone());
+        let value = vec![7; 300];
+        trie.insert(b"key", &value).unwrap();
+        assert_eq!(trie.get(b"key").unwrap(), Some(value.clone()));
+        assert!(store.is_empty().unwrap());
+
+        // A value replaced before the commit is never stored
+        trie.insert(b"key", &[8; 300]).unwrap();
+        trie.root_hash().unwrap();
+        let hash: B256 = keccak(&value).as_fixed_bytes().into();
+        assert_eq!(trie.refs(&hash).unwrap(), 0);
+        assert_eq!(store.len().unwrap(), 2);
+    }
+}
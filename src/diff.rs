@@ -0,0 +1,420 @@
+use std::sync::Arc;
+
+use alloy_primitives::B256;
+use keccak_hash::KECCAK_NULL_RLP;
+
+use crate::db::DB;
+use crate::errors::TrieError;
+use crate::nibbles::Nibbles;
+use crate::node::{empty_children, Node};
+use crate::trie::{decode_node, TrieResult};
+
+/// A changed key, with its value under the old and the new root.
+pub type KeyChange = (Vec<u8>, Option<Vec<u8>>, Option<Vec<u8>>);
+
+/// A stored node, with the nibble path leading to it, its hash and its encoding.
+pub type NodeChange = (Nibbles, B256, Vec<u8>);
+
+/// Returns an iterator over the keys whose values differ between the tries at `root_a`
+/// and `root_b`, in key order.
+///
+/// Both tries are walked side by side, skipping any subtree whose hash is the same in
+/// both, so the cost is proportional to the size of the change rather than the tries.
+pub fn diff<D: DB>(db: &Arc<D>, root_a: B256, root_b: B256) -> DiffIterator<D> {
+    DiffIterator {
+        db: db.clone(),
+        root_a,
+        root_b,
+        stack: vec![(Nibbles::from_hex(&[]), root_node(root_a), root_node(root_b))],
+    }
+}
+
+// Compares two tries given by their root nodes, which may be held in memory. Stored
+// nodes are loaded from `db`, and errors point at `root_hash`.
+pub(crate) fn diff_nodes<D: DB>(db: &Arc<D>, root_hash: B256, a: Node, b: Node) -> DiffIterator<D> {
+    DiffIterator {
+        db: db.clone(),
+        root_a: root_hash,
+        root_b: root_hash,
+        stack: vec![(Nibbles::from_hex(&[]), a, b)],
+    }
+}
+
+pub struct DiffIterator<D>
+where
+    D: DB,
+{
+    db: Arc<D>,
+    root_a: B256,
+    root_b: B256,
+    // Pairs of subtries still to compare, with the path leading to them.
+    stack: Vec<(Nibbles, Node, Node)>,
+}
+
+impl<D> Iterator for DiffIterator<D>
+where
+    D: DB,
+{
+    type Item = TrieResult<KeyChange>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while let Some((path, a, b)) = self.stack.pop() {
+            if let (Node::Hash(hash_a), Node::Hash(hash_b)) = (&a, &b) {
+                if hash_a.hash == hash_b.hash {
+                    continue;
+                }
+            }
+
+            let a = match resolve(&*self.db, a, &path, self.root_a) {
+                Ok(node) => node,
+                Err(e) => return Some(Err(e)),
+            };
+            let b = match resolve(&*self.db, b, &path, self.root_b) {
+                Ok(node) => node,
+                Err(e) => return Some(Err(e)),
+            };
+
+            match (&a, &b) {
+                (Node::Empty, Node::Empty) => continue,
+                (Node::Leaf(leaf_a), Node::Leaf(leaf_b)) if leaf_a.key == leaf_b.key => {
+                    if leaf_a.value != leaf_b.value {
+                        return Some(Ok((
+                            path.join(&leaf_a.key).encode_raw().0,
+                            Some(leaf_a.value.clone()),
+                            Some(leaf_b.value.clone()),
+                        )));
+                    }
+                    continue;
+                }
+                (Node::Empty, Node::Leaf(leaf)) => {
+                    let key = path.join(&leaf.key).encode_raw().0;
+                    return Some(Ok((key, None, Some(leaf.value.clone()))));
+                }
+                (Node::Leaf(leaf), Node::Empty) => {
+                    let key = path.join(&leaf.key).encode_raw().0;
+                    return Some(Ok((key, Some(leaf.value.clone()), None)));
+                }
+                _ => {}
+            }
+
+            // Step both sides down by one nibble and compare them child by child.
+            let (value_a, children_a) = expand(a);
+            let (value_b, children_b) = expand(b);
+            for (i, (child_a, child_b)) in children_a.into_iter().zip(children_b).enumerate().rev()
+            {
+                if matches!((&child_a, &child_b), (Node::Empty, Node::Empty)) {
+                    continue;
+                }
+                let mut child_path = path.clone();
+                child_path.push(i as u8);
+                self.stack.push((child_path, child_a, child_b));
+            }
+
+            if value_a != value_b {
+                let key = path.join(&Nibbles::from_hex(&[16])).encode_raw().0;
+                return Some(Ok((key, value_a, value_b)));
+            }
+        }
+        None
+    }
+}
+
+/// Returns an iterator over the stored nodes of the trie at `root_b` that are not part
+/// of the trie at `root_a`, parents before children.
+///
+/// Writing every yielded node into a store that holds the trie at `root_a` makes the
+/// trie at `root_b` available there too. Subtrees whose hash matches the node at the
+/// same path under `root_a` are skipped without being read.
+pub fn node_diff<D: DB>(db: &Arc<D>, root_a: B256, root_b: B256) -> NodeDiffIterator<D> {
+    NodeDiffIterator {
+        db: db.clone(),
+        root_a,
+        root_b,
+        stack: vec![(Nibbles::from_hex(&[]), root_node(root_b), root_node(root_a))],
+    }
+}
+
+pub struct NodeDiffIterator<D>
+where
+    D: DB,
+{
+    db: Arc<D>,
+    root_a: B256,
+    root_b: B256,
+    // Nodes of the new trie still to visit, each paired with whatever occupies the same
+    // path in the old trie.
+    stack: Vec<(Nibbles, Node, Node)>,
+}
+
+impl<D> Iterator for NodeDiffIterator<D>
+where
+    D: DB,
+{
+    type Item = TrieResult<NodeChange>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while let Some((path, b, a)) = self.stack.pop() {
+            match self.visit(path, b, a) {
+                Ok(Some(change)) => return Some(Ok(change)),
+                Ok(None) => continue,
+                Err(e) => return Some(Err(e)),
+            }
+        }
+        None
+    }
+}
+
+impl<D> NodeDiffIterator<D>
+where
+    D: DB,
+{
+    fn visit(&mut self, path: Nibbles, b: Node, a: Node) -> TrieResult<Option<NodeChange>> {
+        match b {
+            Node::Empty | Node::Leaf(_) => Ok(None),
+            Node::Hash(hash_node) => {
+                if let Node::Hash(ref other) = a {
+                    if other.hash == hash_node.hash {
+                        return Ok(None);
+                    }
+                }
+
+                let encoded = load(&*self.db, hash_node.hash, &path, self.root_b)?;
+                let node = decode_node(&mut encoded.as_slice())?;
+                self.stack.push((path.clone(), node, a));
+                Ok(Some((path, hash_node.hash, encoded)))
+            }
+            Node::Extension(ext) => {
+                let ext = ext.read().clone();
+                let a = self.descend(a, &path, &ext.prefix)?;
+                self.stack
+                    .push((path.join(&ext.prefix), ext.node.clone(), a));
+                Ok(None)
+            }
+            Node::Branch(branch) => {
+                let branch = branch.read().clone();
+                let (_, children_a) = expand(resolve(&*self.db, a, &path, self.root_a)?);
+                for (i, (child_b, child_a)) in
+                    branch.children.iter().zip(children_a).enumerate().rev()
+                {
+                    if matches!(child_b, Node::Empty) {
+                        continue;
+                    }
+                    let mut child_path = path.clone();
+                    child_path.push(i as u8);
+                    self.stack.push((child_path, child_b.clone(), child_a));
+                }
+                Ok(None)
+            }
+        }
+    }
+
+    // Steps the old trie down along `prefix`, starting from the subtrie at `path`.
+    fn descend(&self, mut a: Node, path: &Nibbles, prefix: &Nibbles) -> TrieResult<Node> {
+        let mut path = path.clone();
+        for i in 0..prefix.len() {
+            if matches!(a, Node::Empty) {
+                break;
+            }
+            let (_, mut children) = expand(resolve(&*self.db, a, &path, self.root_a)?);
+            a = std::mem::replace(&mut children[prefix.at(i)], Node::Empty);
+            path.push(prefix.at(i) as u8);
+        }
+        Ok(a)
+    }
+}
+
+fn load<D: DB>(db: &D, node_hash: B256, path: &Nibbles, root_hash: B256) -> TrieResult<Vec<u8>> {
+    db.get(node_hash.as_slice())
+        .map_err(TrieError::db)?
+        .ok_or_else(|| TrieError::MissingTrieNode {
+            node_hash,
+            traversed: Some(path.clone()),
+            root_hash: Some(root_hash),
+            err_key: None,
+        })
+}
+
+fn resolve<D: DB>(db: &D, node: Node, path: &Nibbles, root_hash: B256) -> TrieResult<Node> {
+    match node {
+        Node::Hash(hash_node) => {
+            let encoded = load(db, hash_node.hash, path, root_hash)?;
+            decode_node(&mut encoded.as_slice())
+        }
+        _ => Ok(node),
+    }
+}
+
+fn root_node(root: B256) -> Node {
+    let empty_root: B256 = KECCAK_NULL_RLP.as_fixed_bytes().into();
+    if root == empty_root {
+        Node::Empty
+    } else {
+        Node::from_hash(root)
+    }
+}
+
+// Splits a resolved node into the value stored at its own path and the subtries under
+// each of the 16 next nibbles.
+fn expand(node: Node) -> (Option<Vec<u8>>, [Node; 16]) {
+    let mut children = empty_children();
+    match node {
+        Node::Empty => (None, children),
+        Node::Leaf(leaf) => {
+            if leaf.key.len() == 1 {
+                return (Some(leaf.value.clone()), children);
+            }
+            children[leaf.key.at(0)] = Node::from_leaf(leaf.key.offset(1), leaf.value.clone());
+            (None, children)
+        }
+        Node::Extension(ext) => {
+            let ext = ext.read();
+            children[ext.prefix.at(0)] = if ext.prefix.len() == 1 {
+                ext.node.clone()
+            } else {
+                Node::from_extension(ext.prefix.offset(1), ext.node.clone())
+            };
+            (None, children)
+        }
+        Node::Branch(branch) => {
+            let branch = branch.read();
+            (branch.value.clone(), branch.children.clone())
+        }
+        Node::Hash(_) => unreachable!("hash nodes are resolved before being expanded"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+    use std::sync::Arc;
+
+    use rand::{thread_rng, Rng};
+
+    use super::{diff, node_diff, KeyChange};
+    use crate::db::{MemoryDB, DB};
+    use crate::trie::{EthTrie, TrieRead, TrieWrite};
+
+    fn expected_diff(
+        a: &BTreeMap<Vec<u8>, Vec<u8>>,
+        b: &BTreeMap<Vec<u8>, Vec<u8>>,
+    ) -> Vec<KeyChange> {
+        let mut keys: Vec<&Vec<u8>> = a.keys().chain(b.keys()).collect();
+        keys.sort();
+        keys.dedup();
+        keys.into_iter()
+            .filter(|k| a.get(*k) != b.get(*k))
+            .map(|k| (k.clone(), a.get(k).cloned(), b.get(k).cloned()))
+            .collect()
+    }
+
+    #[test]
+    fn test_diff_identical_roots() {
+        let memdb = Arc::new(MemoryDB::new(false));
+        let mut trie = EthTrie::new(memdb.clone());
+        trie.insert(b"test", b"test").unwrap();
+        let root = trie.root_hash().unwrap();
+
+        assert_eq!(diff(&memdb, root, root).count(), 0);
+    }
+
+    #[test]
+    fn test_diff_against_empty() {
+        let memdb = Arc::new(MemoryDB::new(false));
+        let mut trie = EthTrie::new(memdb.clone());
+        let empty_root = trie.root_hash().unwrap();
+        trie.insert(b"do", b"verb").unwrap();
+        trie.insert(b"dog", b"puppy").unwrap();
+        let root = trie.root_hash().unwrap();
+
+        let changes: Vec<KeyChange> = diff(&memdb, empty_root, root).map(|c| c.unwrap()).collect();
+        assert_eq!(
+            changes,
+            vec![
+                (b"do".to_vec(), None, Some(b"verb".to_vec())),
+                (b"dog".to_vec(), None, Some(b"puppy".to_vec())),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_diff_random() {
+        let memdb = Arc::new(MemoryDB::new(false));
+        let mut trie = EthTrie::new(memdb.clone());
+        let mut kv = BTreeMap::new();
+        let mut rng = thread_rng();
+
+        let mut random_key = || -> Vec<u8> {
+            (0..rng.gen_range(1..5))
+                .map(|_| rng.gen_range(0..6u8))
+                .collect()
+        };
+
+        for _ in 0..300 {
+            let key = random_key();
+            trie.insert(&key, &key.repeat(10)).unwrap();
+            kv.insert(key.clone(), key.repeat(10));
+        }
+        let root_a = trie.root_hash().unwrap();
+        let kv_a = kv.clone();
+
+        for i in 0..100 {
+            let key = random_key();
+            if i % 2 == 0 {
+                trie.remove(&key).unwrap();
+                kv.remove(&key);
+            } else {
+                trie.insert(&key, &key.repeat(3)).unwrap();
+                kv.insert(key.clone(), key.repeat(3));
+            }
+        }
+        let root_b = trie.root_hash().unwrap();
+
+        let changes: Vec<KeyChange> = diff(&memdb, root_a, root_b).map(|c| c.unwrap()).collect();
+        assert_eq!(changes, expected_diff(&kv_a, &kv));
+
+        let changes: Vec<KeyChange> = diff(&memdb, root_b, root_a).map(|c| c.unwrap()).collect();
+        assert_eq!(changes, expected_diff(&kv, &kv_a));
+    }
+
+    #[test]
+    fn test_node_diff_replicates_trie() {
+        let memdb = Arc::new(MemoryDB::new(false));
+        let mut trie = EthTrie::new(memdb.clone());
+        let empty_root = trie.root_hash().unwrap();
+        let mut kv = BTreeMap::new();
+        let mut rng = thread_rng();
+
+        for _ in 0..500 {
+            let key: Vec<u8> = (0..rng.gen_range(1..8)).map(|_| rng.gen()).collect();
+            trie.insert(&key, &key.repeat(5)).unwrap();
+            kv.insert(key.clone(), key.repeat(5));
+        }
+        let root_a = trie.root_hash().unwrap();
+
+        for _ in 0..20 {
+            let key: Vec<u8> = (0..rng.gen_range(1..8)).map(|_| rng.gen()).collect();
+            trie.insert(&key, &key.repeat(2)).unwrap();
+            kv.insert(key.clone(), key.repeat(2));
+        }
+        let root_b = trie.root_hash().unwrap();
+
+        // Replicate the first version from scratch, then apply the increment.
+        let replica = Arc::new(MemoryDB::new(false));
+        let full: Vec<_> = node_diff(&memdb, empty_root, root_a)
+            .map(|n| n.unwrap())
+            .collect();
+        let increment: Vec<_> = node_diff(&memdb, root_a, root_b)
+            .map(|n| n.unwrap())
+            .collect();
+        assert!(increment.len() < full.len());
+
+        for (_, hash, encoded) in full.into_iter().chain(increment) {
+            replica.insert(hash.as_slice(), encoded).unwrap();
+        }
+
+        let replicated = EthTrie::from(replica, root_b).unwrap();
+        let entries: BTreeMap<Vec<u8>, Vec<u8>> =
+            replicated.iter().map(|item| item.unwrap()).collect();
+        assert_eq!(entries, kv);
+    }
+}
@@ -0,0 +1,172 @@
+use alloy_primitives::B256;
+use hashbrown::HashSet;
+
+use crate::db::DB;
+use crate::errors::TrieError;
+use crate::trie::{EthTrie, TrieResult, HASHED_LENGTH};
+
+// The number of the last commit made with epoch pruning.
+const EPOCH_KEY: &[u8] = b"eth-trie:epoch";
+// The last epoch whose stale nodes have been removed.
+const COLLECTED_EPOCH_KEY: &[u8] = b"eth-trie:collected-epoch";
+// Followed by a node hash, the epoch that last wrote the node.
+const NODE_EPOCH_KEY_PREFIX: &[u8] = b"eth-trie:node-epoch:";
+// Followed by an epoch, the hashes of the nodes that commit made stale.
+const STALE_KEY_PREFIX: &[u8] = b"eth-trie:stale:";
+// Followed by an epoch, the root that commit moved away from.
+const STALE_ROOT_KEY_PREFIX: &[u8] = b"eth-trie:stale-root:";
+
+pub(crate) fn node_epoch_key(hash: &B256) -> Vec<u8> {
+    [NODE_EPOCH_KEY_PREFIX, hash.as_slice()].concat()
+}
+
+fn stale_key(epoch: u64) -> Vec<u8> {
+    [STALE_KEY_PREFIX, &epoch.to_be_bytes()].concat()
+}
+
+fn stale_root_key(epoch: u64) -> Vec<u8> {
+    [STALE_ROOT_KEY_PREFIX, &epoch.to_be_bytes()].concat()
+}
+
+fn read_epoch<D: DB>(db: &D, key: &[u8]) -> TrieResult<u64> {
+    match db.get(key).map_err(TrieError::db)? {
+        Some(encoded) => {
+            let bytes: [u8; 8] = encoded.try_into().map_err(|_| TrieError::InvalidData)?;
+            Ok(u64::from_be_bytes(bytes))
+        }
+        None => Ok(0),
+    }
+}
+
+// Starts a new epoch for a commit that wrote the nodes `written`, made the nodes `stale`
+// unreachable and moved away from `stale_root`. Returns the nodes and the roots that went
+// stale more than `keep` epochs ago and have not been written since, which the caller
+// removes along with the epoch tags of the nodes and the leaf counts of the roots.
+pub(crate) fn advance_epoch<D: DB>(
+    db: &D,
+    written: &HashSet<B256>,
+    stale: Vec<B256>,
+    stale_root: Option<B256>,
+    keep: u64,
+) -> TrieResult<(Vec<B256>, Vec<B256>)> {
+    let epoch = read_epoch(db, EPOCH_KEY)? + 1;
+
+    let mut keys = Vec::with_capacity(written.len() + 2);
+    let mut values = Vec::with_capacity(written.len() + 2);
+    for hash in written.iter() {
+        keys.push(node_epoch_key(hash));
+        values.push(epoch.to_be_bytes().to_vec());
+    }
+    if !stale.is_empty() {
+        keys.push(stale_key(epoch));
+        values.push(stale.iter().flat_map(|h| h.0).collect());
+    }
+    if let Some(root) = stale_root {
+        keys.push(stale_root_key(epoch));
+        values.push(root.to_vec());
+    }
+    keys.push(EPOCH_KEY.to_vec());
+    values.push(epoch.to_be_bytes().to_vec());
+
+    // The stale nodes of epoch `e` belong to the root of epoch `e - 1`, so keeping the
+    // last `keep` roots before this one collects every epoch up to `epoch - keep`.
+    let horizon = epoch.saturating_sub(keep);
+    let collected = read_epoch(db, COLLECTED_EPOCH_KEY)?;
+    let mut expired = vec![];
+    let mut expired_roots = vec![];
+    let mut records = vec![];
+    for stale_epoch in collected + 1..=horizon {
+        // A node written at or after the epoch that made it stale is in use again
+        let live = |hash: &B256| -> TrieResult<bool> {
+            Ok(written.contains(hash) || read_epoch(db, &node_epoch_key(hash))? >= stale_epoch)
+        };
+        let key = stale_key(stale_epoch);
+        if let Some(record) = db.get(&key).map_err(TrieError::db)? {
+            for hash in record.chunks(HASHED_LENGTH).map(B256::from_slice) {
+                if !live(&hash)? {
+                    expired.push(hash);
+                }
+            }
+            records.push(key);
+        }
+        let key = stale_root_key(stale_epoch);
+        if let Some(root) = db.get(&key).map_err(TrieError::db)? {
+            if root.len() != HASHED_LENGTH {
+                return Err(TrieError::InvalidData);
+            }
+            let root = B256::from_slice(&root);
+            if !live(&root)? {
+                expired_roots.push(root);
+            }
+            records.push(key);
+        }
+    }
+    if horizon > collected {
+        keys.push(COLLECTED_EPOCH_KEY.to_vec());
+        values.push(horizon.to_be_bytes().to_vec());
+    }
+
+    db.insert_batch(keys, values).map_err(TrieError::db)?;
+    db.remove_batch(&records).map_err(TrieError::db)?;
+    Ok((expired, expired_roots))
+}
+
+impl<D> EthTrie<D>
+where
+    D: DB,
+{
+    /// Returns the number of commits made to the database with epoch pruning, as set
+    /// with `EthTrieBuilder::epoch_pruning`.
+    pub fn epoch(&self) -> TrieResult<u64> {
+        read_epoch(&*self.db, EPOCH_KEY)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use crate::db::MemoryDB;
+    use crate::trie::{EthTrie, TrieRead, TrieWrite};
+
+    #[test]
+    fn test_epoch_pruning() {
+        let memdb = Arc::new(MemoryDB::new(true));
+        let mut trie = EthTrie::builder(memdb.clone())
+            .epoch_pruning(2)
+            .build()
+            .unwrap();
+        let mut roots = vec![];
+        for round in 0..6u8 {
+            trie.insert(b"test", &[round; 40]).unwrap();
+            trie.insert(b"test1", &[round; 40]).unwrap();
+            roots.push(trie.root_hash().unwrap());
+        }
+        assert_eq!(trie.epoch().unwrap(), 6);
+
+        for (round, root) in roots.iter().enumerate() {
+            let old = EthTrie::from(memdb.clone(), *root).unwrap();
+            if round >= 3 {
+                assert_eq!(old.get(b"test1").unwrap(), Some(vec![round as u8; 40]));
+            } else {
+                assert!(old.get(b"test1").is_err());
+            }
+        }
+
+        // The epochs carry over to a trie opened later on the same database
+        let mut trie = EthTrie::builder(memdb.clone())
+            .root(roots[5])
+            .epoch_pruning(2)
+            .build()
+            .unwrap();
+        // Write a value made stale two epochs ago again
+        trie.insert(b"test1", &[4; 40]).unwrap();
+        trie.root_hash().unwrap();
+        trie.insert(b"test", &[7; 40]).unwrap();
+        trie.root_hash().unwrap();
+        trie.insert(b"test", &[8; 40]).unwrap();
+        trie.root_hash().unwrap();
+        assert_eq!(trie.epoch().unwrap(), 9);
+        assert_eq!(trie.get(b"test1").unwrap(), Some(vec![4; 40]));
+    }
+}
@@ -1,5 +1,6 @@
 use std::error::Error;
 use std::fmt;
+use std::sync::Arc;
 
 use alloy_primitives::B256;
 use alloy_rlp::Error as RlpError;
@@ -8,11 +9,17 @@ use crate::nibbles::Nibbles;
 
 #[derive(Debug, PartialEq, Eq)]
 pub enum TrieError {
-    DB(String),
+    DB(DBError),
     Decoder(RlpError),
     InvalidData,
     InvalidStateRoot,
     InvalidProof,
+    /// A stored or proven node is not a valid encoding of a trie node.
+    InvalidNode(NodeDecodeError),
+    /// A node nests more inline nodes than the decoder accepts.
+    DepthLimitExceeded {
+        max_depth: usize,
+    },
     MissingTrieNode {
         node_hash: B256,
         traversed: Option<Nibbles>,
@@ -21,16 +28,38 @@ pub enum TrieError {
     },
 }
 
-impl Error for TrieError {}
+impl TrieError {
+    pub(crate) fn db<E>(error: E) -> Self
+    where
+        E: Error + Send + Sync + 'static,
+    {
+        TrieError::DB(DBError::new(error))
+    }
+}
+
+impl Error for TrieError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            TrieError::DB(err) => Some(err.0.as_ref()),
+            TrieError::Decoder(err) => Some(err),
+            TrieError::InvalidNode(err) => Some(err),
+            _ => None,
+        }
+    }
+}
 
 impl fmt::Display for TrieError {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         let printable = match *self {
-            TrieError::DB(ref err) => format!("trie error: {:?}", err),
+            TrieError::DB(ref err) => format!("trie error: {}", err),
             TrieError::Decoder(ref err) => format!("trie error: {:?}", err),
             TrieError::InvalidData => "trie error: invalid data".to_owned(),
             TrieError::InvalidStateRoot => "trie error: invalid state root".to_owned(),
             TrieError::InvalidProof => "trie error: invalid proof".to_owned(),
+            TrieError::InvalidNode(ref err) => format!("trie error: {}", err),
+            TrieError::DepthLimitExceeded { max_depth } => {
+                format!("trie error: node nesting deeper than {}", max_depth)
+            }
             TrieError::MissingTrieNode { .. } => "trie error: missing node".to_owned(),
         };
         write!(f, "{}", printable)
@@ -43,6 +72,115 @@ impl From<RlpError> for TrieError {
     }
 }
 
+/// Why a node failed to decode, and where.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct NodeDecodeError {
+    pub kind: NodeDecodeErrorKind,
+    /// The offset in the decoded input of the item that is invalid.
+    pub offset: usize,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NodeDecodeErrorKind {
+    /// A node is a list of `items` items, where a branch has 17 and a leaf or an extension
+    /// has 2.
+    ListArity { items: usize },
+    /// The key of a leaf or the prefix of an extension is not in compact encoding.
+    CompactPrefix,
+    /// A reference to a child is a string that is neither empty nor a 32 byte hash. `slot`
+    /// is the nibble of a branch child, `None` for the child of an extension or a node
+    /// decoded on its own.
+    ChildSlot { slot: Option<u8> },
+    /// A child of `len` bytes is embedded in its parent, where it should be referred to by
+    /// its hash.
+    InlineTooLarge { len: usize },
+    /// The value of a leaf or a branch is not an RLP string.
+    Value,
+    /// The input continues past the end of the node.
+    TrailingBytes,
+    /// The RLP header of an item is invalid, or the item runs past the end of the input.
+    Rlp(RlpError),
+    /// The node is valid but not in its canonical encoding.
+    NonCanonical,
+}
+
+impl Error for NodeDecodeError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self.kind {
+            NodeDecodeErrorKind::Rlp(ref err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for NodeDecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.kind {
+            NodeDecodeErrorKind::ListArity { items } => {
+                write!(f, "node is a list of {} items", items)?
+            }
+            NodeDecodeErrorKind::CompactPrefix => write!(f, "invalid compact key")?,
+            NodeDecodeErrorKind::ChildSlot { slot: Some(slot) } => {
+                write!(f, "invalid reference to child {:x}", slot)?
+            }
+            NodeDecodeErrorKind::ChildSlot { slot: None } => write!(f, "invalid node reference")?,
+            NodeDecodeErrorKind::InlineTooLarge { len } => {
+                write!(f, "inline node of {} bytes", len)?
+            }
+            NodeDecodeErrorKind::Value => write!(f, "invalid value")?,
+            NodeDecodeErrorKind::TrailingBytes => write!(f, "trailing bytes")?,
+            NodeDecodeErrorKind::Rlp(err) => write!(f, "invalid RLP item ({})", err)?,
+            NodeDecodeErrorKind::NonCanonical => write!(f, "non-canonical encoding")?,
+        }
+        write!(f, " at offset {}", self.offset)
+    }
+}
+
+/// An error returned by the database, kept with its original type. It is reported as the
+/// source of `TrieError::DB`.
+///
+/// Errors compare equal when they display the same, since the underlying error types
+/// usually can't be compared.
+#[derive(Debug, Clone)]
+pub struct DBError(Arc<dyn Error + Send + Sync>);
+
+impl DBError {
+    pub fn new<E>(error: E) -> Self
+    where
+        E: Error + Send + Sync + 'static,
+    {
+        DBError(Arc::new(error))
+    }
+
+    /// Returns the underlying error if it is of type `E`.
+    pub fn downcast_ref<E>(&self) -> Option<&E>
+    where
+        E: Error + 'static,
+    {
+        self.0.downcast_ref()
+    }
+}
+
+impl PartialEq for DBError {
+    fn eq(&self, other: &Self) -> bool {
+        self.0.to_string() == other.0.to_string()
+    }
+}
+
+impl Eq for DBError {}
+
+impl Error for DBError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        self.0.source()
+    }
+}
+
+impl fmt::Display for DBError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
 #[derive(Debug)]
 pub enum MemDBError {}
 
@@ -53,3 +191,48 @@ impl fmt::Display for MemDBError {
         write!(f, "error")
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::error::Error;
+    use std::fmt;
+
+    use super::{NodeDecodeError, NodeDecodeErrorKind, TrieError};
+
+    #[derive(Debug)]
+    struct DiskFull;
+
+    impl Error for DiskFull {}
+
+    impl fmt::Display for DiskFull {
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            write!(f, "disk full")
+        }
+    }
+
+    #[test]
+    fn test_error_sources() {
+        let err = TrieError::db(DiskFull);
+        assert_eq!(err.to_string(), "trie error: disk full");
+        assert!(err.source().unwrap().is::<DiskFull>());
+        match err {
+            TrieError::DB(ref db_err) => assert!(db_err.downcast_ref::<DiskFull>().is_some()),
+            _ => unreachable!(),
+        }
+        assert_eq!(err, TrieError::db(DiskFull));
+
+        let err = TrieError::from(alloy_rlp::Error::InputTooShort);
+        assert!(err.source().unwrap().is::<alloy_rlp::Error>());
+        assert!(TrieError::InvalidProof.source().is_none());
+
+        let err = TrieError::InvalidNode(NodeDecodeError {
+            kind: NodeDecodeErrorKind::ChildSlot { slot: Some(10) },
+            offset: 12,
+        });
+        assert_eq!(
+            err.to_string(),
+            "trie error: invalid reference to child a at offset 12"
+        );
+        assert!(err.source().unwrap().is::<NodeDecodeError>());
+    }
+}
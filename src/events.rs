@@ -0,0 +1,95 @@
+use std::sync::mpsc::{channel, Receiver};
+
+use alloy_primitives::B256;
+use hashbrown::{HashMap, HashSet};
+
+use crate::db::DB;
+use crate::diff::KeyChange;
+use crate::trie::{EthTrie, RootWithTrieDiff};
+
+/// What a commit changed, as sent to the receivers of `EthTrie::subscribe`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CommitEvent {
+    pub root: B256,
+    pub previous_root: B256,
+    /// The nodes the commit wrote, by hash.
+    pub trie_diff: HashMap<B256, Vec<u8>>,
+    /// The hashes of the nodes the previous root held that the new root doesn't.
+    pub stale_nodes: HashSet<B256>,
+    /// Each changed key with its previous and its new value, in key order.
+    pub changes: Vec<KeyChange>,
+}
+
+impl<D> EthTrie<D>
+where
+    D: DB,
+{
+    /// Returns a channel that receives a `CommitEvent` after each successful commit of
+    /// this trie, including commits made by `auto_flush`. Dropping the receiver ends the
+    /// subscription. While any subscription is open, commits collect the changed keys
+    /// and nodes, which costs a walk over the changes.
+    pub fn subscribe(&mut self) -> Receiver<CommitEvent> {
+        let (sender, receiver) = channel();
+        self.subscribers.push(sender);
+        receiver
+    }
+
+    pub(crate) fn notify(&mut self, commit: &RootWithTrieDiff, changes: Vec<KeyChange>) {
+        let event = CommitEvent {
+            root: commit.root,
+            previous_root: commit.previous_root,
+            trie_diff: commit.trie_diff.clone(),
+            stale_nodes: commit.stale_nodes.clone(),
+            changes,
+        };
+        self.subscribers
+            .retain(|subscriber| subscriber.send(event.clone()).is_ok());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use crate::db::MemoryDB;
+    use crate::trie::{EthTrie, TrieWrite};
+
+    #[test]
+    fn test_subscribe() {
+        let mut trie = EthTrie::new(Arc::new(MemoryDB::new(true)));
+        let events = trie.subscribe();
+        let dropped = trie.subscribe();
+        drop(dropped);
+
+        trie.insert(b"dog", b"puppy").unwrap();
+        let first = trie.root_hash().unwrap();
+        trie.insert(b"dog", b"hound").unwrap();
+        let diff = trie.root_hash_with_changed_nodes().unwrap();
+        assert_eq!(trie.subscribers.len(), 1);
+
+        let event = events.try_recv().unwrap();
+        assert_eq!(event.root, first);
+        assert_eq!(
+            event.changes,
+            vec![(b"dog".to_vec(), None, Some(b"puppy".to_vec()))]
+        );
+        let event = events.try_recv().unwrap();
+        assert_eq!(event.root, diff.root);
+        assert_eq!(event.previous_root, first);
+        assert_eq!(event.trie_diff, diff.trie_diff);
+        assert_eq!(
+            event.changes,
+            vec![(
+                b"dog".to_vec(),
+                Some(b"puppy".to_vec()),
+                Some(b"hound".to_vec())
+            )]
+        );
+        assert!(events.try_recv().is_err());
+
+        drop(events);
+        trie.insert(b"cat", b"kitten").unwrap();
+        trie.root_hash().unwrap();
+        assert!(trie.subscribers.is_empty());
+    }
+}
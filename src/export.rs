@@ -0,0 +1,448 @@
+use std::io::{Read, Write};
+use std::sync::Arc;
+
+use alloy_primitives::{Bytes, B256};
+use keccak_hash::{keccak, KECCAK_NULL_RLP};
+
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+use crate::db::DB;
+use crate::diff::node_diff;
+use crate::errors::TrieError;
+use crate::trie::{encode_node, leaf_count_key, EthTrie, TrieResult, TrieWrite};
+
+// The stream starts with this magic and a version byte.
+const STREAM_MAGIC: &[u8; 7] = b"ETHTRIE";
+const STREAM_VERSION: u8 = 1;
+// Nodes are written to the target database in batches of this many.
+const WRITE_BATCH_SIZE: usize = 1024;
+
+/// Every stored node of a committed trie, enough to rebuild it in another database.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct TrieExport {
+    pub root: B256,
+    /// The encoded nodes, parents before children. Their hashes are recomputed on import.
+    pub nodes: Vec<Bytes>,
+}
+
+impl<D> EthTrie<D>
+where
+    D: DB,
+{
+    /// Collects every node reachable from the last committed root. Uncommitted changes
+    /// are not part of the export.
+    pub fn export(&self) -> TrieResult<TrieExport> {
+        let root = self.root_hash;
+        let empty_root: B256 = KECCAK_NULL_RLP.as_fixed_bytes().into();
+
+        let mut nodes = Vec::new();
+        if root == empty_root {
+            nodes.push(Bytes::copy_from_slice(&[alloy_rlp::EMPTY_STRING_CODE]));
+        }
+        for change in node_diff(&self.db, empty_root, root) {
+            let (_, _, encoded) = change?;
+            nodes.push(encoded.into());
+        }
+
+        Ok(TrieExport { root, nodes })
+    }
+
+    /// Writes the nodes of an export into `db` and opens the trie at the exported root.
+    pub fn import(db: Arc<D>, export: &TrieExport) -> TrieResult<Self> {
+        let mut keys = Vec::with_capacity(export.nodes.len());
+        let mut values = Vec::with_capacity(export.nodes.len());
+        for node in export.nodes.iter() {
+            keys.push(keccak(node).as_bytes().to_vec());
+            values.push(node.to_vec());
+        }
+        if !keys.iter().any(|key| key == export.root.as_slice()) {
+            return Err(TrieError::InvalidStateRoot);
+        }
+
+        db.insert_batch(keys, values).map_err(TrieError::db)?;
+        db.flush().map_err(TrieError::db)?;
+
+        EthTrie::from(db, export.root)
+    }
+
+    /// Writes every node reachable from the last committed root to `writer`, and returns
+    /// the number of nodes written.
+    ///
+    /// The stream holds `ETHTRIE` and a version byte, then each node, root first, as a
+    /// big endian `u32` length and the encoded node, then a zero length and the root hash.
+    /// I/O errors are reported as `TrieError::DB`.
+    pub fn export_to_writer<W: Write>(&self, mut writer: W) -> TrieResult<u64> {
+        let root = self.root_hash;
+        let empty_root: B256 = KECCAK_NULL_RLP.as_fixed_bytes().into();
+
+        writer.write_all(STREAM_MAGIC).map_err(TrieError::db)?;
+        writer.write_all(&[STREAM_VERSION]).map_err(TrieError::db)?;
+
+        let mut count = 0;
+        let mut write_node = |node: &[u8]| -> TrieResult<()> {
+            writer
+                .write_all(&(node.len() as u32).to_be_bytes())
+                .map_err(TrieError::db)?;
+            writer.write_all(node).map_err(TrieError::db)?;
+            count += 1;
+            Ok(())
+        };
+        if root == empty_root {
+            write_node(&[alloy_rlp::EMPTY_STRING_CODE])?;
+        }
+        for change in node_diff(&self.db, empty_root, root) {
+            let (_, _, encoded) = change?;
+            write_node(&encoded)?;
+        }
+
+        writer
+            .write_all(&0u32.to_be_bytes())
+            .map_err(TrieError::db)?;
+        writer.write_all(root.as_slice()).map_err(TrieError::db)?;
+        writer.flush().map_err(TrieError::db)?;
+        Ok(count)
+    }
+
+    /// Writes the nodes of a stream produced by `export_to_writer` into `db` as they are
+    /// read, and opens the trie at the root it ends with.
+    ///
+    /// Fails with `TrieError::InvalidData` if the stream is malformed or of another
+    /// version, and with `TrieError::InvalidStateRoot` if its first node does not hash to
+    /// the root at its end. Nodes read before an error are left in `db`.
+    pub fn import_from_reader<R: Read>(db: Arc<D>, mut reader: R) -> TrieResult<Self> {
+        let mut header = [0u8; STREAM_MAGIC.len() + 1];
+        read_stream(&mut reader, &mut header)?;
+        if &header[..STREAM_MAGIC.len()] != STREAM_MAGIC
+            || header[STREAM_MAGIC.len()] != STREAM_VERSION
+        {
+            return Err(TrieError::InvalidData);
+        }
+
+        let mut first = None;
+        let mut keys = Vec::with_capacity(WRITE_BATCH_SIZE);
+        let mut values = Vec::with_capacity(WRITE_BATCH_SIZE);
+        loop {
+            let mut len = [0u8; 4];
+            read_stream(&mut reader, &mut len)?;
+            let len = u32::from_be_bytes(len) as usize;
+            if len == 0 {
+                break;
+            }
+
+            let mut node = vec![0u8; len];
+            read_stream(&mut reader, &mut node)?;
+            let hash: B256 = keccak(&node).as_fixed_bytes().into();
+            first.get_or_insert(hash);
+            keys.push(hash.to_vec());
+            values.push(node);
+
+            if keys.len() == WRITE_BATCH_SIZE {
+                db.insert_batch(std::mem::take(&mut keys), std::mem::take(&mut values))
+                    .map_err(TrieError::db)?;
+            }
+        }
+
+        let mut root = B256::ZERO;
+        read_stream(&mut reader, root.as_mut_slice())?;
+        if first != Some(root) {
+            return Err(TrieError::InvalidStateRoot);
+        }
+
+        db.insert_batch(keys, values).map_err(TrieError::db)?;
+        db.flush().map_err(TrieError::db)?;
+
+        EthTrie::from(db, root)
+    }
+}
+
+impl<D> EthTrie<D>
+where
+    D: DB,
+{
+    /// Writes every node reachable from the last committed root into `target`, in
+    /// batches, and returns the number of nodes copied. `progress` is called after each
+    /// batch with the number of nodes copied so far. The stored leaf count of the root is
+    /// copied along with the nodes.
+    pub fn copy_to<T, F>(&self, target: Arc<T>, mut progress: F) -> TrieResult<u64>
+    where
+        T: DB,
+        F: FnMut(u64),
+    {
+        let root = self.root_hash;
+        let empty_root: B256 = KECCAK_NULL_RLP.as_fixed_bytes().into();
+
+        let mut copied = 0;
+        let mut keys = Vec::with_capacity(WRITE_BATCH_SIZE);
+        let mut values = Vec::with_capacity(WRITE_BATCH_SIZE);
+        if root == empty_root {
+            keys.push(root.to_vec());
+            values.push(vec![alloy_rlp::EMPTY_STRING_CODE]);
+        }
+        for change in node_diff(&self.db, empty_root, root) {
+            let (_, hash, encoded) = change?;
+            keys.push(hash.to_vec());
+            values.push(encoded);
+
+            if keys.len() == WRITE_BATCH_SIZE {
+                copied += keys.len() as u64;
+                target
+                    .insert_batch(std::mem::take(&mut keys), std::mem::take(&mut values))
+                    .map_err(TrieError::db)?;
+                progress(copied);
+            }
+        }
+        if !keys.is_empty() {
+            copied += keys.len() as u64;
+            target.insert_batch(keys, values).map_err(TrieError::db)?;
+            progress(copied);
+        }
+
+        let count_key = leaf_count_key(&root);
+        if let Some(count) = self.db.get(&count_key).map_err(TrieError::db)? {
+            target.insert(&count_key, count).map_err(TrieError::db)?;
+        }
+        target.flush().map_err(TrieError::db)?;
+        Ok(copied)
+    }
+
+    /// Writes the nodes of the uncommitted changes, along with the leaf count, to
+    /// `target` instead of the database of the trie, and returns the root they make up.
+    /// Nothing is written to or removed from the database of the trie, and the changes
+    /// stay uncommitted, so that `target` can collect a diff on top of a read-only
+    /// snapshot. The nodes that didn't change are only in the database of the trie.
+    pub fn commit_to<T>(&self, target: &T) -> TrieResult<B256>
+    where
+        T: DB,
+    {
+        let mut keys = vec![];
+        let mut values = vec![];
+        let encoded = encode_node(&self.root, &mut |hash, encoded| {
+            keys.push(hash.to_vec());
+            values.push(encoded);
+        });
+        let root: B256 = keccak(&encoded).as_fixed_bytes().into();
+        keys.push(root.to_vec());
+        values.push(encoded);
+        if let Some(count) = self.leaf_count {
+            keys.push(leaf_count_key(&root));
+            values.push((count as u64).to_be_bytes().to_vec());
+        }
+        target.insert_batch(keys, values).map_err(TrieError::db)?;
+        target.flush().map_err(TrieError::db)?;
+        Ok(root)
+    }
+
+    /// Commits the trie and rewrites the nodes reachable from its root into `target`,
+    /// which is expected to be empty, leaving behind the nodes only older roots refer to.
+    /// Returns the trie opened on `target`.
+    pub fn compact_into<T>(&mut self, target: Arc<T>) -> TrieResult<EthTrie<T>>
+    where
+        T: DB,
+    {
+        let root = self.root_hash()?;
+        self.copy_to(target.clone(), |_| {})?;
+        EthTrie::from(target, root)
+    }
+}
+
+// Fills `buf` from the stream, where running out of input means the stream is truncated.
+fn read_stream<R: Read>(reader: &mut R, buf: &mut [u8]) -> TrieResult<()> {
+    reader.read_exact(buf).map_err(|e| match e.kind() {
+        std::io::ErrorKind::UnexpectedEof => TrieError::InvalidData,
+        _ => TrieError::db(e),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use alloy_primitives::B256;
+
+    use crate::db::{IterableDB, MemoryDB, DB};
+    use crate::errors::TrieError;
+    use crate::trie::tests::random_trie;
+    use crate::trie::{EthTrie, TrieRead, TrieWrite};
+
+    fn sample_trie() -> EthTrie<MemoryDB> {
+        let memdb = Arc::new(MemoryDB::new(true));
+        let mut trie = EthTrie::new(memdb);
+        trie.insert(b"do", b"verb").unwrap();
+        trie.insert(b"dog", b"puppy").unwrap();
+        trie.insert(b"doge", b"coin").unwrap();
+        trie.insert(b"horse", b"stallion-with-a-value-long-enough-to-hash")
+            .unwrap();
+        trie.root_hash().unwrap();
+        trie
+    }
+
+    #[test]
+    fn test_export_import() {
+        let mut trie = sample_trie();
+        let export = trie.export().unwrap();
+        assert_eq!(export.root, trie.root_hash().unwrap());
+
+        let mut imported = EthTrie::import(Arc::new(MemoryDB::new(true)), &export).unwrap();
+        assert_eq!(imported.root_hash().unwrap(), export.root);
+        assert_eq!(
+            imported
+                .iter()
+                .map(|item| item.unwrap())
+                .collect::<Vec<_>>(),
+            trie.iter().map(|item| item.unwrap()).collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn test_export_import_empty() {
+        let mut trie = EthTrie::new(Arc::new(MemoryDB::new(true)));
+        trie.root_hash().unwrap();
+        let export = trie.export().unwrap();
+
+        let imported = EthTrie::import(Arc::new(MemoryDB::new(true)), &export).unwrap();
+        assert!(imported.is_empty().unwrap());
+    }
+
+    #[test]
+    fn test_import_missing_root() {
+        let mut export = sample_trie().export().unwrap();
+        export.root = B256::repeat_byte(1);
+
+        let result = EthTrie::import(Arc::new(MemoryDB::new(true)), &export);
+        assert_eq!(result.unwrap_err(), TrieError::InvalidStateRoot);
+    }
+
+    #[test]
+    fn test_export_import_stream() {
+        let (trie, kv) = random_trie(2000);
+        let mut stream = Vec::new();
+        let count = trie.export_to_writer(&mut stream).unwrap();
+        assert_eq!(count as usize, trie.export().unwrap().nodes.len());
+
+        let imported =
+            EthTrie::import_from_reader(Arc::new(MemoryDB::new(true)), stream.as_slice()).unwrap();
+        assert_eq!(imported.root_hash, trie.root_hash);
+        assert_eq!(
+            imported
+                .iter()
+                .map(|item| item.unwrap())
+                .collect::<Vec<_>>(),
+            kv.into_iter().collect::<Vec<_>>()
+        );
+
+        let mut empty = Vec::new();
+        EthTrie::new(Arc::new(MemoryDB::new(true)))
+            .export_to_writer(&mut empty)
+            .unwrap();
+        let imported =
+            EthTrie::import_from_reader(Arc::new(MemoryDB::new(true)), empty.as_slice()).unwrap();
+        assert!(imported.is_empty().unwrap());
+    }
+
+    #[test]
+    fn test_import_stream_invalid() {
+        let mut stream = Vec::new();
+        sample_trie().export_to_writer(&mut stream).unwrap();
+        let import = |stream: &[u8]| {
+            EthTrie::import_from_reader(Arc::new(MemoryDB::new(true)), stream).unwrap_err()
+        };
+
+        assert_eq!(import(&stream[..stream.len() - 1]), TrieError::InvalidData);
+        let mut version = stream.clone();
+        version[7] = 2;
+        assert_eq!(import(&version), TrieError::InvalidData);
+        let mut root = stream.clone();
+        *root.last_mut().unwrap() ^= 1;
+        assert_eq!(import(&root), TrieError::InvalidStateRoot);
+    }
+
+    #[test]
+    fn test_copy_to() {
+        let (trie, kv) = random_trie(3000);
+        let target = Arc::new(MemoryDB::new(true));
+        let mut reports = Vec::new();
+        let copied = trie
+            .copy_to(target.clone(), |done| reports.push(done))
+            .unwrap();
+        assert_eq!(copied as usize, trie.export().unwrap().nodes.len());
+        assert_eq!(reports.last(), Some(&copied));
+        assert!(reports.windows(2).all(|pair| pair[0] < pair[1]));
+
+        let copy = EthTrie::from(target, trie.root_hash).unwrap();
+        assert_eq!(copy.len().unwrap(), kv.len());
+        assert_eq!(
+            copy.iter().map(|item| item.unwrap()).collect::<Vec<_>>(),
+            kv.into_iter().collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn test_commit_to() {
+        let (mut trie, mut kv) = random_trie(500);
+        let source_len = trie.db.len().unwrap();
+        for key in kv.keys().step_by(3).cloned().collect::<Vec<_>>() {
+            trie.remove(&key).unwrap();
+            kv.remove(&key);
+        }
+        trie.insert(b"uncommitted", b"value").unwrap();
+        kv.insert(b"uncommitted".to_vec(), b"value".to_vec());
+
+        let staging = MemoryDB::new(true);
+        let root = trie.commit_to(&staging).unwrap();
+        assert_eq!(trie.db.len().unwrap(), source_len);
+        assert!(trie.is_dirty());
+
+        // The snapshot and the staged diff together hold the new trie.
+        let merged = Arc::new(MemoryDB::new(true));
+        trie.copy_to(merged.clone(), |_| {}).unwrap();
+        for key in staging.keys().unwrap() {
+            merged
+                .insert(&key, staging.get(&key).unwrap().unwrap())
+                .unwrap();
+        }
+        let staged = EthTrie::from(merged, root).unwrap();
+        assert_eq!(staged.len().unwrap(), kv.len());
+        assert_eq!(
+            staged.iter().map(|item| item.unwrap()).collect::<Vec<_>>(),
+            kv.into_iter().collect::<Vec<_>>()
+        );
+        assert_eq!(trie.root_hash().unwrap(), root);
+    }
+
+    #[test]
+    fn test_compact_into() {
+        let (mut trie, mut kv) = random_trie(500);
+        // Leave nodes of older roots behind in the database
+        for key in kv.keys().step_by(3).cloned().collect::<Vec<_>>() {
+            trie.remove(&key).unwrap();
+            kv.remove(&key);
+        }
+        trie.insert(b"uncommitted", b"value").unwrap();
+        kv.insert(b"uncommitted".to_vec(), b"value".to_vec());
+
+        let target = Arc::new(MemoryDB::new(true));
+        let compacted = trie.compact_into(target.clone()).unwrap();
+        assert_eq!(compacted.root_hash, trie.root_hash);
+        assert!(target.len().unwrap() < trie.db.len().unwrap());
+        assert_eq!(
+            compacted
+                .iter()
+                .map(|item| item.unwrap())
+                .collect::<Vec<_>>(),
+            kv.into_iter().collect::<Vec<_>>()
+        );
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_export_json_round_trip() {
+        use super::TrieExport;
+
+        let export = sample_trie().export().unwrap();
+        let json = serde_json::to_string(&export).unwrap();
+        let decoded: TrieExport = serde_json::from_str(&json).unwrap();
+        assert_eq!(decoded, export);
+    }
+}
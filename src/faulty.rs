@@ -0,0 +1,332 @@
+use std::collections::HashSet;
+use std::error::Error;
+use std::fmt;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::thread;
+use std::time::Duration;
+
+use parking_lot::Mutex;
+
+use crate::db::{IterableDB, DB};
+
+/// The failures a `FaultyDB` injects. The default injects none.
+#[derive(Debug, Clone, Default)]
+pub struct Faults {
+    /// The probability of an operation failing with `FaultyDBError::Injected`.
+    pub error_rate: f64,
+    /// The probability of a read finding nothing, whether or not the database holds the
+    /// key.
+    pub miss_rate: f64,
+    /// Keys that are always read as missing.
+    pub missing_keys: HashSet<Vec<u8>>,
+    /// How long each read waits before it is made.
+    pub read_latency: Duration,
+    /// Fails batch writes and removals of more than this many items after making the
+    /// first ones.
+    pub batch_fail_after: Option<usize>,
+}
+
+/// A database wrapper that injects failures into the operations made through it, to test
+/// how an application handles `TrieError::DB` and `TrieError::MissingTrieNode`.
+///
+/// Random failures are drawn from a generator seeded at creation, so the same sequence
+/// of operations fails the same way on every run. Enabled by the `test-utils` feature.
+#[derive(Debug)]
+pub struct FaultyDB<D>
+where
+    D: DB,
+{
+    db: D,
+    faults: Mutex<Faults>,
+    fail_next: AtomicU64,
+    rng: AtomicU64,
+    injected: AtomicU64,
+}
+
+/// The error of a `FaultyDB`: either an injected failure or an error of the inner
+/// database.
+#[derive(Debug)]
+pub enum FaultyDBError<E> {
+    Injected,
+    Inner(E),
+}
+
+impl<E> Error for FaultyDBError<E>
+where
+    E: Error + 'static,
+{
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            FaultyDBError::Injected => None,
+            FaultyDBError::Inner(err) => Some(err),
+        }
+    }
+}
+
+impl<E> fmt::Display for FaultyDBError<E>
+where
+    E: fmt::Display,
+{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            FaultyDBError::Injected => write!(f, "injected failure"),
+            FaultyDBError::Inner(err) => write!(f, "{}", err),
+        }
+    }
+}
+
+impl<D> FaultyDB<D>
+where
+    D: DB,
+{
+    /// Wraps a database, injecting no failures until they are set.
+    pub fn new(db: D, seed: u64) -> Self {
+        Self {
+            db,
+            faults: Mutex::new(Faults::default()),
+            fail_next: AtomicU64::new(0),
+            rng: AtomicU64::new(seed),
+            injected: AtomicU64::new(0),
+        }
+    }
+
+    pub fn with_faults(self, faults: Faults) -> Self {
+        *self.faults.lock() = faults;
+        self
+    }
+
+    /// Replaces the failures injected from now on.
+    pub fn set_faults(&self, faults: Faults) {
+        *self.faults.lock() = faults;
+    }
+
+    /// Fails the next `count` operations, whatever the failures set.
+    pub fn fail_next(&self, count: u64) {
+        self.fail_next.store(count, Ordering::Relaxed);
+    }
+
+    /// The number of failures injected so far, including missing reads.
+    pub fn injected(&self) -> u64 {
+        self.injected.load(Ordering::Relaxed)
+    }
+
+    pub fn inner(&self) -> &D {
+        &self.db
+    }
+
+    pub fn into_inner(self) -> D {
+        self.db
+    }
+
+    // Draws a number in [0, 1) from a splitmix64 generator.
+    fn draw(&self) -> f64 {
+        let mut z = self
+            .rng
+            .fetch_add(0x9e37_79b9_7f4a_7c15, Ordering::Relaxed)
+            .wrapping_add(0x9e37_79b9_7f4a_7c15);
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^= z >> 31;
+        (z >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    fn inject(&self) {
+        self.injected.fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn check(&self) -> Result<(), FaultyDBError<D::Error>> {
+        let forced = self
+            .fail_next
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |n| n.checked_sub(1))
+            .is_ok();
+        let error_rate = self.faults.lock().error_rate;
+        if forced || (error_rate > 0.0 && self.draw() < error_rate) {
+            self.inject();
+            return Err(FaultyDBError::Injected);
+        }
+        Ok(())
+    }
+
+    // Returns how many items of a batch of `len` are made before the batch fails, if it
+    // does.
+    fn batch_failure(&self, len: usize) -> Option<usize> {
+        let after = self
+            .faults
+            .lock()
+            .batch_fail_after
+            .filter(|after| len > *after)?;
+        self.inject();
+        Some(after)
+    }
+}
+
+impl<D> DB for FaultyDB<D>
+where
+    D: DB,
+{
+    type Error = FaultyDBError<D::Error>;
+
+    fn get(&self, key: &[u8]) -> Result<Option<Vec<u8>>, Self::Error> {
+        self.check()?;
+        let (latency, missing, miss_rate) = {
+            let faults = self.faults.lock();
+            (
+                faults.read_latency,
+                faults.missing_keys.contains(key),
+                faults.miss_rate,
+            )
+        };
+        if !latency.is_zero() {
+            thread::sleep(latency);
+        }
+        if missing || (miss_rate > 0.0 && self.draw() < miss_rate) {
+            self.inject();
+            return Ok(None);
+        }
+        self.db.get(key).map_err(FaultyDBError::Inner)
+    }
+
+    fn insert(&self, key: &[u8], value: Vec<u8>) -> Result<(), Self::Error> {
+        self.check()?;
+        self.db.insert(key, value).map_err(FaultyDBError::Inner)
+    }
+
+    fn remove(&self, key: &[u8]) -> Result<(), Self::Error> {
+        self.check()?;
+        self.db.remove(key).map_err(FaultyDBError::Inner)
+    }
+
+    fn insert_batch(
+        &self,
+        mut keys: Vec<Vec<u8>>,
+        mut values: Vec<Vec<u8>>,
+    ) -> Result<(), Self::Error> {
+        self.check()?;
+        match self.batch_failure(keys.len()) {
+            Some(made) => {
+                keys.truncate(made);
+                values.truncate(made);
+                self.db
+                    .insert_batch(keys, values)
+                    .map_err(FaultyDBError::Inner)?;
+                Err(FaultyDBError::Injected)
+            }
+            None => self
+                .db
+                .insert_batch(keys, values)
+                .map_err(FaultyDBError::Inner),
+        }
+    }
+
+    fn remove_batch(&self, keys: &[Vec<u8>]) -> Result<(), Self::Error> {
+        self.check()?;
+        match self.batch_failure(keys.len()) {
+            Some(made) => {
+                self.db
+                    .remove_batch(&keys[..made])
+                    .map_err(FaultyDBError::Inner)?;
+                Err(FaultyDBError::Injected)
+            }
+            None => self.db.remove_batch(keys).map_err(FaultyDBError::Inner),
+        }
+    }
+
+    fn flush(&self) -> Result<(), Self::Error> {
+        self.check()?;
+        self.db.flush().map_err(FaultyDBError::Inner)
+    }
+
+    #[cfg(test)]
+    fn len(&self) -> Result<usize, Self::Error> {
+        self.db.len().map_err(FaultyDBError::Inner)
+    }
+    #[cfg(test)]
+    fn is_empty(&self) -> Result<bool, Self::Error> {
+        self.db.is_empty().map_err(FaultyDBError::Inner)
+    }
+}
+
+impl<D> IterableDB for FaultyDB<D>
+where
+    D: IterableDB,
+{
+    fn keys(&self) -> Result<Vec<Vec<u8>>, Self::Error> {
+        self.check()?;
+        self.db.keys().map_err(FaultyDBError::Inner)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+    use std::sync::Arc;
+
+    use super::{Faults, FaultyDB};
+    use crate::db::{MemoryDB, DB};
+    use crate::errors::TrieError;
+    use crate::trie::{EthTrie, TrieRead, TrieWrite};
+
+    fn faulty_trie() -> (EthTrie<FaultyDB<MemoryDB>>, Vec<u8>) {
+        let db = Arc::new(FaultyDB::new(MemoryDB::new(true), 7));
+        let mut trie = EthTrie::new(db);
+        for i in 0..=255u8 {
+            trie.insert(&[i], &[i; 40]).unwrap();
+        }
+        let root = trie.root_hash().unwrap();
+        let (_, child) = trie.get_node(root).unwrap().unwrap().child_hashes()[4];
+        (trie, child.to_vec())
+    }
+
+    #[test]
+    fn test_faulty_db_errors() {
+        let (mut trie, _) = faulty_trie();
+        let root = trie.root_hash().unwrap();
+        let reopened = EthTrie::from(trie.db.clone(), root).unwrap();
+
+        trie.db.fail_next(1);
+        assert!(matches!(reopened.get(&[0x42]), Err(TrieError::DB(_))));
+        assert_eq!(reopened.get(&[0x42]).unwrap(), Some(vec![0x42; 40]));
+
+        // Every operation fails at a rate of one, and none at zero
+        trie.db.set_faults(Faults {
+            error_rate: 1.0,
+            ..Faults::default()
+        });
+        assert!(reopened.get(&[0x42]).is_err());
+        trie.db.set_faults(Faults::default());
+        assert!(reopened.get(&[0x42]).is_ok());
+        assert_eq!(trie.db.injected(), 2);
+    }
+
+    #[test]
+    fn test_faulty_db_missing_keys() {
+        let (trie, child) = faulty_trie();
+        trie.db.set_faults(Faults {
+            missing_keys: HashSet::from([child.clone()]),
+            ..Faults::default()
+        });
+        assert_eq!(trie.db.get(&child).unwrap(), None);
+
+        let root = trie.root_hash;
+        let reopened = EthTrie::from(trie.db.clone(), root).unwrap();
+        assert!(matches!(
+            reopened.get(&[0x42]),
+            Err(TrieError::MissingTrieNode { node_hash, .. }) if node_hash.as_slice() == child
+        ));
+        assert_eq!(reopened.get(&[0x52]).unwrap(), Some(vec![0x52; 40]));
+    }
+
+    #[test]
+    fn test_faulty_db_partial_batch() {
+        let db = Arc::new(FaultyDB::new(MemoryDB::new(true), 7).with_faults(Faults {
+            batch_fail_after: Some(3),
+            ..Faults::default()
+        }));
+        let mut trie = EthTrie::new(db.clone());
+        for i in 0..=255u8 {
+            trie.insert(&[i], &[i; 40]).unwrap();
+        }
+        assert!(matches!(trie.root_hash(), Err(TrieError::DB(_))));
+        assert_eq!(db.inner().len().unwrap(), 3);
+    }
+}
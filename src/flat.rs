@@ -0,0 +1,329 @@
+use std::sync::Arc;
+
+use alloy_primitives::B256;
+use hashbrown::HashMap;
+use keccak_hash::KECCAK_NULL_RLP;
+
+use crate::db::DB;
+use crate::diff::diff;
+use crate::errors::TrieError;
+use crate::trie::{
+    EthTrie, RootWithTrieDiff, TrieIterator, TrieRead, TrieResult, TrieWrite, HASHED_LENGTH,
+};
+
+// Entries are stored under this byte followed by their key.
+const FLAT_ENTRY_PREFIX: u8 = b'f';
+// The root the flat store holds the entries of is stored under this key.
+const FLAT_ROOT_KEY: &[u8] = b"FlatStateRoot";
+// Entries of the committed trie are copied into the flat store in batches of this many.
+const SYNC_BATCH_SIZE: usize = 10_000;
+
+/// Wraps an `EthTrie` and keeps a flat copy of its entries in another database, so that
+/// `get` reads one key from the flat store rather than a node at each level of the trie.
+/// The trie stays the source of the root hash, proofs and iteration.
+///
+/// The flat store records the root its entries are of. On creation it is brought up to
+/// the committed root of the trie: an empty store is filled with every entry, and one
+/// of an older root is updated with the changes since, which needs the nodes of that
+/// root. Uncommitted writes are kept in memory, and each commit writes them to the flat
+/// store in one `insert_batch` along with the new root, removed keys as empty values
+/// that are removed once the batch is written. With a database that writes batches
+/// atomically, a crash leaves the flat store at either the old root or the new one.
+///
+/// If writing the flat store fails, reads go to the trie until a later commit writes
+/// the changes it missed.
+#[derive(Debug)]
+pub struct FlatTrie<D, F>
+where
+    D: DB,
+    F: DB,
+{
+    trie: EthTrie<D>,
+    flat: Arc<F>,
+    // The uncommitted writes, with `None` for a removal.
+    pending: HashMap<Vec<u8>, Option<Vec<u8>>>,
+    synced: bool,
+}
+
+impl<D, F> FlatTrie<D, F>
+where
+    D: DB,
+    F: DB,
+{
+    /// Wraps a trie, bringing the flat store up to its committed root.
+    ///
+    /// Fails with `TrieError::MissingTrieNode` if the flat store is of a root whose
+    /// nodes are gone from the database of the trie, in which case it should be replaced
+    /// by an empty one.
+    pub fn new(trie: EthTrie<D>, flat: Arc<F>) -> TrieResult<Self> {
+        let mut pending = HashMap::new();
+        for change in trie.pending_changes() {
+            let (key, _, value) = change?;
+            pending.insert(key, value);
+        }
+        let mut flat_trie = Self {
+            trie,
+            flat,
+            pending,
+            synced: false,
+        };
+        flat_trie.sync()?;
+        Ok(flat_trie)
+    }
+
+    pub fn inner(&self) -> &EthTrie<D> {
+        &self.trie
+    }
+
+    pub fn into_inner(self) -> EthTrie<D> {
+        self.trie
+    }
+
+    /// Returns whether reads are served from the flat store.
+    pub fn is_synced(&self) -> bool {
+        self.synced
+    }
+
+    // Brings the flat store from the root it holds up to the committed root of the trie.
+    fn sync(&mut self) -> TrieResult<()> {
+        let empty_root: B256 = KECCAK_NULL_RLP.as_fixed_bytes().into();
+        let flat_root = match self.flat.get(FLAT_ROOT_KEY).map_err(TrieError::db)? {
+            Some(root) if root.len() == HASHED_LENGTH => B256::from_slice(&root),
+            Some(_) => return Err(TrieError::InvalidData),
+            None => empty_root,
+        };
+        let root = self.trie.root_hash;
+
+        // The root is only recorded with the last batch, so that an interrupted sync is
+        // made again from the start
+        if flat_root != root {
+            let mut changes = diff(&self.trie.db, flat_root, root).peekable();
+            loop {
+                let batch = changes
+                    .by_ref()
+                    .take(SYNC_BATCH_SIZE)
+                    .map(|change| change.map(|(key, _, value)| (key, value)))
+                    .collect::<TrieResult<Vec<_>>>()?;
+                let last = changes.peek().is_none();
+                self.write(batch, last.then_some(root))?;
+                if last {
+                    break;
+                }
+            }
+        }
+        self.synced = true;
+        Ok(())
+    }
+
+    // Writes changes to the flat store, along with the root they bring it to if given.
+    fn write<I>(&self, changes: I, root: Option<B256>) -> TrieResult<()>
+    where
+        I: IntoIterator<Item = (Vec<u8>, Option<Vec<u8>>)>,
+    {
+        let mut keys = vec![];
+        let mut values = vec![];
+        let mut removed = vec![];
+        for (key, value) in changes {
+            let key = flat_key(&key);
+            if value.is_none() {
+                removed.push(key.clone());
+            }
+            keys.push(key);
+            values.push(value.unwrap_or_default());
+        }
+        if let Some(root) = root {
+            keys.push(FLAT_ROOT_KEY.to_vec());
+            values.push(root.to_vec());
+        }
+        self.flat
+            .insert_batch(keys, values)
+            .map_err(TrieError::db)?;
+        self.flat.remove_batch(&removed).map_err(TrieError::db)?;
+        self.flat.flush().map_err(TrieError::db)
+    }
+
+    // Writes the changes of the commit that was just made to the flat store. If that
+    // fails, they are kept to be written with those of the next commit.
+    fn commit_flat(&mut self) -> TrieResult<()> {
+        let changes = self.pending.iter().map(|(k, v)| (k.clone(), v.clone()));
+        if let Err(err) = self.write(changes, Some(self.trie.root_hash)) {
+            self.synced = false;
+            return Err(err);
+        }
+        self.pending.clear();
+        self.synced = true;
+        Ok(())
+    }
+}
+
+impl<D, F> TrieRead<D> for FlatTrie<D, F>
+where
+    D: DB,
+    F: DB,
+{
+    type Iter<'a>
+        = TrieIterator<'a, D>
+    where
+        Self: 'a;
+
+    fn get(&self, key: &[u8]) -> TrieResult<Option<Vec<u8>>> {
+        if let Some(value) = self.pending.get(key) {
+            return Ok(value.clone());
+        }
+        if !self.synced {
+            return self.trie.get(key);
+        }
+        let value = self.flat.get(&flat_key(key)).map_err(TrieError::db)?;
+        Ok(value.filter(|value| !value.is_empty()))
+    }
+
+    fn get_with<R, G>(&self, key: &[u8], f: G) -> TrieResult<Option<R>>
+    where
+        G: FnOnce(&[u8]) -> R,
+    {
+        Ok(self.get(key)?.map(|value| f(&value)))
+    }
+
+    fn contains(&self, key: &[u8]) -> TrieResult<bool> {
+        Ok(self.get(key)?.is_some())
+    }
+
+    fn get_proof(&self, key: &[u8]) -> TrieResult<Vec<Vec<u8>>> {
+        self.trie.get_proof(key)
+    }
+
+    fn verify_proof(
+        &self,
+        root_hash: B256,
+        key: &[u8],
+        proof: Vec<Vec<u8>>,
+    ) -> TrieResult<Option<Vec<u8>>> {
+        self.trie.verify_proof(root_hash, key, proof)
+    }
+
+    fn iter(&self) -> Self::Iter<'_> {
+        self.trie.iter()
+    }
+
+    fn seek_iter<'a>(&'a self, iter: &mut Self::Iter<'a>, key: &[u8]) -> TrieResult<()> {
+        self.trie.seek_iter(iter, key)
+    }
+}
+
+impl<D, F> TrieWrite<D> for FlatTrie<D, F>
+where
+    D: DB,
+    F: DB,
+{
+    fn insert(&mut self, key: &[u8], value: &[u8]) -> TrieResult<()> {
+        self.trie.insert(key, value)?;
+        let value = Some(value.to_vec()).filter(|value| !value.is_empty());
+        self.pending.insert(key.to_vec(), value);
+        Ok(())
+    }
+
+    fn remove(&mut self, key: &[u8]) -> TrieResult<bool> {
+        let removed = self.trie.remove(key)?;
+        if removed {
+            self.pending.insert(key.to_vec(), None);
+        }
+        Ok(removed)
+    }
+
+    fn root_hash(&mut self) -> TrieResult<B256> {
+        let root = self.trie.root_hash()?;
+        self.commit_flat()?;
+        Ok(root)
+    }
+
+    fn root_hash_with_changed_nodes(&mut self) -> TrieResult<RootWithTrieDiff> {
+        let diff = self.trie.root_hash_with_changed_nodes()?;
+        self.commit_flat()?;
+        Ok(diff)
+    }
+
+    /// Clears the whole trie from the database, and its entries from the flat store.
+    fn clear_trie_from_db(&mut self) -> TrieResult<()> {
+        let empty_root: B256 = KECCAK_NULL_RLP.as_fixed_bytes().into();
+        let removed = diff(&self.trie.db, self.trie.root_hash, empty_root)
+            .map(|change| change.map(|(key, _, _)| (key, None)))
+            .collect::<TrieResult<Vec<_>>>()?;
+        self.trie.clear_trie_from_db()?;
+        self.pending = removed.into_iter().collect();
+        self.commit_flat()
+    }
+}
+
+fn flat_key(key: &[u8]) -> Vec<u8> {
+    [&[FLAT_ENTRY_PREFIX], key].concat()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::{flat_key, FlatTrie};
+    use crate::db::{MemoryDB, DB};
+    use crate::trie::tests::random_trie;
+    use crate::trie::{EthTrie, TrieRead, TrieWrite};
+
+    #[test]
+    fn test_flat_trie_reads() {
+        let (mut trie, kv) = random_trie(300);
+        trie.insert(b"uncommitted", b"value").unwrap();
+        let flat = Arc::new(MemoryDB::new(true));
+        let mut trie = FlatTrie::new(trie, flat.clone()).unwrap();
+        assert!(trie.is_synced());
+        for (key, value) in kv.iter() {
+            assert_eq!(flat.get(&flat_key(key)).unwrap().as_ref(), Some(value));
+            assert_eq!(trie.get(key).unwrap().as_ref(), Some(value));
+        }
+        assert_eq!(flat.get(&flat_key(b"uncommitted")).unwrap(), None);
+        assert_eq!(trie.get(b"uncommitted").unwrap(), Some(b"value".to_vec()));
+
+        // Commits carry writes and removals over to the flat store
+        let (first, second) = (kv.keys().next().unwrap(), kv.keys().nth(1).unwrap());
+        assert!(trie.remove(first).unwrap());
+        trie.insert(second, b"").unwrap();
+        assert_eq!(trie.get(first).unwrap(), None);
+        let root = trie.root_hash().unwrap();
+        for key in [first, second] {
+            assert_eq!(flat.get(&flat_key(key)).unwrap(), None);
+            assert_eq!(trie.get(key).unwrap(), None);
+        }
+        assert_eq!(
+            flat.get(&flat_key(b"uncommitted")).unwrap(),
+            Some(b"value".to_vec())
+        );
+
+        // Reopened at a later root, the flat store catches up with the changes since
+        let db = trie.inner().db.clone();
+        // The nodes of the root the flat store is at are needed to find the changes
+        let mut later = EthTrie::builder(db.clone())
+            .root(root)
+            .retain_stale_nodes(true)
+            .build()
+            .unwrap();
+        later.insert(b"later", b"value").unwrap();
+        later.remove(b"uncommitted").unwrap();
+        let root = later.root_hash().unwrap();
+        let trie = FlatTrie::new(EthTrie::from(db, root).unwrap(), flat.clone()).unwrap();
+        assert_eq!(
+            flat.get(&flat_key(b"later")).unwrap(),
+            Some(b"value".to_vec())
+        );
+        assert_eq!(trie.get(b"uncommitted").unwrap(), None);
+    }
+
+    #[test]
+    fn test_flat_trie_clear() {
+        let (trie, kv) = random_trie(50);
+        let flat = Arc::new(MemoryDB::new(true));
+        let mut trie = FlatTrie::new(trie, flat.clone()).unwrap();
+        trie.clear_trie_from_db().unwrap();
+        assert_eq!(flat.len().unwrap(), 1);
+        for key in kv.keys() {
+            assert_eq!(trie.get(key).unwrap(), None);
+        }
+    }
+}
@@ -0,0 +1,127 @@
+use std::ops::{Deref, DerefMut};
+
+use alloy_primitives::B256;
+
+use crate::db::DB;
+use crate::trie::{Checkpoint, EthTrie, TrieResult, TrieWrite};
+
+/// Groups a set of changes to a trie so that they are either committed together or not
+/// applied at all.
+///
+/// Created by `EthTrie::begin_update`. The trie is reached through the guard, and
+/// `finish` commits it. If the guard is dropped without a successful `finish`, every
+/// change made since `begin_update` and not yet committed is rolled back.
+pub struct CommitGuard<'a, D>
+where
+    D: DB,
+{
+    trie: &'a mut EthTrie<D>,
+    checkpoint: Option<Checkpoint>,
+}
+
+impl<'a, D> CommitGuard<'a, D>
+where
+    D: DB,
+{
+    /// Commits the trie and returns its new root hash. On error, the changes are rolled
+    /// back when the guard is dropped.
+    pub fn finish(mut self) -> TrieResult<B256> {
+        let root_hash = self.trie.root_hash()?;
+        // Committing drops all checkpoints, there is nothing left to revert.
+        self.checkpoint = None;
+        Ok(root_hash)
+    }
+}
+
+impl<D> Deref for CommitGuard<'_, D>
+where
+    D: DB,
+{
+    type Target = EthTrie<D>;
+
+    fn deref(&self) -> &EthTrie<D> {
+        self.trie
+    }
+}
+
+impl<D> DerefMut for CommitGuard<'_, D>
+where
+    D: DB,
+{
+    fn deref_mut(&mut self) -> &mut EthTrie<D> {
+        self.trie
+    }
+}
+
+impl<D> Drop for CommitGuard<'_, D>
+where
+    D: DB,
+{
+    fn drop(&mut self) {
+        // The checkpoint is gone if the trie was committed through the guard, in which
+        // case the changes are already part of the committed root.
+        if let Some(checkpoint) = self.checkpoint.take() {
+            if self.trie.has_checkpoint(checkpoint) {
+                self.trie.revert_to(checkpoint);
+            }
+        }
+    }
+}
+
+impl<D> EthTrie<D>
+where
+    D: DB,
+{
+    /// Starts a set of changes that is committed by `CommitGuard::finish`, or rolled back
+    /// if the guard is dropped first.
+    pub fn begin_update(&mut self) -> CommitGuard<'_, D> {
+        let checkpoint = self.checkpoint();
+        CommitGuard {
+            trie: self,
+            checkpoint: Some(checkpoint),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use crate::db::MemoryDB;
+    use crate::errors::TrieError;
+    use crate::trie::{EthTrie, TrieRead, TrieResult, TrieWrite};
+
+    #[test]
+    fn test_commit_guard_finish() {
+        let memdb = Arc::new(MemoryDB::new(true));
+        let mut trie = EthTrie::new(memdb);
+
+        let mut update = trie.begin_update();
+        update.insert(b"test", b"test").unwrap();
+        update.insert(b"test1", b"test1").unwrap();
+        let root = update.finish().unwrap();
+
+        assert_eq!(trie.root_hash().unwrap(), root);
+        assert_eq!(trie.get(b"test1").unwrap(), Some(b"test1".to_vec()));
+    }
+
+    #[test]
+    fn test_commit_guard_drop_reverts() {
+        let memdb = Arc::new(MemoryDB::new(true));
+        let mut trie = EthTrie::new(memdb);
+        trie.insert(b"test", b"test").unwrap();
+        let root = trie.root_hash().unwrap();
+
+        fn apply(trie: &mut EthTrie<MemoryDB>) -> TrieResult<()> {
+            let mut update = trie.begin_update();
+            update.insert(b"test", b"changed")?;
+            update.remove(b"test")?;
+            Err(TrieError::InvalidData)
+        }
+        assert!(apply(&mut trie).is_err());
+
+        assert!(!trie.is_dirty());
+        assert_eq!(trie.get(b"test").unwrap(), Some(b"test".to_vec()));
+        assert_eq!(trie.root_hash().unwrap(), root);
+    }
+}
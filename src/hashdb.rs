@@ -0,0 +1,288 @@
+//! Adaptors between this crate's `DB` and `hash_db::HashDB`, for projects built on the
+//! parity trie crates that want to use `EthTrie` with their storage, or the other way
+//! around.
+//!
+//! Both adaptors work with any `Hasher` whose output is 32 bytes long. `KeccakHasher` is
+//! the one Ethereum tries use, for projects that don't have one already.
+
+use std::collections::hash_map::DefaultHasher;
+use std::convert::Infallible;
+use std::fmt;
+
+use alloy_primitives::B256;
+use hash_db::{AsHashDB, HashDB, Hasher, Prefix, EMPTY_PREFIX};
+#[cfg(test)]
+use hashbrown::HashSet;
+use keccak_hash::keccak;
+use parking_lot::Mutex;
+
+use crate::db::DB;
+use crate::trie::HASHED_LENGTH;
+
+/// The keccak-256 `Hasher` of Ethereum tries.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct KeccakHasher;
+
+impl Hasher for KeccakHasher {
+    type Out = B256;
+    type StdHasher = DefaultHasher;
+    const LENGTH: usize = HASHED_LENGTH;
+
+    fn hash(x: &[u8]) -> B256 {
+        keccak(x).as_fixed_bytes().into()
+    }
+}
+
+/// A `DB` stored in a `HashDB`, so that an `EthTrie` can keep its nodes in it.
+///
+/// Nodes are stored under their hash, with an empty prefix, so the `HashDB` must not key
+/// its values by prefix. The few other keys the trie writes, such as leaf counts, are
+/// stored under the hash of the key. The `HashDB` counts references, and the wrapper
+/// doesn't: a value is inserted once however many times it is written, and removed by a
+/// single removal.
+pub struct HashDBAdaptor<H, T>
+where
+    H: Hasher,
+    T: HashDB<H, Vec<u8>>,
+{
+    db: Mutex<T>,
+    // A `HashDB` can't list its keys, so the keys written through the adaptor are kept
+    // to count them.
+    #[cfg(test)]
+    written: Mutex<HashSet<H::Out>>,
+    _hasher: std::marker::PhantomData<H>,
+}
+
+impl<H, T> fmt::Debug for HashDBAdaptor<H, T>
+where
+    H: Hasher,
+    T: HashDB<H, Vec<u8>>,
+{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("HashDBAdaptor").finish_non_exhaustive()
+    }
+}
+
+impl<H, T> HashDBAdaptor<H, T>
+where
+    H: Hasher,
+    T: HashDB<H, Vec<u8>>,
+{
+    pub fn new(db: T) -> Self {
+        assert_eq!(H::LENGTH, HASHED_LENGTH, "the hasher must output 32 bytes");
+        Self {
+            db: Mutex::new(db),
+            #[cfg(test)]
+            written: Mutex::new(HashSet::new()),
+            _hasher: std::marker::PhantomData,
+        }
+    }
+
+    pub fn into_inner(self) -> T {
+        self.db.into_inner()
+    }
+
+    // The `HashDB` key of a key of the trie.
+    fn hash_key(key: &[u8]) -> H::Out {
+        if key.len() != HASHED_LENGTH {
+            return H::hash(key);
+        }
+        let mut out = H::Out::default();
+        out.as_mut().copy_from_slice(key);
+        out
+    }
+}
+
+impl<H, T> DB for HashDBAdaptor<H, T>
+where
+    H: Hasher,
+    T: HashDB<H, Vec<u8>>,
+{
+    type Error = Infallible;
+
+    fn get(&self, key: &[u8]) -> Result<Option<Vec<u8>>, Self::Error> {
+        Ok(self.db.lock().get(&Self::hash_key(key), EMPTY_PREFIX))
+    }
+
+    fn insert(&self, key: &[u8], value: Vec<u8>) -> Result<(), Self::Error> {
+        let key = Self::hash_key(key);
+        #[cfg(test)]
+        self.written.lock().insert(key);
+        let mut db = self.db.lock();
+        // Values are overwritten, rather than counted as another reference
+        match db.get(&key, EMPTY_PREFIX) {
+            Some(stored) if stored == value => return Ok(()),
+            Some(_) => db.remove(&key, EMPTY_PREFIX),
+            None => {}
+        }
+        db.emplace(key, EMPTY_PREFIX, value);
+        Ok(())
+    }
+
+    fn remove(&self, key: &[u8]) -> Result<(), Self::Error> {
+        let key = Self::hash_key(key);
+        let mut db = self.db.lock();
+        if db.contains(&key, EMPTY_PREFIX) {
+            db.remove(&key, EMPTY_PREFIX);
+        }
+        #[cfg(test)]
+        self.written.lock().remove(&key);
+        Ok(())
+    }
+
+    fn flush(&self) -> Result<(), Self::Error> {
+        Ok(())
+    }
+
+    #[cfg(test)]
+    fn len(&self) -> Result<usize, Self::Error> {
+        Ok(self.written.lock().len())
+    }
+    #[cfg(test)]
+    fn is_empty(&self) -> Result<bool, Self::Error> {
+        Ok(self.written.lock().is_empty())
+    }
+}
+
+/// A `HashDB` stored in a `DB`, so that tries of the parity trie crates can keep their
+/// nodes in a database of this crate.
+///
+/// Values are stored under their hash; prefixes are ignored, so the values of tries
+/// relying on them to tell apart identical nodes are stored once. References are not
+/// counted: a value is removed by its first removal. `HashDB` methods can't fail, so
+/// errors of the database are logged, and reads failing return `None`.
+#[derive(Debug)]
+pub struct TrieHashDB<H, D>
+where
+    D: DB,
+{
+    db: D,
+    _hasher: std::marker::PhantomData<fn() -> H>,
+}
+
+impl<H, D> TrieHashDB<H, D>
+where
+    H: Hasher,
+    D: DB,
+{
+    pub fn new(db: D) -> Self {
+        assert_eq!(H::LENGTH, HASHED_LENGTH, "the hasher must output 32 bytes");
+        Self {
+            db,
+            _hasher: std::marker::PhantomData,
+        }
+    }
+
+    pub fn inner(&self) -> &D {
+        &self.db
+    }
+
+    pub fn into_inner(self) -> D {
+        self.db
+    }
+}
+
+impl<H, D> HashDB<H, Vec<u8>> for TrieHashDB<H, D>
+where
+    H: Hasher,
+    D: DB,
+{
+    fn get(&self, key: &H::Out, _prefix: Prefix) -> Option<Vec<u8>> {
+        self.db.get(key.as_ref()).unwrap_or_else(|err| {
+            log::warn!("failed to read {:?} from the database: {}", key, err);
+            None
+        })
+    }
+
+    fn contains(&self, key: &H::Out, prefix: Prefix) -> bool {
+        HashDB::get(self, key, prefix).is_some()
+    }
+
+    fn insert(&mut self, prefix: Prefix, value: &[u8]) -> H::Out {
+        let key = H::hash(value);
+        self.emplace(key, prefix, value.to_vec());
+        key
+    }
+
+    fn emplace(&mut self, key: H::Out, _prefix: Prefix, value: Vec<u8>) {
+        if let Err(err) = self.db.insert(key.as_ref(), value) {
+            log::warn!("failed to write {:?} to the database: {}", key, err);
+        }
+    }
+
+    fn remove(&mut self, key: &H::Out, _prefix: Prefix) {
+        if let Err(err) = self.db.remove(key.as_ref()) {
+            log::warn!("failed to remove {:?} from the database: {}", key, err);
+        }
+    }
+}
+
+impl<H, D> AsHashDB<H, Vec<u8>> for TrieHashDB<H, D>
+where
+    H: Hasher,
+    D: DB,
+{
+    fn as_hash_db(&self) -> &dyn HashDB<H, Vec<u8>> {
+        self
+    }
+
+    fn as_hash_db_mut<'a>(&'a mut self) -> &'a mut (dyn HashDB<H, Vec<u8>> + 'a) {
+        self
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use hash_db::{HashDB, Hasher, EMPTY_PREFIX};
+
+    use super::{HashDBAdaptor, KeccakHasher, TrieHashDB};
+    use crate::db::{MemoryDB, DB};
+    use crate::trie::{EthTrie, TrieRead, TrieWrite};
+
+    #[test]
+    fn test_hash_db_round_trip() {
+        // A trie stored in a `HashDB` stored in a `DB`
+        let hash_db = TrieHashDB::<KeccakHasher, _>::new(MemoryDB::new(true));
+        let db = Arc::new(HashDBAdaptor::new(hash_db));
+        let mut trie = EthTrie::new(db.clone());
+        for i in 0..100u8 {
+            trie.insert(&[i], &[i; 40]).unwrap();
+        }
+        let root = trie.root_hash().unwrap();
+        for i in 0..50u8 {
+            trie.insert(&[i], &[i + 1; 40]).unwrap();
+        }
+        let new_root = trie.root_hash().unwrap();
+
+        let reopened = EthTrie::from(db.clone(), new_root).unwrap();
+        for i in 0..100u8 {
+            let value = if i < 50 { i + 1 } else { i };
+            assert_eq!(reopened.get(&[i]).unwrap(), Some(vec![value; 40]));
+        }
+        // The previous root was pruned through both adaptors
+        let old = EthTrie::from(db.clone(), root).unwrap();
+        assert!(old.get(&[7]).is_err());
+
+        // The nodes are stored in the inner database under their hash
+        drop((trie, reopened, old));
+        let hash_db = Arc::try_unwrap(db).unwrap().into_inner();
+        let encoded = hash_db.inner().get(new_root.as_slice()).unwrap().unwrap();
+        assert_eq!(KeccakHasher::hash(&encoded), new_root);
+        assert_eq!(hash_db.get(&new_root, EMPTY_PREFIX), Some(encoded));
+    }
+
+    #[test]
+    fn test_hash_db_overwrites() {
+        let hash_db = TrieHashDB::<KeccakHasher, _>::new(MemoryDB::new(true));
+        let db = HashDBAdaptor::new(hash_db);
+        db.insert(b"meta", b"first".to_vec()).unwrap();
+        db.insert(b"meta", b"second".to_vec()).unwrap();
+        assert_eq!(db.get(b"meta").unwrap(), Some(b"second".to_vec()));
+        assert_eq!(db.len().unwrap(), 1);
+        db.remove(b"meta").unwrap();
+        assert_eq!(db.get(b"meta").unwrap(), None);
+        assert!(db.is_empty().unwrap());
+    }
+}
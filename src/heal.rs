@@ -0,0 +1,199 @@
+use alloy_primitives::B256;
+use keccak_hash::keccak;
+
+use crate::db::DB;
+use crate::errors::TrieError;
+use crate::nibbles::Nibbles;
+use crate::trie::{EthTrie, TrieResult};
+
+/// A node an operation needed but couldn't find, taken from a
+/// `TrieError::MissingTrieNode`, for fetching it from elsewhere.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HealRequest {
+    pub node_hash: B256,
+    /// The path of the node in the trie, if known.
+    pub path: Option<Nibbles>,
+    /// The root of the trie the node was looked up in, if known.
+    pub root: Option<B256>,
+    /// The key the failed operation was for, if any.
+    pub key: Option<Vec<u8>>,
+}
+
+impl HealRequest {
+    /// Returns the request for the node a `TrieError::MissingTrieNode` reports, or `None`
+    /// for other errors.
+    pub fn from_error(error: &TrieError) -> Option<Self> {
+        match error {
+            TrieError::MissingTrieNode {
+                node_hash,
+                traversed,
+                root_hash,
+                err_key,
+            } => Some(Self {
+                node_hash: *node_hash,
+                path: traversed.clone(),
+                root: *root_hash,
+                key: err_key.clone(),
+            }),
+            _ => None,
+        }
+    }
+
+    fn into_error(self) -> TrieError {
+        TrieError::MissingTrieNode {
+            node_hash: self.node_hash,
+            traversed: self.path,
+            root_hash: self.root,
+            err_key: self.key,
+        }
+    }
+}
+
+impl TryFrom<TrieError> for HealRequest {
+    type Error = TrieError;
+
+    /// Converts a `TrieError::MissingTrieNode`, giving back any other error.
+    fn try_from(error: TrieError) -> Result<Self, TrieError> {
+        Self::from_error(&error).ok_or(error)
+    }
+}
+
+impl TrieError {
+    /// Returns the heal request for a missing node error, or `None` for other errors.
+    pub fn heal_request(&self) -> Option<HealRequest> {
+        HealRequest::from_error(self)
+    }
+}
+
+impl<D> EthTrie<D>
+where
+    D: DB,
+{
+    /// Writes the node a heal request asks for to the database. Fails with
+    /// `TrieError::InvalidData` if `encoded` doesn't hash to the requested node.
+    pub fn supply_node(&self, request: &HealRequest, encoded: Vec<u8>) -> TrieResult<()> {
+        if keccak(&encoded).as_bytes() != request.node_hash.as_slice() {
+            return Err(TrieError::InvalidData);
+        }
+        self.db
+            .insert(request.node_hash.as_slice(), encoded)
+            .map_err(TrieError::db)
+    }
+
+    /// Runs `op`, and each time it fails on a missing node, supplies the node returned by
+    /// `fetch` and runs it again. Returns the error if `fetch` returns `None` or the
+    /// operation fails for another reason.
+    ///
+    /// Each attempt runs from a checkpoint that is reverted to when it fails on a missing
+    /// node, since a removal can fail after changing part of the trie.
+    pub fn retry_healing<T, F, R>(&mut self, mut op: F, mut fetch: R) -> TrieResult<T>
+    where
+        F: FnMut(&mut Self) -> TrieResult<T>,
+        R: FnMut(&HealRequest) -> Option<Vec<u8>>,
+    {
+        loop {
+            let checkpoint = self.checkpoint();
+            let error = match op(self) {
+                Ok(value) => {
+                    self.discard(checkpoint);
+                    return Ok(value);
+                }
+                Err(error) => error,
+            };
+            let request = HealRequest::try_from(error);
+            match request {
+                Ok(_) => self.revert_to(checkpoint),
+                Err(_) => self.discard(checkpoint),
+            }
+            let request = request?;
+            match fetch(&request) {
+                Some(encoded) => self.supply_node(&request, encoded)?,
+                None => return Err(request.into_error()),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::HealRequest;
+    use crate::db::{IterableDB, MemoryDB, DB};
+    use crate::errors::TrieError;
+    use crate::trie::{EthTrie, TrieRead, TrieWrite};
+
+    #[test]
+    fn test_heal_missing_nodes() {
+        let db = Arc::new(MemoryDB::new(true));
+        let mut trie = EthTrie::new(db.clone());
+        for i in 0..100u8 {
+            trie.insert(&[i, i], &[i; 40]).unwrap();
+        }
+        let root = trie.root_hash().unwrap();
+
+        // Moves every node but the root to a backup.
+        let backup = MemoryDB::new(true);
+        for key in db.keys().unwrap() {
+            if key.len() == 32 && key != root.as_slice() {
+                backup.insert(&key, db.get(&key).unwrap().unwrap()).unwrap();
+                db.remove(&key).unwrap();
+            }
+        }
+
+        let mut trie = EthTrie::from(db.clone(), root).unwrap();
+        let error = trie.get(&[5, 5]).unwrap_err();
+        let request = error.heal_request().unwrap();
+        assert_eq!(request.root, Some(root));
+        assert_eq!(request.key, Some(vec![5, 5]));
+        assert_eq!(
+            trie.supply_node(&request, vec![0xc0]),
+            Err(TrieError::InvalidData)
+        );
+        assert_eq!(
+            HealRequest::try_from(TrieError::InvalidProof),
+            Err(TrieError::InvalidProof)
+        );
+
+        let mut fetched = 0;
+        let value = trie
+            .retry_healing(
+                |trie| trie.get(&[5, 5]),
+                |request| {
+                    fetched += 1;
+                    backup.get(request.node_hash.as_slice()).unwrap()
+                },
+            )
+            .unwrap();
+        assert_eq!(value, Some(vec![5; 40]));
+        assert!(fetched > 0);
+
+        trie.retry_healing(
+            |trie| trie.insert(&[9, 9], &[0; 40]),
+            |request| backup.get(request.node_hash.as_slice()).unwrap(),
+        )
+        .unwrap();
+        assert_eq!(trie.get(&[9, 9]).unwrap(), Some(vec![0; 40]));
+
+        let removed = trie
+            .retry_healing(
+                |trie| trie.remove(&[7, 7]),
+                |request| backup.get(request.node_hash.as_slice()).unwrap(),
+            )
+            .unwrap();
+        assert!(removed);
+        assert_eq!(trie.get(&[7, 7]).unwrap(), None);
+
+        let mut expected = EthTrie::new(Arc::new(MemoryDB::new(true)));
+        for i in (0..100u8).filter(|i| *i != 7) {
+            expected.insert(&[i, i], &[i; 40]).unwrap();
+        }
+        expected.insert(&[9, 9], &[0; 40]).unwrap();
+        assert_eq!(trie.root_hash().unwrap(), expected.root_hash().unwrap());
+
+        let error = trie
+            .retry_healing(|trie| trie.get(&[50, 50]), |_| None)
+            .unwrap_err();
+        assert!(error.heal_request().is_some());
+    }
+}
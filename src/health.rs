@@ -0,0 +1,208 @@
+use std::fmt;
+
+use alloy_primitives::B256;
+use keccak_hash::KECCAK_NULL_RLP;
+
+use crate::db::DB;
+use crate::errors::TrieError;
+use crate::nibbles::Nibbles;
+use crate::node::{Node, NodeKind};
+use crate::trie::{EthTrie, TrieResult};
+
+/// What `EthTrie::health_check` found at the root of a trie.
+#[derive(Debug, PartialEq, Eq)]
+pub struct HealthReport {
+    /// The root that was checked.
+    pub root_hash: B256,
+    pub root: RootHealth,
+    /// The number of children of the root node that are stored apart from it.
+    pub children: usize,
+    /// The children of the root node missing from the database, with their path.
+    pub missing: Vec<(Nibbles, B256)>,
+    /// The children of the root node that are stored but don't decode, with their path
+    /// and the error.
+    pub corrupt: Vec<(Nibbles, B256, TrieError)>,
+}
+
+/// The state of the root node of a trie.
+#[derive(Debug, PartialEq, Eq)]
+pub enum RootHealth {
+    /// The root node decodes to a node of this kind.
+    Decoded(NodeKind),
+    /// The database doesn't hold the root node.
+    Missing,
+    /// The root node is stored but doesn't decode.
+    Corrupt(TrieError),
+}
+
+impl HealthReport {
+    /// Returns whether the root node and all its children were found and decode.
+    pub fn is_healthy(&self) -> bool {
+        matches!(self.root, RootHealth::Decoded(_))
+            && self.missing.is_empty()
+            && self.corrupt.is_empty()
+    }
+
+    /// Returns the report if the trie is healthy, or the error reading the first node
+    /// found missing or corrupt would have returned.
+    pub fn into_result(mut self) -> TrieResult<Self> {
+        match self.root {
+            RootHealth::Missing => return Err(TrieError::InvalidStateRoot),
+            RootHealth::Corrupt(err) => return Err(err),
+            RootHealth::Decoded(_) => {}
+        }
+        if let Some((path, hash)) = self.missing.first() {
+            return Err(TrieError::MissingTrieNode {
+                node_hash: *hash,
+                traversed: Some(path.clone()),
+                root_hash: Some(self.root_hash),
+                err_key: None,
+            });
+        }
+        if !self.corrupt.is_empty() {
+            return Err(self.corrupt.swap_remove(0).2);
+        }
+        Ok(self)
+    }
+}
+
+impl fmt::Display for HealthReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "root {:?}: ", self.root_hash)?;
+        match &self.root {
+            RootHealth::Missing => return write!(f, "root node missing"),
+            RootHealth::Corrupt(err) => return write!(f, "root node corrupt ({})", err),
+            RootHealth::Decoded(kind) => write!(f, "{:?} root", kind)?,
+        }
+        write!(
+            f,
+            ", {} of {} children missing, {} corrupt",
+            self.missing.len(),
+            self.children,
+            self.corrupt.len()
+        )
+    }
+}
+
+impl<D> EthTrie<D>
+where
+    D: DB,
+{
+    /// Checks that the committed root node of the trie is stored and decodes, and that so
+    /// do the nodes it refers to, asking the resolver for those missing from the database.
+    /// A database that doesn't hold the trie is reported at once, rather than as a
+    /// `TrieError::MissingTrieNode` from whichever read first reaches a missing node.
+    ///
+    /// Only errors from the database itself are returned as errors. Nodes further down are
+    /// not checked.
+    pub fn health_check(&self) -> TrieResult<HealthReport> {
+        let mut report = HealthReport {
+            root_hash: self.root_hash,
+            root: RootHealth::Decoded(NodeKind::Empty),
+            children: 0,
+            missing: vec![],
+            corrupt: vec![],
+        };
+        if self.root_hash.as_slice() == KECCAK_NULL_RLP.as_bytes() {
+            return Ok(report);
+        }
+
+        let reader = self.reader();
+        let root = match reader.recover(self.root_hash) {
+            Ok(Some(root)) => root,
+            Ok(None) => {
+                report.root = RootHealth::Missing;
+                return Ok(report);
+            }
+            Err(err @ TrieError::DB(_)) => return Err(err),
+            Err(err) => {
+                report.root = RootHealth::Corrupt(err);
+                return Ok(report);
+            }
+        };
+        report.root = RootHealth::Decoded(root.kind());
+
+        let children: Vec<(Nibbles, B256)> = match &root {
+            Node::Branch(_) => root
+                .child_hashes()
+                .into_iter()
+                .map(|(i, hash)| (Nibbles::from_hex(&[i]), hash))
+                .collect(),
+            Node::Extension(ext) => {
+                let ext = ext.read().clone();
+                match ext.node.hash() {
+                    Some(hash) => vec![(ext.prefix, hash)],
+                    None => vec![],
+                }
+            }
+            _ => vec![],
+        };
+        report.children = children.len();
+        for (path, hash) in children {
+            match reader.recover(hash) {
+                Ok(Some(_)) => {}
+                Ok(None) => report.missing.push((path, hash)),
+                Err(err @ TrieError::DB(_)) => return Err(err),
+                Err(err) => report.corrupt.push((path, hash, err)),
+            }
+        }
+        Ok(report)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use keccak_hash::KECCAK_NULL_RLP;
+
+    use super::RootHealth;
+    use crate::db::{MemoryDB, DB};
+    use crate::errors::TrieError;
+    use crate::node::NodeKind;
+    use crate::trie::{EthTrie, TrieWrite};
+
+    #[test]
+    fn test_health_check() {
+        let db = Arc::new(MemoryDB::new(true));
+        let mut trie = EthTrie::new(db.clone());
+        for i in 0..=255u8 {
+            trie.insert(&[i], &[i; 40]).unwrap();
+        }
+        let root = trie.root_hash().unwrap();
+        let report = trie.health_check().unwrap();
+        assert!(report.is_healthy());
+        assert_eq!(report.root, RootHealth::Decoded(NodeKind::Branch));
+        assert_eq!(report.children, 16);
+
+        let empty = EthTrie::new(Arc::new(MemoryDB::new(true)));
+        assert!(empty.health_check().unwrap().is_healthy());
+        assert_eq!(
+            empty.health_check().unwrap().root_hash.as_slice(),
+            KECCAK_NULL_RLP.as_bytes()
+        );
+
+        // A database missing a child of the root fails to open when checked
+        let (_, child) = trie.get_node(root).unwrap().unwrap().child_hashes()[3];
+        db.remove(child.as_slice()).unwrap();
+        let report = EthTrie::from(db.clone(), root)
+            .unwrap()
+            .health_check()
+            .unwrap();
+        assert!(!report.is_healthy());
+        assert_eq!(report.missing.len(), 1);
+        assert_eq!(report.missing[0].1, child);
+        let err = EthTrie::builder(db.clone())
+            .root(root)
+            .health_check(true)
+            .build()
+            .unwrap_err();
+        assert!(matches!(err, TrieError::MissingTrieNode { node_hash, .. } if node_hash == child));
+
+        // So does a corrupt one
+        db.insert(child.as_slice(), vec![0xc2, 0x01, 0x02]).unwrap();
+        let report = EthTrie::from(db, root).unwrap().health_check().unwrap();
+        assert_eq!(report.corrupt.len(), 1);
+        assert!(report.missing.is_empty());
+    }
+}
@@ -0,0 +1,260 @@
+use alloy_primitives::B256;
+
+use crate::db::DB;
+use crate::trie::{EthTrie, RootWithTrieDiff, TrieIterator, TrieRead, TrieResult, TrieWrite};
+
+/// A single write applied through a `JournaledTrie`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct JournalEntry {
+    pub key: Vec<u8>,
+    pub old_value: Option<Vec<u8>>,
+    pub new_value: Option<Vec<u8>>,
+}
+
+/// Wraps an `EthTrie` and records every write applied through it, so that writes can be
+/// undone in reverse order.
+///
+/// Writes are grouped in nested scopes, in the way EVM call frames nest: reverting a scope
+/// undoes the writes made since it was entered, exiting it keeps them as part of the
+/// enclosing scope. Since writes are undone by applying their inverse, a scope can still
+/// be reverted after the trie has been committed.
+#[derive(Debug)]
+pub struct JournaledTrie<D>
+where
+    D: DB,
+{
+    trie: EthTrie<D>,
+    journal: Vec<JournalEntry>,
+    // The journal length at the start of each open scope.
+    scopes: Vec<usize>,
+}
+
+impl<D> JournaledTrie<D>
+where
+    D: DB,
+{
+    pub fn new(trie: EthTrie<D>) -> Self {
+        Self {
+            trie,
+            journal: Vec::new(),
+            scopes: Vec::new(),
+        }
+    }
+
+    /// Returns the writes recorded so far, oldest first.
+    pub fn journal(&self) -> &[JournalEntry] {
+        &self.journal
+    }
+
+    /// Returns the number of open scopes.
+    pub fn depth(&self) -> usize {
+        self.scopes.len()
+    }
+
+    /// Opens a new scope.
+    pub fn enter_scope(&mut self) {
+        self.scopes.push(self.journal.len());
+    }
+
+    /// Closes the innermost scope, keeping its writes as part of the enclosing scope.
+    pub fn exit_scope(&mut self) {
+        self.scopes.pop().expect("No journal scope to exit");
+    }
+
+    /// Closes the innermost scope, undoing the writes made since it was entered.
+    pub fn revert_scope(&mut self) -> TrieResult<()> {
+        let start = self.scopes.pop().expect("No journal scope to revert");
+        self.undo_to(start)
+    }
+
+    /// Undoes every recorded write and closes all scopes.
+    pub fn revert_all(&mut self) -> TrieResult<()> {
+        self.scopes.clear();
+        self.undo_to(0)
+    }
+
+    /// Forgets the recorded writes, which can then no longer be undone.
+    pub fn clear_journal(&mut self) {
+        self.journal.clear();
+        self.scopes.clear();
+    }
+
+    pub fn inner(&self) -> &EthTrie<D> {
+        &self.trie
+    }
+
+    pub fn into_inner(self) -> EthTrie<D> {
+        self.trie
+    }
+
+    fn undo_to(&mut self, len: usize) -> TrieResult<()> {
+        while self.journal.len() > len {
+            let entry = self.journal.pop().unwrap();
+            match entry.old_value {
+                Some(value) => self.trie.insert(&entry.key, &value)?,
+                None => {
+                    self.trie.remove(&entry.key)?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+impl<D> TrieRead<D> for JournaledTrie<D>
+where
+    D: DB,
+{
+    type Iter<'a>
+        = TrieIterator<'a, D>
+    where
+        Self: 'a;
+
+    fn get(&self, key: &[u8]) -> TrieResult<Option<Vec<u8>>> {
+        self.trie.get(key)
+    }
+
+    fn get_with<R, F>(&self, key: &[u8], f: F) -> TrieResult<Option<R>>
+    where
+        F: FnOnce(&[u8]) -> R,
+    {
+        self.trie.get_with(key, f)
+    }
+
+    fn contains(&self, key: &[u8]) -> TrieResult<bool> {
+        self.trie.contains(key)
+    }
+
+    fn get_proof(&self, key: &[u8]) -> TrieResult<Vec<Vec<u8>>> {
+        self.trie.get_proof(key)
+    }
+
+    fn verify_proof(
+        &self,
+        root_hash: B256,
+        key: &[u8],
+        proof: Vec<Vec<u8>>,
+    ) -> TrieResult<Option<Vec<u8>>> {
+        self.trie.verify_proof(root_hash, key, proof)
+    }
+
+    fn iter(&self) -> Self::Iter<'_> {
+        self.trie.iter()
+    }
+
+    fn seek_iter<'a>(&'a self, iter: &mut Self::Iter<'a>, key: &[u8]) -> TrieResult<()> {
+        self.trie.seek_iter(iter, key)
+    }
+}
+
+impl<D> TrieWrite<D> for JournaledTrie<D>
+where
+    D: DB,
+{
+    fn insert(&mut self, key: &[u8], value: &[u8]) -> TrieResult<()> {
+        let old_value = self.trie.get(key)?;
+        self.trie.insert(key, value)?;
+        self.journal.push(JournalEntry {
+            key: key.to_vec(),
+            old_value,
+            new_value: (!value.is_empty()).then(|| value.to_vec()),
+        });
+        Ok(())
+    }
+
+    fn remove(&mut self, key: &[u8]) -> TrieResult<bool> {
+        let old_value = self.trie.get(key)?;
+        let removed = self.trie.remove(key)?;
+        self.journal.push(JournalEntry {
+            key: key.to_vec(),
+            old_value,
+            new_value: None,
+        });
+        Ok(removed)
+    }
+
+    fn root_hash(&mut self) -> TrieResult<B256> {
+        self.trie.root_hash()
+    }
+
+    fn root_hash_with_changed_nodes(&mut self) -> TrieResult<RootWithTrieDiff> {
+        self.trie.root_hash_with_changed_nodes()
+    }
+
+    /// Clears the whole trie from the database, along with the journal, since the removed
+    /// nodes can't be brought back.
+    fn clear_trie_from_db(&mut self) -> TrieResult<()> {
+        self.clear_journal();
+        self.trie.clear_trie_from_db()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::{JournalEntry, JournaledTrie};
+    use crate::db::MemoryDB;
+    use crate::trie::{EthTrie, TrieRead, TrieWrite};
+
+    #[test]
+    fn test_journal_records_writes() {
+        let memdb = Arc::new(MemoryDB::new(true));
+        let mut trie = JournaledTrie::new(EthTrie::new(memdb));
+        trie.insert(b"test", b"test").unwrap();
+        trie.insert(b"test", b"test2").unwrap();
+        trie.remove(b"test").unwrap();
+
+        assert_eq!(
+            trie.journal(),
+            &[
+                JournalEntry {
+                    key: b"test".to_vec(),
+                    old_value: None,
+                    new_value: Some(b"test".to_vec()),
+                },
+                JournalEntry {
+                    key: b"test".to_vec(),
+                    old_value: Some(b"test".to_vec()),
+                    new_value: Some(b"test2".to_vec()),
+                },
+                JournalEntry {
+                    key: b"test".to_vec(),
+                    old_value: Some(b"test2".to_vec()),
+                    new_value: None,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_journal_nested_scopes() {
+        let memdb = Arc::new(MemoryDB::new(true));
+        let mut trie = JournaledTrie::new(EthTrie::new(memdb));
+        trie.insert(b"test", b"test").unwrap();
+        let root = trie.root_hash().unwrap();
+
+        trie.enter_scope();
+        trie.insert(b"test1", b"test").unwrap();
+
+        trie.enter_scope();
+        trie.insert(b"test", b"changed").unwrap();
+        trie.insert(b"test2", b"test").unwrap();
+        trie.revert_scope().unwrap();
+        assert_eq!(trie.get(b"test").unwrap(), Some(b"test".to_vec()));
+        assert_eq!(trie.get(b"test2").unwrap(), None);
+
+        trie.enter_scope();
+        trie.remove(b"test").unwrap();
+        trie.exit_scope();
+        assert_eq!(trie.depth(), 1);
+
+        // Committing doesn't prevent the outer scope from being reverted
+        trie.root_hash().unwrap();
+        trie.revert_scope().unwrap();
+        assert_eq!(trie.get(b"test").unwrap(), Some(b"test".to_vec()));
+        assert_eq!(trie.get(b"test1").unwrap(), None);
+        assert_eq!(trie.root_hash().unwrap(), root);
+        assert_eq!(trie.journal().len(), 1);
+    }
+}
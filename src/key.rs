@@ -0,0 +1,131 @@
+use alloy_primitives::{Address, B256, U256};
+use keccak_hash::keccak;
+
+/// A type that can be turned into the key bytes a consensus trie stores it under.
+///
+/// - `Address` keys the state trie by the hash of the address.
+/// - `U256` keys a storage trie by the hash of the slot, as a 32 byte big-endian word.
+/// - `u64` keys a transaction or receipt trie by the RLP encoding of the index.
+/// - `B256` and byte slices are used as they are, so a `B256` is taken to be an
+///   already hashed key.
+///
+/// The two integer keys are encoded differently on purpose, as the consensus tries they
+/// stand for are: a `U256` is a storage slot, hashed as a full word, while a `u64` is an
+/// index, RLP encoded and not hashed. To key a storage trie by a small slot number, or a
+/// transaction trie by a `U256` index, convert it to the other type first.
+///
+/// `TypedTrie` takes its keys through this trait, and every `TrieRead` and `TrieWrite`
+/// has `get_keyed`, `contains_keyed`, `get_proof_keyed`, `insert_keyed` and
+/// `remove_keyed`, which take them in place of the raw bytes.
+pub trait TrieKey {
+    fn trie_key(&self) -> Vec<u8>;
+}
+
+impl TrieKey for [u8] {
+    fn trie_key(&self) -> Vec<u8> {
+        self.to_vec()
+    }
+}
+
+impl<const N: usize> TrieKey for [u8; N] {
+    fn trie_key(&self) -> Vec<u8> {
+        self.to_vec()
+    }
+}
+
+impl TrieKey for Vec<u8> {
+    fn trie_key(&self) -> Vec<u8> {
+        self.clone()
+    }
+}
+
+impl TrieKey for B256 {
+    fn trie_key(&self) -> Vec<u8> {
+        self.to_vec()
+    }
+}
+
+impl TrieKey for Address {
+    fn trie_key(&self) -> Vec<u8> {
+        keccak(self).as_bytes().to_vec()
+    }
+}
+
+impl TrieKey for U256 {
+    fn trie_key(&self) -> Vec<u8> {
+        keccak(self.to_be_bytes::<32>()).as_bytes().to_vec()
+    }
+}
+
+impl TrieKey for u64 {
+    fn trie_key(&self) -> Vec<u8> {
+        alloy_rlp::encode(self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use alloy_primitives::{address, b256, hex, Address, B256, U256};
+
+    use super::TrieKey;
+    use crate::db::MemoryDB;
+    use crate::trie::{EthTrie, TrieRead, TrieWrite};
+
+    #[test]
+    fn test_index_keys() {
+        assert_eq!(0u64.trie_key(), vec![0x80]);
+        assert_eq!(1u64.trie_key(), vec![0x01]);
+        assert_eq!(127u64.trie_key(), vec![0x7f]);
+        assert_eq!(128u64.trie_key(), vec![0x81, 0x80]);
+        assert_eq!(1024u64.trie_key(), vec![0x82, 0x04, 0x00]);
+    }
+
+    #[test]
+    fn test_hashed_keys() {
+        let address = address!("0000000000000000000000000000000000000000");
+        assert_eq!(
+            address.trie_key(),
+            hex!("5380c7b7ae81a58eb98d9c78de4a1fd7fd9535fc953ed2be602daaa41767312a")
+        );
+        assert_eq!(
+            U256::ZERO.trie_key(),
+            hex!("290decd9548b62a8d60345a988386fc84ba6bc95484008f6362f93160ef3e563")
+        );
+
+        let hashed = b256!("290decd9548b62a8d60345a988386fc84ba6bc95484008f6362f93160ef3e563");
+        assert_eq!(hashed.trie_key(), hashed.to_vec());
+        assert_eq!(B256::ZERO.trie_key(), vec![0; 32]);
+        assert_eq!(b"key".trie_key(), b"key".to_vec());
+        assert_eq!(Address::ZERO.trie_key(), address.trie_key());
+    }
+
+    #[test]
+    fn test_keyed_trie_methods() {
+        let mut trie = EthTrie::new(Arc::new(MemoryDB::new(true)));
+        let address = address!("5df9b87991262f6ba471f09758cde1c0fc1de734");
+        trie.insert_keyed(&address, b"account").unwrap();
+        trie.insert_keyed(&7u64, b"transaction").unwrap();
+        assert_eq!(
+            trie.get(&address.trie_key()).unwrap(),
+            Some(b"account".to_vec())
+        );
+        assert_eq!(trie.get(&[0x07]).unwrap(), Some(b"transaction".to_vec()));
+        assert!(trie.contains_keyed(&address).unwrap());
+
+        // The same number keys a slot and an index differently
+        assert!(!trie.contains_keyed(&U256::from(7)).unwrap());
+        assert_eq!(
+            trie.get_keyed(&7u64).unwrap(),
+            Some(b"transaction".to_vec())
+        );
+
+        let root = trie.root_hash().unwrap();
+        let proof = trie.get_proof_keyed(&address).unwrap();
+        let value = trie.verify_proof(root, &address.trie_key(), proof).unwrap();
+        assert_eq!(value, Some(b"account".to_vec()));
+        assert!(trie.remove_keyed(&7u64).unwrap());
+        assert_eq!(trie.get_keyed(&7u64).unwrap(), None);
+    }
+}
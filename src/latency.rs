@@ -0,0 +1,271 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use parking_lot::Mutex;
+
+use crate::db::{IterableDB, DB};
+
+/// A database wrapper that makes every operation take as long as it would on a slower
+/// store, to benchmark trie algorithms under disk or network costs rather than at the
+/// speed of a `MemoryDB`.
+///
+/// Each read waits for the read latency, and each write or batch for the write latency.
+/// Operations made from several threads wait at the same time, as requests to a disk or
+/// a remote store do, but share the throughput limits: the bytes moved and the reads
+/// made are queued so that neither rate is ever exceeded. Enabled by the `test-utils`
+/// feature.
+///
+/// ```
+/// use std::sync::Arc;
+/// use std::time::Duration;
+///
+/// use eth_trie::{EthTrie, LatencyDB, MemoryDB, TrieWrite};
+///
+/// let db = LatencyDB::new(MemoryDB::new(true))
+///     .read_latency(Duration::from_micros(100))
+///     .bytes_per_sec(50_000_000);
+/// let mut trie = EthTrie::new(Arc::new(db));
+/// trie.insert(b"test", b"test").unwrap();
+/// trie.root_hash().unwrap();
+/// ```
+#[derive(Debug)]
+pub struct LatencyDB<D>
+where
+    D: DB,
+{
+    db: D,
+    read_latency: Duration,
+    write_latency: Duration,
+    bytes: Option<Throttle>,
+    reads_limit: Option<Throttle>,
+    reads: AtomicU64,
+    bytes_read: AtomicU64,
+    bytes_written: AtomicU64,
+}
+
+// Queues uses of a resource of limited rate, each holding it for a time.
+#[derive(Debug)]
+struct Throttle {
+    // The time a unit of use holds the resource for.
+    per_unit: Duration,
+    // When the resource is free again.
+    free_at: Mutex<Option<Instant>>,
+}
+
+impl Throttle {
+    fn new(per_sec: u64) -> Self {
+        Self {
+            per_unit: Duration::from_secs(1) / per_sec.clamp(1, u32::MAX as u64) as u32,
+            free_at: Mutex::new(None),
+        }
+    }
+
+    // Queues a use of `units`, returning when it is done.
+    fn take(&self, units: u64) -> Instant {
+        let now = Instant::now();
+        let mut free_at = self.free_at.lock();
+        let start = free_at.filter(|at| *at > now).unwrap_or(now);
+        let done = start + self.per_unit * units.min(u32::MAX as u64) as u32;
+        *free_at = Some(done);
+        done
+    }
+}
+
+impl<D> LatencyDB<D>
+where
+    D: DB,
+{
+    /// Wraps a database without adding any cost until one is set.
+    pub fn new(db: D) -> Self {
+        Self {
+            db,
+            read_latency: Duration::ZERO,
+            write_latency: Duration::ZERO,
+            bytes: None,
+            reads_limit: None,
+            reads: AtomicU64::new(0),
+            bytes_read: AtomicU64::new(0),
+            bytes_written: AtomicU64::new(0),
+        }
+    }
+
+    pub fn read_latency(mut self, latency: Duration) -> Self {
+        self.read_latency = latency;
+        self
+    }
+
+    /// Sets the latency of each write, batch and flush.
+    pub fn write_latency(mut self, latency: Duration) -> Self {
+        self.write_latency = latency;
+        self
+    }
+
+    /// Limits the bytes read and written per second, keys and values together.
+    pub fn bytes_per_sec(mut self, bytes: u64) -> Self {
+        self.bytes = Some(Throttle::new(bytes));
+        self
+    }
+
+    /// Limits the reads made per second.
+    pub fn reads_per_sec(mut self, reads: u64) -> Self {
+        self.reads_limit = Some(Throttle::new(reads));
+        self
+    }
+
+    /// The number of reads made so far.
+    pub fn reads(&self) -> u64 {
+        self.reads.load(Ordering::Relaxed)
+    }
+
+    /// The number of bytes read so far, keys and values together.
+    pub fn bytes_read(&self) -> u64 {
+        self.bytes_read.load(Ordering::Relaxed)
+    }
+
+    /// The number of bytes written so far, keys and values together.
+    pub fn bytes_written(&self) -> u64 {
+        self.bytes_written.load(Ordering::Relaxed)
+    }
+
+    pub fn inner(&self) -> &D {
+        &self.db
+    }
+
+    pub fn into_inner(self) -> D {
+        self.db
+    }
+
+    // Waits until an operation moving `bytes` with the given latency is done.
+    fn wait(&self, latency: Duration, bytes: u64, read: bool) {
+        let mut done = Instant::now() + latency;
+        if let Some(throttle) = self.bytes.as_ref() {
+            done = done.max(throttle.take(bytes));
+        }
+        if let Some(throttle) = self.reads_limit.as_ref().filter(|_| read) {
+            done = done.max(throttle.take(1));
+        }
+        let now = Instant::now();
+        if done > now {
+            thread::sleep(done - now);
+        }
+    }
+
+    fn written(&self, bytes: usize) {
+        self.bytes_written
+            .fetch_add(bytes as u64, Ordering::Relaxed);
+        self.wait(self.write_latency, bytes as u64, false);
+    }
+}
+
+impl<D> DB for LatencyDB<D>
+where
+    D: DB,
+{
+    type Error = D::Error;
+
+    fn get(&self, key: &[u8]) -> Result<Option<Vec<u8>>, Self::Error> {
+        let value = self.db.get(key)?;
+        let bytes = (key.len() + value.as_ref().map_or(0, Vec::len)) as u64;
+        self.reads.fetch_add(1, Ordering::Relaxed);
+        self.bytes_read.fetch_add(bytes, Ordering::Relaxed);
+        self.wait(self.read_latency, bytes, true);
+        Ok(value)
+    }
+
+    fn insert(&self, key: &[u8], value: Vec<u8>) -> Result<(), Self::Error> {
+        self.written(key.len() + value.len());
+        self.db.insert(key, value)
+    }
+
+    fn remove(&self, key: &[u8]) -> Result<(), Self::Error> {
+        self.written(key.len());
+        self.db.remove(key)
+    }
+
+    fn insert_batch(&self, keys: Vec<Vec<u8>>, values: Vec<Vec<u8>>) -> Result<(), Self::Error> {
+        let bytes = keys.iter().chain(values.iter()).map(Vec::len).sum();
+        self.written(bytes);
+        self.db.insert_batch(keys, values)
+    }
+
+    fn remove_batch(&self, keys: &[Vec<u8>]) -> Result<(), Self::Error> {
+        self.written(keys.iter().map(Vec::len).sum());
+        self.db.remove_batch(keys)
+    }
+
+    fn flush(&self) -> Result<(), Self::Error> {
+        self.written(0);
+        self.db.flush()
+    }
+
+    #[cfg(test)]
+    fn len(&self) -> Result<usize, Self::Error> {
+        self.db.len()
+    }
+    #[cfg(test)]
+    fn is_empty(&self) -> Result<bool, Self::Error> {
+        self.db.is_empty()
+    }
+}
+
+impl<D> IterableDB for LatencyDB<D>
+where
+    D: IterableDB,
+{
+    fn keys(&self) -> Result<Vec<Vec<u8>>, Self::Error> {
+        self.db.keys()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use std::time::{Duration, Instant};
+
+    use super::LatencyDB;
+    use crate::db::{MemoryDB, DB};
+    use crate::trie::{EthTrie, TrieRead, TrieWrite};
+
+    #[test]
+    fn test_latency_db_read_latency() {
+        let db =
+            Arc::new(LatencyDB::new(MemoryDB::new(true)).read_latency(Duration::from_millis(2)));
+        let mut trie = EthTrie::new(db.clone());
+        for i in 0..=255u8 {
+            trie.insert(&[i], &[i; 40]).unwrap();
+        }
+        let root = trie.root_hash().unwrap();
+
+        let reopened = EthTrie::from(db.clone(), root).unwrap();
+        let before = db.reads();
+        let start = Instant::now();
+        assert_eq!(reopened.get(&[0x42]).unwrap(), Some(vec![0x42; 40]));
+        let reads = (db.reads() - before) as u32;
+        assert!(reads > 0);
+        assert!(start.elapsed() >= Duration::from_millis(2) * reads);
+        assert!(db.bytes_read() > 40);
+    }
+
+    #[test]
+    fn test_latency_db_throughput() {
+        // Ten reads of 100 bytes at 20 000 bytes per second take 50ms
+        let db = LatencyDB::new(MemoryDB::new(true)).bytes_per_sec(20_000);
+        db.insert(&[1; 20], vec![1; 80]).unwrap();
+        assert_eq!(db.bytes_written(), 100);
+        let start = Instant::now();
+        for _ in 0..10 {
+            db.get(&[1; 20]).unwrap();
+        }
+        assert!(start.elapsed() >= Duration::from_millis(50));
+        assert_eq!(db.bytes_read(), 1000);
+
+        // So do ten reads at 200 reads per second, whatever their size
+        let db = LatencyDB::new(MemoryDB::new(true)).reads_per_sec(200);
+        let start = Instant::now();
+        for _ in 0..10 {
+            db.get(&[1; 20]).unwrap();
+        }
+        assert!(start.elapsed() >= Duration::from_millis(45));
+    }
+}
@@ -0,0 +1,126 @@
+use crate::db::DB;
+use crate::node::Node;
+use crate::trie::{TrieIterator, TrieResult};
+
+/// An iterator whose items may borrow from the iterator itself, so that each item is only
+/// valid until the next call to `next`.
+pub trait LendingIterator {
+    type Item<'b>
+    where
+        Self: 'b;
+
+    fn next(&mut self) -> Option<Self::Item<'_>>;
+}
+
+/// Iterates over the entries of a trie in key order like `TrieIterator`, but lends out
+/// each key and value instead of allocating them.
+///
+/// Keys and the values of branch nodes are written into buffers that are reused from one
+/// entry to the next. The values of leaves are borrowed from the node itself.
+pub struct TrieLendingIterator<'a, D>
+where
+    D: DB,
+{
+    inner: TrieIterator<'a, D>,
+    key: Vec<u8>,
+    value: Vec<u8>,
+    // The node holding the current entry.
+    current: Node,
+}
+
+impl<'a, D> TrieLendingIterator<'a, D>
+where
+    D: DB,
+{
+    /// Repositions the iterator so that the next entry lent out is the first one whose
+    /// key is greater than or equal to `key`.
+    pub fn seek(&mut self, key: &[u8]) -> TrieResult<()> {
+        self.inner.seek(key)
+    }
+}
+
+impl<'a, D> LendingIterator for TrieLendingIterator<'a, D>
+where
+    D: DB,
+{
+    type Item<'b>
+        = TrieResult<(&'b [u8], &'b [u8])>
+    where
+        Self: 'b;
+
+    fn next(&mut self) -> Option<Self::Item<'_>> {
+        self.current = match self.inner.step()? {
+            Ok(node) => node,
+            Err(e) => return Some(Err(e)),
+        };
+
+        self.key.clear();
+        let nibbles = self.inner.nibble().get_data();
+        let nibbles = match nibbles.last() {
+            Some(16) => &nibbles[..nibbles.len() - 1],
+            _ => nibbles,
+        };
+        self.key
+            .extend(nibbles.chunks_exact(2).map(|pair| pair[0] * 16 + pair[1]));
+
+        let value = match &self.current {
+            Node::Leaf(leaf) => leaf.value.as_slice(),
+            Node::Branch(branch) => {
+                self.value.clear();
+                self.value
+                    .extend_from_slice(branch.read().value.as_ref().unwrap());
+                self.value.as_slice()
+            }
+            _ => unreachable!(),
+        };
+        Some(Ok((self.key.as_slice(), value)))
+    }
+}
+
+impl<'a, D> TrieIterator<'a, D>
+where
+    D: DB,
+{
+    /// Turns the iterator into one that lends out its entries instead of allocating them.
+    pub fn lending(self) -> TrieLendingIterator<'a, D> {
+        TrieLendingIterator {
+            inner: self,
+            key: Vec::new(),
+            value: Vec::new(),
+            current: Node::Empty,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::LendingIterator;
+    use crate::trie::tests::random_trie;
+    use crate::trie::TrieRead;
+
+    #[test]
+    fn test_lending_iterator() {
+        let (trie, kv) = random_trie(300);
+
+        let mut entries = kv.iter();
+        let mut iter = trie.iter().lending();
+        while let Some(item) = iter.next() {
+            let (key, value) = item.unwrap();
+            let (expected_key, expected_value) = entries.next().unwrap();
+            assert_eq!(key, expected_key.as_slice());
+            assert_eq!(value, expected_value.as_slice());
+        }
+        assert!(entries.next().is_none());
+    }
+
+    #[test]
+    fn test_lending_iterator_seek() {
+        let (trie, kv) = random_trie(300);
+        let start = vec![2u8];
+
+        let mut iter = trie.iter().lending();
+        iter.seek(&start).unwrap();
+        let (key, _) = iter.next().unwrap().unwrap();
+        assert_eq!(key, kv.range(start..).next().unwrap().0.as_slice());
+    }
+}
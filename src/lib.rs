@@ -1,14 +1,189 @@
+pub mod compat;
 pub mod nibbles;
 pub mod node;
+#[cfg(feature = "test-utils")]
+pub mod test_utils;
 mod tests;
 
+mod atomic;
+mod background;
+#[cfg(feature = "binary-trie")]
+mod binary;
+mod bloom;
+mod builder;
+mod cached;
+mod codec;
+mod cursor;
 mod db;
+mod debug;
+mod dedup;
+mod diff;
+mod epoch;
 mod errors;
+mod events;
+mod export;
+#[cfg(feature = "test-utils")]
+mod faulty;
+mod flat;
+mod guard;
+#[cfg(feature = "hash-db")]
+mod hashdb;
+mod heal;
+mod health;
+mod journal;
+mod key;
+#[cfg(feature = "test-utils")]
+mod latency;
+#[cfg(feature = "csv-export")]
+mod leaf_export;
+mod lending;
+mod manager;
+mod merge;
+mod node_iter;
+mod observer;
+mod parallel;
+mod path_trie;
+mod pipeline;
+mod post_state;
+mod prefetch;
+mod proof_iter;
+mod pruner;
+mod reader;
+mod resolver;
+mod resume;
+mod revert;
+mod root_manager;
+#[cfg(feature = "test-utils")]
+mod shadow;
+mod snapshot;
+mod split;
+mod staging;
+mod stats;
+#[cfg(feature = "stream")]
+mod stream;
+mod subtrie;
+mod sync;
+#[cfg(feature = "test-utils")]
+mod tester;
 mod trie;
+mod triehash;
+mod typed;
+mod versioned;
+mod view;
+mod witness;
 
-pub use db::{MemoryDB, DB};
-pub use errors::{MemDBError, TrieError};
-pub use trie::{decode_node, EthTrie, RootWithTrieDiff, Trie};
+pub use atomic::AtomicCommit;
+pub use background::BackgroundCommit;
+#[cfg(feature = "binary-trie")]
+pub use binary::{BinaryTrie, BinaryTrieIterator};
+pub use bloom::BloomDB;
+pub use builder::EthTrieBuilder;
+pub use cached::CachedTrie;
+pub use codec::NodeCodec;
+pub use cursor::TrieCursor;
+pub use db::{IterableDB, MemoryDB, DB};
+pub use dedup::{DedupIterator, DedupTrie};
+pub use diff::{diff, node_diff, DiffIterator, KeyChange, NodeChange, NodeDiffIterator};
+pub use errors::{DBError, MemDBError, NodeDecodeError, NodeDecodeErrorKind, TrieError};
+pub use events::CommitEvent;
+pub use export::TrieExport;
+#[cfg(feature = "test-utils")]
+pub use faulty::{Faults, FaultyDB, FaultyDBError};
+pub use flat::FlatTrie;
+pub use guard::CommitGuard;
+#[cfg(feature = "hash-db")]
+pub use hashdb::{HashDBAdaptor, KeccakHasher, TrieHashDB};
+pub use heal::HealRequest;
+pub use health::{HealthReport, RootHealth};
+pub use journal::{JournalEntry, JournaledTrie};
+pub use key::TrieKey;
+#[cfg(feature = "test-utils")]
+pub use latency::LatencyDB;
+pub use lending::{LendingIterator, TrieLendingIterator};
+pub use manager::{StateRoots, TrieManager};
+pub use nibbles::{decode_compact, encode_compact, NibbleSlice, Nibbles};
+pub use node::{Node, NodeKind};
+pub use node_iter::{NodeEntry, NodeIterator};
+pub use observer::{CommitObserver, CommitStats};
+pub use path_trie::{PathDB, PathDBError, PathTrie};
+pub use post_state::{
+    HashedAccount, HashedPostState, HashedStorage, StateRootUpdates, TrieAccount,
+};
+pub use prefetch::PrefetchDB;
+pub use proof_iter::{ProofIterator, ProvenEntry};
+pub use pruner::{PruneStats, Pruner};
+pub use reader::TrieReader;
+pub use resolver::NodeResolver;
+pub use resume::IterCursor;
+pub use root_manager::RootManager;
+#[cfg(feature = "test-utils")]
+pub use shadow::ShadowTrie;
+pub use snapshot::{
+    snapshot_account_key, snapshot_storage_key, SnapshotGenerator, SnapshotMarker,
+    SNAPSHOT_ACCOUNT_PREFIX, SNAPSHOT_STORAGE_PREFIX,
+};
+pub use stats::{FrontierNode, SampledStats, TrieStats};
+#[cfg(feature = "stream")]
+pub use stream::TrieStream;
+#[cfg(feature = "test-utils")]
+pub use tester::{TesterReport, TrieTester};
+pub use trie::{
+    decode_node, decode_node_strict, decode_node_with_max_depth, Checkpoint, EthTrie,
+    RemoveOutcome, RootWithKeyChanges, RootWithTrieDiff, Trie, TrieIterator, TrieRangeIterator,
+    TrieRead, TrieWrite, MAX_DECODE_DEPTH,
+};
+pub use triehash::{ordered_trie_root, sec_trie_root, trie_root};
+pub use typed::{DecodedIterator, TypedTrie};
+pub use versioned::{VersionedTrie, VersionedView};
+pub use view::TrieView;
+pub use witness::Witness;
+
+// Fails to compile if a public type stops being `Send` or `Sync` for some database, so
+// that tries, views, iterators and proofs can be used from other threads and async tasks.
+#[allow(dead_code)]
+fn assert_thread_safe<D: DB + 'static>() {
+    fn send_sync<T: Send + Sync>() {}
+    send_sync::<EthTrie<D>>();
+    send_sync::<EthTrieBuilder<D>>();
+    send_sync::<TrieView<D>>();
+    send_sync::<TrieReader<D>>();
+    send_sync::<TypedTrie<D, std::rc::Rc<u8>>>();
+    send_sync::<JournaledTrie<D>>();
+    send_sync::<TrieManager<D>>();
+    send_sync::<RootManager<D>>();
+    send_sync::<VersionedTrie<D>>();
+    send_sync::<VersionedView<D>>();
+    send_sync::<BloomDB<D>>();
+    send_sync::<PrefetchDB<D>>();
+    send_sync::<AtomicCommit<'static, D>>();
+    send_sync::<BackgroundCommit<D>>();
+    send_sync::<CommitGuard<'static, D>>();
+    send_sync::<TrieIterator<'static, D>>();
+    send_sync::<TrieRangeIterator<TrieIterator<'static, D>>>();
+    send_sync::<DecodedIterator<'static, D, std::rc::Rc<u8>>>();
+    send_sync::<ProofIterator<'static, D>>();
+    send_sync::<NodeIterator<'static, D>>();
+    send_sync::<TrieCursor<'static, D>>();
+    send_sync::<TrieLendingIterator<'static, D>>();
+    send_sync::<DiffIterator<D>>();
+    send_sync::<NodeDiffIterator<D>>();
+    send_sync::<Node>();
+    send_sync::<Witness>();
+    send_sync::<ProvenEntry>();
+    send_sync::<TrieExport>();
+    send_sync::<RootWithTrieDiff>();
+    send_sync::<CommitEvent>();
+    send_sync::<TrieError>();
+    #[cfg(feature = "stream")]
+    send_sync::<TrieStream<TrieIterator<'static, D>>>();
+    #[cfg(feature = "hash-db")]
+    send_sync::<TrieHashDB<KeccakHasher, D>>();
+    #[cfg(feature = "binary-trie")]
+    {
+        send_sync::<BinaryTrie<D>>();
+        send_sync::<BinaryTrieIterator<'static, D>>();
+    }
+}
 
 #[doc = include_str!("../README.md")]
 #[cfg(doctest)]
@@ -0,0 +1,188 @@
+use std::sync::Arc;
+
+use alloy_primitives::B256;
+use hashbrown::HashMap;
+use keccak_hash::KECCAK_NULL_RLP;
+
+use crate::db::DB;
+use crate::trie::{EthTrie, TrieResult, TrieWrite};
+
+/// The roots produced by `TrieManager::commit`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StateRoots {
+    pub state_root: B256,
+    /// The new root of every open storage trie, by account key.
+    pub storage_roots: HashMap<B256, B256>,
+}
+
+/// Keeps the account trie and the storage tries of a state open over one database.
+///
+/// Storage tries are opened on first use and kept until they are closed, so repeated
+/// accesses to the same account within a block share one trie. `commit` writes them all,
+/// storage tries first, so that the new storage roots can be recorded in the accounts
+/// before the account trie is committed.
+///
+/// Tries over the same database can hold identical nodes, so a database that removes
+/// stale nodes on commit may remove a node another trie still uses. Use a database that
+/// retains them.
+#[derive(Debug)]
+pub struct TrieManager<D>
+where
+    D: DB,
+{
+    db: Arc<D>,
+    accounts: EthTrie<D>,
+    storage: HashMap<B256, EthTrie<D>>,
+}
+
+impl<D> TrieManager<D>
+where
+    D: DB,
+{
+    /// Starts from an empty state.
+    pub fn new(db: Arc<D>) -> Self {
+        Self {
+            accounts: EthTrie::new(db.clone()),
+            db,
+            storage: HashMap::new(),
+        }
+    }
+
+    /// Opens the state at `state_root`.
+    pub fn from(db: Arc<D>, state_root: B256) -> TrieResult<Self> {
+        Ok(Self {
+            accounts: EthTrie::from(db.clone(), state_root)?,
+            db,
+            storage: HashMap::new(),
+        })
+    }
+
+    pub fn accounts(&mut self) -> &mut EthTrie<D> {
+        &mut self.accounts
+    }
+
+    /// Returns the storage trie of the account at `account_key`, opening it at
+    /// `storage_root` if it isn't open yet. Once open, `storage_root` is ignored.
+    pub fn storage(
+        &mut self,
+        account_key: B256,
+        storage_root: B256,
+    ) -> TrieResult<&mut EthTrie<D>> {
+        if !self.storage.contains_key(&account_key) {
+            let empty_root: B256 = KECCAK_NULL_RLP.as_fixed_bytes().into();
+            let trie = if storage_root == empty_root {
+                EthTrie::new(self.db.clone())
+            } else {
+                EthTrie::from(self.db.clone(), storage_root)?
+            };
+            self.storage.insert(account_key, trie);
+        }
+        Ok(self.storage.get_mut(&account_key).unwrap())
+    }
+
+    /// Returns the number of open storage tries.
+    pub fn open_storage_count(&self) -> usize {
+        self.storage.len()
+    }
+
+    /// Closes the storage trie of an account, dropping its uncommitted changes.
+    pub fn close_storage(&mut self, account_key: &B256) {
+        self.storage.remove(account_key);
+    }
+
+    /// Commits every open storage trie, passes their new roots to `update_accounts` so
+    /// that it can write them into the accounts, then commits the account trie.
+    ///
+    /// The storage tries stay open.
+    pub fn commit<F>(&mut self, update_accounts: F) -> TrieResult<StateRoots>
+    where
+        F: FnOnce(&mut EthTrie<D>, &HashMap<B256, B256>) -> TrieResult<()>,
+    {
+        let mut storage_roots = HashMap::with_capacity(self.storage.len());
+        for (account_key, trie) in self.storage.iter_mut() {
+            storage_roots.insert(*account_key, trie.root_hash()?);
+        }
+        update_accounts(&mut self.accounts, &storage_roots)?;
+
+        Ok(StateRoots {
+            state_root: self.accounts.root_hash()?,
+            storage_roots,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use alloy_primitives::{Address, B256};
+    use keccak_hash::KECCAK_NULL_RLP;
+
+    use super::TrieManager;
+    use crate::db::MemoryDB;
+    use crate::key::TrieKey;
+    use crate::trie::{EthTrie, TrieRead, TrieWrite};
+
+    #[test]
+    fn test_manager_commit() {
+        let memdb = Arc::new(MemoryDB::new(false));
+        let empty_root: B256 = KECCAK_NULL_RLP.as_fixed_bytes().into();
+        let alice = B256::from_slice(&Address::repeat_byte(1).trie_key());
+        let bob = B256::from_slice(&Address::repeat_byte(2).trie_key());
+
+        let mut manager = TrieManager::new(memdb.clone());
+        manager
+            .storage(alice, empty_root)
+            .unwrap()
+            .insert(b"slot", b"value")
+            .unwrap();
+        manager
+            .storage(bob, empty_root)
+            .unwrap()
+            .insert(b"slot", b"other")
+            .unwrap();
+        // Reopening an open trie returns the same handle, with its pending changes
+        assert!(manager.storage(alice, empty_root).unwrap().is_dirty());
+        assert_eq!(manager.open_storage_count(), 2);
+
+        // Accounts here just hold their storage root
+        let roots = manager
+            .commit(|accounts, storage_roots| {
+                for (key, root) in storage_roots {
+                    accounts.insert(key.as_slice(), root.as_slice())?;
+                }
+                Ok(())
+            })
+            .unwrap();
+
+        let alice_root = roots.storage_roots[&alice];
+        let mut expected = EthTrie::new(Arc::new(MemoryDB::new(true)));
+        expected.insert(b"slot", b"value").unwrap();
+        assert_eq!(expected.root_hash().unwrap(), alice_root);
+
+        let mut reopened = TrieManager::from(memdb, roots.state_root).unwrap();
+        let stored_root = reopened.accounts().get(alice.as_slice()).unwrap().unwrap();
+        assert_eq!(stored_root, alice_root.to_vec());
+        let storage = reopened.storage(alice, alice_root).unwrap();
+        assert_eq!(storage.get(b"slot").unwrap(), Some(b"value".to_vec()));
+    }
+
+    #[test]
+    fn test_manager_close_storage() {
+        let memdb = Arc::new(MemoryDB::new(false));
+        let empty_root: B256 = KECCAK_NULL_RLP.as_fixed_bytes().into();
+        let key = B256::repeat_byte(1);
+
+        let mut manager = TrieManager::new(memdb);
+        manager
+            .storage(key, empty_root)
+            .unwrap()
+            .insert(b"slot", b"value")
+            .unwrap();
+        manager.close_storage(&key);
+        assert_eq!(manager.open_storage_count(), 0);
+
+        let storage = manager.storage(key, empty_root).unwrap();
+        assert_eq!(storage.get(b"slot").unwrap(), None);
+    }
+}
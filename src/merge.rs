@@ -0,0 +1,155 @@
+use crate::db::DB;
+use crate::diff::diff_nodes;
+use crate::trie::{EthTrie, TrieResult, TrieWrite};
+
+impl<D> EthTrie<D>
+where
+    D: DB,
+{
+    /// Adds the entries of `other` to this trie, leaving the result uncommitted. Both
+    /// tries must read their nodes from the same database.
+    ///
+    /// Subtrees with the same hash in both tries are skipped without being loaded. For a
+    /// key with different values in the two tries, `resolve` is called with the key, the
+    /// value here and the value in `other`, and returns the value to keep. Returns the
+    /// number of keys whose value changed.
+    pub fn merge<F>(&mut self, other: &EthTrie<D>, mut resolve: F) -> TrieResult<usize>
+    where
+        F: FnMut(&[u8], &[u8], &[u8]) -> TrieResult<Vec<u8>>,
+    {
+        // Collect the changes first, the walk reads the nodes the inserts replace
+        let changes = diff_nodes(
+            &self.db,
+            self.root_hash,
+            self.root.clone(),
+            other.root.clone(),
+        )
+        .collect::<TrieResult<Vec<_>>>()?;
+
+        let mut changed = 0;
+        for (key, ours, theirs) in changes {
+            let value = match (ours, theirs) {
+                (None, Some(theirs)) => theirs,
+                (Some(ours), Some(theirs)) => {
+                    let value = resolve(&key, &ours, &theirs)?;
+                    if value == ours {
+                        continue;
+                    }
+                    value
+                }
+                _ => continue,
+            };
+            self.insert(&key, &value)?;
+            changed += 1;
+        }
+        Ok(changed)
+    }
+
+    /// Returns true if every entry of this trie is in `other` with the same value. Both
+    /// tries must read their nodes from the same database. Subtrees with the same hash in
+    /// both tries are skipped without being loaded.
+    pub fn is_subset_of(&self, other: &EthTrie<D>) -> TrieResult<bool> {
+        let changes = diff_nodes(
+            &self.db,
+            self.root_hash,
+            self.root.clone(),
+            other.root.clone(),
+        );
+        for change in changes {
+            if let (_, Some(_), _) = change? {
+                return Ok(false);
+            }
+        }
+        Ok(true)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+    use std::sync::Arc;
+
+    use crate::db::MemoryDB;
+    use crate::errors::TrieError;
+    use crate::trie::{EthTrie, TrieRead, TrieWrite};
+
+    #[test]
+    fn test_merge() {
+        let memdb = Arc::new(MemoryDB::new(false));
+        let mut base = EthTrie::new(memdb.clone());
+        for i in 0..200u32 {
+            base.insert(&i.to_be_bytes(), b"shared value").unwrap();
+        }
+        let root = base.root_hash().unwrap();
+
+        let mut ours = EthTrie::from(memdb.clone(), root).unwrap();
+        let mut theirs = EthTrie::from(memdb.clone(), root).unwrap();
+        ours.insert(b"only ours", b"1").unwrap();
+        theirs.insert(b"only theirs", b"2").unwrap();
+        ours.insert(&7u32.to_be_bytes(), b"ours").unwrap();
+        theirs.insert(&7u32.to_be_bytes(), b"theirs").unwrap();
+        theirs.insert(&8u32.to_be_bytes(), b"theirs").unwrap();
+        theirs.root_hash().unwrap();
+
+        let mut conflicts = vec![];
+        let changed = ours
+            .merge(&theirs, |key, a, b| {
+                conflicts.push(key.to_vec());
+                Ok([a, b].concat())
+            })
+            .unwrap();
+        assert_eq!(changed, 3);
+        assert_eq!(
+            conflicts,
+            vec![7u32.to_be_bytes().to_vec(), 8u32.to_be_bytes().to_vec()]
+        );
+
+        let mut expected: BTreeMap<Vec<u8>, Vec<u8>> = (0..200u32)
+            .map(|i| (i.to_be_bytes().to_vec(), b"shared value".to_vec()))
+            .collect();
+        expected.insert(b"only ours".to_vec(), b"1".to_vec());
+        expected.insert(b"only theirs".to_vec(), b"2".to_vec());
+        expected.insert(7u32.to_be_bytes().to_vec(), b"ourstheirs".to_vec());
+        expected.insert(8u32.to_be_bytes().to_vec(), b"shared valuetheirs".to_vec());
+        assert_eq!(
+            ours.iter().map(|item| item.unwrap()).collect::<Vec<_>>(),
+            expected.into_iter().collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn test_is_subset_of() {
+        let memdb = Arc::new(MemoryDB::new(false));
+        let mut small = EthTrie::new(memdb.clone());
+        for i in 0..100u32 {
+            small.insert(&i.to_be_bytes(), b"value").unwrap();
+        }
+        let root = small.root_hash().unwrap();
+
+        let mut large = EthTrie::from(memdb.clone(), root).unwrap();
+        large.insert(b"extra", b"value").unwrap();
+        assert!(small.is_subset_of(&large).unwrap());
+        assert!(small.is_subset_of(&small).unwrap());
+        assert!(!large.is_subset_of(&small).unwrap());
+
+        large.insert(&5u32.to_be_bytes(), b"changed").unwrap();
+        assert!(!small.is_subset_of(&large).unwrap());
+
+        let empty = EthTrie::new(memdb);
+        assert!(empty.is_subset_of(&small).unwrap());
+        assert!(!small.is_subset_of(&empty).unwrap());
+    }
+
+    #[test]
+    fn test_merge_resolver_error() {
+        let memdb = Arc::new(MemoryDB::new(false));
+        let mut ours = EthTrie::new(memdb.clone());
+        let mut theirs = EthTrie::new(memdb);
+        ours.insert(b"key", b"a").unwrap();
+        theirs.insert(b"key", b"b").unwrap();
+
+        let result = ours.merge(&theirs, |_, _, _| Err(TrieError::InvalidData));
+        assert_eq!(result.unwrap_err(), TrieError::InvalidData);
+        assert_eq!(ours.get(b"key").unwrap(), Some(b"a".to_vec()));
+    }
+}
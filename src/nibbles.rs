@@ -1,17 +1,31 @@
-use std::cmp::min;
+use std::cmp::{min, Ordering};
+use std::fmt;
+use std::str::FromStr;
 
-#[derive(Debug, Clone, Eq, PartialEq)]
+use crate::errors::TrieError;
+
+// Marks the end of a leaf key, after the last nibble.
+const TERMINATOR: u8 = 16;
+
+/// A path through the trie, as a sequence of nibbles (half bytes, 0 to 15).
+///
+/// Paths to values end with a terminator (16), which marks them as leaf keys. Nibbles
+/// display as hex digits, with the terminator left out, and order like the keys they
+/// come from: a path sorts before any path it is a prefix of.
+#[derive(Debug, Clone, Eq, PartialEq, Hash)]
 pub struct Nibbles {
     hex_data: Vec<u8>,
 }
 
 impl Nibbles {
+    /// Creates nibbles from their values, one per byte. A trailing 16 is a terminator.
     pub fn from_hex(hex: &[u8]) -> Self {
         Nibbles {
             hex_data: hex.to_vec(),
         }
     }
 
+    /// Splits each byte of `raw` into two nibbles, adding a terminator if `is_leaf`.
     pub fn from_raw(raw: &[u8], is_leaf: bool) -> Self {
         let mut hex_data = vec![];
         for item in raw.iter() {
@@ -24,67 +38,39 @@ impl Nibbles {
         Nibbles { hex_data }
     }
 
+    /// Decodes the compact (hex-prefix) encoding of a path.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `compact` is not a valid encoding. Use `try_from_compact` for data
+    /// that isn't known to be valid.
     pub fn from_compact(compact: &[u8]) -> Self {
-        let mut hex = vec![];
-        let flag = compact[0];
-
-        let mut is_leaf = false;
-        match flag >> 4 {
-            0x0 => {}
-            0x1 => hex.push(flag % 16),
-            0x2 => is_leaf = true,
-            0x3 => {
-                is_leaf = true;
-                hex.push(flag % 16);
-            }
-            _ => panic!("invalid data"),
-        }
+        Self::try_from_compact(compact).expect("invalid data")
+    }
 
-        for item in &compact[1..] {
-            hex.push(item / 16);
-            hex.push(item % 16);
-        }
+    /// Decodes the compact (hex-prefix) encoding of a path, failing with
+    /// `TrieError::InvalidData` if it is not valid.
+    pub fn try_from_compact(compact: &[u8]) -> Result<Self, TrieError> {
+        let (mut hex_data, is_leaf) = decode_compact(compact)?;
         if is_leaf {
-            hex.push(16);
+            hex_data.push(TERMINATOR);
         }
-
-        Nibbles { hex_data: hex }
+        Ok(Nibbles { hex_data })
     }
 
+    /// Returns true if the path ends with a terminator.
     pub fn is_leaf(&self) -> bool {
-        self.hex_data[self.hex_data.len() - 1] == 16
+        self.hex_data.last() == Some(&TERMINATOR)
     }
 
+    /// Returns the compact (hex-prefix) encoding of the path.
     pub fn encode_compact(&self) -> Vec<u8> {
-        let mut compact = vec![];
         let is_leaf = self.is_leaf();
-        let mut hex = if is_leaf {
-            &self.hex_data[0..self.hex_data.len() - 1]
-        } else {
-            &self.hex_data[0..]
-        };
-        // node type    path length    |    prefix    hexchar
-        // --------------------------------------------------
-        // extension    even           |    0000      0x0
-        // extension    odd            |    0001      0x1
-        // leaf         even           |    0010      0x2
-        // leaf         odd            |    0011      0x3
-        let v = if hex.len() % 2 == 1 {
-            let v = 0x10 + hex[0];
-            hex = &hex[1..];
-            v
-        } else {
-            0x00
-        };
-
-        compact.push(v + if is_leaf { 0x20 } else { 0x00 });
-        for i in 0..(hex.len() / 2) {
-            compact.push((hex[i * 2] * 16) + (hex[i * 2 + 1]));
-        }
-
-        compact
+        encode_compact(&self.hex_data[..self.len() - is_leaf as usize], is_leaf)
     }
 
+    /// Packs the nibbles back into bytes, returning whether the path had a terminator.
+    /// A trailing odd nibble is dropped.
     pub fn encode_raw(&self) -> (Vec<u8>, bool) {
         let mut raw = vec![];
         let is_leaf = self.is_leaf();
@@ -101,6 +87,15 @@ impl Nibbles {
         (raw, is_leaf)
     }
 
+    /// Returns the first key whose nibbles start with the path, to seek an iterator to:
+    /// the nibbles packed into bytes, with a zero nibble padding an odd one.
+    pub(crate) fn seek_key(&self) -> Vec<u8> {
+        self.hex_data[..self.len() - self.is_leaf() as usize]
+            .chunks(2)
+            .map(|pair| pair[0] << 4 | pair.get(1).copied().unwrap_or(0))
+            .collect()
+    }
+
     pub fn len(&self) -> usize {
         self.hex_data.len()
     }
@@ -109,10 +104,12 @@ impl Nibbles {
         self.len() == 0
     }
 
+    /// Returns the nibble at `i`.
     pub fn at(&self, i: usize) -> usize {
         self.hex_data[i] as usize
     }
 
+    /// Returns the length of the prefix shared with `other_partial`.
     pub fn common_prefix(&self, other_partial: &Nibbles) -> usize {
         let s = min(self.len(), other_partial.len());
         let mut i = 0usize;
@@ -133,10 +130,24 @@ impl Nibbles {
         Nibbles::from_hex(&self.hex_data[start..end])
     }
 
+    /// Returns a view of the nibbles from `start` to `end`, without copying them.
+    pub fn view(&self, start: usize, end: usize) -> NibbleSlice<'_> {
+        NibbleSlice(&self.hex_data[start..end])
+    }
+
+    pub fn as_slice(&self) -> NibbleSlice<'_> {
+        NibbleSlice(&self.hex_data)
+    }
+
+    /// Returns the nibble values, one per byte, including any terminator.
     pub fn get_data(&self) -> &[u8] {
         &self.hex_data
     }
 
+    pub fn iter(&self) -> impl Iterator<Item = u8> + '_ {
+        self.hex_data.iter().copied()
+    }
+
     pub fn join(&self, b: &Nibbles) -> Nibbles {
         let hex_data = [self.get_data(), b.get_data()].concat();
         Nibbles::from_hex(&hex_data)
@@ -159,6 +170,167 @@ impl Nibbles {
     }
 }
 
+impl PartialOrd for Nibbles {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Nibbles {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.as_slice().cmp(&other.as_slice())
+    }
+}
+
+impl fmt::Display for Nibbles {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.as_slice().fmt(f)
+    }
+}
+
+/// Parses hex digits, one nibble each, into a path without a terminator.
+impl FromStr for Nibbles {
+    type Err = TrieError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let hex_data = s
+            .chars()
+            .map(|c| {
+                c.to_digit(16)
+                    .map(|d| d as u8)
+                    .ok_or(TrieError::InvalidData)
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(Nibbles { hex_data })
+    }
+}
+
+/// A borrowed view of a run of nibbles. See `Nibbles::view`.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash)]
+pub struct NibbleSlice<'a>(&'a [u8]);
+
+impl<'a> NibbleSlice<'a> {
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    pub fn at(&self, i: usize) -> usize {
+        self.0[i] as usize
+    }
+
+    pub fn is_leaf(&self) -> bool {
+        self.0.last() == Some(&TERMINATOR)
+    }
+
+    /// Returns a narrower view, without copying.
+    pub fn view(&self, start: usize, end: usize) -> NibbleSlice<'a> {
+        NibbleSlice(&self.0[start..end])
+    }
+
+    pub fn starts_with(&self, prefix: NibbleSlice) -> bool {
+        self.0.starts_with(prefix.0)
+    }
+
+    pub fn get_data(&self) -> &'a [u8] {
+        self.0
+    }
+
+    pub fn to_nibbles(&self) -> Nibbles {
+        Nibbles::from_hex(self.0)
+    }
+
+    // The nibbles without the terminator.
+    fn path(&self) -> &'a [u8] {
+        match self.0.split_last() {
+            Some((&TERMINATOR, path)) => path,
+            _ => self.0,
+        }
+    }
+}
+
+impl PartialOrd for NibbleSlice<'_> {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for NibbleSlice<'_> {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.path()
+            .cmp(other.path())
+            .then(self.is_leaf().cmp(&other.is_leaf()))
+    }
+}
+
+impl fmt::Display for NibbleSlice<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for nibble in self.path() {
+            write!(f, "{:x}", nibble)?;
+        }
+        Ok(())
+    }
+}
+
+/// Encodes a path in compact (hex-prefix) form, the encoding of keys in leaf and extension
+/// nodes defined in appendix C of the Yellow Paper.
+///
+/// `nibbles` holds one nibble (0 to 15) per byte, without a terminator; `is_leaf` marks
+/// the path of a leaf. The first byte carries both flags and, for a path of odd length,
+/// its first nibble:
+///
+/// ```text
+/// node type    path length    |    flag nibble
+/// --------------------------------------------
+/// extension    even           |    0x0
+/// extension    odd            |    0x1
+/// leaf         even           |    0x2
+/// leaf         odd            |    0x3
+/// ```
+///
+/// The remaining nibbles are packed two to a byte.
+pub fn encode_compact(nibbles: &[u8], is_leaf: bool) -> Vec<u8> {
+    let mut compact = Vec::with_capacity(nibbles.len() / 2 + 1);
+    let flag = if is_leaf { 0x20 } else { 0x00 };
+    let rest = if nibbles.len() % 2 == 1 {
+        compact.push(flag + 0x10 + nibbles[0]);
+        &nibbles[1..]
+    } else {
+        compact.push(flag);
+        nibbles
+    };
+    compact.extend(rest.chunks_exact(2).map(|pair| pair[0] * 16 + pair[1]));
+    compact
+}
+
+/// Decodes a compact (hex-prefix) path into its nibbles, one per byte, and whether it is
+/// the path of a leaf. The inverse of `encode_compact`.
+///
+/// Fails with `TrieError::InvalidData` if the flag nibble is unknown, or if the padding
+/// nibble of an even length path is not zero.
+pub fn decode_compact(compact: &[u8]) -> Result<(Vec<u8>, bool), TrieError> {
+    let (is_leaf, odd) = match compact.first().map(|flag| (flag >> 4, flag & 0x0f)) {
+        Some((0, 0)) => (false, false),
+        Some((1, _)) => (false, true),
+        Some((2, 0)) => (true, false),
+        Some((3, _)) => (true, true),
+        _ => return Err(TrieError::InvalidData),
+    };
+
+    let mut nibbles = Vec::with_capacity(compact.len() * 2);
+    if odd {
+        nibbles.push(compact[0] & 0x0f);
+    }
+    for byte in &compact[1..] {
+        nibbles.push(byte / 16);
+        nibbles.push(byte % 16);
+    }
+    Ok((nibbles, is_leaf))
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -172,4 +344,80 @@ mod tests {
         assert!(is_leaf);
         assert_eq!(raw, b"key1");
     }
+
+    #[test]
+    fn test_nibbles_display_and_parse() {
+        let n = Nibbles::from_raw(b"do", true);
+        assert_eq!(n.to_string(), "646f");
+        assert_eq!(
+            "646f".parse::<Nibbles>().unwrap(),
+            Nibbles::from_raw(b"do", false)
+        );
+        assert_eq!("6x".parse::<Nibbles>(), Err(TrieError::InvalidData));
+        assert_eq!(n.view(1, 3).to_string(), "46");
+        assert!(n.as_slice().starts_with(n.view(0, 2)));
+    }
+
+    #[test]
+    fn test_seek_key() {
+        assert_eq!(Nibbles::from_hex(&[]).seek_key(), Vec::<u8>::new());
+        assert_eq!(Nibbles::from_hex(&[6, 4, 6]).seek_key(), vec![0x64, 0x60]);
+        assert_eq!(Nibbles::from_raw(b"do", false).seek_key(), b"do".to_vec());
+        assert_eq!(Nibbles::from_raw(b"do", true).seek_key(), b"do".to_vec());
+    }
+
+    #[test]
+    fn test_nibbles_ordering() {
+        let mut keys: Vec<&[u8]> = vec![b"dog", b"do", b"a", b"doge", b"\xff"];
+        let mut paths: Vec<_> = keys.iter().map(|k| Nibbles::from_raw(k, true)).collect();
+        keys.sort();
+        paths.sort();
+        let sorted: Vec<_> = paths.iter().map(|n| n.encode_raw().0).collect();
+        assert_eq!(sorted, keys);
+        assert!(Nibbles::from_hex(&[1, 2]) < Nibbles::from_hex(&[1, 2, 16]));
+    }
+
+    #[test]
+    fn test_try_from_compact() {
+        let n = Nibbles::from_hex(&[1, 2, 3]);
+        assert_eq!(Nibbles::try_from_compact(&n.encode_compact()).unwrap(), n);
+        assert_eq!(Nibbles::try_from_compact(&[]), Err(TrieError::InvalidData));
+        assert_eq!(
+            Nibbles::try_from_compact(&[0x40]),
+            Err(TrieError::InvalidData)
+        );
+        assert_eq!(
+            Nibbles::try_from_compact(&[0x05]),
+            Err(TrieError::InvalidData)
+        );
+        assert!(!Nibbles::from_hex(&[]).is_leaf());
+    }
+
+    #[test]
+    fn test_compact_spec_vectors() {
+        // From hexencodetest.json in ethereum/tests
+        let vectors: [(&[u8], bool, &[u8]); 8] = [
+            (&[], false, &[0x00]),
+            (&[], true, &[0x20]),
+            (&[1, 2, 3, 4, 5], false, &[0x11, 0x23, 0x45]),
+            (&[0, 1, 2, 3, 4, 5], false, &[0x00, 0x01, 0x23, 0x45]),
+            (&[0, 0, 1, 2, 3, 4, 5], false, &[0x10, 0x01, 0x23, 0x45]),
+            (&[0, 15, 1, 12, 11, 8], true, &[0x20, 0x0f, 0x1c, 0xb8]),
+            (&[15, 1, 12, 11, 8], true, &[0x3f, 0x1c, 0xb8]),
+            (&[0, 0, 1, 2, 3, 4, 5], true, &[0x30, 0x01, 0x23, 0x45]),
+        ];
+        for (nibbles, is_leaf, compact) in vectors {
+            assert_eq!(encode_compact(nibbles, is_leaf), compact);
+            assert_eq!(
+                decode_compact(compact).unwrap(),
+                (nibbles.to_vec(), is_leaf)
+            );
+
+            let mut path = Nibbles::from_hex(nibbles);
+            if is_leaf {
+                path.push(TERMINATOR);
+            }
+            assert_eq!(path.encode_compact(), compact);
+        }
+    }
 }
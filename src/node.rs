@@ -1,9 +1,12 @@
-use std::sync::{Arc, RwLock};
+use std::sync::Arc;
 
 use alloy_primitives::B256;
 
 use crate::nibbles::Nibbles;
+use crate::sync::RwLock;
 
+// The lock of a node is never held while the lock of another is taken: the children of
+// a branch or extension are copied out, and the lock released, before they are visited.
 #[derive(Debug, Clone)]
 pub enum Node {
     Empty,
@@ -33,6 +36,85 @@ impl Node {
         let hash_node = Arc::new(HashNode { hash });
         Node::Hash(hash_node)
     }
+
+    pub fn kind(&self) -> NodeKind {
+        match self {
+            Node::Empty => NodeKind::Empty,
+            Node::Leaf(_) => NodeKind::Leaf,
+            Node::Extension(_) => NodeKind::Extension,
+            Node::Branch(_) => NodeKind::Branch,
+            Node::Hash(_) => NodeKind::Hash,
+        }
+    }
+
+    /// Returns the key of a leaf, with its terminator, or the prefix of an extension.
+    pub fn prefix(&self) -> Option<Nibbles> {
+        match self {
+            Node::Leaf(leaf) => Some(leaf.key.clone()),
+            Node::Extension(ext) => Some(ext.read().prefix.clone()),
+            _ => None,
+        }
+    }
+
+    /// Returns the value held by a leaf or a branch.
+    pub fn value(&self) -> Option<Vec<u8>> {
+        match self {
+            Node::Leaf(leaf) => Some(leaf.value.clone()),
+            Node::Branch(branch) => branch.read().value.clone(),
+            _ => None,
+        }
+    }
+
+    /// Returns the hash a hash node refers to.
+    pub fn hash(&self) -> Option<B256> {
+        match self {
+            Node::Hash(hash_node) => Some(hash_node.hash),
+            _ => None,
+        }
+    }
+
+    /// Returns the non-empty children of a branch, with their nibble.
+    pub fn children(&self) -> Vec<(u8, Node)> {
+        match self {
+            Node::Branch(branch) => branch
+                .read()
+                .children
+                .iter()
+                .enumerate()
+                .filter(|(_, child)| !matches!(child, Node::Empty))
+                .map(|(i, child)| (i as u8, child.clone()))
+                .collect(),
+            _ => vec![],
+        }
+    }
+
+    /// Returns the hashes of the children of a branch that are stored apart from it,
+    /// with their nibble.
+    pub fn child_hashes(&self) -> Vec<(u8, B256)> {
+        self.children()
+            .into_iter()
+            .filter_map(|(i, child)| child.hash().map(|hash| (i, hash)))
+            .collect()
+    }
+
+    /// Returns the node an extension leads to.
+    pub fn extension_child(&self) -> Option<Node> {
+        match self {
+            Node::Extension(ext) => Some(ext.read().node.clone()),
+            _ => None,
+        }
+    }
+}
+
+/// The kind of a `Node`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NodeKind {
+    Empty,
+    Leaf,
+    Extension,
+    Branch,
+    /// A reference to a node stored apart from its parent, not loaded yet.
+    Hash,
 }
 
 #[derive(Debug)]
@@ -41,7 +123,7 @@ pub struct LeafNode {
     pub value: Vec<u8>,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct BranchNode {
     pub children: [Node; 16],
     pub value: Option<Vec<u8>>,
@@ -62,7 +144,7 @@ impl BranchNode {
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct ExtensionNode {
     pub prefix: Nibbles,
     pub node: Node,
@@ -93,3 +175,40 @@ pub fn empty_children() -> [Node; 16] {
         Node::Empty,
     ]
 }
+
+#[cfg(test)]
+mod tests {
+    use alloy_primitives::B256;
+
+    use super::{empty_children, Node, NodeKind};
+    use crate::nibbles::Nibbles;
+
+    #[test]
+    fn test_node_accessors() {
+        let leaf = Node::from_leaf(Nibbles::from_hex(&[1, 16]), b"leaf".to_vec());
+        let mut children = empty_children();
+        children[2] = leaf.clone();
+        children[5] = Node::from_hash(B256::repeat_byte(5));
+        let branch = Node::from_branch(children, Some(b"branch".to_vec()));
+        let ext = Node::from_extension(Nibbles::from_hex(&[3, 4]), branch.clone());
+
+        assert_eq!(leaf.kind(), NodeKind::Leaf);
+        assert_eq!(leaf.prefix(), Some(Nibbles::from_hex(&[1, 16])));
+        assert_eq!(leaf.value(), Some(b"leaf".to_vec()));
+
+        assert_eq!(branch.value(), Some(b"branch".to_vec()));
+        let kinds: Vec<_> = branch
+            .children()
+            .iter()
+            .map(|(i, child)| (*i, child.kind()))
+            .collect();
+        assert_eq!(kinds, vec![(2, NodeKind::Leaf), (5, NodeKind::Hash)]);
+        assert_eq!(branch.child_hashes(), vec![(5, B256::repeat_byte(5))]);
+
+        assert_eq!(ext.kind(), NodeKind::Extension);
+        assert_eq!(ext.prefix(), Some(Nibbles::from_hex(&[3, 4])));
+        assert_eq!(ext.extension_child().unwrap().kind(), NodeKind::Branch);
+        assert!(ext.value().is_none());
+        assert!(Node::Empty.children().is_empty());
+    }
+}
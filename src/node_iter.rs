@@ -0,0 +1,164 @@
+use alloy_primitives::B256;
+
+use crate::db::DB;
+use crate::debug::resolve;
+use crate::nibbles::Nibbles;
+use crate::node::Node;
+use crate::trie::{EthTrie, NodeReader, TrieResult};
+use crate::view::TrieView;
+
+/// A node reached by `NodeIterator`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NodeEntry {
+    /// The nibble path from the root to the node.
+    pub path: Nibbles,
+    /// The hash the node is stored under, or `None` if it is inlined in its parent.
+    pub hash: Option<B256>,
+    pub encoded: Vec<u8>,
+}
+
+/// Iterates over every node of a trie, depth first, parents before children and children
+/// in nibble order. Created by `EthTrie::iter_nodes` and `TrieView::iter_nodes`.
+pub struct NodeIterator<'a, D>
+where
+    D: DB,
+{
+    reader: NodeReader<'a, D>,
+    // The nodes left to visit with their paths, next one last.
+    stack: Vec<(Nibbles, Node)>,
+}
+
+impl<'a, D> NodeIterator<'a, D>
+where
+    D: DB,
+{
+    fn new(reader: NodeReader<'a, D>, root: Node) -> Self {
+        let stack = match root {
+            Node::Empty => vec![],
+            root => vec![(Nibbles::from_hex(&[]), root)],
+        };
+        Self { reader, stack }
+    }
+}
+
+impl<D> Iterator for NodeIterator<'_, D>
+where
+    D: DB,
+{
+    type Item = TrieResult<NodeEntry>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let (path, node) = self.stack.pop()?;
+        let (node, encoded, hash) = match resolve(&self.reader, &node, &path, path.is_empty()) {
+            Ok(resolved) => resolved,
+            Err(e) => {
+                self.stack.clear();
+                return Some(Err(e));
+            }
+        };
+
+        match &node {
+            Node::Branch(branch) => {
+                let branch = branch.read();
+                for (i, child) in branch.children.iter().enumerate().rev() {
+                    if !matches!(child, Node::Empty) {
+                        let mut child_path = path.clone();
+                        child_path.push(i as u8);
+                        self.stack.push((child_path, child.clone()));
+                    }
+                }
+            }
+            Node::Extension(ext) => {
+                let ext = ext.read();
+                self.stack.push((path.join(&ext.prefix), ext.node.clone()));
+            }
+            _ => {}
+        }
+
+        Some(Ok(NodeEntry {
+            path,
+            hash,
+            encoded,
+        }))
+    }
+}
+
+impl<D> EthTrie<D>
+where
+    D: DB,
+{
+    /// Returns an iterator over every node of the trie, including uncommitted changes.
+    /// The hashes of changed nodes are computed but the nodes are not stored.
+    pub fn iter_nodes(&self) -> NodeIterator<'_, D> {
+        NodeIterator::new(self.reader(), self.root.clone())
+    }
+}
+
+impl<D> TrieView<D>
+where
+    D: DB,
+{
+    /// Returns an iterator over every node of the trie.
+    pub fn iter_nodes(&self) -> NodeIterator<'_, D> {
+        NodeIterator::new(self.reader(), self.root.clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use alloy_primitives::B256;
+    use keccak_hash::keccak;
+
+    use crate::db::{MemoryDB, DB};
+    use crate::nibbles::Nibbles;
+    use crate::trie::tests::random_trie;
+    use crate::trie::{EthTrie, TrieRead, TrieWrite};
+
+    #[test]
+    fn test_iter_nodes_covers_storage() {
+        let (mut trie, _) = random_trie(300);
+        let root = trie.root_hash().unwrap();
+
+        let entries: Vec<_> = trie.iter_nodes().map(|entry| entry.unwrap()).collect();
+        assert_eq!(entries[0].hash, Some(root));
+        assert!(entries[0].path.is_empty());
+        assert!(entries.windows(2).all(|pair| pair[0].path < pair[1].path));
+
+        // Every stored node is reached, and copying them rebuilds the trie
+        let export = trie.export().unwrap();
+        let stored: Vec<_> = entries
+            .iter()
+            .filter(|entry| entry.hash.is_some())
+            .collect();
+        assert_eq!(stored.len(), export.nodes.len());
+
+        let copy = Arc::new(MemoryDB::new(true));
+        for entry in stored {
+            let hash: B256 = keccak(&entry.encoded).as_fixed_bytes().into();
+            assert_eq!(entry.hash, Some(hash));
+            copy.insert(hash.as_slice(), entry.encoded.clone()).unwrap();
+        }
+        let copied = EthTrie::from(copy, root).unwrap();
+        assert_eq!(
+            copied.iter().map(|item| item.unwrap()).collect::<Vec<_>>(),
+            trie.iter().map(|item| item.unwrap()).collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn test_iter_nodes_inline() {
+        let mut trie = EthTrie::new(Arc::new(MemoryDB::new(true)));
+        assert!(trie.iter_nodes().next().is_none());
+
+        trie.insert(b"a", b"x1").unwrap();
+        trie.insert(b"b", b"x2").unwrap();
+        let entries: Vec<_> = trie.iter_nodes().map(|entry| entry.unwrap()).collect();
+        // A branch below an extension, with two small leaves inlined
+        assert_eq!(entries.len(), 4);
+        assert!(entries[0].hash.is_some());
+        assert_eq!(entries[2].path, Nibbles::from_hex(&[6, 1]));
+        assert!(entries[2].hash.is_none());
+    }
+}
@@ -0,0 +1,125 @@
+use std::fmt;
+use std::sync::Arc;
+
+use alloy_primitives::B256;
+
+use crate::db::DB;
+use crate::trie::EthTrie;
+
+/// Figures about a finished commit, passed to `CommitObserver::commit_finished`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CommitStats {
+    pub root: B256,
+    pub previous_root: B256,
+    pub nodes_written: usize,
+    /// The number of stale nodes whose removal was passed to the database.
+    pub nodes_removed: usize,
+}
+
+/// Callbacks made by the commits of the tries an observer is attached to, with
+/// `EthTrieBuilder::observer` or `EthTrie::add_observer`. Every method does nothing by
+/// default.
+///
+/// Callbacks are made once the database calls they report have succeeded, so a failed
+/// commit may have reported some writes but never reports `commit_finished`.
+pub trait CommitObserver: Send + Sync {
+    /// A node was written to the database.
+    fn node_written(&self, _hash: &B256, _encoded: &[u8]) {}
+
+    /// A stale node was removed from the database. Databases that retain removed keys
+    /// still report them.
+    fn node_removed(&self, _hash: &B256) {}
+
+    fn commit_finished(&self, _stats: &CommitStats) {}
+}
+
+// The observers attached to a trie.
+#[derive(Clone, Default)]
+pub(crate) struct Observers(pub(crate) Vec<Arc<dyn CommitObserver>>);
+
+impl fmt::Debug for Observers {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Observers({})", self.0.len())
+    }
+}
+
+impl<D> EthTrie<D>
+where
+    D: DB,
+{
+    /// Attaches an observer to the commits of this trie.
+    pub fn add_observer(&mut self, observer: Arc<dyn CommitObserver>) {
+        self.config.observers.0.push(observer);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use alloy_primitives::B256;
+    use keccak_hash::keccak;
+
+    use super::{CommitObserver, CommitStats};
+    use crate::db::{MemoryDB, DB};
+    use crate::trie::{EthTrie, TrieRead, TrieWrite};
+
+    // Copies every written node to a replica and keeps the commit stats.
+    #[derive(Default)]
+    struct Replicator {
+        replica: Arc<MemoryDB>,
+        removed: Mutex<Vec<B256>>,
+        commits: Mutex<Vec<CommitStats>>,
+    }
+
+    impl CommitObserver for Replicator {
+        fn node_written(&self, hash: &B256, encoded: &[u8]) {
+            assert_eq!(keccak(encoded).as_bytes(), hash.as_slice());
+            self.replica
+                .insert(hash.as_slice(), encoded.to_vec())
+                .unwrap();
+        }
+
+        fn node_removed(&self, hash: &B256) {
+            self.removed.lock().unwrap().push(*hash);
+        }
+
+        fn commit_finished(&self, stats: &CommitStats) {
+            self.commits.lock().unwrap().push(*stats);
+        }
+    }
+
+    #[test]
+    fn test_commit_observer() {
+        let replicator = Arc::new(Replicator {
+            replica: Arc::new(MemoryDB::new(true)),
+            ..Default::default()
+        });
+        let mut trie = EthTrie::builder(Arc::new(MemoryDB::new(true)))
+            .observer(replicator.clone())
+            .build()
+            .unwrap();
+
+        for i in 0..50u8 {
+            trie.insert(&[i], &[i; 40]).unwrap();
+        }
+        let first = trie.root_hash().unwrap();
+        trie.insert(&[7], &[0; 40]).unwrap();
+        let second = trie.root_hash().unwrap();
+
+        let replica = EthTrie::from(replicator.replica.clone(), second).unwrap();
+        assert_eq!(replica.get(&[7]).unwrap(), Some(vec![0; 40]));
+        assert_eq!(replica.get(&[8]).unwrap(), Some(vec![8; 40]));
+
+        let commits = replicator.commits.lock().unwrap();
+        assert_eq!(commits.len(), 2);
+        assert_eq!(commits[0].root, first);
+        assert_eq!(commits[1].previous_root, first);
+        assert_eq!(commits[1].root, second);
+        assert_eq!(
+            commits[1].nodes_removed,
+            replicator.removed.lock().unwrap().len()
+        );
+        assert!(commits[1].nodes_removed > 0);
+    }
+}
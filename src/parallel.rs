@@ -0,0 +1,264 @@
+use std::sync::Arc;
+
+use crate::db::DB;
+use crate::errors::TrieError;
+use crate::nibbles::Nibbles;
+use crate::node::{empty_children, BranchNode, Node};
+use crate::sync::RwLock;
+use crate::trie::{EthTrie, TrieResult};
+
+// The updates under one child of the root: the path, key and value of each.
+type Partition = Vec<(Nibbles, Vec<u8>, Vec<u8>)>;
+
+impl<D> EthTrie<D>
+where
+    D: DB,
+{
+    /// Applies a large set of inserts and removals, updating the subtrees under each of
+    /// the 16 children of the root on a separate thread. As with `insert`, an empty value
+    /// removes the key, or fails with `TrieError::InvalidData` if the trie is strict.
+    /// Updates to the same key are applied in the order given.
+    ///
+    /// The trie ends up as if the updates were applied one by one, with the changes left
+    /// uncommitted, or unchanged if an update fails. The updates count towards
+    /// `auto_flush` once they are all applied.
+    pub fn update_parallel(&mut self, mut updates: Vec<(Vec<u8>, Vec<u8>)>) -> TrieResult<()> {
+        if self.config.strict && updates.iter().any(|(_, value)| value.is_empty()) {
+            return Err(TrieError::InvalidData);
+        }
+        let writes = updates.len();
+        // The sort is stable, so updates to the same key stay in order
+        updates.sort_by(|a, b| a.0.cmp(&b.0));
+
+        let checkpoint = self.checkpoint();
+        if let Err(error) = self.apply_partitioned(updates) {
+            self.revert_to(checkpoint);
+            return Err(error);
+        }
+        self.discard(checkpoint);
+        self.count_writes(writes)
+    }
+
+    fn apply_partitioned(&mut self, updates: Vec<(Vec<u8>, Vec<u8>)>) -> TrieResult<()> {
+        let mut root = self.root_branch()?;
+        let mut partitions: Vec<Partition> = vec![vec![]; 16];
+        for (key, value) in updates {
+            let path = Nibbles::from_raw(&key, true);
+            match path.at(0) {
+                // The empty key, whose value the root branch holds
+                0x10 => {
+                    let had_value = root.value.is_some();
+                    root.value = (!value.is_empty()).then_some(value);
+                    if had_value != root.value.is_some() {
+                        self.adjust_leaf_count(!had_value);
+                    }
+                }
+                nibble => partitions[nibble].push((path, key, value)),
+            }
+        }
+
+        let leaf_count = self.leaf_count;
+        let results: Vec<_> = std::thread::scope(|scope| {
+            let workers: Vec<_> = partitions
+                .into_iter()
+                .enumerate()
+                .filter(|(_, updates)| !updates.is_empty())
+                .map(|(nibble, updates)| {
+                    let mut worker = self.worker();
+                    let child = root.children[nibble].clone();
+                    let handle = scope.spawn(move || {
+                        let child = worker.apply_updates(child, updates)?;
+                        TrieResult::Ok((child, worker))
+                    });
+                    (nibble, handle)
+                })
+                .collect();
+            workers
+                .into_iter()
+                .map(|(nibble, handle)| (nibble, handle.join().expect("an update worker panicked")))
+                .collect()
+        });
+
+        let mut added = 0isize;
+        for (nibble, result) in results {
+            let (child, worker) = result?;
+            root.children[nibble] = child;
+            self.passing_keys.extend(worker.passing_keys);
+            if let (Some(before), Some(after)) = (leaf_count, worker.leaf_count) {
+                added += after as isize - before as isize;
+            }
+        }
+        self.leaf_count = leaf_count.map(|count| (count as isize + added) as usize);
+
+        let is_empty =
+            root.value.is_none() && root.children.iter().all(|c| matches!(c, Node::Empty));
+        self.root = match is_empty {
+            true => Node::Empty,
+            false => self.degenerate(Node::Branch(Arc::new(RwLock::new(root))))?,
+        };
+        Ok(())
+    }
+
+    // Returns the root as a new branch, splitting a leaf or extension root into one.
+    fn root_branch(&self) -> TrieResult<BranchNode> {
+        let mut branch = BranchNode {
+            children: empty_children(),
+            value: None,
+        };
+        let root = match &self.root {
+            Node::Hash(hash) => {
+                self.get_node(hash.hash)?
+                    .ok_or_else(|| TrieError::MissingTrieNode {
+                        node_hash: hash.hash,
+                        traversed: None,
+                        root_hash: Some(self.root_hash),
+                        err_key: None,
+                    })?
+            }
+            root => root.clone(),
+        };
+        match root {
+            Node::Empty | Node::Hash(_) => {}
+            Node::Leaf(leaf) => match leaf.key.at(0) {
+                0x10 => branch.value = Some(leaf.value.clone()),
+                nibble => {
+                    branch.children[nibble] =
+                        Node::from_leaf(leaf.key.offset(1), leaf.value.clone())
+                }
+            },
+            Node::Extension(ext) => {
+                let ext = ext.read();
+                branch.children[ext.prefix.at(0)] = match ext.prefix.len() {
+                    1 => ext.node.clone(),
+                    _ => Node::from_extension(ext.prefix.offset(1), ext.node.clone()),
+                };
+            }
+            Node::Branch(root) => {
+                let root = root.read();
+                branch.children = root.children.clone();
+                branch.value = root.value.clone();
+            }
+        }
+        Ok(branch)
+    }
+
+    // Returns a trie to apply the updates of one partition with. It reads from the same
+    // database, and tracks the leaf count and the nodes the updates replace.
+    fn worker(&self) -> Self {
+        let mut worker = EthTrie::new(self.db.clone());
+        worker.root_hash = self.root_hash;
+        worker.leaf_count = self.leaf_count;
+        worker.config = self.config.clone();
+        worker
+    }
+
+    // Applies the updates of a partition to the child of the root that holds it.
+    fn apply_updates(&mut self, mut node: Node, updates: Partition) -> TrieResult<Node> {
+        for (path, key, value) in updates {
+            let result = match value.is_empty() {
+                true => self.delete_at(&node, &path, 1).map(|(node, _)| node),
+                false => self.insert_at(node, &path, 1, value),
+            };
+            node = result.map_err(|error| match error {
+                TrieError::MissingTrieNode {
+                    node_hash,
+                    traversed,
+                    root_hash,
+                    err_key: _,
+                } => TrieError::MissingTrieNode {
+                    node_hash,
+                    traversed,
+                    root_hash,
+                    err_key: Some(key),
+                },
+                error => error,
+            })?;
+        }
+        Ok(node)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use crate::db::{IterableDB, MemoryDB, DB};
+    use crate::errors::TrieError;
+    use crate::trie::{EthTrie, TrieRead, TrieWrite};
+
+    #[test]
+    fn test_update_parallel() {
+        let memdb = Arc::new(MemoryDB::new(true));
+        let mut trie = EthTrie::new(memdb.clone());
+        let mut expected = EthTrie::new(Arc::new(MemoryDB::new(true)));
+        let updates: Vec<_> = (0..1000u32)
+            .map(|i| (i.to_be_bytes().to_vec(), i.to_le_bytes().repeat(10)))
+            .chain([(vec![], b"empty key".to_vec())])
+            .collect();
+        trie.update_parallel(updates.clone()).unwrap();
+        for (key, value) in updates.iter() {
+            expected.insert(key, value).unwrap();
+        }
+        assert_eq!(trie.len().unwrap(), 1001);
+        assert_eq!(trie.root_hash().unwrap(), expected.root_hash().unwrap());
+
+        // Changes, removals and repeated keys on a committed trie
+        let updates: Vec<_> = (0..1000u32)
+            .step_by(3)
+            .map(|i| (i.to_be_bytes().to_vec(), vec![]))
+            .chain((0..50u32).map(|i| (i.to_be_bytes().to_vec(), b"first".to_vec())))
+            .chain((0..50u32).map(|i| (i.to_be_bytes().to_vec(), b"second".to_vec())))
+            .chain([(vec![], vec![])])
+            .collect();
+        trie.update_parallel(updates.clone()).unwrap();
+        for (key, value) in updates.iter() {
+            expected.insert(key, value).unwrap();
+        }
+        assert_eq!(trie.len().unwrap(), expected.len().unwrap());
+        assert_eq!(trie.root_hash().unwrap(), expected.root_hash().unwrap());
+        assert_eq!(
+            trie.get(&3u32.to_be_bytes()).unwrap(),
+            Some(b"second".to_vec())
+        );
+        assert_eq!(memdb.len().unwrap(), expected.db.len().unwrap());
+
+        // Removing everything leaves an empty trie
+        let updates = (0..1000u32).map(|i| (i.to_be_bytes().to_vec(), vec![]));
+        trie.update_parallel(updates.collect()).unwrap();
+        assert!(trie.is_empty().unwrap());
+        assert_eq!(trie.len().unwrap(), 0);
+    }
+
+    #[test]
+    fn test_update_parallel_leaves_trie_on_error() {
+        let memdb = Arc::new(MemoryDB::new(true));
+        let mut trie = EthTrie::new(memdb.clone());
+        for i in 0..100u8 {
+            trie.insert(&[i], &[i; 40]).unwrap();
+        }
+        let root = trie.root_hash().unwrap();
+        let missing = memdb
+            .keys()
+            .unwrap()
+            .into_iter()
+            .find(|key| key.len() == 32 && key != root.as_slice())
+            .unwrap();
+        memdb.remove(&missing).unwrap();
+
+        let updates: Vec<_> = (0..100u8).map(|i| (vec![i], vec![0xff; 40])).collect();
+        let error = trie.update_parallel(updates).unwrap_err();
+        assert!(matches!(
+            error,
+            TrieError::MissingTrieNode {
+                err_key: Some(_),
+                ..
+            }
+        ));
+        assert!(!trie.is_dirty());
+
+        let mut strict = EthTrie::builder(memdb).strict(true).build().unwrap();
+        let updates = vec![(b"a".to_vec(), b"a".to_vec()), (b"b".to_vec(), vec![])];
+        assert_eq!(strict.update_parallel(updates), Err(TrieError::InvalidData));
+        assert!(!strict.is_dirty());
+    }
+}
@@ -0,0 +1,573 @@
+use std::error::Error;
+use std::fmt;
+use std::sync::Arc;
+
+use alloy_primitives::B256;
+use hashbrown::{HashMap, HashSet};
+use keccak_hash::{keccak, KECCAK_NULL_RLP};
+use parking_lot::Mutex;
+
+use crate::db::DB;
+use crate::errors::TrieError;
+use crate::node::Node;
+use crate::trie::{
+    decode_node, EthTrie, RootWithTrieDiff, TrieIterator, TrieRead, TrieResult, TrieWrite,
+    HASHED_LENGTH,
+};
+
+// Nodes are stored under this byte followed by their path, one nibble per byte.
+const PATH_NODE_PREFIX: u8 = b'n';
+// Other keys the trie writes are stored under this byte followed by the key.
+const PATH_META_PREFIX: u8 = b'm';
+
+/// The database a `PathTrie` stores its nodes through. The trie asks for nodes by hash;
+/// the wrapper stores each under its nibble path from the root instead, so that the
+/// inner database holds one trie, updated in place.
+///
+/// The path of a node is found from its parent: the root is stored under the empty path,
+/// and the path of each node read is recorded for the children it refers to by hash.
+/// A batch of nodes is placed by walking it from the one node no other node of the batch
+/// refers to, so it must hold the nodes of one commit, as `EthTrie` writes them. Reads are
+/// checked against the hash they ask for, so a node replaced in place reads as missing.
+#[derive(Debug)]
+pub struct PathDB<D>
+where
+    D: DB,
+{
+    db: D,
+    state: Mutex<PathState>,
+}
+
+#[derive(Debug, Default)]
+struct PathState {
+    // The paths of the nodes read or written since the last batch, by hash.
+    paths: HashMap<B256, Vec<Vec<u8>>>,
+    // The paths known before the last batch, where the nodes it made stale were.
+    previous: HashMap<B256, Vec<Vec<u8>>>,
+    // The nodes of the last batch by path, until the stale nodes after it are removed.
+    written: Option<HashMap<Vec<u8>, Node>>,
+}
+
+impl PathState {
+    fn record(&mut self, hash: B256, path: Vec<u8>) {
+        let paths = self.paths.entry(hash).or_default();
+        if !paths.contains(&path) {
+            paths.push(path);
+        }
+    }
+
+    // Records the paths of the children of a node at `path` that are stored apart from it.
+    fn record_children(&mut self, path: &[u8], node: &Node) {
+        for (hash, child_path) in hashed_children(path, node) {
+            self.record(hash, child_path);
+        }
+    }
+}
+
+/// The error of a `PathDB`: either a batch of nodes that doesn't form one trie, or an
+/// error of the inner database.
+#[derive(Debug)]
+pub enum PathDBError<E> {
+    /// A node was written that can't be placed under the root of its batch.
+    Unplaced(B256),
+    Inner(E),
+}
+
+impl<E> Error for PathDBError<E>
+where
+    E: Error + 'static,
+{
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            PathDBError::Unplaced(_) => None,
+            PathDBError::Inner(err) => Some(err),
+        }
+    }
+}
+
+impl<E> fmt::Display for PathDBError<E>
+where
+    E: fmt::Display,
+{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            PathDBError::Unplaced(hash) => write!(f, "node {} has no path in its batch", hash),
+            PathDBError::Inner(err) => write!(f, "{}", err),
+        }
+    }
+}
+
+impl<D> PathDB<D>
+where
+    D: DB,
+{
+    pub fn new(db: D) -> Self {
+        Self {
+            db,
+            state: Mutex::new(PathState::default()),
+        }
+    }
+
+    pub fn inner(&self) -> &D {
+        &self.db
+    }
+
+    /// Returns the encoded node stored under a nibble path, one nibble per byte.
+    pub fn node_at(&self, path: &[u8]) -> Result<Option<Vec<u8>>, PathDBError<D::Error>> {
+        self.db.get(&node_key(path)).map_err(PathDBError::Inner)
+    }
+
+    /// Returns the hash of the root node stored, or `None` if no trie is stored.
+    pub fn root(&self) -> Result<Option<B256>, PathDBError<D::Error>> {
+        Ok(self
+            .node_at(&[])?
+            .map(|node| keccak(node).as_fixed_bytes().into()))
+    }
+
+    // Reads the node stored under `hash` from the paths it is known at, falling back to
+    // the root.
+    fn get_node(&self, hash: B256) -> Result<Option<Vec<u8>>, PathDBError<D::Error>> {
+        let mut state = self.state.lock();
+        let mut paths = state.paths.get(&hash).cloned().unwrap_or_default();
+        paths.push(vec![]);
+        for path in paths {
+            let Some(encoded) = self.node_at(&path)? else {
+                continue;
+            };
+            if B256::from(keccak(&encoded).as_fixed_bytes()) != hash {
+                continue;
+            }
+            if let Ok(node) = decode_node(&mut encoded.as_slice()) {
+                state.record_children(&path, &node);
+            }
+            return Ok(Some(encoded));
+        }
+        Ok(None)
+    }
+
+    // Removes the node stored under `hash` at each path it was known at that the last
+    // batch didn't write or keep.
+    fn remove_node(&self, state: &PathState, hash: B256, removed: &mut HashSet<Vec<u8>>) {
+        let paths = state.previous.get(&hash).into_iter().flatten();
+        for path in paths.chain(state.paths.get(&hash).into_iter().flatten()) {
+            let kept = state
+                .written
+                .as_ref()
+                .is_some_and(|written| reachable(written, path));
+            if !kept {
+                removed.insert(node_key(path));
+            }
+        }
+    }
+
+    // Removes the nodes at every path known before the last batch that the trie it wrote
+    // no longer reaches. A node the trie doesn't report stale can still be left at such a
+    // path: identical nodes are common, and one written again elsewhere by the batch
+    // isn't stale by hash.
+    fn remove_unreachable(&self, state: &PathState, removed: &mut HashSet<Vec<u8>>) {
+        let Some(written) = state.written.as_ref() else {
+            return;
+        };
+        for path in state.previous.values().flatten() {
+            if !reachable(written, path) {
+                removed.insert(node_key(path));
+            }
+        }
+    }
+}
+
+impl<D> DB for PathDB<D>
+where
+    D: DB,
+{
+    type Error = PathDBError<D::Error>;
+
+    fn get(&self, key: &[u8]) -> Result<Option<Vec<u8>>, Self::Error> {
+        match key.len() == HASHED_LENGTH {
+            true => self.get_node(B256::from_slice(key)),
+            false => self.db.get(&meta_key(key)).map_err(PathDBError::Inner),
+        }
+    }
+
+    fn insert(&self, key: &[u8], value: Vec<u8>) -> Result<(), Self::Error> {
+        self.insert_batch(vec![key.to_vec()], vec![value])
+    }
+
+    fn remove(&self, key: &[u8]) -> Result<(), Self::Error> {
+        self.remove_batch(&[key.to_vec()])
+    }
+
+    fn insert_batch(&self, keys: Vec<Vec<u8>>, values: Vec<Vec<u8>>) -> Result<(), Self::Error> {
+        let mut db_keys = vec![];
+        let mut db_values = vec![];
+        let mut nodes = HashMap::new();
+        for (key, value) in keys.into_iter().zip(values) {
+            let node = match key.len() == HASHED_LENGTH {
+                true => decode_node(&mut value.as_slice()).ok(),
+                false => None,
+            };
+            match node {
+                Some(node) => {
+                    nodes.insert(B256::from_slice(&key), (value, node));
+                }
+                None => {
+                    db_keys.push(meta_key(&key));
+                    db_values.push(value);
+                }
+            }
+        }
+
+        // The root is the one node no other node of the batch refers to
+        let referenced: HashSet<B256> = nodes
+            .values()
+            .flat_map(|(_, node)| hashed_children(&[], node))
+            .map(|(hash, _)| hash)
+            .collect();
+        let mut roots = nodes.keys().filter(|hash| !referenced.contains(*hash));
+        let root = roots.next().copied();
+        if let Some(other) = roots.next() {
+            return Err(PathDBError::Unplaced(*other));
+        }
+
+        let mut state = self.state.lock();
+        let mut paths = PathState::default();
+        let mut written = HashMap::new();
+        let mut placed = HashSet::new();
+        let mut stack: Vec<(B256, Vec<u8>)> = root.map(|root| (root, vec![])).into_iter().collect();
+        while let Some((hash, path)) = stack.pop() {
+            let (encoded, node) = &nodes[&hash];
+            for (child, child_path) in hashed_children(&path, node) {
+                match nodes.contains_key(&child) {
+                    true => stack.push((child, child_path)),
+                    // Nodes the commit didn't change stay where they are
+                    false => paths.record(child, child_path),
+                }
+            }
+            db_keys.push(node_key(&path));
+            db_values.push(encoded.clone());
+            paths.record(hash, path.clone());
+            written.insert(path, node.clone());
+            placed.insert(hash);
+        }
+        if let Some(hash) = nodes.keys().find(|hash| !placed.contains(*hash)) {
+            return Err(PathDBError::Unplaced(*hash));
+        }
+
+        self.db
+            .insert_batch(db_keys, db_values)
+            .map_err(PathDBError::Inner)?;
+        if !written.is_empty() {
+            state.previous = std::mem::replace(&mut state.paths, paths.paths);
+            state.written = Some(written);
+        }
+        Ok(())
+    }
+
+    fn remove_batch(&self, keys: &[Vec<u8>]) -> Result<(), Self::Error> {
+        let mut state = self.state.lock();
+        let mut removed = HashSet::new();
+        for key in keys {
+            match key.len() == HASHED_LENGTH {
+                true => self.remove_node(&state, B256::from_slice(key), &mut removed),
+                false => {
+                    removed.insert(meta_key(key));
+                }
+            }
+        }
+        // Only the removals made right after a batch are of the nodes it made stale
+        self.remove_unreachable(&state, &mut removed);
+        state.written = None;
+        state.previous.clear();
+        let removed: Vec<Vec<u8>> = removed.into_iter().collect();
+        self.db.remove_batch(&removed).map_err(PathDBError::Inner)
+    }
+
+    fn flush(&self) -> Result<(), Self::Error> {
+        self.db.flush().map_err(PathDBError::Inner)
+    }
+
+    #[cfg(test)]
+    fn len(&self) -> Result<usize, Self::Error> {
+        self.db.len().map_err(PathDBError::Inner)
+    }
+    #[cfg(test)]
+    fn is_empty(&self) -> Result<bool, Self::Error> {
+        self.db.is_empty().map_err(PathDBError::Inner)
+    }
+}
+
+/// A trie that stores its nodes under their nibble path rather than their hash, through a
+/// `PathDB`, for applications that only ever need the latest root of one trie.
+///
+/// A commit writes each changed node over the one at its path, and removes only the nodes
+/// whose paths the trie no longer reaches, so stale nodes never pile up and need no
+/// pruning. Nodes at nearby paths are stored under nearby keys, which suits range reads
+/// of ordered databases. Roots, proofs and iteration are those of an `EthTrie` with the
+/// same entries; reading at older roots is not possible, as their nodes are overwritten.
+#[derive(Debug)]
+pub struct PathTrie<D>
+where
+    D: DB,
+{
+    trie: EthTrie<PathDB<D>>,
+}
+
+impl<D> PathTrie<D>
+where
+    D: DB,
+{
+    /// Opens the trie stored in `db`, or an empty one if it holds none.
+    pub fn new(db: D) -> TrieResult<Self> {
+        let db = Arc::new(PathDB::new(db));
+        let empty_root: B256 = KECCAK_NULL_RLP.as_fixed_bytes().into();
+        let trie = match db.root().map_err(TrieError::db)? {
+            Some(root) if root != empty_root => EthTrie::from(db, root)?,
+            _ => EthTrie::new(db),
+        };
+        Ok(Self { trie })
+    }
+
+    pub fn db(&self) -> &Arc<PathDB<D>> {
+        &self.trie.db
+    }
+
+    /// Returns the entries whose keys start with `prefix`, in key order.
+    pub fn scan_prefix<'a>(
+        &'a self,
+        prefix: &'a [u8],
+    ) -> TrieResult<impl Iterator<Item = TrieResult<(Vec<u8>, Vec<u8>)>> + 'a> {
+        let mut iter = self.trie.iter();
+        iter.seek(prefix)?;
+        Ok(iter.take_while(move |entry| match entry {
+            Ok((key, _)) => key.starts_with(prefix),
+            Err(_) => true,
+        }))
+    }
+}
+
+impl<D> TrieRead<PathDB<D>> for PathTrie<D>
+where
+    D: DB,
+{
+    type Iter<'a>
+        = TrieIterator<'a, PathDB<D>>
+    where
+        Self: 'a;
+
+    fn get(&self, key: &[u8]) -> TrieResult<Option<Vec<u8>>> {
+        self.trie.get(key)
+    }
+
+    fn get_with<R, G>(&self, key: &[u8], f: G) -> TrieResult<Option<R>>
+    where
+        G: FnOnce(&[u8]) -> R,
+    {
+        self.trie.get_with(key, f)
+    }
+
+    fn contains(&self, key: &[u8]) -> TrieResult<bool> {
+        self.trie.contains(key)
+    }
+
+    fn get_proof(&self, key: &[u8]) -> TrieResult<Vec<Vec<u8>>> {
+        self.trie.get_proof(key)
+    }
+
+    fn verify_proof(
+        &self,
+        root_hash: B256,
+        key: &[u8],
+        proof: Vec<Vec<u8>>,
+    ) -> TrieResult<Option<Vec<u8>>> {
+        self.trie.verify_proof(root_hash, key, proof)
+    }
+
+    fn iter(&self) -> Self::Iter<'_> {
+        self.trie.iter()
+    }
+
+    fn seek_iter<'a>(&'a self, iter: &mut Self::Iter<'a>, key: &[u8]) -> TrieResult<()> {
+        self.trie.seek_iter(iter, key)
+    }
+}
+
+impl<D> TrieWrite<PathDB<D>> for PathTrie<D>
+where
+    D: DB,
+{
+    fn insert(&mut self, key: &[u8], value: &[u8]) -> TrieResult<()> {
+        self.trie.insert(key, value)
+    }
+
+    fn remove(&mut self, key: &[u8]) -> TrieResult<bool> {
+        self.trie.remove(key)
+    }
+
+    fn root_hash(&mut self) -> TrieResult<B256> {
+        self.trie.root_hash()
+    }
+
+    fn root_hash_with_changed_nodes(&mut self) -> TrieResult<RootWithTrieDiff> {
+        self.trie.root_hash_with_changed_nodes()
+    }
+
+    fn clear_trie_from_db(&mut self) -> TrieResult<()> {
+        self.trie.clear_trie_from_db()
+    }
+}
+
+fn node_key(path: &[u8]) -> Vec<u8> {
+    [&[PATH_NODE_PREFIX], path].concat()
+}
+
+fn meta_key(key: &[u8]) -> Vec<u8> {
+    [&[PATH_META_PREFIX], key].concat()
+}
+
+// Returns the children of a node at `path` that are stored apart from it, with their
+// paths.
+fn hashed_children(path: &[u8], node: &Node) -> Vec<(B256, Vec<u8>)> {
+    let mut children: Vec<(B256, Vec<u8>)> = node
+        .child_hashes()
+        .into_iter()
+        .map(|(i, hash)| (hash, [path, &[i]].concat()))
+        .collect();
+    if let (Some(Node::Hash(child)), Some(prefix)) = (node.extension_child(), node.prefix()) {
+        children.push((child.hash, [path, prefix.get_data()].concat()));
+    }
+    children
+}
+
+// Returns whether the trie whose changed nodes were just written still has a node at
+// `path`: either one of them, or one under a node the commit didn't change.
+fn reachable(written: &HashMap<Vec<u8>, Node>, path: &[u8]) -> bool {
+    let mut at = vec![];
+    loop {
+        if at == path {
+            return true;
+        }
+        let Some(node) = written.get(&at) else {
+            return true;
+        };
+        let next = match node {
+            Node::Branch(branch) => {
+                let i = path[at.len()];
+                match branch.read().children[i as usize] {
+                    Node::Hash(_) => vec![i],
+                    _ => return false,
+                }
+            }
+            Node::Extension(ext) => {
+                let ext = ext.read();
+                let prefix = ext.prefix.get_data();
+                match ext.node {
+                    Node::Hash(_) if path[at.len()..].starts_with(prefix) => prefix.to_vec(),
+                    _ => return false,
+                }
+            }
+            _ => return false,
+        };
+        at.extend(next);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use rand::rngs::StdRng;
+    use rand::{Rng, SeedableRng};
+
+    use super::PathTrie;
+    use crate::db::{IterableDB, MemoryDB};
+    use crate::trie::{EthTrie, TrieRead, TrieWrite};
+
+    // Returns the number of nodes the trie stores apart from their parent.
+    fn stored_nodes(trie: &PathTrie<MemoryDB>) -> usize {
+        trie.trie
+            .iter_nodes()
+            .filter(|entry| entry.as_ref().unwrap().hash.is_some())
+            .count()
+    }
+
+    #[test]
+    fn test_path_trie_matches_eth_trie() {
+        for seed in 0..10 {
+            check_path_trie_matches_eth_trie(seed);
+        }
+    }
+
+    fn check_path_trie_matches_eth_trie(seed: u64) {
+        let mut rng = StdRng::seed_from_u64(seed);
+        let mut path_trie = PathTrie::new(MemoryDB::new(true)).unwrap();
+        // Identical nodes at several paths are common with these entries, which removing
+        // stale nodes by hash doesn't allow for
+        let mut trie = EthTrie::builder(Arc::new(MemoryDB::new(true)))
+            .retain_stale_nodes(true)
+            .build()
+            .unwrap();
+        let mut keys = vec![];
+        for round in 0..10 {
+            for _ in 0..100 {
+                let key: Vec<u8> = (0..rng.gen_range(1..6)).map(|_| rng.gen()).collect();
+                let value = vec![round; rng.gen_range(1..40)];
+                path_trie.insert(&key, &value).unwrap();
+                trie.insert(&key, &value).unwrap();
+                keys.push(key);
+            }
+            for _ in 0..30 {
+                let key = &keys[rng.gen_range(0..keys.len())];
+                assert_eq!(path_trie.remove(key).unwrap(), trie.remove(key).unwrap());
+            }
+            let root = path_trie.root_hash().unwrap();
+            assert_eq!(
+                root,
+                trie.root_hash().unwrap(),
+                "seed {seed}, round {round}"
+            );
+
+            // Nodes are overwritten in place, and those at paths the trie no longer
+            // reaches removed
+            let db = path_trie.db().inner();
+            let node_keys = db.keys().unwrap().into_iter().filter(|key| key[0] == b'n');
+            assert_eq!(
+                node_keys.count(),
+                stored_nodes(&path_trie),
+                "seed {seed}, round {round}"
+            );
+        }
+
+        let root = trie.root_hash().unwrap();
+        let db = path_trie.db().clone();
+        drop(path_trie);
+        let db = Arc::try_unwrap(db).unwrap().db;
+        let mut reopened = PathTrie::new(db).unwrap();
+        assert_eq!(reopened.root_hash().unwrap(), root);
+        for key in keys.iter() {
+            assert_eq!(reopened.get(key).unwrap(), trie.get(key).unwrap());
+        }
+    }
+
+    #[test]
+    fn test_path_trie_scan_prefix() {
+        let mut trie = PathTrie::new(MemoryDB::new(true)).unwrap();
+        for i in 0..=255u8 {
+            trie.insert(&[i >> 4, i], &[i; 8]).unwrap();
+        }
+        trie.root_hash().unwrap();
+        let scanned: Vec<_> = trie
+            .scan_prefix(&[3])
+            .unwrap()
+            .map(Result::unwrap)
+            .collect();
+        assert_eq!(scanned.len(), 16);
+        assert!(scanned
+            .iter()
+            .all(|(key, value)| key[0] == 3 && value[0] == key[1]));
+
+        trie.clear_trie_from_db().unwrap();
+        assert_eq!(trie.get(&[3, 0x30]).unwrap(), None);
+        let db = trie.db().inner();
+        assert!(db.keys().unwrap().iter().all(|key| key[0] != b'n'));
+    }
+}
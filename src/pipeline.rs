@@ -0,0 +1,161 @@
+use std::sync::mpsc;
+
+use alloy_primitives::B256;
+
+use crate::db::DB;
+use crate::errors::TrieError;
+use crate::trie::{EthTrie, PreparedCommit, TrieResult};
+
+// The number of batches encoding can get ahead of the writes.
+const PIPELINE_DEPTH: usize = 2;
+
+impl<D> EthTrie<D>
+where
+    D: DB,
+{
+    // Prepares a commit like `prepare_commit`, while another thread writes the nodes
+    // encoded so far in batches of `batch_size`. A node can only be encoded once its
+    // children are hashed, so encoding and hashing stay on this thread and the writes
+    // overlap with both. The last batch, which holds the root node, is left in the
+    // prepared commit.
+    pub(crate) fn prepare_commit_pipelined(
+        &mut self,
+        return_changed_nodes: bool,
+        batch_size: usize,
+    ) -> TrieResult<PreparedCommit> {
+        let db = self.db.clone();
+        let keep = return_changed_nodes
+            || !self.subscribers.is_empty()
+            || !self.config.observers.0.is_empty();
+        let (sender, receiver) = mpsc::sync_channel::<Vec<(B256, Vec<u8>)>>(PIPELINE_DEPTH);
+
+        std::thread::scope(|scope| {
+            let writer = scope.spawn(move || {
+                for batch in receiver {
+                    let (keys, values) = batch.into_iter().map(|(k, v)| (k.to_vec(), v)).unzip();
+                    db.insert_batch(keys, values)?;
+                }
+                Ok(())
+            });
+
+            let mut spilled_count = 0;
+            let mut spilled = vec![];
+            let prepared = self.prepare_commit_with(return_changed_nodes, &mut |cache| {
+                if cache.len() < batch_size {
+                    return;
+                }
+                let batch: Vec<_> = cache.drain().collect();
+                spilled_count += batch.len();
+                if keep {
+                    spilled.extend(batch.iter().cloned());
+                }
+                // The writer only stops early on an error, which is returned below
+                let _ = sender.send(batch);
+            });
+            drop(sender);
+            let written: Result<(), D::Error> = writer.join().expect("the commit writer panicked");
+
+            let mut prepared = prepared?;
+            written.map_err(TrieError::db)?;
+            prepared.nodes_written += spilled_count;
+            if prepared.return_changed_nodes {
+                prepared.changed_nodes.extend(spilled.iter().cloned());
+            }
+            if !self.config.observers.0.is_empty() {
+                prepared.written.extend(spilled);
+            }
+            Ok(prepared)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use crate::db::{MemoryDB, DB};
+    use crate::trie::{EthTrie, TrieWrite};
+
+    // Records the keys of the batches written to a `MemoryDB`.
+    struct BatchDB {
+        inner: MemoryDB,
+        batches: parking_lot::Mutex<Vec<Vec<Vec<u8>>>>,
+    }
+
+    impl DB for BatchDB {
+        type Error = <MemoryDB as DB>::Error;
+
+        fn get(&self, key: &[u8]) -> Result<Option<Vec<u8>>, Self::Error> {
+            self.inner.get(key)
+        }
+
+        fn insert(&self, key: &[u8], value: Vec<u8>) -> Result<(), Self::Error> {
+            self.inner.insert(key, value)
+        }
+
+        fn remove(&self, key: &[u8]) -> Result<(), Self::Error> {
+            self.inner.remove(key)
+        }
+
+        fn insert_batch(
+            &self,
+            keys: Vec<Vec<u8>>,
+            values: Vec<Vec<u8>>,
+        ) -> Result<(), Self::Error> {
+            self.batches.lock().push(keys.clone());
+            self.inner.insert_batch(keys, values)
+        }
+
+        fn flush(&self) -> Result<(), Self::Error> {
+            self.inner.flush()
+        }
+
+        fn len(&self) -> Result<usize, Self::Error> {
+            self.inner.len()
+        }
+
+        fn is_empty(&self) -> Result<bool, Self::Error> {
+            self.inner.is_empty()
+        }
+    }
+
+    #[test]
+    fn test_pipelined_commit() {
+        let db = Arc::new(BatchDB {
+            inner: MemoryDB::new(true),
+            batches: Default::default(),
+        });
+        let mut trie = EthTrie::builder(db.clone())
+            .pipelined_commit(16)
+            .build()
+            .unwrap();
+        let mut expected = EthTrie::new(Arc::new(MemoryDB::new(true)));
+        for i in 0..500u32 {
+            let key = i.to_be_bytes();
+            trie.insert(&key, &key.repeat(10)).unwrap();
+            expected.insert(&key, &key.repeat(10)).unwrap();
+        }
+
+        let diff = trie.root_hash_with_changed_nodes().unwrap();
+        let expected_diff = expected.root_hash_with_changed_nodes().unwrap();
+        assert_eq!(diff.root, expected_diff.root);
+        assert_eq!(diff.trie_diff, expected_diff.trie_diff);
+
+        // The root node is written with the last batch
+        let batches = db.batches.lock().clone();
+        assert!(batches.len() > 1);
+        assert!(batches[..batches.len() - 1].iter().all(|b| b.len() == 16));
+        assert!(batches.last().unwrap().contains(&diff.root.to_vec()));
+
+        let reopened = EthTrie::from(db.clone(), diff.root).unwrap();
+        assert_eq!(reopened.len().unwrap(), 500);
+
+        // Stale nodes are removed as without pipelining
+        for i in 0..100u32 {
+            trie.insert(&i.to_be_bytes(), b"changed").unwrap();
+            expected.insert(&i.to_be_bytes(), b"changed").unwrap();
+        }
+        assert_eq!(trie.root_hash().unwrap(), expected.root_hash().unwrap());
+        assert_eq!(db.len().unwrap(), expected.db.len().unwrap());
+    }
+}
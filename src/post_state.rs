@@ -0,0 +1,342 @@
+use std::sync::Arc;
+
+use alloy_primitives::{B256, U256};
+use alloy_rlp::{Decodable, RlpDecodable, RlpEncodable};
+use hashbrown::{HashMap, HashSet};
+use keccak_hash::KECCAK_NULL_RLP;
+
+use crate::db::DB;
+use crate::errors::TrieError;
+use crate::trie::{EthTrie, TrieRead, TrieResult, TrieWrite};
+
+/// An account as the state trie stores it, RLP encoded.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, RlpEncodable, RlpDecodable)]
+pub struct TrieAccount {
+    pub nonce: u64,
+    pub balance: U256,
+    pub storage_root: B256,
+    pub code_hash: B256,
+}
+
+/// The fields of an account a state change sets. The storage root is derived from the
+/// account's storage.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HashedAccount {
+    pub nonce: u64,
+    pub balance: U256,
+    pub code_hash: B256,
+}
+
+/// The changed storage slots of an account, keyed by the hash of the slot.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct HashedStorage {
+    /// Whether all the storage the account held before is dropped, as when it is
+    /// destroyed and created again.
+    pub wiped: bool,
+    /// The new values of the slots. A zero value removes the slot.
+    pub storage: HashMap<B256, U256>,
+}
+
+/// The changes a block makes to a state, with accounts and slots keyed by their hash.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct HashedPostState {
+    /// The new fields of every changed account, or `None` for a destroyed one.
+    pub accounts: HashMap<B256, Option<HashedAccount>>,
+    /// The changed storage of every account, by account hash.
+    pub storages: HashMap<B256, HashedStorage>,
+}
+
+/// The roots and nodes produced by `HashedPostState::state_root`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StateRootUpdates {
+    pub state_root: B256,
+    /// The new storage root of every account whose storage changed, by account hash.
+    pub storage_roots: HashMap<B256, B256>,
+    /// The nodes to write to persist the new state, by hash.
+    pub nodes: HashMap<B256, Vec<u8>>,
+    /// The hashes of the nodes the previous state held that the new one doesn't, from
+    /// the account trie and every changed storage trie. Tries can hold identical nodes,
+    /// so one of them may still be used by a storage trie that didn't change.
+    pub stale_nodes: HashSet<B256>,
+}
+
+impl HashedPostState {
+    /// Applies the changes to the state at `state_root` and returns the new state root
+    /// with the nodes to write and remove to persist it. Nothing is written to the
+    /// database.
+    ///
+    /// Returns `TrieError::InvalidData` if the storage of an account that neither exists
+    /// nor is created changes.
+    pub fn state_root<D>(&self, db: Arc<D>, state_root: B256) -> TrieResult<StateRootUpdates>
+    where
+        D: DB,
+    {
+        let empty_root: B256 = KECCAK_NULL_RLP.as_fixed_bytes().into();
+        let mut updates = StateRootUpdates {
+            state_root,
+            storage_roots: HashMap::new(),
+            nodes: HashMap::new(),
+            stale_nodes: HashSet::new(),
+        };
+        let mut accounts = open(db.clone(), state_root)?;
+
+        let keys: HashSet<B256> = self
+            .accounts
+            .keys()
+            .chain(self.storages.keys())
+            .copied()
+            .collect();
+        for key in keys {
+            let existing = accounts
+                .get_with(key.as_slice(), |mut value| TrieAccount::decode(&mut value))?
+                .transpose()?;
+            let previous_storage_root = existing.map_or(empty_root, |a| a.storage_root);
+
+            let account = match (self.accounts.get(&key), existing) {
+                (Some(Some(account)), _) => *account,
+                (Some(None), _) => {
+                    updates.drop_storage(&db, previous_storage_root)?;
+                    accounts.remove(key.as_slice())?;
+                    continue;
+                }
+                (None, Some(existing)) => HashedAccount {
+                    nonce: existing.nonce,
+                    balance: existing.balance,
+                    code_hash: existing.code_hash,
+                },
+                (None, None) => return Err(TrieError::InvalidData),
+            };
+
+            let storage_root = match self.storages.get(&key) {
+                Some(storage) => {
+                    let mut base = previous_storage_root;
+                    if storage.wiped {
+                        updates.drop_storage(&db, base)?;
+                        base = empty_root;
+                    }
+                    let mut trie = open(db.clone(), base)?;
+                    for (slot, value) in storage.storage.iter() {
+                        match value.is_zero() {
+                            true => trie.remove(slot.as_slice()).map(|_| ())?,
+                            false => trie.insert(slot.as_slice(), &alloy_rlp::encode(value))?,
+                        }
+                    }
+                    let root = updates.prepare(&mut trie)?;
+                    updates.storage_roots.insert(key, root);
+                    root
+                }
+                None => previous_storage_root,
+            };
+
+            let account = TrieAccount {
+                nonce: account.nonce,
+                balance: account.balance,
+                storage_root,
+                code_hash: account.code_hash,
+            };
+            accounts.insert(key.as_slice(), &alloy_rlp::encode(account))?;
+        }
+
+        updates.state_root = updates.prepare(&mut accounts)?;
+        // A node one trie drops may be written by another
+        let nodes = &updates.nodes;
+        updates.stale_nodes.retain(|hash| !nodes.contains_key(hash));
+        Ok(updates)
+    }
+}
+
+impl StateRootUpdates {
+    // Encodes the changes of a trie without writing them, and adds its nodes.
+    fn prepare<D>(&mut self, trie: &mut EthTrie<D>) -> TrieResult<B256>
+    where
+        D: DB,
+    {
+        let prepared = trie.prepare_commit(true)?;
+        self.stale_nodes
+            .extend(trie.stale_nodes(prepared.root_hash));
+        self.nodes.extend(prepared.changed_nodes);
+        Ok(prepared.root_hash)
+    }
+
+    // Marks every node of the storage trie at `storage_root` stale.
+    fn drop_storage<D>(&mut self, db: &Arc<D>, storage_root: B256) -> TrieResult<()>
+    where
+        D: DB,
+    {
+        let empty_root: B256 = KECCAK_NULL_RLP.as_fixed_bytes().into();
+        if storage_root == empty_root {
+            return Ok(());
+        }
+        let trie = EthTrie::from(db.clone(), storage_root)?;
+        for node in trie.iter_nodes() {
+            self.stale_nodes.extend(node?.hash);
+        }
+        Ok(())
+    }
+}
+
+fn open<D>(db: Arc<D>, root: B256) -> TrieResult<EthTrie<D>>
+where
+    D: DB,
+{
+    let empty_root: B256 = KECCAK_NULL_RLP.as_fixed_bytes().into();
+    match root == empty_root {
+        true => Ok(EthTrie::new(db)),
+        false => EthTrie::from(db, root),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use alloy_primitives::{B256, U256};
+    use hashbrown::HashMap;
+    use keccak_hash::KECCAK_NULL_RLP;
+
+    use super::{HashedAccount, HashedPostState, HashedStorage, StateRootUpdates, TrieAccount};
+    use crate::db::{IterableDB, MemoryDB, DB};
+    use crate::manager::TrieManager;
+    use crate::trie::{EthTrie, TrieRead, TrieWrite};
+
+    fn account(nonce: u64) -> Option<HashedAccount> {
+        Some(HashedAccount {
+            nonce,
+            balance: U256::from(nonce * 1000),
+            code_hash: KECCAK_NULL_RLP.as_fixed_bytes().into(),
+        })
+    }
+
+    fn storage(wiped: bool, slots: &[(u8, u64)]) -> HashedStorage {
+        HashedStorage {
+            wiped,
+            storage: slots
+                .iter()
+                .map(|(slot, value)| (B256::repeat_byte(*slot), U256::from(*value)))
+                .collect(),
+        }
+    }
+
+    // Writes the nodes of the updates into a copy of `db`.
+    fn persist(db: &MemoryDB, updates: &StateRootUpdates) -> Arc<MemoryDB> {
+        let copy = Arc::new(MemoryDB::new(true));
+        for key in db.keys().unwrap() {
+            copy.insert(&key, db.get(&key).unwrap().unwrap()).unwrap();
+        }
+        for (hash, encoded) in updates.nodes.iter() {
+            copy.insert(hash.as_slice(), encoded.clone()).unwrap();
+        }
+        copy
+    }
+
+    #[test]
+    fn test_post_state_root() {
+        let memdb = Arc::new(MemoryDB::new(true));
+        let empty_root: B256 = KECCAK_NULL_RLP.as_fixed_bytes().into();
+        let (alice, bob, carol) = (
+            B256::repeat_byte(1),
+            B256::repeat_byte(2),
+            B256::repeat_byte(3),
+        );
+
+        let mut block = HashedPostState::default();
+        block.accounts.insert(alice, account(1));
+        block.accounts.insert(bob, account(2));
+        block
+            .storages
+            .insert(alice, storage(false, &[(1, 10), (2, 20)]));
+        block.storages.insert(bob, storage(false, &[(1, 30)]));
+        let first = block.state_root(memdb.clone(), empty_root).unwrap();
+        assert!(memdb.is_empty().unwrap());
+        let db = persist(&memdb, &first);
+
+        // The same state built slot by slot and account by account
+        let mut manager = TrieManager::new(Arc::new(MemoryDB::new(true)));
+        for (key, slots) in [(alice, vec![(1, 10), (2, 20)]), (bob, vec![(1, 30)])] {
+            let trie = manager.storage(key, empty_root).unwrap();
+            for (slot, value) in slots {
+                let encoded = alloy_rlp::encode(U256::from(value));
+                trie.insert(B256::repeat_byte(slot).as_slice(), &encoded)
+                    .unwrap();
+            }
+        }
+        let roots = manager
+            .commit(|accounts, storage_roots| {
+                for (key, nonce) in [(alice, 1), (bob, 2)] {
+                    let account = account(nonce).unwrap();
+                    let account = TrieAccount {
+                        nonce,
+                        balance: account.balance,
+                        storage_root: storage_roots[&key],
+                        code_hash: account.code_hash,
+                    };
+                    accounts.insert(key.as_slice(), &alloy_rlp::encode(account))?;
+                }
+                Ok(())
+            })
+            .unwrap();
+        assert_eq!(first.state_root, roots.state_root);
+        assert_eq!(first.storage_roots, roots.storage_roots);
+
+        // Alice's storage changes, Bob is destroyed and Carol is created
+        let mut block = HashedPostState::default();
+        block.accounts.insert(bob, None);
+        block.accounts.insert(carol, account(3));
+        block
+            .storages
+            .insert(alice, storage(false, &[(1, 0), (3, 40)]));
+        let second = block.state_root(db.clone(), first.state_root).unwrap();
+        let db = persist(&db, &second);
+
+        let accounts = EthTrie::from(db.clone(), second.state_root).unwrap();
+        assert_eq!(accounts.get(bob.as_slice()).unwrap(), None);
+        let decode = |key: B256| -> TrieAccount {
+            alloy_rlp::decode_exact(accounts.get(key.as_slice()).unwrap().unwrap()).unwrap()
+        };
+        assert_eq!(decode(carol).storage_root, empty_root);
+        assert_eq!(decode(alice).storage_root, second.storage_roots[&alice]);
+        let storage = EthTrie::from(db.clone(), second.storage_roots[&alice]).unwrap();
+        let slots: HashMap<_, _> = storage.iter().map(|entry| entry.unwrap()).collect();
+        assert_eq!(slots.len(), 2);
+
+        // Bob's storage root, Alice's old one and the old state root are all stale
+        assert!(second.stale_nodes.contains(&first.storage_roots[&bob]));
+        assert!(second.stale_nodes.contains(&first.storage_roots[&alice]));
+        assert!(second.stale_nodes.contains(&first.state_root));
+        assert!(second
+            .stale_nodes
+            .iter()
+            .all(|hash| !second.nodes.contains_key(hash)));
+    }
+
+    #[test]
+    fn test_post_state_wiped_storage() {
+        let memdb = Arc::new(MemoryDB::new(true));
+        let empty_root: B256 = KECCAK_NULL_RLP.as_fixed_bytes().into();
+        let alice = B256::repeat_byte(1);
+
+        let mut block = HashedPostState::default();
+        block.accounts.insert(alice, account(1));
+        block
+            .storages
+            .insert(alice, storage(false, &[(1, 10), (2, 20)]));
+        let first = block.state_root(memdb.clone(), empty_root).unwrap();
+        let db = persist(&memdb, &first);
+
+        let mut wiped = HashedPostState::default();
+        wiped.storages.insert(alice, storage(true, &[(2, 20)]));
+        let second = wiped.state_root(db.clone(), first.state_root).unwrap();
+        let mut fresh = HashedPostState::default();
+        fresh.accounts.insert(alice, account(1));
+        fresh.storages.insert(alice, storage(false, &[(2, 20)]));
+        let expected = fresh.state_root(memdb.clone(), empty_root).unwrap();
+        assert_eq!(second.state_root, expected.state_root);
+
+        // Storage can't change for an account that doesn't exist
+        let mut orphan = HashedPostState::default();
+        orphan
+            .storages
+            .insert(B256::repeat_byte(9), storage(false, &[(1, 1)]));
+        assert!(orphan.state_root(db, first.state_root).is_err());
+    }
+}
@@ -0,0 +1,396 @@
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::mpsc::{self, Sender};
+use std::sync::Arc;
+use std::thread::JoinHandle;
+
+use alloy_primitives::B256;
+use hashbrown::HashMap;
+
+use crate::db::{IterableDB, DB};
+use crate::nibbles::Nibbles;
+use crate::node::Node;
+use crate::trie::{decode_node, EthTrie, HASHED_LENGTH};
+
+/// A database wrapper with a background worker that loads the nodes a trie is about to
+/// need into a cache, so that reading them later doesn't wait on the inner database.
+///
+/// The worker is given hints, keys or prefixes about to be accessed under a root, and
+/// loads the nodes on the path to each of them, along with every node below a prefix,
+/// until the cache is full. Reads are served from the cache when they can be; writes and
+/// removals go to the inner database, dropping the keys they touch from the cache.
+/// Prefetching is best effort: hints the worker can't follow, such as those under a root
+/// that isn't stored, are dropped.
+#[derive(Debug)]
+pub struct PrefetchDB<D>
+where
+    D: DB,
+{
+    shared: Arc<Shared<D>>,
+    hints: Option<Sender<Hint>>,
+    worker: Option<JoinHandle<()>>,
+}
+
+#[derive(Debug)]
+struct Shared<D> {
+    db: D,
+    cache: parking_lot::Mutex<NodeCache>,
+    // The hints sent but not yet followed.
+    pending: AtomicUsize,
+    hits: AtomicU64,
+}
+
+#[derive(Debug)]
+enum Hint {
+    Key(B256, Vec<u8>),
+    Prefix(B256, Nibbles),
+}
+
+impl<D> PrefetchDB<D>
+where
+    D: DB + 'static,
+{
+    /// Wraps `db`, with a cache holding up to `capacity` prefetched nodes, and starts the
+    /// worker. The worker stops when the wrapper is dropped.
+    pub fn new(db: D, capacity: usize) -> Self {
+        let shared = Arc::new(Shared {
+            db,
+            cache: parking_lot::Mutex::new(NodeCache::new(capacity)),
+            pending: AtomicUsize::new(0),
+            hits: AtomicU64::new(0),
+        });
+        let (hints, receiver) = mpsc::channel();
+        let worker = {
+            let shared = shared.clone();
+            std::thread::spawn(move || {
+                for hint in receiver {
+                    shared.follow(hint);
+                    shared.pending.fetch_sub(1, Ordering::Release);
+                }
+            })
+        };
+        Self {
+            shared,
+            hints: Some(hints),
+            worker: Some(worker),
+        }
+    }
+}
+
+impl<D> PrefetchDB<D>
+where
+    D: DB,
+{
+    /// Asks the worker to load the nodes on the path to `key` in the trie at `root`.
+    pub fn hint(&self, root: B256, key: &[u8]) {
+        self.send(Hint::Key(root, key.to_vec()));
+    }
+
+    /// Asks the worker to load the nodes on the path to `prefix` in the trie at `root`,
+    /// and the nodes below it, as many as fit in the cache.
+    pub fn hint_prefix(&self, root: B256, prefix: &Nibbles) {
+        self.send(Hint::Prefix(root, prefix.clone()));
+    }
+
+    /// Returns true once the worker has followed every hint sent so far.
+    pub fn is_idle(&self) -> bool {
+        self.shared.pending.load(Ordering::Acquire) == 0
+    }
+
+    /// The number of reads served from the cache.
+    pub fn hits(&self) -> u64 {
+        self.shared.hits.load(Ordering::Relaxed)
+    }
+
+    pub fn inner(&self) -> &D {
+        &self.shared.db
+    }
+
+    fn send(&self, hint: Hint) {
+        if let Some(hints) = self.hints.as_ref() {
+            self.shared.pending.fetch_add(1, Ordering::Release);
+            if hints.send(hint).is_err() {
+                self.shared.pending.fetch_sub(1, Ordering::Release);
+            }
+        }
+    }
+}
+
+impl<D> Drop for PrefetchDB<D>
+where
+    D: DB,
+{
+    fn drop(&mut self) {
+        // Closing the channel stops the worker once it is done with the hints it has
+        self.hints.take();
+        if let Some(worker) = self.worker.take() {
+            let _ = worker.join();
+        }
+    }
+}
+
+impl<D> Shared<D>
+where
+    D: DB,
+{
+    // Loads the nodes on the path of a hint, and below it for a prefix.
+    fn follow(&self, hint: Hint) {
+        let (root, path) = match hint {
+            Hint::Key(root, key) => (root, Nibbles::from_raw(&key, true)),
+            Hint::Prefix(root, prefix) => (root, prefix),
+        };
+        let Some(mut node) = self.load(root) else {
+            return;
+        };
+        let mut index = 0;
+        loop {
+            let remaining = path.offset(index);
+            node = match node {
+                Node::Hash(hash) => match self.load(hash.hash) {
+                    Some(node) => node,
+                    None => return,
+                },
+                Node::Branch(branch) => {
+                    if remaining.is_empty() {
+                        return self.load_below(Node::Branch(branch));
+                    }
+                    let nibble = remaining.at(0);
+                    if nibble == 0x10 {
+                        return;
+                    }
+                    index += 1;
+                    let child = branch.read().children[nibble].clone();
+                    child
+                }
+                Node::Extension(ext) => {
+                    let ext = ext.read();
+                    let matched = remaining.common_prefix(&ext.prefix);
+                    if matched == ext.prefix.len() {
+                        index += matched;
+                        ext.node.clone()
+                    } else if matched == remaining.len() {
+                        return self.load_below(ext.node.clone());
+                    } else {
+                        return;
+                    }
+                }
+                Node::Leaf(_) | Node::Empty => return,
+            };
+        }
+    }
+
+    // Loads the nodes below `node` until the cache is full.
+    fn load_below(&self, node: Node) {
+        let mut loaded = 0;
+        let capacity = self.cache.lock().capacity;
+        let mut stack = vec![node];
+        while let Some(node) = stack.pop() {
+            match node {
+                Node::Hash(hash) => {
+                    if loaded >= capacity {
+                        return;
+                    }
+                    loaded += 1;
+                    if let Some(node) = self.load(hash.hash) {
+                        stack.push(node);
+                    }
+                }
+                Node::Branch(branch) => {
+                    stack.extend(branch.read().children.iter().cloned());
+                }
+                Node::Extension(ext) => stack.push(ext.read().node.clone()),
+                Node::Leaf(_) | Node::Empty => {}
+            }
+        }
+    }
+
+    // Returns the decoded node stored under `hash`, adding it to the cache.
+    fn load(&self, hash: B256) -> Option<Node> {
+        let cached = self.cache.lock().get(&hash);
+        let encoded = match cached {
+            Some(encoded) => encoded,
+            None => {
+                let encoded = self.db.get(hash.as_slice()).ok()??;
+                self.cache.lock().insert(hash, encoded.clone());
+                encoded
+            }
+        };
+        decode_node(&mut encoded.as_slice()).ok()
+    }
+
+    fn invalidate(&self, key: &[u8]) {
+        if key.len() == HASHED_LENGTH {
+            self.cache.lock().remove(&B256::from_slice(key));
+        }
+    }
+}
+
+impl<D> DB for PrefetchDB<D>
+where
+    D: DB,
+{
+    type Error = D::Error;
+
+    fn get(&self, key: &[u8]) -> Result<Option<Vec<u8>>, Self::Error> {
+        if key.len() == HASHED_LENGTH {
+            if let Some(encoded) = self.shared.cache.lock().get(&B256::from_slice(key)) {
+                self.shared.hits.fetch_add(1, Ordering::Relaxed);
+                return Ok(Some(encoded));
+            }
+        }
+        self.shared.db.get(key)
+    }
+
+    fn insert(&self, key: &[u8], value: Vec<u8>) -> Result<(), Self::Error> {
+        self.shared.invalidate(key);
+        self.shared.db.insert(key, value)
+    }
+
+    fn remove(&self, key: &[u8]) -> Result<(), Self::Error> {
+        self.shared.invalidate(key);
+        self.shared.db.remove(key)
+    }
+
+    fn insert_batch(&self, keys: Vec<Vec<u8>>, values: Vec<Vec<u8>>) -> Result<(), Self::Error> {
+        for key in keys.iter() {
+            self.shared.invalidate(key);
+        }
+        self.shared.db.insert_batch(keys, values)
+    }
+
+    fn remove_batch(&self, keys: &[Vec<u8>]) -> Result<(), Self::Error> {
+        for key in keys {
+            self.shared.invalidate(key);
+        }
+        self.shared.db.remove_batch(keys)
+    }
+
+    fn flush(&self) -> Result<(), Self::Error> {
+        self.shared.db.flush()
+    }
+
+    #[cfg(test)]
+    fn len(&self) -> Result<usize, Self::Error> {
+        self.shared.db.len()
+    }
+    #[cfg(test)]
+    fn is_empty(&self) -> Result<bool, Self::Error> {
+        self.shared.db.is_empty()
+    }
+}
+
+impl<D> IterableDB for PrefetchDB<D>
+where
+    D: IterableDB,
+{
+    fn keys(&self) -> Result<Vec<Vec<u8>>, Self::Error> {
+        self.shared.db.keys()
+    }
+}
+
+impl<D> EthTrie<PrefetchDB<D>>
+where
+    D: DB,
+{
+    /// Asks the prefetch worker of the database to load the nodes on the path to `key`
+    /// under the last committed root.
+    pub fn prefetch(&self, key: &[u8]) {
+        self.db.hint(self.root_hash, key);
+    }
+}
+
+// Prefetched nodes by hash, dropping the oldest once full.
+#[derive(Debug)]
+struct NodeCache {
+    nodes: HashMap<B256, Vec<u8>>,
+    order: VecDeque<B256>,
+    capacity: usize,
+}
+
+impl NodeCache {
+    fn new(capacity: usize) -> Self {
+        Self {
+            nodes: HashMap::new(),
+            order: VecDeque::new(),
+            capacity,
+        }
+    }
+
+    fn get(&self, hash: &B256) -> Option<Vec<u8>> {
+        self.nodes.get(hash).cloned()
+    }
+
+    fn insert(&mut self, hash: B256, encoded: Vec<u8>) {
+        if self.capacity == 0 || self.nodes.insert(hash, encoded).is_some() {
+            return;
+        }
+        self.order.push_back(hash);
+        while self.order.len() > self.capacity {
+            if let Some(oldest) = self.order.pop_front() {
+                self.nodes.remove(&oldest);
+            }
+        }
+    }
+
+    fn remove(&mut self, hash: &B256) {
+        self.nodes.remove(hash);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::PrefetchDB;
+    use crate::db::MemoryDB;
+    use crate::errors::TrieError;
+    use crate::nibbles::Nibbles;
+    use crate::trie::{EthTrie, TrieRead, TrieWrite};
+
+    fn wait_idle(db: &PrefetchDB<MemoryDB>) {
+        while !db.is_idle() {
+            std::thread::yield_now();
+        }
+    }
+
+    #[test]
+    fn test_prefetch_warms_cache() {
+        let db = Arc::new(PrefetchDB::new(MemoryDB::new(true), 1000));
+        let mut trie = EthTrie::new(db.clone());
+        for i in 0..200u32 {
+            trie.insert(&i.to_be_bytes(), &i.to_le_bytes().repeat(10))
+                .unwrap();
+        }
+        let root = trie.root_hash().unwrap();
+
+        // Without hints, every node is read from the inner database
+        let trie = EthTrie::from(db.clone(), root).unwrap();
+        assert_eq!(trie.get(&7u32.to_be_bytes()).unwrap().unwrap().len(), 40);
+        assert_eq!(db.hits(), 0);
+
+        trie.prefetch(&9u32.to_be_bytes());
+        wait_idle(&db);
+        assert_eq!(trie.get(&9u32.to_be_bytes()).unwrap().unwrap().len(), 40);
+        let hits = db.hits();
+        assert!(hits > 0);
+
+        // A prefix hint loads the whole subtree below it
+        db.hint_prefix(root, &Nibbles::from_hex(&[0, 0, 0, 0, 0, 0]));
+        wait_idle(&db);
+        for i in 0..200u32 {
+            assert!(trie.get(&i.to_be_bytes()).unwrap().is_some());
+        }
+        assert!(db.hits() > hits + 100);
+
+        // Removed nodes are dropped from the cache
+        let mut writer = EthTrie::from(db.clone(), root).unwrap();
+        for i in 0..200u32 {
+            writer.remove(&i.to_be_bytes()).unwrap();
+        }
+        writer.root_hash().unwrap();
+        assert!(matches!(
+            trie.get(&9u32.to_be_bytes()),
+            Err(TrieError::MissingTrieNode { .. })
+        ));
+    }
+}
@@ -0,0 +1,161 @@
+use crate::db::DB;
+use crate::node::Node;
+use crate::trie::{EthTrie, NodeReader, TrieIterator, TrieRead, TrieResult};
+use crate::view::TrieView;
+
+/// An entry returned by `ProofIterator`, with the part of its proof not shared with the
+/// entry before it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ProvenEntry {
+    pub key: Vec<u8>,
+    pub value: Vec<u8>,
+    /// How many nodes at the start of the proof are the same as in the proof of the
+    /// previous entry.
+    pub shared: usize,
+    /// The rest of the proof.
+    pub nodes: Vec<Vec<u8>>,
+}
+
+impl ProvenEntry {
+    /// Turns the proof of the previous entry into the proof of this one, as
+    /// `TrieRead::get_proof` would return it.
+    pub fn extend_proof(&self, proof: &mut Vec<Vec<u8>>) {
+        proof.truncate(self.shared);
+        proof.extend(self.nodes.iter().cloned());
+    }
+}
+
+/// Iterates over the entries of a trie in key order along with their proofs. Created by
+/// `EthTrie::iter_with_proofs` and `TrieView::iter_with_proofs`.
+pub struct ProofIterator<'a, D>
+where
+    D: DB,
+{
+    inner: TrieIterator<'a, D>,
+    reader: NodeReader<'a, D>,
+    root: Node,
+    // The proof of the last entry returned.
+    previous: Vec<Vec<u8>>,
+}
+
+impl<'a, D> ProofIterator<'a, D>
+where
+    D: DB,
+{
+    /// Repositions the iterator so that the next item returned is the first entry
+    /// whose key is greater than or equal to `key`. Proofs still share nodes with the
+    /// entry returned before seeking.
+    pub fn seek(&mut self, key: &[u8]) -> TrieResult<()> {
+        self.inner.seek(key)
+    }
+}
+
+impl<D> Iterator for ProofIterator<'_, D>
+where
+    D: DB,
+{
+    type Item = TrieResult<ProvenEntry>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let (key, value) = match self.inner.next()? {
+            Ok(entry) => entry,
+            Err(e) => return Some(Err(e)),
+        };
+        let proof = match self.reader.get_proof(&self.root, &key) {
+            Ok(proof) => proof,
+            Err(e) => return Some(Err(e)),
+        };
+
+        let shared = proof
+            .iter()
+            .zip(self.previous.iter())
+            .take_while(|(a, b)| a == b)
+            .count();
+        let nodes = proof[shared..].to_vec();
+        self.previous = proof;
+
+        Some(Ok(ProvenEntry {
+            key,
+            value,
+            shared,
+            nodes,
+        }))
+    }
+}
+
+impl<D> EthTrie<D>
+where
+    D: DB,
+{
+    /// Returns an iterator over the entries of the trie, including uncommitted changes,
+    /// with the proof nodes each one needs beyond those of the entry before it.
+    pub fn iter_with_proofs(&self) -> ProofIterator<'_, D> {
+        ProofIterator {
+            inner: self.iter(),
+            reader: self.reader(),
+            root: self.root.clone(),
+            previous: vec![],
+        }
+    }
+}
+
+impl<D> TrieView<D>
+where
+    D: DB,
+{
+    /// Returns an iterator over the entries of the trie with the proof nodes each one
+    /// needs beyond those of the entry before it.
+    pub fn iter_with_proofs(&self) -> ProofIterator<'_, D> {
+        ProofIterator {
+            inner: self.iter(),
+            reader: self.reader(),
+            root: self.root.clone(),
+            previous: vec![],
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::trie::tests::random_trie;
+    use crate::trie::{verify_proof, TrieRead, TrieWrite};
+
+    #[test]
+    fn test_iter_with_proofs() {
+        let (mut trie, kv) = random_trie(300);
+        let root = trie.root_hash().unwrap();
+
+        let mut proof = Vec::new();
+        let mut sent = 0;
+        let mut full = 0;
+        let mut count = 0;
+        for entry in trie.iter_with_proofs() {
+            let entry = entry.unwrap();
+            entry.extend_proof(&mut proof);
+            assert_eq!(proof, trie.get_proof(&entry.key).unwrap());
+            assert_eq!(
+                verify_proof(root, &entry.key, proof.clone()).unwrap(),
+                Some(entry.value.clone())
+            );
+            assert_eq!(kv.get(&entry.key), Some(&entry.value));
+            sent += entry.nodes.len();
+            full += proof.len();
+            count += 1;
+        }
+        assert_eq!(count, kv.len());
+        assert!(sent < full);
+    }
+
+    #[test]
+    fn test_iter_with_proofs_seek() {
+        let (trie, kv) = random_trie(100);
+        let start = kv.keys().nth(40).unwrap();
+
+        let mut iter = trie.iter_with_proofs();
+        iter.seek(start).unwrap();
+        let first = iter.next().unwrap().unwrap();
+        assert_eq!(&first.key, start);
+        assert_eq!(first.shared, 0);
+        assert_eq!(first.nodes, trie.get_proof(start).unwrap());
+    }
+}
@@ -0,0 +1,229 @@
+use std::sync::Arc;
+
+use alloy_primitives::B256;
+use hashbrown::HashSet;
+use keccak_hash::{keccak, KECCAK_NULL_RLP};
+
+use crate::db::{IterableDB, DB};
+use crate::errors::TrieError;
+use crate::node::Node;
+use crate::root_manager::KEPT_KEY_PREFIX;
+use crate::trie::{decode_node, TrieResult, HASHED_LENGTH, LEAF_COUNT_KEY_PREFIX};
+
+// The default number of keys read or removed per database call.
+pub(crate) const DEFAULT_BATCH_SIZE: usize = 1024;
+
+/// What `Pruner::prune` did.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct PruneStats {
+    /// The number of nodes reachable from the retained roots.
+    pub marked: usize,
+    /// The number of unreachable nodes removed.
+    pub swept: usize,
+}
+
+/// Removes the nodes that none of a set of retained roots can reach.
+///
+/// Pruning marks every node reachable from the retained roots, reading them in batches,
+/// then removes the other trie nodes, along with the leaf counts of the roots that are
+/// not retained. Keys that are not trie nodes, meaning their value doesn't hash to them,
+/// are left alone. Tries committing to the database while it is pruned may lose nodes.
+#[derive(Debug)]
+pub struct Pruner<D>
+where
+    D: IterableDB,
+{
+    db: Arc<D>,
+    batch_size: usize,
+}
+
+impl<D> Pruner<D>
+where
+    D: IterableDB,
+{
+    pub fn new(db: Arc<D>) -> Self {
+        Self {
+            db,
+            batch_size: DEFAULT_BATCH_SIZE,
+        }
+    }
+
+    /// Sets how many keys are read or removed per database call.
+    pub fn batch_size(mut self, batch_size: usize) -> Self {
+        self.batch_size = batch_size.max(1);
+        self
+    }
+
+    /// Returns the hashes of every node reachable from `roots`. Fails with
+    /// `TrieError::MissingTrieNode` if one of them is missing.
+    pub fn mark<I>(&self, roots: I) -> TrieResult<HashSet<B256>>
+    where
+        I: IntoIterator<Item = B256>,
+    {
+        mark_reachable(&*self.db, roots, self.batch_size)
+    }
+
+    /// Removes every trie node that `roots` can't reach.
+    pub fn prune<I>(&self, roots: I) -> TrieResult<PruneStats>
+    where
+        I: IntoIterator<Item = B256>,
+    {
+        let roots: Vec<B256> = roots.into_iter().collect();
+        let marked = self.mark(roots.iter().copied())?;
+
+        let mut swept = 0;
+        let keys = self.db.keys().map_err(TrieError::db)?;
+        let candidates: Vec<Vec<u8>> = keys
+            .into_iter()
+            .filter(|key| {
+                if let Some(root) = key.strip_prefix(LEAF_COUNT_KEY_PREFIX) {
+                    !roots.iter().any(|r| r.as_slice() == root)
+                } else if let Some(hash) = key.strip_prefix(KEPT_KEY_PREFIX) {
+                    // The mark of a stale node kept for a root manager
+                    !marked.contains(hash)
+                } else {
+                    key.len() == HASHED_LENGTH && !marked.contains(key.as_slice())
+                }
+            })
+            .collect();
+
+        for chunk in candidates.chunks(self.batch_size) {
+            let values = self.db.get_batch(chunk).map_err(TrieError::db)?;
+            let mut removed = Vec::with_capacity(chunk.len());
+            for (key, value) in chunk.iter().zip(values) {
+                let Some(value) = value else { continue };
+                if key.starts_with(LEAF_COUNT_KEY_PREFIX) || key.starts_with(KEPT_KEY_PREFIX) {
+                    removed.push(key.clone());
+                } else if keccak(&value).as_bytes() == key.as_slice() {
+                    removed.push(key.clone());
+                    swept += 1;
+                }
+            }
+            self.db.remove_batch(&removed).map_err(TrieError::db)?;
+        }
+        self.db.flush().map_err(TrieError::db)?;
+
+        Ok(PruneStats {
+            marked: marked.len(),
+            swept,
+        })
+    }
+}
+
+// Collects the hashes of the nodes reachable from `roots`, reading `batch_size` nodes per
+// database call.
+pub(crate) fn mark_reachable<D, I>(db: &D, roots: I, batch_size: usize) -> TrieResult<HashSet<B256>>
+where
+    D: DB,
+    I: IntoIterator<Item = B256>,
+{
+    let empty_root: B256 = KECCAK_NULL_RLP.as_fixed_bytes().into();
+    let mut marked = HashSet::new();
+    let mut pending: Vec<(B256, B256)> = roots
+        .into_iter()
+        .filter(|root| *root != empty_root)
+        .map(|root| (root, root))
+        .collect();
+
+    while !pending.is_empty() {
+        let at = pending.len().saturating_sub(batch_size);
+        let batch: Vec<(B256, B256)> = pending
+            .split_off(at)
+            .into_iter()
+            .filter(|(hash, _)| marked.insert(*hash))
+            .collect();
+        let keys: Vec<Vec<u8>> = batch.iter().map(|(hash, _)| hash.to_vec()).collect();
+        let values = db.get_batch(&keys).map_err(TrieError::db)?;
+
+        for ((node_hash, root), value) in batch.into_iter().zip(values) {
+            let encoded = value.ok_or(TrieError::MissingTrieNode {
+                node_hash,
+                traversed: None,
+                root_hash: Some(root),
+                err_key: None,
+            })?;
+            let node = decode_node(&mut encoded.as_slice())?;
+            for (_, hash) in node.child_hashes() {
+                pending.push((hash, root));
+            }
+            if let Some(Node::Hash(hash_node)) = node.extension_child() {
+                pending.push((hash_node.hash, root));
+            }
+        }
+    }
+    Ok(marked)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use alloy_primitives::B256;
+
+    use super::{PruneStats, Pruner};
+    use crate::db::{MemoryDB, DB};
+    use crate::errors::TrieError;
+    use crate::root_manager::kept_key;
+    use crate::trie::{EthTrie, TrieRead, TrieWrite};
+
+    #[test]
+    fn test_pruner() {
+        let memdb = Arc::new(MemoryDB::new(true));
+        let mut trie = EthTrie::builder(memdb.clone())
+            .retain_stale_nodes(true)
+            .build()
+            .unwrap();
+        let mut roots = vec![];
+        for round in 0..4u8 {
+            for i in 0..100u8 {
+                trie.insert(&[i], &[round; 40]).unwrap();
+            }
+            roots.push(trie.root_hash().unwrap());
+        }
+        memdb.insert(b"application data", b"kept".to_vec()).unwrap();
+        // Marks of nodes kept by a root manager, one of them no longer reachable
+        memdb.insert(&kept_key(&roots[0]), vec![]).unwrap();
+        memdb.insert(&kept_key(&roots[3]), vec![]).unwrap();
+        let before = memdb.len().unwrap();
+
+        let pruner = Pruner::new(memdb.clone()).batch_size(7);
+        let retained = [roots[1], roots[3]];
+        let stats = pruner.prune(retained).unwrap();
+        assert_eq!(stats.marked, pruner.mark(retained).unwrap().len());
+        assert!(stats.swept > 0);
+        // Two leaf counts and a mark went along with the nodes
+        assert_eq!(memdb.len().unwrap(), before - stats.swept - 3);
+        assert!(memdb.get(&kept_key(&roots[0])).unwrap().is_none());
+        assert!(memdb.get(&kept_key(&roots[3])).unwrap().is_some());
+
+        for (i, root) in roots.iter().enumerate() {
+            let trie = EthTrie::from(memdb.clone(), *root);
+            if i % 2 == 1 {
+                let trie = trie.unwrap();
+                assert_eq!(trie.iter().count(), 100);
+                assert_eq!(trie.len().unwrap(), 100);
+            } else {
+                assert!(trie.is_err());
+            }
+        }
+        assert!(memdb.get(b"application data").unwrap().is_some());
+
+        // Nothing more to remove
+        let again = pruner.prune(retained).unwrap();
+        assert_eq!(
+            again,
+            PruneStats {
+                marked: stats.marked,
+                swept: 0
+            }
+        );
+    }
+
+    #[test]
+    fn test_pruner_missing_root() {
+        let memdb = Arc::new(MemoryDB::new(true));
+        let pruner = Pruner::new(memdb);
+        let result = pruner.prune([B256::repeat_byte(1)]);
+        assert!(matches!(result, Err(TrieError::MissingTrieNode { .. })));
+    }
+}
@@ -0,0 +1,163 @@
+use std::sync::Arc;
+
+use alloy_primitives::B256;
+
+use crate::db::DB;
+use crate::errors::TrieError;
+use crate::trie::{EthTrie, Published, TrieRead, TrieResult};
+use crate::view::TrieView;
+
+/// A handle for reading the last committed root of an `EthTrie` from other threads while
+/// the trie itself keeps changing and committing.
+///
+/// Readers only ever see committed roots: changes become visible once the trie commits
+/// them, all at once, and are never seen before. Each read is made at the root committed
+/// last when it starts, so two reads may see different roots; `snapshot` returns a view
+/// to make several reads at the same root. If a commit removes the nodes a read needs
+/// before it is done, the read is made again at the new root.
+///
+/// Handles are cheap to clone, and reads never wait on the writer.
+#[derive(Debug)]
+pub struct TrieReader<D>
+where
+    D: DB,
+{
+    published: Published<D>,
+}
+
+impl<D> Clone for TrieReader<D>
+where
+    D: DB,
+{
+    fn clone(&self) -> Self {
+        Self {
+            published: self.published.clone(),
+        }
+    }
+}
+
+impl<D> TrieReader<D>
+where
+    D: DB,
+{
+    /// Returns the last committed root.
+    pub fn root_hash(&self) -> B256 {
+        self.snapshot().root_hash()
+    }
+
+    /// Returns a view of the last committed root, which stays at that root. Its reads can
+    /// fail with `TrieError::MissingTrieNode` once a later commit removes stale nodes,
+    /// unless they are retained.
+    pub fn snapshot(&self) -> Arc<TrieView<D>> {
+        self.published.read().clone()
+    }
+
+    pub fn get(&self, key: &[u8]) -> TrieResult<Option<Vec<u8>>> {
+        self.read(|view| view.get(key))
+    }
+
+    pub fn contains(&self, key: &[u8]) -> TrieResult<bool> {
+        self.read(|view| view.contains(key))
+    }
+
+    /// Returns the proof for `key` at the root returned along with it.
+    pub fn get_proof(&self, key: &[u8]) -> TrieResult<(B256, Vec<Vec<u8>>)> {
+        self.read(|view| Ok((view.root_hash(), view.get_proof(key)?)))
+    }
+
+    // Makes a read at the last committed root, again for as long as it fails on a node
+    // that a commit made since has removed.
+    fn read<T, F>(&self, read: F) -> TrieResult<T>
+    where
+        F: Fn(&TrieView<D>) -> TrieResult<T>,
+    {
+        loop {
+            let view = self.snapshot();
+            match read(&view) {
+                Err(TrieError::MissingTrieNode { .. }) if self.root_hash() != view.root_hash() => {
+                    continue
+                }
+                result => return result,
+            }
+        }
+    }
+}
+
+impl<D> EthTrie<D>
+where
+    D: DB,
+{
+    /// Returns a handle for reading the last committed root of this trie from other
+    /// threads. The handle follows the commits made here afterwards.
+    pub fn trie_reader(&self) -> TrieReader<D> {
+        TrieReader {
+            published: self.published.clone(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::Arc;
+
+    use crate::db::MemoryDB;
+    use crate::trie::{verify_proof, EthTrie, TrieRead, TrieResult, TrieWrite};
+
+    #[test]
+    fn test_trie_reader_sees_committed_roots() {
+        let mut trie = EthTrie::new(Arc::new(MemoryDB::new(true)));
+        let reader = trie.trie_reader();
+        trie.insert(&[0], &[0; 40]).unwrap();
+        assert_eq!(reader.get(&[0]).unwrap(), None);
+        trie.root_hash().unwrap();
+        assert_eq!(reader.get(&[0]).unwrap(), Some(vec![0; 40]));
+
+        // The writer commits round after round, removing stale nodes, while readers check
+        // that each snapshot holds every key at the same round.
+        let done = AtomicBool::new(false);
+        std::thread::scope(|scope| {
+            let readers: Vec<_> = (0..4)
+                .map(|_| {
+                    let reader = reader.clone();
+                    let done = &done;
+                    scope.spawn(move || {
+                        let mut reads = 0;
+                        while !done.load(Ordering::Relaxed) || reads == 0 {
+                            let value = reader.get(&[1]).unwrap();
+                            assert!(value.map_or(true, |v| v == [v[0]; 40]));
+                            let (root, proof) = reader.get_proof(&[2]).unwrap();
+                            verify_proof(root, &[2], proof).unwrap();
+
+                            // A later commit may have removed the nodes of the snapshot
+                            let snapshot = reader.snapshot();
+                            let values: TrieResult<Vec<_>> =
+                                (0..20u8).map(|i| snapshot.get(&[i])).collect();
+                            if let Ok(values) = values {
+                                let last = &values[19];
+                                assert!(values[1..].iter().all(|v| v == last));
+                                if last.is_some() {
+                                    assert_eq!(&values[0], last);
+                                }
+                            }
+                            reads += 1;
+                        }
+                    })
+                })
+                .collect();
+
+            for round in 1..50u8 {
+                for i in 0..20u8 {
+                    trie.insert(&[i], &[round; 40]).unwrap();
+                }
+                trie.root_hash().unwrap();
+            }
+            done.store(true, Ordering::Relaxed);
+            for reader in readers {
+                reader.join().unwrap();
+            }
+        });
+        assert_eq!(reader.root_hash(), trie.root_hash);
+        assert_eq!(reader.get(&[5]).unwrap(), Some(vec![49; 40]));
+    }
+}
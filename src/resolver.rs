@@ -0,0 +1,137 @@
+use std::fmt;
+use std::sync::Arc;
+
+use alloy_primitives::B256;
+
+use crate::db::DB;
+use crate::errors::DBError;
+use crate::trie::EthTrie;
+
+/// Fetches nodes missing from the database of a trie, for example from the network.
+/// Attached with `EthTrieBuilder::resolver` or `EthTrie::set_resolver`.
+///
+/// A trie asks its resolver for every node its database doesn't have, checks the node
+/// against its hash, writes it to the database and carries on, so reads and writes see
+/// a complete trie. Closures taking the hash implement the trait.
+pub trait NodeResolver: Send + Sync {
+    /// Returns the encoded node with the given hash, or `None` if it can't be found.
+    fn resolve(&self, hash: B256) -> Result<Option<Vec<u8>>, DBError>;
+}
+
+impl<F> NodeResolver for F
+where
+    F: Fn(B256) -> Result<Option<Vec<u8>>, DBError> + Send + Sync,
+{
+    fn resolve(&self, hash: B256) -> Result<Option<Vec<u8>>, DBError> {
+        self(hash)
+    }
+}
+
+// The resolver attached to a trie.
+#[derive(Clone, Default)]
+pub(crate) struct Resolver(pub(crate) Option<Arc<dyn NodeResolver>>);
+
+impl fmt::Debug for Resolver {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.0 {
+            Some(_) => write!(f, "Resolver(Some(..))"),
+            None => write!(f, "Resolver(None)"),
+        }
+    }
+}
+
+impl<D> EthTrie<D>
+where
+    D: DB,
+{
+    /// Sets the resolver asked for the nodes missing from the database, replacing any
+    /// previous one.
+    pub fn set_resolver(&mut self, resolver: Arc<dyn NodeResolver>) {
+        self.config.resolver.0 = Some(resolver);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    use alloy_primitives::B256;
+
+    use crate::db::{IterableDB, MemoryDB, DB};
+    use crate::errors::{DBError, TrieError};
+    use crate::trie::{EthTrie, TrieRead, TrieWrite};
+
+    #[test]
+    fn test_resolver_reads_through() {
+        let remote = Arc::new(MemoryDB::new(true));
+        let mut trie = EthTrie::new(remote.clone());
+        for i in 0..100u8 {
+            trie.insert(&[i, i], &[i; 40]).unwrap();
+        }
+        let root = trie.root_hash().unwrap();
+        let count = trie.len().unwrap();
+
+        let fetched = Arc::new(AtomicUsize::new(0));
+        let resolver = {
+            let remote = remote.clone();
+            let fetched = fetched.clone();
+            move |hash: B256| -> Result<Option<Vec<u8>>, DBError> {
+                fetched.fetch_add(1, Ordering::Relaxed);
+                remote.get(hash.as_slice()).map_err(DBError::new)
+            }
+        };
+
+        // Only the root is local.
+        let local = Arc::new(MemoryDB::new(true));
+        local
+            .insert(
+                root.as_slice(),
+                remote.get(root.as_slice()).unwrap().unwrap(),
+            )
+            .unwrap();
+        let mut trie = EthTrie::builder(local.clone())
+            .root(root)
+            .resolver(Arc::new(resolver))
+            .build()
+            .unwrap();
+        assert_eq!(trie.get(&[5, 5]).unwrap(), Some(vec![5; 40]));
+        let after_get = fetched.load(Ordering::Relaxed);
+        assert!(after_get > 0);
+
+        // Resolved nodes are persisted, so they are fetched once.
+        assert_eq!(trie.get(&[5, 5]).unwrap(), Some(vec![5; 40]));
+        assert_eq!(fetched.load(Ordering::Relaxed), after_get);
+
+        trie.remove(&[7, 7]).unwrap();
+        assert_eq!(trie.iter().count(), count - 1);
+        let new_root = trie.root_hash().unwrap();
+
+        let mut expected = EthTrie::from(remote, root).unwrap();
+        expected.remove(&[7, 7]).unwrap();
+        assert_eq!(new_root, expected.root_hash().unwrap());
+        assert!(local.keys().unwrap().len() > 100);
+    }
+
+    #[test]
+    fn test_resolver_rejects_wrong_node() {
+        let db = Arc::new(MemoryDB::new(true));
+        let mut trie = EthTrie::new(db.clone());
+        for i in 0..20u8 {
+            trie.insert(&[i], &[i; 40]).unwrap();
+        }
+        let root = trie.root_hash().unwrap();
+        for key in db.keys().unwrap() {
+            if key != root.as_slice() {
+                db.remove(&key).unwrap();
+            }
+        }
+
+        let mut trie = EthTrie::from(db, root).unwrap();
+        trie.set_resolver(Arc::new(|_| Ok(Some(vec![0xc0]))));
+        assert_eq!(trie.get(&[3]), Err(TrieError::InvalidData));
+
+        trie.set_resolver(Arc::new(|_| Ok(None)));
+        assert!(trie.get(&[3]).unwrap_err().heal_request().is_some());
+    }
+}
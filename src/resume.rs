@@ -0,0 +1,119 @@
+use alloy_primitives::{Bytes, B256};
+
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+use crate::errors::TrieError;
+use crate::trie::{TrieResult, HASHED_LENGTH};
+
+/// The position of a `TrieIterator`, taken with `TrieIterator::cursor` and restored with
+/// `TrieIterator::resume`, possibly on another handle to the same trie in another process.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct IterCursor {
+    /// The committed root of the trie being iterated.
+    pub root: B256,
+    /// The key the iterator continues from, or `None` once it is exhausted. The next entry
+    /// returned is the first one whose key is greater than or equal to it.
+    pub next: Option<Bytes>,
+}
+
+impl IterCursor {
+    /// Encodes the cursor as the root, a byte telling whether the iterator is exhausted,
+    /// and the key to continue from.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut out =
+            Vec::with_capacity(HASHED_LENGTH + 1 + self.next.as_ref().map_or(0, |k| k.len()));
+        out.extend_from_slice(self.root.as_slice());
+        match &self.next {
+            Some(key) => {
+                out.push(1);
+                out.extend_from_slice(key);
+            }
+            None => out.push(0),
+        }
+        out
+    }
+
+    /// Decodes a cursor written by `to_bytes`.
+    pub fn from_bytes(data: &[u8]) -> TrieResult<Self> {
+        if data.len() <= HASHED_LENGTH {
+            return Err(TrieError::InvalidData);
+        }
+        let root = B256::from_slice(&data[..HASHED_LENGTH]);
+        let next = match (data[HASHED_LENGTH], &data[HASHED_LENGTH + 1..]) {
+            (0, []) => None,
+            (1, key) => Some(Bytes::copy_from_slice(key)),
+            _ => return Err(TrieError::InvalidData),
+        };
+        Ok(Self { root, next })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::IterCursor;
+    use crate::db::MemoryDB;
+    use crate::errors::TrieError;
+    use crate::trie::tests::random_trie;
+    use crate::trie::{EthTrie, TrieRead, TrieWrite};
+
+    #[test]
+    fn test_iter_cursor_chunks() {
+        let (mut trie, kv) = random_trie(500);
+        let db = trie.db.clone();
+        let root = trie.root_hash().unwrap();
+
+        let mut cursor = trie.iter().cursor().to_bytes();
+        let mut collected = Vec::new();
+        loop {
+            // Each chunk runs on a fresh handle, starting from the encoded cursor
+            let trie = EthTrie::from(db.clone(), root).unwrap();
+            let mut iter = trie.iter();
+            iter.resume(&IterCursor::from_bytes(&cursor).unwrap())
+                .unwrap();
+            collected.extend(iter.by_ref().take(37).map(|item| item.unwrap()));
+            let next = iter.cursor();
+            if next.next.is_none() {
+                break;
+            }
+            cursor = next.to_bytes();
+        }
+        let expected: Vec<_> = kv.into_iter().collect();
+        assert_eq!(collected, expected);
+    }
+
+    #[test]
+    fn test_iter_cursor_checks_root() {
+        let (trie, _) = random_trie(20);
+        let cursor = trie.iter().cursor();
+
+        let mut other = EthTrie::new(Arc::new(MemoryDB::new(true)));
+        other.insert(b"key", b"value").unwrap();
+        other.root_hash().unwrap();
+        assert!(matches!(
+            other.iter().resume(&cursor),
+            Err(TrieError::InvalidStateRoot)
+        ));
+    }
+
+    #[test]
+    fn test_iter_cursor_bytes() {
+        let (trie, _) = random_trie(20);
+        let mut iter = trie.iter();
+        iter.seek(b"").unwrap();
+        for cursor in [iter.cursor(), {
+            iter.by_ref().for_each(drop);
+            iter.cursor()
+        }] {
+            assert_eq!(IterCursor::from_bytes(&cursor.to_bytes()).unwrap(), cursor);
+        }
+        assert_eq!(iter.cursor().next, None);
+
+        assert!(IterCursor::from_bytes(&[0; 32]).is_err());
+        assert!(IterCursor::from_bytes(&[0; 34]).is_err());
+        assert!(IterCursor::from_bytes(&[[0; 32].as_slice(), &[2]].concat()).is_err());
+    }
+}
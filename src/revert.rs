@@ -0,0 +1,95 @@
+use hashbrown::HashSet;
+
+use crate::db::DB;
+use crate::diff::node_diff;
+use crate::errors::TrieError;
+use crate::trie::{leaf_count_key, EthTrie, RootWithTrieDiff, TrieResult};
+
+impl<D> EthTrie<D>
+where
+    D: DB,
+{
+    /// Undoes the commit that returned `diff`, which must be the last commit of this
+    /// trie, and reopens the trie at the root before it. Uncommitted changes are dropped.
+    ///
+    /// The nodes the commit wrote are removed from the database, except those the
+    /// previous trie also holds along the paths where the two differ. The previous
+    /// trie must still be complete, so the commit must have retained the nodes it made
+    /// stale. Fails with `TrieError::InvalidStateRoot` if the trie is at another root.
+    pub fn revert_diff(&mut self, diff: &RootWithTrieDiff) -> TrieResult<()> {
+        if self.root_hash != diff.root {
+            return Err(TrieError::InvalidStateRoot);
+        }
+        if diff.root == diff.previous_root {
+            return Ok(());
+        }
+
+        // Check that the previous trie can be opened before removing anything
+        let mut previous = EthTrie::from(self.db.clone(), diff.previous_root)?;
+
+        let mut kept = HashSet::new();
+        for change in node_diff(&self.db, diff.root, diff.previous_root) {
+            let (_, hash, _) = change?;
+            kept.insert(hash);
+        }
+        let mut removed = vec![];
+        for change in node_diff(&self.db, diff.previous_root, diff.root) {
+            let (_, hash, _) = change?;
+            if diff.trie_diff.contains_key(&hash) && !kept.contains(&hash) {
+                removed.push(hash.to_vec());
+            }
+        }
+        removed.push(leaf_count_key(&diff.root));
+        self.db.remove_batch(&removed).map_err(TrieError::db)?;
+        self.db.flush().map_err(TrieError::db)?;
+
+        previous.config = self.config.clone();
+        // Readers of this trie move to the previous root with it
+        previous.published = self.published.clone();
+        *self = previous;
+        self.publish();
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use crate::db::{MemoryDB, DB};
+    use crate::errors::TrieError;
+    use crate::trie::{EthTrie, TrieRead, TrieWrite};
+
+    #[test]
+    fn test_revert_diff() {
+        let memdb = Arc::new(MemoryDB::new(true));
+        let mut trie = EthTrie::builder(memdb.clone())
+            .retain_stale_nodes(true)
+            .build()
+            .unwrap();
+        for i in 0..100u8 {
+            trie.insert(&[i], &[i; 40]).unwrap();
+        }
+        let root = trie.root_hash().unwrap();
+        let size = memdb.len().unwrap();
+
+        for i in 0..10u8 {
+            trie.insert(&[i], &[0xff; 40]).unwrap();
+        }
+        trie.insert(b"new key", b"new value").unwrap();
+        let diff = trie.root_hash_with_changed_nodes().unwrap();
+        assert_eq!(diff.previous_root, root);
+
+        let reader = trie.trie_reader();
+        trie.revert_diff(&diff).unwrap();
+        assert_eq!(trie.root_hash, root);
+        assert_eq!(reader.root_hash(), root);
+        assert_eq!(trie.get(&[3]).unwrap(), Some(vec![3; 40]));
+        assert_eq!(trie.get(b"new key").unwrap(), None);
+        assert_eq!(trie.len().unwrap(), 100);
+        assert_eq!(memdb.len().unwrap(), size);
+
+        // The trie only reverts the commit it is at
+        assert_eq!(trie.revert_diff(&diff), Err(TrieError::InvalidStateRoot));
+    }
+}
@@ -0,0 +1,576 @@
+use std::collections::BTreeMap;
+use std::sync::Arc;
+
+use alloy_primitives::B256;
+use hashbrown::HashSet;
+use parking_lot::RwLock;
+
+use crate::db::DB;
+use crate::errors::TrieError;
+use crate::pruner::{mark_reachable, DEFAULT_BATCH_SIZE};
+use crate::trie::{TrieResult, HASHED_LENGTH};
+
+// The number of roots recorded.
+const ROOT_COUNT_KEY: &[u8] = b"eth-trie:root-count";
+// Followed by the position of a root in the order they were recorded, the root.
+const ROOT_KEY_PREFIX: &[u8] = b"eth-trie:root:";
+// Followed by a root, its position.
+const ROOT_POSITION_KEY_PREFIX: &[u8] = b"eth-trie:root-position:";
+// The pinned roots, as a sequence of records made of the root and a big endian `u32` pin
+// count.
+const PINS_KEY: &[u8] = b"eth-trie:pins";
+const PIN_RECORD_LENGTH: usize = HASHED_LENGTH + 4;
+// Followed by the hash of a stale node kept because it was protected, an empty value.
+pub(crate) const KEPT_KEY_PREFIX: &[u8] = b"eth-trie:kept:";
+
+fn root_key(position: u64) -> Vec<u8> {
+    [ROOT_KEY_PREFIX, &position.to_be_bytes()].concat()
+}
+
+fn root_position_key(root: &B256) -> Vec<u8> {
+    [ROOT_POSITION_KEY_PREFIX, root.as_slice()].concat()
+}
+
+pub(crate) fn kept_key(hash: &B256) -> Vec<u8> {
+    [KEPT_KEY_PREFIX, hash.as_slice()].concat()
+}
+
+fn encode_pins(pins: &BTreeMap<B256, u32>) -> Vec<u8> {
+    let mut out = Vec::with_capacity(pins.len() * PIN_RECORD_LENGTH);
+    for (root, pins) in pins.iter() {
+        out.extend_from_slice(root.as_slice());
+        out.extend_from_slice(&pins.to_be_bytes());
+    }
+    out
+}
+
+// A root that isn't recorded yet, returned by `RootRegistry::record`.
+#[derive(Debug)]
+pub(crate) struct PendingRoot {
+    root: B256,
+    position: u64,
+}
+
+impl PendingRoot {
+    // The entries recording the root.
+    pub(crate) fn entries(&self) -> Vec<(Vec<u8>, Vec<u8>)> {
+        vec![
+            (root_key(self.position), self.root.to_vec()),
+            (
+                root_position_key(&self.root),
+                self.position.to_be_bytes().to_vec(),
+            ),
+            (
+                ROOT_COUNT_KEY.to_vec(),
+                (self.position + 1).to_be_bytes().to_vec(),
+            ),
+        ]
+    }
+}
+
+// The state shared between a `RootManager` and the tries it is attached to.
+#[derive(Debug, Default)]
+pub(crate) struct RootRegistry {
+    // The number of roots recorded, and the last of them.
+    root_count: u64,
+    last_root: Option<B256>,
+    pins: BTreeMap<B256, u32>,
+    // Pins held by open `VersionedView`s, which are not persisted.
+    leases: BTreeMap<B256, u32>,
+    // The nodes reachable from a pinned or leased root.
+    protected: HashSet<B256>,
+    // Stale nodes that were kept because they were protected, to remove once they no
+    // longer are.
+    kept: HashSet<B256>,
+    // The nodes added to and dropped from `kept` since it was last stored.
+    kept_added: HashSet<B256>,
+    kept_dropped: HashSet<B256>,
+}
+
+impl RootRegistry {
+    fn holds(&self, root: &B256) -> bool {
+        self.pins.contains_key(root) || self.leases.contains_key(root)
+    }
+
+    // Returns the stale nodes that aren't protected, keeping the others for later.
+    pub(crate) fn filter_protected(&mut self, stale: Vec<B256>) -> Vec<B256> {
+        let (kept, removed): (Vec<B256>, Vec<B256>) =
+            stale.into_iter().partition(|h| self.protected.contains(h));
+        for hash in kept {
+            self.kept_dropped.remove(&hash);
+            self.kept_added.insert(hash);
+            self.kept.insert(hash);
+        }
+        removed
+    }
+
+    // Forgets the kept nodes that were written again, which are live once more.
+    pub(crate) fn revive(&mut self, written: &HashSet<B256>) {
+        let revived: Vec<B256> = self
+            .kept
+            .iter()
+            .filter(|h| written.contains(*h))
+            .copied()
+            .collect();
+        self.drop_kept(revived);
+    }
+
+    fn drop_kept(&mut self, hashes: impl IntoIterator<Item = B256>) {
+        for hash in hashes {
+            self.kept.remove(&hash);
+            self.kept_added.remove(&hash);
+            self.kept_dropped.insert(hash);
+        }
+    }
+
+    // The pinned and leased roots.
+    fn held(&self) -> Vec<B256> {
+        let mut held: Vec<B256> = self.pins.keys().copied().collect();
+        held.extend(self.leases.keys().filter(|r| !self.pins.contains_key(*r)));
+        held
+    }
+
+    pub(crate) fn is_protected(&self, hash: &B256) -> bool {
+        self.protected.contains(hash)
+    }
+
+    // Returns the root to record if `root` is new. The registry only counts it once
+    // `recorded` is called, after the entries of the root are written, so that a failed
+    // write leaves it as it was.
+    pub(crate) fn record<D: DB>(&self, db: &D, root: B256) -> TrieResult<Option<PendingRoot>> {
+        if self.last_root == Some(root) {
+            return Ok(None);
+        }
+        if db
+            .get(&root_position_key(&root))
+            .map_err(TrieError::db)?
+            .is_some()
+        {
+            return Ok(None);
+        }
+        Ok(Some(PendingRoot {
+            root,
+            position: self.root_count,
+        }))
+    }
+
+    // Counts a root returned by `record` whose entries were written.
+    pub(crate) fn recorded(&mut self, pending: PendingRoot) {
+        self.root_count = pending.position + 1;
+        self.last_root = Some(pending.root);
+    }
+
+    // Writes the changes to the kept nodes since they were last stored.
+    pub(crate) fn store_kept<D: DB>(&mut self, db: &D) -> TrieResult<()> {
+        if !self.kept_added.is_empty() {
+            let keys = self.kept_added.iter().map(kept_key).collect();
+            let values = vec![vec![]; self.kept_added.len()];
+            db.insert_batch(keys, values).map_err(TrieError::db)?;
+            self.kept_added.clear();
+        }
+        if !self.kept_dropped.is_empty() {
+            let keys: Vec<Vec<u8>> = self.kept_dropped.iter().map(kept_key).collect();
+            db.remove_batch(&keys).map_err(TrieError::db)?;
+            self.kept_dropped.clear();
+        }
+        Ok(())
+    }
+
+    // Finds the kept nodes among the protected ones. Nodes kept for a pinned root are
+    // protected as long as it is pinned; those kept for leases, which aren't persisted,
+    // are left to the `Pruner`.
+    fn load_kept<D: DB>(&mut self, db: &D) -> TrieResult<()> {
+        let protected: Vec<B256> = self.protected.iter().copied().collect();
+        for chunk in protected.chunks(DEFAULT_BATCH_SIZE) {
+            let keys: Vec<Vec<u8>> = chunk.iter().map(kept_key).collect();
+            let values = db.get_batch(&keys).map_err(TrieError::db)?;
+            for (hash, value) in chunk.iter().zip(values) {
+                if value.is_some() {
+                    self.kept.insert(*hash);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn load<D: DB>(db: &D) -> TrieResult<Self> {
+        let mut registry = RootRegistry::default();
+        if let Some(encoded) = db.get(ROOT_COUNT_KEY).map_err(TrieError::db)? {
+            let bytes: [u8; 8] = encoded.try_into().map_err(|_| TrieError::InvalidData)?;
+            registry.root_count = u64::from_be_bytes(bytes);
+        }
+        if let Some(position) = registry.root_count.checked_sub(1) {
+            let encoded = db.get(&root_key(position)).map_err(TrieError::db)?;
+            registry.last_root = Some(decode_root(encoded)?);
+        }
+
+        let pins = db.get(PINS_KEY).map_err(TrieError::db)?.unwrap_or_default();
+        if pins.len() % PIN_RECORD_LENGTH != 0 {
+            return Err(TrieError::InvalidData);
+        }
+        for record in pins.chunks(PIN_RECORD_LENGTH) {
+            let root = B256::from_slice(&record[..HASHED_LENGTH]);
+            let pins = u32::from_be_bytes(record[HASHED_LENGTH..].try_into().unwrap());
+            registry.pins.insert(root, pins);
+        }
+        Ok(registry)
+    }
+}
+
+fn decode_root(encoded: Option<Vec<u8>>) -> TrieResult<B256> {
+    match encoded {
+        Some(encoded) if encoded.len() == HASHED_LENGTH => Ok(B256::from_slice(&encoded)),
+        _ => Err(TrieError::InvalidData),
+    }
+}
+
+/// Keeps track of the roots committed to a database and protects pinned roots from
+/// stale node removal.
+///
+/// Tries attached with `EthTrieBuilder::root_manager` record each root they commit, and
+/// keep any node reachable from a pinned root when a commit makes it stale, so that
+/// pinned roots stay readable. The roots, the pins and the kept nodes are persisted in
+/// the database, each root and each kept node under a key of its own. Handles are cheap
+/// to clone and share their state.
+#[derive(Debug)]
+pub struct RootManager<D>
+where
+    D: DB,
+{
+    db: Arc<D>,
+    pub(crate) registry: Arc<RwLock<RootRegistry>>,
+}
+
+impl<D> Clone for RootManager<D>
+where
+    D: DB,
+{
+    fn clone(&self) -> Self {
+        Self {
+            db: self.db.clone(),
+            registry: self.registry.clone(),
+        }
+    }
+}
+
+impl<D> RootManager<D>
+where
+    D: DB,
+{
+    /// Loads the roots and pins stored in `db`, and collects the nodes of the pinned
+    /// roots.
+    pub fn open(db: Arc<D>) -> TrieResult<Self> {
+        let mut registry = RootRegistry::load(&*db)?;
+        let pinned: Vec<B256> = registry.pins.keys().copied().collect();
+        registry.protected = mark_reachable(&*db, pinned, DEFAULT_BATCH_SIZE)?;
+        registry.load_kept(&*db)?;
+        Ok(Self {
+            db,
+            registry: Arc::new(RwLock::new(registry)),
+        })
+    }
+
+    /// Returns the committed roots, oldest first, reading them from the database.
+    pub fn roots(&self) -> TrieResult<Vec<B256>> {
+        let count = self.registry.read().root_count;
+        let keys: Vec<Vec<u8>> = (0..count).map(root_key).collect();
+        let mut roots = Vec::with_capacity(keys.len());
+        for chunk in keys.chunks(DEFAULT_BATCH_SIZE) {
+            for encoded in self.db.get_batch(chunk).map_err(TrieError::db)? {
+                roots.push(decode_root(encoded)?);
+            }
+        }
+        Ok(roots)
+    }
+
+    /// Returns the root recorded last.
+    pub fn last_root(&self) -> Option<B256> {
+        self.registry.read().last_root
+    }
+
+    /// Records a root committed without an attached trie.
+    pub fn record(&self, root: B256) -> TrieResult<()> {
+        let mut registry = self.registry.write();
+        if let Some(pending) = registry.record(&*self.db, root)? {
+            self.store(pending.entries())?;
+            registry.recorded(pending);
+        }
+        Ok(())
+    }
+
+    /// Pins `root`, which keeps its nodes from being removed as stale. Pins are counted,
+    /// so a root pinned twice stays pinned until it is unpinned twice. Fails with
+    /// `TrieError::MissingTrieNode` if the trie at `root` is incomplete.
+    pub fn pin(&self, root: B256) -> TrieResult<()> {
+        let mut registry = self.registry.write();
+        let reachable = match registry.holds(&root) {
+            true => HashSet::new(),
+            false => mark_reachable(&*self.db, [root], DEFAULT_BATCH_SIZE)?,
+        };
+        let mut pins = registry.pins.clone();
+        *pins.entry(root).or_insert(0) += 1;
+        let pending = registry.record(&*self.db, root)?;
+        let mut entries: Vec<_> = pending.iter().flat_map(PendingRoot::entries).collect();
+        entries.push((PINS_KEY.to_vec(), encode_pins(&pins)));
+        self.store(entries)?;
+
+        registry.protected.extend(reachable);
+        registry.pins = pins;
+        if let Some(pending) = pending {
+            registry.recorded(pending);
+        }
+        Ok(())
+    }
+
+    /// Drops a pin of `root`, returning false if it wasn't pinned. Once its last pin is
+    /// dropped, its nodes are no longer protected, except those another pinned root
+    /// reaches, and the ones commits kept for it are removed.
+    pub fn unpin(&self, root: B256) -> TrieResult<bool> {
+        let mut registry = self.registry.write();
+        match registry.pins.get_mut(&root) {
+            None => return Ok(false),
+            Some(pins) if *pins > 1 => *pins -= 1,
+            Some(_) => {
+                registry.pins.remove(&root);
+                if !registry.leases.contains_key(&root) {
+                    let held = registry.held();
+                    registry.protected = mark_reachable(&*self.db, held, DEFAULT_BATCH_SIZE)?;
+                    self.remove_unprotected(&mut registry)?;
+                }
+            }
+        }
+        registry.store_kept(&*self.db)?;
+        self.store(vec![(PINS_KEY.to_vec(), encode_pins(&registry.pins))])?;
+        Ok(true)
+    }
+
+    // Pins `root` in memory only, for as long as a view is open at it.
+    pub(crate) fn lease(&self, root: B256) -> TrieResult<()> {
+        let mut registry = self.registry.write();
+        if !registry.holds(&root) {
+            let reachable = mark_reachable(&*self.db, [root], DEFAULT_BATCH_SIZE)?;
+            registry.protected.extend(reachable);
+        }
+        *registry.leases.entry(root).or_insert(0) += 1;
+        Ok(())
+    }
+
+    // Drops a lease taken by `lease`. If the nodes left protected can't be worked out,
+    // the previous ones stay protected.
+    pub(crate) fn release(&self, root: B256) {
+        let mut registry = self.registry.write();
+        match registry.leases.get_mut(&root) {
+            None => return,
+            Some(leases) if *leases > 1 => *leases -= 1,
+            Some(_) => {
+                registry.leases.remove(&root);
+            }
+        }
+        if !registry.holds(&root) {
+            let held = registry.held();
+            if let Ok(protected) = mark_reachable(&*self.db, held, DEFAULT_BATCH_SIZE) {
+                registry.protected = protected;
+                // Nodes that fail to be removed are left to the `Pruner`
+                if self.remove_unprotected(&mut registry).is_ok()
+                    && registry.store_kept(&*self.db).is_ok()
+                {
+                    let _ = self.db.flush();
+                }
+            }
+        }
+    }
+
+    // Removes the kept stale nodes that are no longer protected.
+    fn remove_unprotected(&self, registry: &mut RootRegistry) -> TrieResult<()> {
+        let freed: Vec<B256> = registry
+            .kept
+            .iter()
+            .filter(|h| !registry.protected.contains(*h))
+            .copied()
+            .collect();
+        let keys: Vec<Vec<u8>> = freed.iter().map(|h| h.to_vec()).collect();
+        self.db.remove_batch(&keys).map_err(TrieError::db)?;
+        registry.drop_kept(freed);
+        Ok(())
+    }
+
+    pub fn is_pinned(&self, root: &B256) -> bool {
+        self.registry.read().pins.contains_key(root)
+    }
+
+    /// Returns the pinned roots.
+    pub fn pinned(&self) -> Vec<B256> {
+        self.registry.read().pins.keys().copied().collect()
+    }
+
+    /// Returns true if the node with hash `hash` is reachable from a pinned root.
+    pub fn is_protected(&self, hash: &B256) -> bool {
+        self.registry.read().is_protected(hash)
+    }
+
+    fn store(&self, entries: Vec<(Vec<u8>, Vec<u8>)>) -> TrieResult<()> {
+        if entries.is_empty() {
+            return Ok(());
+        }
+        let (keys, values) = entries.into_iter().unzip();
+        self.db.insert_batch(keys, values).map_err(TrieError::db)?;
+        self.db.flush().map_err(TrieError::db)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use alloy_primitives::B256;
+
+    use super::{kept_key, RootManager, PINS_KEY};
+    use crate::db::{MemoryDB, DB};
+    #[cfg(feature = "test-utils")]
+    use crate::faulty::{Faults, FaultyDB};
+    use crate::trie::{EthTrie, TrieRead, TrieWrite};
+
+    #[test]
+    fn test_root_manager_pins() {
+        let memdb = Arc::new(MemoryDB::new(true));
+        let manager = RootManager::open(memdb.clone()).unwrap();
+        let mut trie = EthTrie::builder(memdb.clone())
+            .root_manager(&manager)
+            .build()
+            .unwrap();
+
+        let mut roots = vec![];
+        for round in 0..3u8 {
+            for i in 0..50u8 {
+                trie.insert(&[i], &[round; 40]).unwrap();
+            }
+            roots.push(trie.root_hash().unwrap());
+            if round == 0 {
+                manager.pin(roots[0]).unwrap();
+            }
+        }
+        assert_eq!(manager.roots().unwrap(), roots);
+
+        // The pinned root survived the commits after it, the other did not
+        let pinned = EthTrie::from(memdb.clone(), roots[0]).unwrap();
+        assert_eq!(pinned.get(&[7]).unwrap(), Some(vec![0; 40]));
+        let stale = EthTrie::from(memdb.clone(), roots[1]).unwrap();
+        assert!(stale.get(&[7]).is_err());
+
+        // Reopening finds the same roots and pins
+        let reopened = RootManager::open(memdb.clone()).unwrap();
+        assert_eq!(reopened.roots().unwrap(), roots);
+        assert_eq!(reopened.last_root(), roots.last().copied());
+        assert_eq!(reopened.pinned(), vec![roots[0]]);
+
+        assert!(manager.unpin(roots[0]).unwrap());
+        assert!(!manager.unpin(roots[0]).unwrap());
+        assert!(pinned.get(&[7]).is_err());
+        for i in 0..50u8 {
+            trie.insert(&[i], &[9; 40]).unwrap();
+        }
+        trie.root_hash().unwrap();
+        assert!(!manager.is_protected(&roots[0]));
+        let stale = EthTrie::from(memdb.clone(), roots[2]).unwrap();
+        assert!(stale.get(&[7]).is_err());
+    }
+
+    #[test]
+    fn test_root_manager_pin_counts() {
+        let memdb = Arc::new(MemoryDB::new(true));
+        let mut trie = EthTrie::new(memdb.clone());
+        trie.insert(b"key", &[1; 40]).unwrap();
+        let root = trie.root_hash().unwrap();
+
+        let manager = RootManager::open(memdb).unwrap();
+        manager.pin(root).unwrap();
+        manager.pin(root).unwrap();
+        assert!(manager.is_protected(&root));
+        assert!(manager.unpin(root).unwrap());
+        assert!(manager.is_pinned(&root));
+        assert!(manager.unpin(root).unwrap());
+        assert!(!manager.is_pinned(&root));
+        assert!(!manager.is_protected(&root));
+        assert_eq!(manager.roots().unwrap(), vec![root]);
+    }
+
+    #[test]
+    fn test_root_manager_reopened_removes_kept() {
+        let memdb = Arc::new(MemoryDB::new(true));
+        let manager = RootManager::open(memdb.clone()).unwrap();
+        let mut trie = EthTrie::builder(memdb.clone())
+            .root_manager(&manager)
+            .build()
+            .unwrap();
+        for i in 0..50u8 {
+            trie.insert(&[i], &[i; 40]).unwrap();
+        }
+        let pinned = trie.root_hash().unwrap();
+        manager.pin(pinned).unwrap();
+        let mut roots = vec![pinned];
+        for round in 1..20u8 {
+            trie.insert(&[round], &[round + 100; 40]).unwrap();
+            roots.push(trie.root_hash().unwrap());
+        }
+        let old = EthTrie::from(memdb.clone(), pinned).unwrap();
+        assert_eq!(old.get(&[1]).unwrap(), Some(vec![1; 40]));
+        drop((trie, manager));
+
+        // Every root is recorded once, and each kept node is marked under its own key
+        let reopened = RootManager::open(memdb.clone()).unwrap();
+        assert_eq!(reopened.roots().unwrap(), roots);
+        let kept: Vec<B256> = reopened.registry.read().kept.iter().copied().collect();
+        assert!(!kept.is_empty());
+        for hash in kept.iter() {
+            assert!(memdb.get(&kept_key(hash)).unwrap().is_some());
+        }
+        assert!(!memdb.get(PINS_KEY).unwrap().unwrap().is_empty());
+
+        // The nodes kept for the pinned root are removed once it is unpinned, even by a
+        // manager that didn't keep them
+        assert!(reopened.unpin(pinned).unwrap());
+        assert!(old.get(&[1]).is_err());
+        for hash in kept.iter() {
+            assert_eq!(memdb.get(hash.as_slice()).unwrap(), None);
+            assert_eq!(memdb.get(&kept_key(hash)).unwrap(), None);
+        }
+        let head = EthTrie::from(memdb.clone(), *roots.last().unwrap()).unwrap();
+        assert_eq!(head.get(&[19]).unwrap(), Some(vec![119; 40]));
+        assert_eq!(head.get(&[30]).unwrap(), Some(vec![30; 40]));
+    }
+
+    #[cfg(feature = "test-utils")]
+    #[test]
+    fn test_root_manager_failed_write() {
+        let db = Arc::new(FaultyDB::new(MemoryDB::new(true), 0));
+        let manager = RootManager::open(db.clone()).unwrap();
+        let mut trie = EthTrie::builder(db.clone())
+            .root_manager(&manager)
+            .build()
+            .unwrap();
+        trie.insert(b"key", &[1; 40]).unwrap();
+        let root = trie.root_hash().unwrap();
+
+        // Roots whose records fail to be written are not counted
+        db.set_faults(Faults {
+            batch_fail_after: Some(0),
+            ..Faults::default()
+        });
+        trie.insert(b"key", &[2; 40]).unwrap();
+        assert!(trie.root_hash().is_err());
+        let other = B256::repeat_byte(7);
+        assert!(manager.record(other).is_err());
+        assert!(manager.pin(root).is_err());
+        db.set_faults(Faults::default());
+        assert_eq!(manager.roots().unwrap(), vec![root]);
+        assert_eq!(manager.last_root(), Some(root));
+        assert!(!manager.is_pinned(&root));
+
+        manager.record(other).unwrap();
+        assert_eq!(manager.roots().unwrap(), vec![root, other]);
+        assert_eq!(
+            RootManager::open(db).unwrap().roots().unwrap(),
+            vec![root, other]
+        );
+    }
+}
@@ -0,0 +1,232 @@
+use std::collections::BTreeMap;
+use std::fmt::Debug;
+
+use alloy_primitives::{hex, B256};
+
+use crate::db::DB;
+use crate::trie::{EthTrie, RootWithTrieDiff, TrieIterator, TrieRead, TrieResult, TrieWrite};
+
+/// Wraps an `EthTrie` and keeps a map of what it should hold alongside it, checking every
+/// read, removal, iteration and commit against the map, to track down bugs in how the
+/// trie is laid out. Panics with a report of the key and both values as soon as the trie
+/// and the map disagree.
+///
+/// Each iteration first reads the whole trie, and each commit reads it back from the
+/// database, so this is only meant for debugging. Enabled by the `test-utils` feature.
+/// Errors from the database are passed on rather than taken as a disagreement.
+#[derive(Debug)]
+pub struct ShadowTrie<D>
+where
+    D: DB,
+{
+    trie: EthTrie<D>,
+    shadow: BTreeMap<Vec<u8>, Vec<u8>>,
+}
+
+impl<D> ShadowTrie<D>
+where
+    D: DB,
+{
+    /// Wraps a trie, reading all its entries into the map.
+    pub fn new(trie: EthTrie<D>) -> TrieResult<Self> {
+        let shadow = trie.iter().collect::<TrieResult<_>>()?;
+        Ok(Self { trie, shadow })
+    }
+
+    /// Returns the entries the trie should hold.
+    pub fn shadow(&self) -> &BTreeMap<Vec<u8>, Vec<u8>> {
+        &self.shadow
+    }
+
+    pub fn inner(&self) -> &EthTrie<D> {
+        &self.trie
+    }
+
+    pub fn into_inner(self) -> EthTrie<D> {
+        self.trie
+    }
+
+    fn check<T>(&self, op: &str, key: &[u8], found: T, expected: T)
+    where
+        T: PartialEq + Debug,
+    {
+        if found != expected {
+            panic!(
+                "shadow map diverged on {} of key {}: the trie has {:?}, the map {:?} \
+                 (root {:?}, {} entries, {} uncommitted writes)",
+                op,
+                hex::encode_prefixed(key),
+                found,
+                expected,
+                self.trie.root_hash,
+                self.shadow.len(),
+                self.trie.writes_since_commit,
+            );
+        }
+    }
+
+    // Checks that the trie holds exactly the entries of the map.
+    fn check_entries<I>(&self, op: &str, entries: I) -> TrieResult<()>
+    where
+        I: Iterator<Item = TrieResult<(Vec<u8>, Vec<u8>)>>,
+    {
+        let mut expected = self.shadow.iter();
+        for entry in entries {
+            let (key, value) = entry?;
+            match expected.next() {
+                Some((k, v)) if *k == key => self.check(op, &key, Some(&value), Some(v)),
+                Some((k, _)) if *k < key => self.check(op, k, None, self.shadow.get(k)),
+                _ => self.check(op, &key, Some(&value), None),
+            }
+        }
+        if let Some((k, v)) = expected.next() {
+            self.check(op, k, None, Some(v));
+        }
+        Ok(())
+    }
+
+    // Reads the committed trie back from the database and checks it against the map.
+    fn check_committed(&self, root: B256) -> TrieResult<()> {
+        let committed = EthTrie::from(self.trie.db.clone(), root)?;
+        self.check_entries("re-read after commit", committed.iter())
+    }
+}
+
+impl<D> TrieRead<D> for ShadowTrie<D>
+where
+    D: DB,
+{
+    type Iter<'a>
+        = TrieIterator<'a, D>
+    where
+        Self: 'a;
+
+    fn get(&self, key: &[u8]) -> TrieResult<Option<Vec<u8>>> {
+        let value = self.trie.get(key)?;
+        self.check("get", key, value.as_ref(), self.shadow.get(key));
+        Ok(value)
+    }
+
+    fn get_with<R, F>(&self, key: &[u8], f: F) -> TrieResult<Option<R>>
+    where
+        F: FnOnce(&[u8]) -> R,
+    {
+        Ok(self.get(key)?.map(|value| f(&value)))
+    }
+
+    fn contains(&self, key: &[u8]) -> TrieResult<bool> {
+        let found = self.trie.contains(key)?;
+        self.check("contains", key, found, self.shadow.contains_key(key));
+        Ok(found)
+    }
+
+    fn get_proof(&self, key: &[u8]) -> TrieResult<Vec<Vec<u8>>> {
+        self.trie.get_proof(key)
+    }
+
+    fn verify_proof(
+        &self,
+        root_hash: B256,
+        key: &[u8],
+        proof: Vec<Vec<u8>>,
+    ) -> TrieResult<Option<Vec<u8>>> {
+        self.trie.verify_proof(root_hash, key, proof)
+    }
+
+    /// Checks the entries of the trie against the map before returning a new iterator
+    /// over them.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the trie and the map disagree, or if the trie can't be read to the end.
+    fn iter(&self) -> Self::Iter<'_> {
+        self.check_entries("iteration", self.trie.iter())
+            .expect("to read the trie");
+        self.trie.iter()
+    }
+
+    fn seek_iter<'a>(&'a self, iter: &mut Self::Iter<'a>, key: &[u8]) -> TrieResult<()> {
+        self.trie.seek_iter(iter, key)
+    }
+}
+
+impl<D> TrieWrite<D> for ShadowTrie<D>
+where
+    D: DB,
+{
+    fn insert(&mut self, key: &[u8], value: &[u8]) -> TrieResult<()> {
+        self.trie.insert(key, value)?;
+        // An empty value removes the key, unless the trie is strict and has rejected it
+        if value.is_empty() {
+            self.shadow.remove(key);
+        } else {
+            self.shadow.insert(key.to_vec(), value.to_vec());
+        }
+        self.get(key)?;
+        Ok(())
+    }
+
+    fn remove(&mut self, key: &[u8]) -> TrieResult<bool> {
+        let removed = self.trie.remove(key)?;
+        let expected = self.shadow.remove(key).is_some();
+        self.check("remove", key, removed, expected);
+        Ok(removed)
+    }
+
+    fn root_hash(&mut self) -> TrieResult<B256> {
+        let root = self.trie.root_hash()?;
+        self.check_committed(root)?;
+        Ok(root)
+    }
+
+    fn root_hash_with_changed_nodes(&mut self) -> TrieResult<RootWithTrieDiff> {
+        let diff = self.trie.root_hash_with_changed_nodes()?;
+        self.check_committed(diff.root)?;
+        Ok(diff)
+    }
+
+    fn clear_trie_from_db(&mut self) -> TrieResult<()> {
+        self.trie.clear_trie_from_db()?;
+        self.shadow.clear();
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::ShadowTrie;
+    use crate::db::MemoryDB;
+    use crate::trie::{tests::random_trie, EthTrie, TrieRead, TrieWrite};
+
+    #[test]
+    fn test_shadow_trie_agrees() {
+        let (trie, kv) = random_trie(300);
+        let mut trie = ShadowTrie::new(trie).unwrap();
+        assert_eq!(trie.shadow(), &kv);
+
+        for (i, key) in kv.keys().enumerate() {
+            match i % 3 {
+                0 => assert!(trie.remove(key).unwrap()),
+                1 => trie.insert(key, &key.repeat(10)).unwrap(),
+                _ => trie.insert(key, b"").unwrap(),
+            }
+            assert!(!trie.contains(key).unwrap() || i % 3 == 1);
+        }
+        assert!(!trie.remove(b"missing").unwrap());
+        assert_eq!(trie.iter().count(), trie.shadow().len());
+        trie.root_hash().unwrap();
+        trie.root_hash_with_changed_nodes().unwrap();
+    }
+
+    #[test]
+    #[should_panic(expected = "shadow map diverged on get of key 0x74657374")]
+    fn test_shadow_trie_diverged() {
+        let memdb = Arc::new(MemoryDB::new(true));
+        let mut trie = ShadowTrie::new(EthTrie::new(memdb)).unwrap();
+        trie.insert(b"test", b"test").unwrap();
+        trie.shadow.insert(b"test".to_vec(), b"tset".to_vec());
+        trie.get(b"test").unwrap();
+    }
+}
@@ -0,0 +1,337 @@
+use std::sync::Arc;
+
+use alloy_primitives::{Bytes, B256};
+use alloy_rlp::Decodable;
+use keccak_hash::KECCAK_NULL_RLP;
+
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+use crate::db::DB;
+use crate::errors::TrieError;
+use crate::post_state::TrieAccount;
+use crate::resume::IterCursor;
+use crate::trie::{EthTrie, TrieRead, TrieResult, HASHED_LENGTH};
+
+/// The flat keys of accounts start with this byte, followed by the account hash.
+pub const SNAPSHOT_ACCOUNT_PREFIX: u8 = b'a';
+/// The flat keys of storage slots start with this byte, followed by the account hash and
+/// the slot hash.
+pub const SNAPSHOT_STORAGE_PREFIX: u8 = b'o';
+// The key the progress of the generation is stored under in the target database.
+const SNAPSHOT_MARKER_KEY: &[u8] = b"SnapshotGenerator";
+
+/// Returns the flat key of an account.
+pub fn snapshot_account_key(account: &B256) -> Vec<u8> {
+    [&[SNAPSHOT_ACCOUNT_PREFIX], account.as_slice()].concat()
+}
+
+/// Returns the flat key of a storage slot of an account.
+pub fn snapshot_storage_key(account: &B256, slot: &[u8]) -> Vec<u8> {
+    [&[SNAPSHOT_STORAGE_PREFIX], account.as_slice(), slot].concat()
+}
+
+/// How far a `SnapshotGenerator` got, stored in the target database along with each
+/// batch of entries.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct SnapshotMarker {
+    /// The state root the snapshot is taken of.
+    pub root: B256,
+    /// The flat key generation continues from, or `None` once the snapshot is complete.
+    pub next: Option<Bytes>,
+}
+
+impl SnapshotMarker {
+    /// Encodes the marker as the root, a byte telling whether the snapshot is complete,
+    /// and the key to continue from.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        IterCursor {
+            root: self.root,
+            next: self.next.clone(),
+        }
+        .to_bytes()
+    }
+
+    /// Decodes a marker written by `to_bytes`.
+    pub fn from_bytes(data: &[u8]) -> TrieResult<Self> {
+        let IterCursor { root, next } = IterCursor::from_bytes(data)?;
+        Ok(Self { root, next })
+    }
+}
+
+/// Walks the account trie of a state and the storage trie of every account, writing a
+/// flat copy of their entries to another database, to serve range reads and snap sync
+/// requests without walking the tries.
+///
+/// Accounts are written under `snapshot_account_key` and slots under
+/// `snapshot_storage_key`, with their values as the tries hold them, so that the keys of
+/// each kind sort as the tries do. Entries are generated in batches, each written along
+/// with a `SnapshotMarker` of where the next batch starts, so that a generator created
+/// later on the same target continues where the last batch ended, even in another
+/// process.
+#[derive(Debug)]
+pub struct SnapshotGenerator<D, T>
+where
+    D: DB,
+    T: DB,
+{
+    accounts: EthTrie<D>,
+    target: Arc<T>,
+    marker: SnapshotMarker,
+    batch_size: usize,
+}
+
+impl<D, T> SnapshotGenerator<D, T>
+where
+    D: DB,
+    T: DB,
+{
+    /// Creates a generator of the state at `root` in `db`, continuing from the marker
+    /// stored in `target` if there is one.
+    ///
+    /// Fails with `TrieError::InvalidStateRoot` if `target` holds a snapshot of another
+    /// root, whose entries would otherwise be mixed with those of this one.
+    pub fn new(db: Arc<D>, target: Arc<T>, root: B256) -> TrieResult<Self> {
+        let marker = match target.get(SNAPSHOT_MARKER_KEY).map_err(TrieError::db)? {
+            Some(stored) => SnapshotMarker::from_bytes(&stored)?,
+            None => SnapshotMarker {
+                root,
+                next: Some(snapshot_account_key(&B256::ZERO).into()),
+            },
+        };
+        if marker.root != root {
+            return Err(TrieError::InvalidStateRoot);
+        }
+
+        let empty_root: B256 = KECCAK_NULL_RLP.as_fixed_bytes().into();
+        let accounts = match root == empty_root {
+            true => EthTrie::new(db),
+            false => EthTrie::from(db, root)?,
+        };
+        Ok(Self {
+            accounts,
+            target,
+            marker,
+            batch_size: 10_000,
+        })
+    }
+
+    /// Sets the number of entries `run` writes at a time.
+    pub fn batch_size(mut self, entries: usize) -> Self {
+        self.batch_size = entries.max(1);
+        self
+    }
+
+    pub fn marker(&self) -> &SnapshotMarker {
+        &self.marker
+    }
+
+    pub fn is_done(&self) -> bool {
+        self.marker.next.is_none()
+    }
+
+    /// Generates the rest of the snapshot.
+    pub fn run(&mut self) -> TrieResult<()> {
+        while !self.is_done() {
+            self.step(self.batch_size)?;
+        }
+        Ok(())
+    }
+
+    /// Generates and writes up to `limit` entries, and returns how many were written.
+    pub fn step(&mut self, limit: usize) -> TrieResult<usize> {
+        let Some(next) = self.marker.next.clone() else {
+            return Ok(0);
+        };
+        let (start, mut start_slot) = parse_key(&next)?;
+        let limit = limit.max(1);
+
+        let empty_root: B256 = KECCAK_NULL_RLP.as_fixed_bytes().into();
+        let mut keys = Vec::with_capacity(limit + 1);
+        let mut values = Vec::with_capacity(limit + 1);
+        let mut next = None;
+        let mut iter = self.accounts.iter();
+        iter.seek(start.as_slice())?;
+        'accounts: for entry in iter {
+            let (key, value) = entry?;
+            if key.len() != HASHED_LENGTH {
+                return Err(TrieError::InvalidData);
+            }
+            let account = B256::from_slice(&key);
+            if keys.len() >= limit {
+                next = Some(snapshot_account_key(&account));
+                break;
+            }
+
+            // A batch ending within the storage of an account has written the account
+            let resume_slot = start_slot.take().filter(|_| account == start);
+            if resume_slot.is_none() {
+                keys.push(snapshot_account_key(&account));
+                values.push(value.clone());
+            }
+            let storage_root = TrieAccount::decode(&mut value.as_slice())?.storage_root;
+            if storage_root == empty_root {
+                continue;
+            }
+            let storage = EthTrie::from(self.accounts.db.clone(), storage_root)?;
+            let mut slots = storage.iter();
+            if let Some(slot) = resume_slot {
+                slots.seek(&slot)?;
+            }
+            for slot in slots {
+                let (slot, value) = slot?;
+                if keys.len() >= limit {
+                    next = Some(snapshot_storage_key(&account, &slot));
+                    break 'accounts;
+                }
+                keys.push(snapshot_storage_key(&account, &slot));
+                values.push(value);
+            }
+        }
+
+        let written = keys.len();
+        let marker = SnapshotMarker {
+            root: self.marker.root,
+            next: next.map(Bytes::from),
+        };
+        keys.push(SNAPSHOT_MARKER_KEY.to_vec());
+        values.push(marker.to_bytes());
+        self.target
+            .insert_batch(keys, values)
+            .map_err(TrieError::db)?;
+        self.target.flush().map_err(TrieError::db)?;
+        self.marker = marker;
+        Ok(written)
+    }
+}
+
+// Splits a flat key into the account it belongs to and, for a slot, the slot.
+fn parse_key(key: &[u8]) -> TrieResult<(B256, Option<Vec<u8>>)> {
+    match key.split_first() {
+        Some((&SNAPSHOT_ACCOUNT_PREFIX, account)) if account.len() == HASHED_LENGTH => {
+            Ok((B256::from_slice(account), None))
+        }
+        Some((&SNAPSHOT_STORAGE_PREFIX, rest)) if rest.len() >= HASHED_LENGTH => {
+            let (account, slot) = rest.split_at(HASHED_LENGTH);
+            Ok((B256::from_slice(account), Some(slot.to_vec())))
+        }
+        _ => Err(TrieError::InvalidData),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+    use std::sync::Arc;
+
+    use alloy_primitives::{B256, U256};
+    use keccak_hash::KECCAK_NULL_RLP;
+
+    use super::{snapshot_account_key, snapshot_storage_key, SnapshotGenerator};
+    use crate::db::{IterableDB, MemoryDB, DB};
+    use crate::errors::TrieError;
+    use crate::post_state::{HashedAccount, HashedPostState, HashedStorage};
+    use crate::trie::{EthTrie, TrieRead};
+
+    // Builds a state of 20 accounts, every other one with 0 to 9 slots.
+    fn state() -> (Arc<MemoryDB>, B256) {
+        let memdb = Arc::new(MemoryDB::new(true));
+        let mut post_state = HashedPostState::default();
+        for i in 0..20u8 {
+            let account = B256::repeat_byte(i);
+            post_state.accounts.insert(
+                account,
+                Some(HashedAccount {
+                    nonce: i as u64,
+                    balance: U256::from(i),
+                    code_hash: KECCAK_NULL_RLP.as_fixed_bytes().into(),
+                }),
+            );
+            if i % 2 == 0 {
+                let storage = (1..=i / 2)
+                    .map(|slot| (B256::repeat_byte(slot), U256::from(slot)))
+                    .collect();
+                post_state.storages.insert(
+                    account,
+                    HashedStorage {
+                        wiped: false,
+                        storage,
+                    },
+                );
+            }
+        }
+        let updates = post_state
+            .state_root(memdb.clone(), KECCAK_NULL_RLP.as_fixed_bytes().into())
+            .unwrap();
+        for (hash, node) in updates.nodes {
+            memdb.insert(hash.as_slice(), node).unwrap();
+        }
+        (memdb, updates.state_root)
+    }
+
+    #[test]
+    fn test_snapshot_generation_resumes() {
+        let (memdb, root) = state();
+        let mut expected = BTreeMap::new();
+        let accounts = EthTrie::from(memdb.clone(), root).unwrap();
+        for entry in accounts.iter() {
+            let (key, value) = entry.unwrap();
+            let account = B256::from_slice(&key);
+            expected.insert(snapshot_account_key(&account), value);
+        }
+        for i in (0..20u8).step_by(2) {
+            for slot in 1..=i / 2 {
+                let value = alloy_rlp::encode(U256::from(slot));
+                let key = snapshot_storage_key(&B256::repeat_byte(i), &[slot; 32]);
+                expected.insert(key, value);
+            }
+        }
+
+        // Each batch of 7 entries runs on a new generator, which continues from the marker
+        let target = Arc::new(MemoryDB::new(true));
+        let mut steps = 0;
+        loop {
+            let mut generator =
+                SnapshotGenerator::new(memdb.clone(), target.clone(), root).unwrap();
+            if generator.is_done() {
+                break;
+            }
+            assert!(generator.step(7).unwrap() <= 7);
+            steps += 1;
+        }
+        assert_eq!(steps, expected.len().div_ceil(7));
+
+        let generated: BTreeMap<_, _> = target
+            .keys()
+            .unwrap()
+            .into_iter()
+            .filter(|key| key.as_slice() != b"SnapshotGenerator")
+            .map(|key| (key.clone(), target.get(&key).unwrap().unwrap()))
+            .collect();
+        assert_eq!(generated, expected);
+
+        // In one go, to the same entries
+        let other = Arc::new(MemoryDB::new(true));
+        let mut generator = SnapshotGenerator::new(memdb.clone(), other.clone(), root)
+            .unwrap()
+            .batch_size(5);
+        generator.run().unwrap();
+        assert_eq!(other.len().unwrap(), target.len().unwrap());
+    }
+
+    #[test]
+    fn test_snapshot_of_another_root() {
+        let (memdb, root) = state();
+        let target = Arc::new(MemoryDB::new(true));
+        SnapshotGenerator::new(memdb.clone(), target.clone(), root)
+            .unwrap()
+            .step(3)
+            .unwrap();
+        let empty_root = KECCAK_NULL_RLP.as_fixed_bytes().into();
+        assert!(matches!(
+            SnapshotGenerator::new(memdb, target, empty_root),
+            Err(TrieError::InvalidStateRoot)
+        ));
+    }
+}
@@ -0,0 +1,75 @@
+use crate::db::DB;
+use crate::nibbles::Nibbles;
+use crate::trie::{EthTrie, TrieRead, TrieResult, TrieWrite};
+
+impl<D> EthTrie<D>
+where
+    D: DB,
+{
+    /// Splits the trie, including its uncommitted changes, into the entries whose keys
+    /// start with the nibbles of `prefix` and the rest. Both tries are committed to the
+    /// database of this one and returned in that order.
+    pub fn split(mut self, prefix: &Nibbles) -> TrieResult<(Self, Self)> {
+        let mut inside = vec![];
+        let mut iter = self.iter();
+        iter.seek(&prefix.seek_key())?;
+        for item in iter {
+            let (key, value) = item?;
+            let nibbles = Nibbles::from_raw(&key, false);
+            if !nibbles.get_data().starts_with(prefix.get_data()) {
+                break;
+            }
+            inside.push((key, value));
+        }
+
+        // Commit the outside first: committing it removes stale nodes, some of which may
+        // also belong to the inside trie.
+        for (key, _) in inside.iter() {
+            self.remove(key)?;
+        }
+        self.root_hash()?;
+
+        let mut trie = EthTrie::new(self.db.clone());
+        trie.insert_batch(inside)?;
+        trie.root_hash()?;
+        Ok((trie, self))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::nibbles::Nibbles;
+    use crate::trie::tests::random_trie;
+    use crate::trie::{EthTrie, TrieRead, TrieWrite};
+
+    #[test]
+    fn test_split() {
+        for prefix in [vec![], vec![0], vec![0, 2], vec![0, 2, 0], vec![9]] {
+            let (mut trie, mut kv) = random_trie(300);
+            trie.insert(b"\x02uncommitted", b"value").unwrap();
+            kv.insert(b"\x02uncommitted".to_vec(), b"value".to_vec());
+            let db = trie.db.clone();
+
+            let prefix = Nibbles::from_hex(&prefix);
+            let (inside, outside) = trie.split(&prefix).unwrap();
+
+            let (expected_in, expected_out): (Vec<_>, Vec<_>) =
+                kv.into_iter().partition(|(key, _)| {
+                    Nibbles::from_raw(key, false)
+                        .get_data()
+                        .starts_with(prefix.get_data())
+                });
+            for (trie, expected) in [(inside, expected_in), (outside, expected_out)] {
+                // Both tries are committed and readable from the database
+                let reopened = EthTrie::from(db.clone(), trie.root_hash).unwrap();
+                assert_eq!(
+                    reopened
+                        .iter()
+                        .map(|item| item.unwrap())
+                        .collect::<Vec<_>>(),
+                    expected
+                );
+            }
+        }
+    }
+}
@@ -0,0 +1,231 @@
+use std::sync::Arc;
+
+use alloy_primitives::B256;
+use hashbrown::{HashMap, HashSet};
+use keccak_hash::{keccak, KECCAK_NULL_RLP};
+
+use crate::db::DB;
+use crate::errors::TrieError;
+use crate::node::{empty_children, Node};
+use crate::trie::{decode_node, encode_node, EthTrie, TrieResult, HASHED_LENGTH};
+
+// Uncommitted state is staged under this prefix followed by the name it was staged with.
+const STAGING_KEY_PREFIX: &[u8] = b"eth-trie:staging:";
+const STAGING_VERSION: u8 = 1;
+
+fn staging_key(name: &[u8]) -> Vec<u8> {
+    [STAGING_KEY_PREFIX, name].concat()
+}
+
+impl<D> EthTrie<D>
+where
+    D: DB,
+{
+    /// Writes the uncommitted changes of the trie to the database under `name`, so that
+    /// `EthTrie::restore_pending` can bring them back after a restart. Replaces anything
+    /// staged under the same name.
+    ///
+    /// Staged nodes are kept apart from the committed ones, so pruning and other tries
+    /// don't see them. The staged state stays until `EthTrie::discard_pending` removes
+    /// it, which should follow the commit of the changes.
+    pub fn persist_pending(&self, name: &[u8]) -> TrieResult<()> {
+        let mut nodes = vec![];
+        let root = encode_node(&self.root, &mut |_, encoded| nodes.push(encoded));
+
+        let mut record = vec![STAGING_VERSION];
+        record.extend_from_slice(self.root_hash.as_slice());
+        match self.leaf_count {
+            Some(count) => {
+                record.push(1);
+                record.extend_from_slice(&(count as u64).to_be_bytes());
+            }
+            None => record.push(0),
+        }
+        record.extend_from_slice(&(self.writes_since_commit as u64).to_be_bytes());
+        record.extend_from_slice(&(self.passing_keys.len() as u32).to_be_bytes());
+        for hash in self.passing_keys.iter() {
+            record.extend_from_slice(hash.as_slice());
+        }
+        record.extend_from_slice(&(nodes.len() as u32 + 1).to_be_bytes());
+        for encoded in std::iter::once(&root).chain(nodes.iter()) {
+            record.extend_from_slice(&(encoded.len() as u32).to_be_bytes());
+            record.extend_from_slice(encoded);
+        }
+
+        self.db
+            .insert(&staging_key(name), record)
+            .map_err(TrieError::db)?;
+        self.db.flush().map_err(TrieError::db)
+    }
+
+    /// Opens the trie staged under `name` by `EthTrie::persist_pending`, at the root it
+    /// was committed at and with its uncommitted changes, or returns `None` if nothing is
+    /// staged under that name. Fails with `TrieError::InvalidData` if the staged state is
+    /// corrupt.
+    pub fn restore_pending(db: Arc<D>, name: &[u8]) -> TrieResult<Option<Self>> {
+        let Some(record) = db.get(&staging_key(name)).map_err(TrieError::db)? else {
+            return Ok(None);
+        };
+        let mut data = record.as_slice();
+        if take(&mut data, 1)? != [STAGING_VERSION] {
+            return Err(TrieError::InvalidData);
+        }
+        let committed_root = B256::from_slice(take(&mut data, HASHED_LENGTH)?);
+        let leaf_count = match take(&mut data, 1)? {
+            [0] => None,
+            [1] => Some(take_u64(&mut data)? as usize),
+            _ => return Err(TrieError::InvalidData),
+        };
+        let writes_since_commit = take_u64(&mut data)? as usize;
+
+        let mut passing_keys = HashSet::new();
+        for _ in 0..take_u32(&mut data)? {
+            passing_keys.insert(B256::from_slice(take(&mut data, HASHED_LENGTH)?));
+        }
+
+        let mut nodes = HashMap::new();
+        let mut root = None;
+        for _ in 0..take_u32(&mut data)? {
+            let len = take_u32(&mut data)? as usize;
+            let encoded = take(&mut data, len)?;
+            if root.is_none() {
+                root = Some(encoded);
+            } else {
+                nodes.insert(B256::from(keccak(encoded).0), encoded);
+            }
+        }
+        let root = root.ok_or(TrieError::InvalidData)?;
+        if !data.is_empty() {
+            return Err(TrieError::InvalidData);
+        }
+
+        let mut trie = match committed_root.as_slice() == KECCAK_NULL_RLP.as_bytes() {
+            true => EthTrie::new(db),
+            false => EthTrie::from(db, committed_root)?,
+        };
+        trie.root = inflate(decode_node(&mut &root[..])?, &nodes)?;
+        trie.leaf_count = leaf_count;
+        trie.passing_keys = passing_keys;
+        trie.writes_since_commit = writes_since_commit;
+        Ok(Some(trie))
+    }
+
+    /// Removes the state staged under `name`, if any.
+    pub fn discard_pending(&self, name: &[u8]) -> TrieResult<()> {
+        self.db.remove(&staging_key(name)).map_err(TrieError::db)
+    }
+}
+
+// Replaces the references to staged nodes with the nodes themselves. The other
+// references are to committed nodes, which are loaded as usual.
+fn inflate(node: Node, nodes: &HashMap<B256, &[u8]>) -> TrieResult<Node> {
+    match node {
+        Node::Hash(hash) => match nodes.get(&hash.hash) {
+            Some(encoded) => inflate(decode_node(&mut &encoded[..])?, nodes),
+            None => Ok(Node::Hash(hash)),
+        },
+        Node::Branch(branch) => {
+            let branch = branch.read().clone();
+            let mut children = empty_children();
+            for (i, child) in branch.children.iter().enumerate() {
+                children[i] = inflate(child.clone(), nodes)?;
+            }
+            Ok(Node::from_branch(children, branch.value.clone()))
+        }
+        Node::Extension(ext) => {
+            let ext = ext.read().clone();
+            Ok(Node::from_extension(
+                ext.prefix.clone(),
+                inflate(ext.node.clone(), nodes)?,
+            ))
+        }
+        _ => Ok(node),
+    }
+}
+
+fn take<'a>(data: &mut &'a [u8], len: usize) -> TrieResult<&'a [u8]> {
+    if data.len() < len {
+        return Err(TrieError::InvalidData);
+    }
+    let (head, tail) = data.split_at(len);
+    *data = tail;
+    Ok(head)
+}
+
+fn take_u32(data: &mut &[u8]) -> TrieResult<u32> {
+    Ok(u32::from_be_bytes(take(data, 4)?.try_into().unwrap()))
+}
+
+fn take_u64(data: &mut &[u8]) -> TrieResult<u64> {
+    Ok(u64::from_be_bytes(take(data, 8)?.try_into().unwrap()))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use crate::db::{MemoryDB, DB};
+    use crate::errors::TrieError;
+    use crate::trie::{EthTrie, TrieRead, TrieWrite};
+
+    // Commits 50 entries, then changes some without committing.
+    fn pending_trie(memdb: Arc<MemoryDB>) -> EthTrie<MemoryDB> {
+        let mut trie = EthTrie::new(memdb);
+        for i in 0..50u8 {
+            trie.insert(&[i], &[i; 40]).unwrap();
+        }
+        trie.root_hash().unwrap();
+        for i in 0..10u8 {
+            trie.insert(&[i], &[0xff; 40]).unwrap();
+            trie.remove(&[i + 20]).unwrap();
+        }
+        trie.insert(b"new key", b"new value").unwrap();
+        trie
+    }
+
+    #[test]
+    fn test_restore_pending() {
+        let memdb = Arc::new(MemoryDB::new(true));
+        pending_trie(memdb.clone())
+            .persist_pending(b"state")
+            .unwrap();
+
+        let mut restored = EthTrie::restore_pending(memdb.clone(), b"state")
+            .unwrap()
+            .unwrap();
+        assert_eq!(restored.len().unwrap(), 41);
+        assert_eq!(restored.get(&[3]).unwrap(), Some(vec![0xff; 40]));
+        assert_eq!(restored.get(&[23]).unwrap(), None);
+        restored.discard_pending(b"state").unwrap();
+        assert!(EthTrie::restore_pending(memdb.clone(), b"state")
+            .unwrap()
+            .is_none());
+
+        // Committing the restored trie removes the same stale nodes as committing the
+        // original would have.
+        let expected_db = Arc::new(MemoryDB::new(true));
+        let mut expected = pending_trie(expected_db.clone());
+        assert_eq!(restored.root_hash().unwrap(), expected.root_hash().unwrap());
+        assert_eq!(memdb.len().unwrap(), expected_db.len().unwrap());
+    }
+
+    #[test]
+    fn test_restore_pending_of_new_trie() {
+        let memdb = Arc::new(MemoryDB::new(true));
+        let mut trie = EthTrie::new(memdb.clone());
+        trie.insert(b"test", &[1; 40]).unwrap();
+        trie.insert(b"test1", &[2; 40]).unwrap();
+        trie.persist_pending(b"").unwrap();
+
+        let restored = EthTrie::restore_pending(memdb.clone(), b"")
+            .unwrap()
+            .unwrap();
+        assert_eq!(restored.get(b"test1").unwrap(), Some(vec![2; 40]));
+
+        memdb.insert(b"eth-trie:staging:", vec![1, 2, 3]).unwrap();
+        assert!(matches!(
+            EthTrie::restore_pending(memdb, b""),
+            Err(TrieError::InvalidData)
+        ));
+    }
+}
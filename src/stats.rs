@@ -0,0 +1,366 @@
+use alloy_primitives::B256;
+use keccak_hash::keccak;
+
+use crate::db::DB;
+use crate::debug::resolve;
+use crate::nibbles::Nibbles;
+use crate::node::Node;
+use crate::trie::{EthTrie, NodeReader, TrieResult};
+
+/// Figures about the shape and size of a trie, as collected by `EthTrie::stats`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TrieStats {
+    pub branch_nodes: usize,
+    pub extension_nodes: usize,
+    pub leaf_nodes: usize,
+    /// The number of nodes at each depth, counting the root as depth 0.
+    pub depth_histogram: Vec<usize>,
+    /// The number of nodes stored by hash, including the root.
+    pub hashed_nodes: usize,
+    /// The number of nodes small enough to be stored inside their parent.
+    pub inline_nodes: usize,
+    /// The total size of the encoded nodes stored by hash. Inline nodes count towards
+    /// their parent.
+    pub encoded_bytes: usize,
+    /// The total number of children over all branch nodes.
+    pub branch_children: usize,
+    /// The number of branch nodes holding a value.
+    pub branch_values: usize,
+}
+
+impl TrieStats {
+    pub fn total_nodes(&self) -> usize {
+        self.branch_nodes + self.extension_nodes + self.leaf_nodes
+    }
+
+    /// Returns the deepest level holding a node, if the trie isn't empty.
+    pub fn max_depth(&self) -> Option<usize> {
+        self.depth_histogram.len().checked_sub(1)
+    }
+
+    /// Returns the average number of children per branch node.
+    pub fn average_branching_factor(&self) -> f64 {
+        if self.branch_nodes == 0 {
+            return 0.0;
+        }
+        self.branch_children as f64 / self.branch_nodes as f64
+    }
+}
+
+/// A subtree below the depth cutoff of `EthTrie::sample_stats`, with estimates of its
+/// size.
+///
+/// The estimates follow a single path from the subtree root to a leaf, picking a child at
+/// each branch by the hash of the branch, and assume every branch on the same level has
+/// as many children as the one on the path. They are exact for regular subtrees and
+/// unbiased over randomly chosen paths.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FrontierNode {
+    /// The nibble path from the root to the subtree.
+    pub path: Nibbles,
+    /// The hash of the subtree root, or `None` if it is inlined in its parent.
+    pub hash: Option<B256>,
+    pub estimated_nodes: f64,
+    /// The estimated number of entries, counting leaves and branch values.
+    pub estimated_entries: f64,
+    /// The estimated total size of the encoded nodes stored by hash.
+    pub estimated_bytes: f64,
+}
+
+/// Statistics about the top levels of a trie, as collected by `EthTrie::sample_stats`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SampledStats {
+    /// The figures of the nodes above the cutoff.
+    pub stats: TrieStats,
+    /// The subtrees at the cutoff depth, in path order.
+    pub frontier: Vec<FrontierNode>,
+}
+
+impl SampledStats {
+    /// Returns the estimated number of nodes in the whole trie.
+    pub fn estimated_nodes(&self) -> f64 {
+        let below: f64 = self.frontier.iter().map(|f| f.estimated_nodes).sum();
+        self.stats.total_nodes() as f64 + below
+    }
+
+    /// Returns the estimated total size of the encoded nodes stored by hash.
+    pub fn estimated_bytes(&self) -> f64 {
+        let below: f64 = self.frontier.iter().map(|f| f.estimated_bytes).sum();
+        self.stats.encoded_bytes as f64 + below
+    }
+
+    /// Returns the estimated number of entries in the whole trie.
+    pub fn estimated_entries(&self) -> f64 {
+        let below: f64 = self.frontier.iter().map(|f| f.estimated_entries).sum();
+        (self.stats.leaf_nodes + self.stats.branch_values) as f64 + below
+    }
+}
+
+impl<D> EthTrie<D>
+where
+    D: DB,
+{
+    /// Walks the whole trie, including uncommitted changes, and collects statistics about
+    /// its nodes.
+    pub fn stats(&self) -> TrieResult<TrieStats> {
+        let mut stats = TrieStats::default();
+        collect(
+            &self.reader(),
+            &self.root,
+            Nibbles::from_hex(&[]),
+            0,
+            usize::MAX,
+            &mut stats,
+            &mut vec![],
+        )?;
+        Ok(stats)
+    }
+
+    /// Collects statistics about the nodes above `max_depth`, counting the root as depth
+    /// 0, and estimates the size of each subtree at that depth by sampling one path
+    /// through it. Includes uncommitted changes.
+    pub fn sample_stats(&self, max_depth: usize) -> TrieResult<SampledStats> {
+        let mut stats = TrieStats::default();
+        let mut frontier = vec![];
+        collect(
+            &self.reader(),
+            &self.root,
+            Nibbles::from_hex(&[]),
+            0,
+            max_depth,
+            &mut stats,
+            &mut frontier,
+        )?;
+        Ok(SampledStats { stats, frontier })
+    }
+}
+
+fn collect<D: DB>(
+    reader: &NodeReader<'_, D>,
+    node: &Node,
+    path: Nibbles,
+    depth: usize,
+    max_depth: usize,
+    stats: &mut TrieStats,
+    frontier: &mut Vec<FrontierNode>,
+) -> TrieResult<()> {
+    if matches!(node, Node::Empty) {
+        return Ok(());
+    }
+    if depth == max_depth {
+        frontier.push(estimate(reader, node, path, depth == 0)?);
+        return Ok(());
+    }
+    let (node, encoded, hash) = resolve(reader, node, &path, depth == 0)?;
+
+    if stats.depth_histogram.len() <= depth {
+        stats.depth_histogram.resize(depth + 1, 0);
+    }
+    stats.depth_histogram[depth] += 1;
+    if hash.is_some() {
+        stats.hashed_nodes += 1;
+        stats.encoded_bytes += encoded.len();
+    } else {
+        stats.inline_nodes += 1;
+    }
+
+    match &node {
+        Node::Leaf(_) => stats.leaf_nodes += 1,
+        Node::Extension(ext) => {
+            stats.extension_nodes += 1;
+            let ext = ext.read().clone();
+            let child_path = path.join(&ext.prefix);
+            collect(
+                reader,
+                &ext.node,
+                child_path,
+                depth + 1,
+                max_depth,
+                stats,
+                frontier,
+            )?;
+        }
+        Node::Branch(branch) => {
+            stats.branch_nodes += 1;
+            let branch = branch.read().clone();
+            if branch.value.is_some() {
+                stats.branch_values += 1;
+            }
+            for (i, child) in branch.children.iter().enumerate() {
+                if matches!(child, Node::Empty) {
+                    continue;
+                }
+                stats.branch_children += 1;
+                let mut child_path = path.clone();
+                child_path.push(i as u8);
+                collect(
+                    reader,
+                    child,
+                    child_path,
+                    depth + 1,
+                    max_depth,
+                    stats,
+                    frontier,
+                )?;
+            }
+        }
+        Node::Empty | Node::Hash(_) => unreachable!(),
+    }
+    Ok(())
+}
+
+// Estimates the size of the subtree at `node` from a single path down to a leaf, weighing
+// each node on the path by the product of the branching factors above it.
+fn estimate<D: DB>(
+    reader: &NodeReader<'_, D>,
+    node: &Node,
+    path: Nibbles,
+    is_root: bool,
+) -> TrieResult<FrontierNode> {
+    let mut sample = FrontierNode {
+        path: path.clone(),
+        hash: None,
+        estimated_nodes: 0.0,
+        estimated_entries: 0.0,
+        estimated_bytes: 0.0,
+    };
+
+    let mut node = node.clone();
+    let mut path = path;
+    let mut weight = 1.0;
+    let mut is_root = is_root;
+    loop {
+        let (resolved, encoded, hash) = resolve(reader, &node, &path, is_root)?;
+        if path == sample.path {
+            sample.hash = hash;
+        }
+        sample.estimated_nodes += weight;
+        if hash.is_some() {
+            sample.estimated_bytes += weight * encoded.len() as f64;
+        }
+        is_root = false;
+
+        match &resolved {
+            Node::Leaf(_) => {
+                sample.estimated_entries += weight;
+                break;
+            }
+            Node::Extension(ext) => {
+                let ext = ext.read();
+                path = path.join(&ext.prefix);
+                node = ext.node.clone();
+            }
+            Node::Branch(branch) => {
+                let branch = branch.read();
+                if branch.value.is_some() {
+                    sample.estimated_entries += weight;
+                }
+                let children: Vec<_> = branch
+                    .children
+                    .iter()
+                    .enumerate()
+                    .filter(|(_, child)| !matches!(child, Node::Empty))
+                    .collect();
+                if children.is_empty() {
+                    break;
+                }
+                let pick = keccak(&encoded)[0] as usize % children.len();
+                let (nibble, child) = children[pick];
+                weight *= children.len() as f64;
+                path.push(nibble as u8);
+                node = child.clone();
+            }
+            Node::Empty | Node::Hash(_) => break,
+        }
+    }
+    Ok(sample)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use keccak_hash::keccak;
+
+    use super::TrieStats;
+    use crate::db::MemoryDB;
+    use crate::trie::{EthTrie, TrieWrite};
+
+    #[test]
+    fn test_stats() {
+        let memdb = Arc::new(MemoryDB::new(true));
+        let mut trie = EthTrie::new(memdb.clone());
+        trie.insert(b"do", b"verb").unwrap();
+        trie.insert(b"dog", b"puppy").unwrap();
+        trie.insert(b"horse", &[0xaa; 40]).unwrap();
+        let root = trie.root_hash().unwrap();
+
+        // The stored nodes are exactly the ones an export holds
+        let encoded_bytes = trie.export().unwrap().nodes.iter().map(|n| n.len()).sum();
+        let expected = TrieStats {
+            branch_nodes: 2,
+            extension_nodes: 2,
+            leaf_nodes: 2,
+            depth_histogram: vec![1, 1, 2, 1, 1],
+            hashed_nodes: 4,
+            inline_nodes: 2,
+            encoded_bytes,
+            branch_children: 3,
+            branch_values: 1,
+        };
+        assert_eq!(trie.stats().unwrap(), expected);
+        assert_eq!(expected.total_nodes(), 6);
+        assert_eq!(expected.max_depth(), Some(4));
+        assert_eq!(expected.average_branching_factor(), 1.5);
+
+        // Loading the nodes from the database gives the same figures
+        let trie = EthTrie::from(memdb, root).unwrap();
+        assert_eq!(trie.stats().unwrap(), expected);
+    }
+
+    #[test]
+    fn test_stats_empty() {
+        let trie = EthTrie::new(Arc::new(MemoryDB::new(true)));
+        let stats = trie.stats().unwrap();
+        assert_eq!(stats, TrieStats::default());
+        assert_eq!(stats.max_depth(), None);
+        assert_eq!(stats.average_branching_factor(), 0.0);
+
+        let sampled = trie.sample_stats(0).unwrap();
+        assert!(sampled.frontier.is_empty());
+        assert_eq!(sampled.estimated_entries(), 0.0);
+    }
+
+    #[test]
+    fn test_sample_stats() {
+        let memdb = Arc::new(MemoryDB::new(true));
+        let mut trie = EthTrie::new(memdb);
+        for i in 0..2000u32 {
+            trie.insert(keccak(i.to_be_bytes()).as_bytes(), &[0xaa; 40])
+                .unwrap();
+        }
+        let root = trie.root_hash().unwrap();
+        let stats = trie.stats().unwrap();
+
+        // Below the deepest node nothing is estimated
+        let sampled = trie.sample_stats(64).unwrap();
+        assert_eq!(sampled.stats, stats);
+        assert!(sampled.frontier.is_empty());
+        assert_eq!(sampled.estimated_entries(), 2000.0);
+
+        let sampled = trie.sample_stats(0).unwrap();
+        assert_eq!(sampled.stats, TrieStats::default());
+        assert_eq!(sampled.frontier.len(), 1);
+        assert_eq!(sampled.frontier[0].hash, Some(root));
+
+        let sampled = trie.sample_stats(2).unwrap();
+        assert_eq!(sampled.stats.total_nodes(), 17);
+        assert_eq!(sampled.frontier.len(), 256);
+        assert!(sampled.frontier.windows(2).all(|f| f[0].path < f[1].path));
+        let close =
+            |estimate: f64, actual: usize| (estimate - actual as f64).abs() < actual as f64 * 0.2;
+        assert!(close(sampled.estimated_entries(), 2000));
+        assert!(close(sampled.estimated_nodes(), stats.total_nodes()));
+        assert!(close(sampled.estimated_bytes(), stats.encoded_bytes));
+    }
+}
@@ -0,0 +1,143 @@
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+use futures_core::Stream;
+
+// The number of items a stream yields before giving the executor back its thread.
+const DEFAULT_YIELD_EVERY: usize = 256;
+
+/// A `futures::Stream` over a trie iterator, or any other iterator such as a
+/// `NodeIterator`, that gives the executor its thread back every few items, so that
+/// scanning a large trie doesn't hold a thread for the whole walk:
+///
+/// ```ignore
+/// use futures::StreamExt;
+///
+/// let mut stream = TrieStream::new(view.iter());
+/// while let Some(item) = stream.next().await {
+///     let (key, value) = item?;
+/// }
+/// ```
+///
+/// Each item is still read synchronously from the database; a database read that blocks
+/// blocks the executor thread for its duration.
+#[derive(Debug)]
+pub struct TrieStream<I> {
+    iter: I,
+    yield_every: usize,
+    since_yield: usize,
+}
+
+impl<I> TrieStream<I>
+where
+    I: Iterator + Unpin,
+{
+    pub fn new(iter: I) -> Self {
+        Self {
+            iter,
+            yield_every: DEFAULT_YIELD_EVERY,
+            since_yield: 0,
+        }
+    }
+
+    /// Sets the number of items yielded between two yields to the executor.
+    pub fn yield_every(mut self, items: usize) -> Self {
+        self.yield_every = items.max(1);
+        self
+    }
+
+    pub fn into_inner(self) -> I {
+        self.iter
+    }
+}
+
+impl<I> Stream for TrieStream<I>
+where
+    I: Iterator + Unpin,
+{
+    type Item = I::Item;
+
+    /// Returns the next item, or `Poll::Pending` after waking the task if the stream has
+    /// yielded enough items in a row.
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<I::Item>> {
+        if self.since_yield >= self.yield_every {
+            self.since_yield = 0;
+            cx.waker().wake_by_ref();
+            return Poll::Pending;
+        }
+        self.since_yield += 1;
+        Poll::Ready(self.iter.next())
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.iter.size_hint()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::future::Future;
+    use std::pin::pin;
+    use std::sync::Arc;
+    use std::task::{Context, Poll};
+
+    use futures::task::noop_waker;
+    use futures::{executor, StreamExt};
+
+    use super::TrieStream;
+    use crate::db::MemoryDB;
+    use crate::trie::{EthTrie, TrieRead, TrieWrite};
+
+    // Polls `future` to completion, counting the times it was pending.
+    fn poll_counting<F: Future>(future: F) -> (F::Output, usize) {
+        let waker = noop_waker();
+        let mut cx = Context::from_waker(&waker);
+        let mut future = pin!(future);
+        let mut pending = 0;
+        loop {
+            match future.as_mut().poll(&mut cx) {
+                Poll::Ready(output) => return (output, pending),
+                Poll::Pending => pending += 1,
+            }
+        }
+    }
+
+    #[test]
+    fn test_trie_stream_yields() {
+        let mut trie = EthTrie::new(Arc::new(MemoryDB::new(true)));
+        for i in 0..100u8 {
+            trie.insert(&[i], &[i; 40]).unwrap();
+        }
+        trie.root_hash().unwrap();
+
+        let mut stream = TrieStream::new(trie.iter()).yield_every(10);
+        let (keys, pending) = poll_counting(async {
+            let mut keys = vec![];
+            while let Some(item) = stream.next().await {
+                keys.push(item.unwrap().0);
+            }
+            keys
+        });
+        assert_eq!(keys, (0..100u8).map(|i| vec![i]).collect::<Vec<_>>());
+        assert_eq!(pending, 10);
+
+        // Stream combinators work on it, with any executor
+        let stream = TrieStream::new(trie.iter_nodes()).yield_every(7);
+        let count = executor::block_on(
+            stream
+                .filter(|node| {
+                    let hashed = node.as_ref().unwrap().hash.is_some();
+                    async move { hashed }
+                })
+                .count(),
+        );
+        assert!(count > 1);
+        let values: Vec<_> = executor::block_on(
+            TrieStream::new(trie.iter())
+                .map(|item| item.unwrap().1[0])
+                .take(3)
+                .collect(),
+        );
+        assert_eq!(values, vec![0, 1, 2]);
+    }
+}
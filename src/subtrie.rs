@@ -0,0 +1,234 @@
+use alloy_primitives::B256;
+use keccak_hash::{keccak, KECCAK_NULL_RLP};
+
+use crate::db::DB;
+use crate::debug::resolve;
+use crate::nibbles::Nibbles;
+use crate::node::Node;
+use crate::trie::{encode_node, EthTrie, NodeReader, TrieRead, TrieResult, TrieWrite};
+use crate::view::TrieView;
+
+impl<D> EthTrie<D>
+where
+    D: DB,
+{
+    /// Returns the root of the trie holding the entries whose keys start with the nibbles
+    /// of `prefix`, with the prefix removed from their keys. Includes uncommitted changes.
+    ///
+    /// When `prefix` ends on a node this is the hash of that node, even if it is inlined
+    /// in its parent. When no key starts with `prefix` it is the empty root.
+    pub fn subtrie_root(&self, prefix: &Nibbles) -> TrieResult<B256> {
+        subtrie_root(&self.reader(), &self.root, prefix)
+    }
+
+    /// Removes the entries whose keys start with the nibbles of `prefix` and commits the
+    /// trie, which takes the nodes of the subtree below `prefix` out of the database as
+    /// the trie handles stale nodes, leaving the rest of the trie as it is. Returns the
+    /// number of entries removed.
+    ///
+    /// Other uncommitted changes are committed along with the removals.
+    pub fn clear_subtrie_from_db(&mut self, prefix: &Nibbles) -> TrieResult<usize> {
+        let mut keys = vec![];
+        let mut iter = self.iter();
+        iter.seek(&prefix.seek_key())?;
+        for item in iter {
+            let (key, _) = item?;
+            if !Nibbles::from_raw(&key, false)
+                .as_slice()
+                .starts_with(prefix.as_slice())
+            {
+                break;
+            }
+            keys.push(key);
+        }
+
+        for key in keys.iter() {
+            self.remove(key)?;
+        }
+        self.root_hash()?;
+        Ok(keys.len())
+    }
+}
+
+impl<D> TrieView<D>
+where
+    D: DB,
+{
+    /// Returns the root of the trie holding the entries whose keys start with the nibbles
+    /// of `prefix`, with the prefix removed from their keys.
+    pub fn subtrie_root(&self, prefix: &Nibbles) -> TrieResult<B256> {
+        subtrie_root(&self.reader(), &self.root, prefix)
+    }
+}
+
+fn subtrie_root<D: DB>(
+    reader: &NodeReader<'_, D>,
+    root: &Node,
+    prefix: &Nibbles,
+) -> TrieResult<B256> {
+    let mut node = root.clone();
+    let mut path = Nibbles::from_hex(&[]);
+    loop {
+        let (resolved, _, _) = resolve(reader, &node, &path, path.is_empty())?;
+        let rest = prefix.offset(path.len());
+        if rest.is_empty() {
+            return Ok(node_root(&resolved));
+        }
+
+        let below = match &resolved {
+            Node::Empty | Node::Hash(_) => None,
+            Node::Leaf(leaf) => {
+                // The key of a leaf ends with the terminator, which the prefix never holds
+                let covered = leaf.key.len() > rest.len()
+                    && leaf.key.slice(0, rest.len()).get_data() == rest.get_data();
+                if covered {
+                    let key = leaf.key.offset(rest.len());
+                    return Ok(node_root(&Node::from_leaf(key, leaf.value.clone())));
+                }
+                None
+            }
+            Node::Extension(ext) => {
+                let ext = ext.read().clone();
+                let common = rest.common_prefix(&ext.prefix);
+                if common == ext.prefix.len() {
+                    path = path.join(&ext.prefix);
+                    Some(ext.node.clone())
+                } else if common == rest.len() {
+                    let prefix = ext.prefix.offset(rest.len());
+                    let node = Node::from_extension(prefix, ext.node.clone());
+                    return Ok(node_root(&node));
+                } else {
+                    None
+                }
+            }
+            Node::Branch(branch) => {
+                let nibble = rest.at(0);
+                path.push(nibble as u8);
+                Some(branch.read().children[nibble].clone())
+            }
+        };
+
+        match below {
+            Some(Node::Empty) | None => return Ok(KECCAK_NULL_RLP.as_fixed_bytes().into()),
+            Some(below) => node = below,
+        }
+    }
+}
+
+// The root of a trie whose root node is `node`.
+fn node_root(node: &Node) -> B256 {
+    match node {
+        Node::Empty => KECCAK_NULL_RLP.as_fixed_bytes().into(),
+        node => keccak(encode_node(node, &mut |_, _| {}))
+            .as_fixed_bytes()
+            .into(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use alloy_primitives::B256;
+    use keccak_hash::KECCAK_NULL_RLP;
+
+    use crate::db::{MemoryDB, DB};
+    use crate::nibbles::Nibbles;
+    use crate::trie::tests::random_trie;
+    use crate::trie::{EthTrie, TrieRead, TrieWrite};
+
+    #[test]
+    fn test_clear_subtrie_from_db() {
+        let (mut trie, kv) = random_trie(1000);
+        let old_root = trie.root_hash;
+        let before = trie.export().unwrap();
+
+        let prefix = Nibbles::from_hex(&[0, 1]);
+        let cleared = trie.clear_subtrie_from_db(&prefix).unwrap();
+        let expected: Vec<_> = kv.iter().filter(|(k, _)| k[0] != 1).collect();
+        assert_eq!(cleared, kv.len() - expected.len());
+        assert!(cleared > 0);
+        assert!(!trie.is_dirty());
+        assert_eq!(trie.len().unwrap(), expected.len());
+        for (key, value) in expected {
+            assert_eq!(trie.get(key).unwrap().as_ref(), Some(value));
+        }
+
+        // The nodes only the old trie held are gone, except its root node
+        let after = trie.export().unwrap();
+        for node in before.nodes.iter().filter(|n| !after.nodes.contains(n)) {
+            let hash = keccak_hash::keccak(node);
+            if hash.as_bytes() != old_root.as_slice() {
+                assert_eq!(trie.db.get(hash.as_bytes()).unwrap(), None);
+            }
+        }
+
+        // An odd prefix, and one nothing starts with
+        let cleared = trie
+            .clear_subtrie_from_db(&Nibbles::from_hex(&[0, 2, 0]))
+            .unwrap();
+        assert!(cleared > 0);
+        assert!(trie
+            .iter()
+            .map(Result::unwrap)
+            .all(|(k, _)| k[0] != 2 || k.len() == 1));
+        assert!(trie.get(&[2]).unwrap().is_some());
+        assert_eq!(
+            trie.clear_subtrie_from_db(&Nibbles::from_hex(&[0xf]))
+                .unwrap(),
+            0
+        );
+    }
+
+    #[test]
+    fn test_subtrie_root() {
+        let (mut trie, kv) = random_trie(300);
+        let root = trie.root_hash().unwrap();
+        assert_eq!(trie.subtrie_root(&Nibbles::from_hex(&[])).unwrap(), root);
+
+        // Keys are made of the bytes 0 to 3, so every prefix of two nibbles is a byte
+        for byte in 0..4u8 {
+            let mut expected = EthTrie::new(Arc::new(MemoryDB::new(true)));
+            for (key, value) in kv.iter().filter(|(key, _)| key[0] == byte) {
+                expected.insert(&key[1..], value).unwrap();
+            }
+            let prefix = Nibbles::from_raw(&[byte], false);
+            assert_eq!(
+                trie.subtrie_root(&prefix).unwrap(),
+                expected.root_hash().unwrap()
+            );
+        }
+
+        let empty: B256 = KECCAK_NULL_RLP.as_fixed_bytes().into();
+        let prefix = Nibbles::from_raw(&[0xff], false);
+        assert_eq!(trie.subtrie_root(&prefix).unwrap(), empty);
+    }
+
+    #[test]
+    fn test_subtrie_root_within_node() {
+        let mut trie = EthTrie::new(Arc::new(MemoryDB::new(true)));
+        trie.insert(b"\x12\x34\x56", b"leaf value").unwrap();
+
+        let mut expected = EthTrie::new(Arc::new(MemoryDB::new(true)));
+        expected.insert(b"\x56", b"leaf value").unwrap();
+        let prefix = Nibbles::from_hex(&[1, 2, 3, 4]);
+        assert_eq!(
+            trie.subtrie_root(&prefix).unwrap(),
+            expected.root_hash().unwrap()
+        );
+
+        // Within the prefix of an extension
+        trie.insert(b"\x12\x34\x57", b"other value").unwrap();
+        expected.insert(b"\x57", b"other value").unwrap();
+        assert_eq!(
+            trie.subtrie_root(&prefix).unwrap(),
+            expected.root_hash().unwrap()
+        );
+
+        let empty: B256 = KECCAK_NULL_RLP.as_fixed_bytes().into();
+        assert_eq!(
+            trie.subtrie_root(&Nibbles::from_hex(&[1, 3])).unwrap(),
+            empty
+        );
+    }
+}
@@ -0,0 +1,129 @@
+//! The locks of branch and extension nodes.
+//!
+//! They are parking_lot locks, which a panic while one is held doesn't poison. Built with
+//! `--cfg loom`, they are loom's instead, so that the tests in `loom_tests` can explore
+//! the ways readers and writers of shared nodes interleave:
+//!
+//! ```sh
+//! RUSTFLAGS="--cfg loom" cargo test --release --lib loom_tests
+//! ```
+
+#[cfg(not(loom))]
+pub use parking_lot::RwLock;
+
+#[cfg(loom)]
+pub use self::loom_lock::RwLock;
+
+#[cfg(loom)]
+mod loom_lock {
+    use loom::sync::{RwLockReadGuard, RwLockWriteGuard};
+
+    /// A loom lock with the methods of the parking_lot one.
+    #[derive(Debug)]
+    pub struct RwLock<T>(loom::sync::RwLock<T>);
+
+    impl<T> RwLock<T> {
+        pub fn new(value: T) -> Self {
+            Self(loom::sync::RwLock::new(value))
+        }
+
+        pub fn read(&self) -> RwLockReadGuard<'_, T> {
+            self.0.read().unwrap()
+        }
+
+        pub fn write(&self) -> RwLockWriteGuard<'_, T> {
+            self.0.write().unwrap()
+        }
+    }
+}
+
+#[cfg(all(test, loom))]
+mod loom_tests {
+    use std::sync::Arc;
+
+    use loom::thread;
+
+    use crate::db::MemoryDB;
+    use crate::trie::{EthTrie, TrieRead, TrieWrite};
+
+    fn model<F>(f: F)
+    where
+        F: Fn() + Send + Sync + 'static,
+    {
+        let mut builder = loom::model::Builder::new();
+        builder.preemption_bound = Some(2);
+        builder.check(f);
+    }
+
+    // A committed trie whose root branch holds two branches small enough to be inlined,
+    // so that reads go through the locks of nested nodes.
+    fn committed_trie() -> EthTrie<MemoryDB> {
+        let mut trie = EthTrie::new(Arc::new(MemoryDB::new(false)));
+        for key in [0x00, 0x01, 0x10, 0x11u8] {
+            trie.insert(&[key], &[key]).unwrap();
+        }
+        trie.root_hash().unwrap();
+        trie
+    }
+
+    #[test]
+    fn loom_snapshot_readers_with_writer() {
+        model(|| {
+            let mut trie = committed_trie();
+            let snapshot = trie.snapshot();
+            let readers: Vec<_> = [0x01, 0x10u8]
+                .into_iter()
+                .map(|key| {
+                    let snapshot = snapshot.clone();
+                    thread::spawn(move || {
+                        assert_eq!(snapshot.get(&[key]).unwrap(), Some(vec![key]));
+                    })
+                })
+                .collect();
+
+            // The writer changes the nodes the readers are going through
+            trie.insert(&[0x01], b"changed").unwrap();
+            assert!(trie.remove(&[0x10]).unwrap());
+            assert_eq!(trie.get(&[0x01]).unwrap(), Some(b"changed".to_vec()));
+
+            for reader in readers {
+                reader.join().unwrap();
+            }
+            assert_eq!(snapshot.get(&[0x10]).unwrap(), Some(vec![0x10]));
+        });
+    }
+
+    #[test]
+    fn loom_snapshot_proofs_while_committing() {
+        model(|| {
+            let mut trie = committed_trie();
+            let snapshot = trie.snapshot();
+            let prover = {
+                let snapshot = snapshot.clone();
+                thread::spawn(move || {
+                    let proof = snapshot.get_proof(&[0x11]).unwrap();
+                    let root = snapshot.root_hash();
+                    let value = snapshot.verify_proof(root, &[0x11], proof).unwrap();
+                    assert_eq!(value, Some(vec![0x11]));
+                })
+            };
+            let iterator = {
+                let snapshot = snapshot.clone();
+                thread::spawn(move || {
+                    let keys: Vec<_> = snapshot.iter().map(|entry| entry.unwrap().0).collect();
+                    assert_eq!(keys, vec![vec![0x00], vec![0x01], vec![0x10], vec![0x11]]);
+                })
+            };
+
+            trie.insert(&[0x11], b"changed").unwrap();
+            let root = trie.root_hash().unwrap();
+            let latest = trie.snapshot();
+            assert_eq!(latest.root_hash(), root);
+            assert_eq!(latest.get(&[0x11]).unwrap(), Some(b"changed".to_vec()));
+
+            prover.join().unwrap();
+            iterator.join().unwrap();
+            assert_eq!(snapshot.get(&[0x11]).unwrap(), Some(vec![0x11]));
+        });
+    }
+}
@@ -0,0 +1,134 @@
+//! Proptest strategies for keys, nibbles, nodes and whole tries, for property testing code
+//! built on the trie. Enabled by the `test-utils` feature.
+
+use std::collections::BTreeMap;
+use std::sync::Arc;
+
+use proptest::arbitrary::Arbitrary;
+use proptest::collection::{btree_map, vec};
+use proptest::prelude::*;
+
+use crate::db::MemoryDB;
+use crate::nibbles::Nibbles;
+use crate::node::Node;
+use crate::trie::{EthTrie, TrieWrite};
+
+/// Returns keys that either share prefixes with each other, drawn from a few bytes, or
+/// look like 32 byte hashes, as in a secure trie.
+pub fn key() -> impl Strategy<Value = Vec<u8>> {
+    prop_oneof![
+        vec(
+            prop::sample::select(vec![0x00u8, 0x01, 0x10, 0x11, 0xff]),
+            0..8
+        ),
+        any::<[u8; 32]>().prop_map(|hash| hash.to_vec()),
+    ]
+}
+
+/// Returns non-empty values, both small enough for their leaf to be inlined in its
+/// parent and large enough for it to be hashed.
+pub fn value() -> impl Strategy<Value = Vec<u8>> {
+    prop_oneof![vec(any::<u8>(), 1..32), vec(any::<u8>(), 32..128)]
+}
+
+/// Returns the nibbles of a path of up to 64 nibbles, with a terminator if `leaf`.
+pub fn nibbles(leaf: bool) -> impl Strategy<Value = Nibbles> {
+    vec(0..16u8, 0..=64).prop_map(move |mut hex| {
+        if leaf {
+            hex.push(16);
+        }
+        Nibbles::from_hex(&hex)
+    })
+}
+
+/// Returns valid nodes up to `depth` levels deep: branches with at least two entries,
+/// extensions leading to a branch or a hash, leaves with non-empty values, and hashes.
+pub fn node(depth: u32) -> impl Strategy<Value = Node> {
+    let hash = any::<[u8; 32]>().prop_map(|hash| Node::from_hash(hash.into()));
+    let leaf = (nibbles(true), value()).prop_map(|(key, value)| Node::from_leaf(key, value));
+    prop_oneof![leaf, hash].prop_recursive(depth, 64, 16, |inner| {
+        let child = prop_oneof![1 => inner.clone(), 2 => Just(Node::Empty)];
+        let branch = (prop::array::uniform16(child), prop::option::of(value()))
+            .prop_filter("branch with fewer than two entries", |(children, value)| {
+                let entries = children
+                    .iter()
+                    .filter(|child| !matches!(child, Node::Empty))
+                    .count();
+                entries + value.is_some() as usize >= 2
+            })
+            .prop_map(|(children, value)| Node::from_branch(children, value));
+        let extension = (
+            vec(0..16u8, 1..=32),
+            inner.prop_filter("extension of a leaf", |node| {
+                matches!(node, Node::Branch(_) | Node::Hash(_))
+            }),
+        )
+            .prop_map(|(prefix, node)| Node::from_extension(Nibbles::from_hex(&prefix), node));
+        prop_oneof![branch, extension]
+    })
+}
+
+/// Returns up to `max_entries` key-value pairs, drawn from `key` and `value`.
+pub fn entries(max_entries: usize) -> impl Strategy<Value = BTreeMap<Vec<u8>, Vec<u8>>> {
+    btree_map(key(), value(), 0..=max_entries)
+}
+
+/// Returns committed tries of up to `max_entries` entries in a `MemoryDB`, with the
+/// entries they hold.
+pub fn trie(
+    max_entries: usize,
+) -> impl Strategy<Value = (EthTrie<MemoryDB>, BTreeMap<Vec<u8>, Vec<u8>>)> {
+    entries(max_entries).prop_map(|entries| {
+        let mut trie = EthTrie::from_iter(Arc::new(MemoryDB::new(true)), &entries)
+            .expect("to insert into trie");
+        trie.root_hash().expect("to commit trie");
+        (trie, entries)
+    })
+}
+
+impl Arbitrary for Nibbles {
+    type Parameters = ();
+    type Strategy = BoxedStrategy<Self>;
+
+    fn arbitrary_with(_: ()) -> Self::Strategy {
+        any::<bool>().prop_flat_map(nibbles).boxed()
+    }
+}
+
+impl Arbitrary for Node {
+    type Parameters = ();
+    type Strategy = BoxedStrategy<Self>;
+
+    fn arbitrary_with(_: ()) -> Self::Strategy {
+        node(4).boxed()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use proptest::prelude::*;
+
+    use super::trie;
+    use crate::codec::NodeCodec;
+    use crate::node::Node;
+    use crate::trie::TrieRead;
+
+    proptest! {
+        #![proptest_config(ProptestConfig::with_cases(64))]
+
+        #[test]
+        fn test_node_strategy_roundtrips(node in any::<Node>()) {
+            // A hash on its own encodes as a reference, not as a node
+            prop_assume!(!matches!(node, Node::Hash(_)));
+            let encoded = NodeCodec::encode(&node);
+            let decoded = NodeCodec::decode_strict(&encoded).unwrap();
+            prop_assert_eq!(NodeCodec::encode(&decoded), encoded);
+        }
+
+        #[test]
+        fn test_trie_strategy((trie, entries) in trie(64)) {
+            let found: Vec<_> = trie.iter().collect::<Result<_, _>>().unwrap();
+            prop_assert_eq!(found, entries.into_iter().collect::<Vec<_>>());
+        }
+    }
+}
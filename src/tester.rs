@@ -0,0 +1,287 @@
+use std::collections::BTreeMap;
+use std::sync::Arc;
+
+use alloy_primitives::{hex, B256};
+use keccak_hash::keccak;
+
+use crate::db::{MemoryDB, DB};
+use crate::trie::{EthTrie, TrieRead, TrieResult, TrieWrite};
+
+/// Runs randomized workloads against a database through an `EthTrie` and checks the trie
+/// against a map of what it should hold, to soak-test a database or a setup with one call.
+///
+/// Each round makes a number of random inserts and removals, commits, and checks that the
+/// trie reads, iterates and proves every entry, and that its root is the one a trie
+/// holding the same entries gets. Every few rounds the trie is reopened from the database
+/// at its root and checked again. Workloads are drawn from a seeded generator, so a
+/// failing run can be repeated with the seed it reports. Enabled by the `test-utils`
+/// feature.
+///
+/// ```
+/// use std::sync::Arc;
+///
+/// use eth_trie::{MemoryDB, TrieTester};
+///
+/// let report = TrieTester::new(Arc::new(MemoryDB::new(true)))
+///     .seed(42)
+///     .rounds(5)
+///     .run()
+///     .unwrap();
+/// assert_eq!(report.rounds, 5);
+/// ```
+#[derive(Debug)]
+pub struct TrieTester<D>
+where
+    D: DB,
+{
+    db: Arc<D>,
+    seed: u64,
+    rounds: usize,
+    ops_per_round: usize,
+    key_space: u64,
+    max_value_len: usize,
+    remove_ratio: f64,
+    hashed_keys: bool,
+    reopen_every: usize,
+}
+
+/// What a `TrieTester` run did.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TesterReport {
+    pub rounds: usize,
+    pub inserts: usize,
+    pub removes: usize,
+    pub reopens: usize,
+    /// The number of entries the trie holds at the end.
+    pub entries: usize,
+    pub root: B256,
+}
+
+impl<D> TrieTester<D>
+where
+    D: DB,
+{
+    /// Creates a tester running 20 rounds of 200 operations over 1000 hashed keys.
+    pub fn new(db: Arc<D>) -> Self {
+        Self {
+            db,
+            seed: 0,
+            rounds: 20,
+            ops_per_round: 200,
+            key_space: 1000,
+            max_value_len: 64,
+            remove_ratio: 0.3,
+            hashed_keys: true,
+            reopen_every: 5,
+        }
+    }
+
+    pub fn seed(mut self, seed: u64) -> Self {
+        self.seed = seed;
+        self
+    }
+
+    pub fn rounds(mut self, rounds: usize) -> Self {
+        self.rounds = rounds;
+        self
+    }
+
+    pub fn ops_per_round(mut self, ops: usize) -> Self {
+        self.ops_per_round = ops;
+        self
+    }
+
+    /// Draws keys from `keys` distinct ones, so that smaller spaces update and remove
+    /// existing entries more often.
+    pub fn key_space(mut self, keys: u64) -> Self {
+        self.key_space = keys.max(1);
+        self
+    }
+
+    /// Draws values of 1 to `len` bytes.
+    pub fn max_value_len(mut self, len: usize) -> Self {
+        self.max_value_len = len.max(1);
+        self
+    }
+
+    /// Sets the share of operations that are removals rather than inserts.
+    pub fn remove_ratio(mut self, ratio: f64) -> Self {
+        self.remove_ratio = ratio.clamp(0.0, 1.0);
+        self
+    }
+
+    /// Uses hashes as keys, as a secure trie does, instead of short keys sharing
+    /// prefixes.
+    pub fn hashed_keys(mut self, hashed: bool) -> Self {
+        self.hashed_keys = hashed;
+        self
+    }
+
+    /// Reopens the trie from the database every `rounds` rounds, never if zero.
+    pub fn reopen_every(mut self, rounds: usize) -> Self {
+        self.reopen_every = rounds;
+        self
+    }
+
+    /// Runs the workload. Errors from the trie or the database are returned.
+    ///
+    /// # Panics
+    ///
+    /// Panics with the seed and round if the trie and the entries it should hold
+    /// disagree.
+    pub fn run(&self) -> TrieResult<TesterReport> {
+        let mut rng = SplitMix64(self.seed);
+        let mut trie = EthTrie::new(self.db.clone());
+        let mut model = BTreeMap::new();
+        let mut report = TesterReport {
+            rounds: 0,
+            inserts: 0,
+            removes: 0,
+            reopens: 0,
+            entries: 0,
+            root: trie.root_hash,
+        };
+
+        for round in 0..self.rounds {
+            for _ in 0..self.ops_per_round {
+                let key = self.key(rng.below(self.key_space));
+                if rng.unit() < self.remove_ratio {
+                    let removed = trie.remove(&key)?;
+                    self.check(round, "remove", &key, removed, model.remove(&key).is_some());
+                    report.removes += 1;
+                } else {
+                    let len = 1 + rng.below(self.max_value_len as u64) as usize;
+                    let value: Vec<u8> = (0..len).map(|_| rng.next_u64() as u8).collect();
+                    trie.insert(&key, &value)?;
+                    model.insert(key, value);
+                    report.inserts += 1;
+                }
+            }
+
+            let root = trie.root_hash()?;
+            self.check_trie(round, &trie, &model)?;
+            let expected =
+                EthTrie::from_iter(Arc::new(MemoryDB::new(true)), &model)?.root_hash()?;
+            self.check(round, "root", &[], root, expected);
+
+            if self.reopen_every > 0 && (round + 1) % self.reopen_every == 0 {
+                trie = EthTrie::from(self.db.clone(), root)?;
+                self.check_trie(round, &trie, &model)?;
+                report.reopens += 1;
+            }
+            report.rounds += 1;
+            report.root = root;
+        }
+        report.entries = model.len();
+        Ok(report)
+    }
+
+    fn key(&self, index: u64) -> Vec<u8> {
+        match self.hashed_keys {
+            true => keccak(index.to_be_bytes()).as_bytes().to_vec(),
+            false => {
+                let bytes = index.to_be_bytes();
+                let skip = bytes.iter().take_while(|b| **b == 0).count().min(7);
+                bytes[skip..].to_vec()
+            }
+        }
+    }
+
+    fn check<T>(&self, round: usize, what: &str, key: &[u8], found: T, expected: T)
+    where
+        T: PartialEq + std::fmt::Debug,
+    {
+        if found != expected {
+            panic!(
+                "trie tester failed at round {} with seed {}: {} of key {} is {:?}, \
+                 expected {:?}",
+                round,
+                self.seed,
+                what,
+                hex::encode_prefixed(key),
+                found,
+                expected
+            );
+        }
+    }
+
+    // Checks that the trie reads, iterates and proves exactly the entries of the model.
+    fn check_trie(
+        &self,
+        round: usize,
+        trie: &EthTrie<D>,
+        model: &BTreeMap<Vec<u8>, Vec<u8>>,
+    ) -> TrieResult<()> {
+        let entries = trie.iter().collect::<TrieResult<Vec<_>>>()?;
+        self.check(round, "entry count", &[], entries.len(), model.len());
+        for ((key, value), (k, v)) in entries.iter().zip(model) {
+            self.check(round, "iteration", key, (key, value), (k, v));
+        }
+        for (key, value) in model {
+            self.check(round, "get", key, trie.get(key)?.as_ref(), Some(value));
+            let proof = trie.get_proof(key)?;
+            let proven = trie.verify_proof(trie.root_hash, key, proof)?;
+            self.check(round, "proof", key, proven.as_ref(), Some(value));
+        }
+        Ok(())
+    }
+}
+
+struct SplitMix64(u64);
+
+impl SplitMix64 {
+    fn next_u64(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    fn below(&mut self, bound: u64) -> u64 {
+        self.next_u64() % bound
+    }
+
+    fn unit(&mut self) -> f64 {
+        (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::TrieTester;
+    use crate::db::MemoryDB;
+    use crate::faulty::{Faults, FaultyDB};
+
+    #[test]
+    fn test_trie_tester() {
+        let run = |seed| {
+            TrieTester::new(Arc::new(MemoryDB::new(true)))
+                .seed(seed)
+                .rounds(6)
+                .ops_per_round(100)
+                .key_space(150)
+                .hashed_keys(seed % 2 == 0)
+                .reopen_every(2)
+                .run()
+                .unwrap()
+        };
+        let report = run(1);
+        assert_eq!(report.rounds, 6);
+        assert_eq!(report.reopens, 3);
+        assert_eq!(report.inserts + report.removes, 600);
+        assert_eq!(run(1), report);
+        assert_ne!(run(2).root, report.root);
+    }
+
+    #[test]
+    fn test_trie_tester_db_errors() {
+        let db = FaultyDB::new(MemoryDB::new(true), 3).with_faults(Faults {
+            error_rate: 0.05,
+            ..Faults::default()
+        });
+        assert!(TrieTester::new(Arc::new(db)).rounds(3).run().is_err());
+    }
+}
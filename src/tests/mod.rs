@@ -1,3 +1,6 @@
+#[cfg(test)]
+mod vectors;
+
 #[cfg(test)]
 mod trie_tests {
     use hex::FromHex;
@@ -5,7 +8,7 @@ mod trie_tests {
     use std::sync::Arc;
 
     use crate::db::MemoryDB;
-    use crate::trie::{EthTrie, Trie};
+    use crate::trie::{EthTrie, TrieRead, TrieWrite};
 
     fn assert_root(data: Vec<(&[u8], &[u8])>, hash: &str) {
         let memdb = Arc::new(MemoryDB::new(true));
@@ -544,6 +547,76 @@ mod trie_tests {
         );
     }
 
+    // Transaction and receipt tries are keyed by the RLP encoding of the index, which is
+    // 0x80 for index 0 and grows a length prefix from index 128 on.
+    #[test]
+    fn test_index_keyed_trie() {
+        // Typed transactions start with their type byte rather than an RLP list
+        let items: Vec<Vec<u8>> = (0..300u32)
+            .map(|i| [&[0x02u8][..], &i.to_be_bytes(), &[0xaa; 40]].concat())
+            .collect();
+        let build = |order: &mut dyn Iterator<Item = usize>| {
+            let mut trie = EthTrie::new(Arc::new(MemoryDB::new(true)));
+            for i in order {
+                trie.insert(&alloy_rlp::encode(i), &items[i]).unwrap();
+            }
+            trie
+        };
+        let mut trie = build(&mut (0..items.len()));
+        let root = trie.root_hash().unwrap();
+        assert_eq!(
+            build(&mut (0..items.len()).rev()).root_hash().unwrap(),
+            root
+        );
+
+        for (key, i) in [
+            (&[0x80][..], 0),
+            (&[0x01], 1),
+            (&[0x7f], 127),
+            (&[0x81, 0x80], 128),
+            (&[0x81, 0xff], 255),
+            (&[0x82, 0x01, 0x00], 256),
+        ] {
+            assert_eq!(trie.get(key).unwrap().as_ref(), Some(&items[i]));
+        }
+
+        // Keying by the bare big-endian index, a common mistake, gives another root
+        let mut bare = EthTrie::new(Arc::new(MemoryDB::new(true)));
+        for (i, item) in items.iter().enumerate() {
+            bare.insert(&(i as u32).to_be_bytes(), item).unwrap();
+        }
+        assert_ne!(bare.root_hash().unwrap(), root);
+
+        // The transactions and receipts of mainnet block 46147, with the roots of its header
+        let block: serde_json::Value =
+            serde_json::from_str(include_str!("fixtures/mainnet_block_46147.json")).unwrap();
+        let list = |name: &str| -> Vec<Vec<u8>> {
+            let items = block[name].as_array().unwrap().iter();
+            items
+                .map(|item| alloy_primitives::hex::decode(item.as_str().unwrap()).unwrap())
+                .collect()
+        };
+        let index_keyed_root = |items: &[Vec<u8>]| {
+            let mut trie = EthTrie::new(Arc::new(MemoryDB::new(true)));
+            for (i, item) in items.iter().enumerate() {
+                trie.insert(&alloy_rlp::encode(i), item).unwrap();
+            }
+            format!("{}", trie.root_hash().unwrap())
+        };
+        let transactions = list("transactions");
+        for (transaction, hash) in transactions.iter().zip(list("transactionHashes")) {
+            assert_eq!(keccak_hash::keccak(transaction).as_bytes(), hash.as_slice());
+        }
+        assert_eq!(
+            index_keyed_root(&transactions),
+            block["transactionsRoot"].as_str().unwrap()
+        );
+        assert_eq!(
+            index_keyed_root(&list("receipts")),
+            block["receiptsRoot"].as_str().unwrap()
+        );
+    }
+
     // proof test ref:
     // - https://github.com/ethereum/go-ethereum/blob/master/trie/proof_test.go
     // - https://github.com/ethereum/py-trie/blob/master/tests/test_proof.py
@@ -635,9 +708,11 @@ mod trie_tests {
     fn test_proof_empty_trie() {
         let memdb = Arc::new(MemoryDB::new(true));
         let mut trie = EthTrie::new(Arc::clone(&memdb));
-        trie.root_hash().unwrap();
+        let root = trie.root_hash().unwrap();
         let proof = trie.get_proof(b"not-exist").unwrap();
         assert_eq!(proof.len(), 0);
+        let value = trie.verify_proof(root, b"not-exist", proof).unwrap();
+        assert_eq!(value, None);
     }
 
     #[test]
@@ -0,0 +1,151 @@
+// Runs the `TrieTests` suites of https://github.com/ethereum/tests. The vectors under
+// `fixtures` are taken from them: `trietest.json` and `trieanyorder.json` in full, and
+// `hex_encoded_securetrie_test.json` holding its `test1` and `test2` only. The other
+// secure trie suites run with `test_ethereum_tests_checkout`, which is ignored unless
+// asked for:
+//
+//     ETHEREUM_TESTS=/path/to/ethereum/tests cargo test test_ethereum_tests_checkout -- --ignored
+
+use std::path::Path;
+use std::sync::Arc;
+
+use keccak_hash::keccak;
+use serde_json::Value;
+
+use crate::db::MemoryDB;
+use crate::trie::{EthTrie, TrieWrite};
+
+/// A test of a suite: the operations to make on an empty trie, in order, and the root the
+/// trie must end up with. An operation with no value removes the key.
+#[derive(Debug, Clone)]
+struct TrieTest {
+    name: String,
+    ops: Vec<(Vec<u8>, Option<Vec<u8>>)>,
+    root: String,
+    /// Whether the operations can be made in any order, as in the `trieanyorder` suites.
+    any_order: bool,
+}
+
+/// Loads the tests of a suite. Operations are either a list of key-value pairs, made in
+/// order, or an object, made in any order. Keys and values starting with `0x` are hex,
+/// others are taken as they are.
+fn load(json: &str) -> Vec<TrieTest> {
+    let suite: Value = serde_json::from_str(json).expect("invalid suite");
+    suite
+        .as_object()
+        .expect("suite is not an object")
+        .iter()
+        .map(|(name, test)| {
+            let (ops, any_order) = match &test["in"] {
+                Value::Array(ops) => (
+                    ops.iter()
+                        .map(|op| (bytes(&op[0]).unwrap(), bytes(&op[1])))
+                        .collect(),
+                    false,
+                ),
+                Value::Object(ops) => (
+                    ops.iter()
+                        .map(|(key, value)| (parse(key), bytes(value)))
+                        .collect(),
+                    true,
+                ),
+                _ => panic!("{}: invalid input", name),
+            };
+            TrieTest {
+                name: name.clone(),
+                ops,
+                root: test["root"].as_str().expect("missing root").to_owned(),
+                any_order,
+            }
+        })
+        .collect()
+}
+
+fn bytes(value: &Value) -> Option<Vec<u8>> {
+    value.as_str().map(parse)
+}
+
+fn parse(s: &str) -> Vec<u8> {
+    match s.strip_prefix("0x") {
+        Some(hex) => hex::decode(hex).expect("invalid hex"),
+        None => s.as_bytes().to_vec(),
+    }
+}
+
+/// Runs a test, hashing the keys first if `secure`, as the secure trie suites do. Tests
+/// that can be run in any order are also run in reverse.
+fn run(test: &TrieTest, secure: bool) {
+    let mut orders = vec![test.ops.clone()];
+    if test.any_order {
+        orders.push(test.ops.iter().rev().cloned().collect());
+    }
+    for ops in orders {
+        let memdb = Arc::new(MemoryDB::new(true));
+        let mut trie = EthTrie::new(memdb.clone());
+        for (key, value) in &ops {
+            let key = match secure {
+                true => keccak(key).as_bytes().to_vec(),
+                false => key.clone(),
+            };
+            match value {
+                Some(value) => trie.insert(&key, value).unwrap(),
+                None => {
+                    trie.remove(&key).unwrap();
+                }
+            }
+        }
+        let root = trie.root_hash().unwrap();
+        assert_eq!(
+            format!("0x{}", hex::encode(root)),
+            test.root,
+            "{}",
+            test.name
+        );
+
+        let reopened = EthTrie::from(memdb, root).unwrap();
+        assert_eq!(reopened.root_hash, root, "{}", test.name);
+    }
+}
+
+fn run_suite(json: &str, secure: bool) {
+    let tests = load(json);
+    assert!(!tests.is_empty());
+    for test in &tests {
+        run(test, secure);
+    }
+}
+
+#[test]
+fn test_trietest() {
+    run_suite(include_str!("fixtures/trietest.json"), false);
+}
+
+#[test]
+fn test_trieanyorder() {
+    run_suite(include_str!("fixtures/trieanyorder.json"), false);
+}
+
+#[test]
+fn test_hex_encoded_securetrie() {
+    run_suite(
+        include_str!("fixtures/hex_encoded_securetrie_test.json"),
+        true,
+    );
+}
+
+#[test]
+#[ignore = "needs ETHEREUM_TESTS set to a checkout of ethereum/tests"]
+fn test_ethereum_tests_checkout() {
+    let dir = std::env::var_os("ETHEREUM_TESTS").expect("ETHEREUM_TESTS is not set");
+    let dir = Path::new(&dir).join("TrieTests");
+    for (file, secure) in [
+        ("trietest.json", false),
+        ("trieanyorder.json", false),
+        ("trietest_secureTrie.json", true),
+        ("trieanyorder_secureTrie.json", true),
+        ("hex_encoded_securetrie_test.json", true),
+    ] {
+        let json = std::fs::read_to_string(dir.join(file)).unwrap();
+        run_suite(&json, secure);
+    }
+}
@@ -1,37 +1,211 @@
-use std::sync::{Arc, RwLock};
+use std::collections::VecDeque;
+use std::ops::{Bound, Deref, RangeBounds};
+use std::sync::mpsc::Sender;
+use std::sync::Arc;
 use std::vec;
 
 use alloy_primitives::{Bytes, B256};
-use alloy_rlp::{Buf, BufMut, Encodable, Header, EMPTY_STRING_CODE};
+use alloy_rlp::{BufMut, Encodable, Header, EMPTY_LIST_CODE, EMPTY_STRING_CODE};
 use hashbrown::{HashMap, HashSet};
 use keccak_hash::{keccak, KECCAK_NULL_RLP};
 
+use crate::codec::NodeCodec;
 use crate::db::{MemoryDB, DB};
-use crate::errors::TrieError;
+use crate::diff::{diff_nodes, DiffIterator, KeyChange};
+use crate::epoch::{advance_epoch, node_epoch_key};
+use crate::errors::{NodeDecodeError, NodeDecodeErrorKind, TrieError};
+use crate::events::CommitEvent;
+use crate::key::TrieKey;
 use crate::nibbles::Nibbles;
 use crate::node::{empty_children, BranchNode, Node};
+use crate::observer::{CommitStats, Observers};
+use crate::resolver::{NodeResolver, Resolver};
+use crate::resume::IterCursor;
+use crate::root_manager::{PendingRoot, RootRegistry};
+use crate::sync::RwLock;
+use crate::view::TrieView;
 
 pub type TrieResult<T> = Result<T, TrieError>;
-const HASHED_LENGTH: usize = 32;
+pub(crate) const HASHED_LENGTH: usize = 32;
+// Leaf counts are stored next to the nodes, keyed by this prefix followed by the root hash.
+pub(crate) const LEAF_COUNT_KEY_PREFIX: &[u8] = b"eth-trie:leaf-count:";
+
+/// What `EthTrie::remove_checked` did.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RemoveOutcome {
+    /// The leaf holding the value was removed.
+    Removed,
+    /// The key had no value, the trie is unchanged.
+    NotFound,
+    /// The value was held by a branch node rather than a leaf, so the keys below it
+    /// remain.
+    BranchValueCleared,
+}
 
 pub struct RootWithTrieDiff {
     pub root: B256,
+    /// The root the trie was committed at before.
+    pub previous_root: B256,
+    /// The nodes the commit wrote, by hash.
     pub trie_diff: HashMap<B256, Vec<u8>>,
+    /// The hashes of the nodes the previous root held that the new root doesn't. Whether
+    /// they were removed from the database depends on how the trie handles stale nodes.
+    pub stale_nodes: HashSet<B256>,
+}
+
+// A commit whose nodes are encoded but not written yet.
+pub(crate) struct PreparedCommit {
+    pub(crate) root_hash: B256,
+    pub(crate) return_changed_nodes: bool,
+    key_changes: Option<Vec<KeyChange>>,
+    pub(crate) changed_nodes: HashMap<B256, Vec<u8>>,
+    pub(crate) nodes_written: usize,
+    // The written nodes, kept for the observers.
+    pub(crate) written: Vec<(B256, Vec<u8>)>,
+    pub(crate) keys: Vec<Vec<u8>>,
+    pub(crate) values: Vec<Vec<u8>>,
+}
+
+/// The root produced by `EthTrie::root_hash_with_key_changes`, with the keys the commit
+/// changed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RootWithKeyChanges {
+    pub root: B256,
+    /// Each changed key with its previous and its new value, in key order.
+    pub changes: Vec<KeyChange>,
 }
 
-pub trait Trie<D: DB> {
+/// The read operations of a trie.
+pub trait TrieRead<D: DB> {
+    /// The iterator over the entries of the trie returned by `iter`.
+    type Iter<'a>: Iterator<Item = TrieResult<(Vec<u8>, Vec<u8>)>>
+    where
+        Self: 'a;
+
     /// Returns the value for key stored in the trie.
     fn get(&self, key: &[u8]) -> TrieResult<Option<Vec<u8>>>;
 
+    /// Passes the value for key to `f` without copying it out of the trie, and returns
+    /// the result of `f`, or None if the key is not present.
+    fn get_with<R, F>(&self, key: &[u8], f: F) -> TrieResult<Option<R>>
+    where
+        F: FnOnce(&[u8]) -> R,
+        Self: Sized,
+    {
+        Ok(self.get(key)?.map(|value| f(&value)))
+    }
+
     /// Checks that the key is present in the trie
     fn contains(&self, key: &[u8]) -> TrieResult<bool>;
 
+    /// Returns the value stored under the trie bytes of `key`, as given by `TrieKey`.
+    fn get_keyed<K>(&self, key: &K) -> TrieResult<Option<Vec<u8>>>
+    where
+        K: TrieKey + ?Sized,
+        Self: Sized,
+    {
+        self.get(&key.trie_key())
+    }
+
+    /// Checks that the trie bytes of `key`, as given by `TrieKey`, are present.
+    fn contains_keyed<K>(&self, key: &K) -> TrieResult<bool>
+    where
+        K: TrieKey + ?Sized,
+        Self: Sized,
+    {
+        self.contains(&key.trie_key())
+    }
+
+    /// Prove constructs a merkle proof for key. The result contains all encoded nodes
+    /// on the path to the value at key. The value itself is also included in the last
+    /// node and can be retrieved by verifying the proof.
+    ///
+    /// If the trie does not contain a value for key, the returned proof contains all
+    /// nodes of the longest existing prefix of the key (at least the root node), ending
+    /// with the node that proves the absence of the key.
+    fn get_proof(&self, key: &[u8]) -> TrieResult<Vec<Vec<u8>>>;
+
+    /// Constructs a merkle proof for the trie bytes of `key`, as given by `TrieKey`.
+    fn get_proof_keyed<K>(&self, key: &K) -> TrieResult<Vec<Vec<u8>>>
+    where
+        K: TrieKey + ?Sized,
+        Self: Sized,
+    {
+        self.get_proof(&key.trie_key())
+    }
+
+    /// return value if key exists, None if key not exist, Error if proof is wrong
+    fn verify_proof(
+        &self,
+        root_hash: B256,
+        key: &[u8],
+        proof: Vec<Vec<u8>>,
+    ) -> TrieResult<Option<Vec<u8>>>;
+
+    /// Returns an iterator over all the entries of the trie, in key order.
+    fn iter(&self) -> Self::Iter<'_>;
+
+    /// Moves `iter` towards the first entry whose key is not below `key`, for `range` to
+    /// start from there. The default leaves it where it is, and `range` skips the
+    /// entries below its start one by one.
+    fn seek_iter<'a>(&'a self, iter: &mut Self::Iter<'a>, key: &[u8]) -> TrieResult<()> {
+        let _ = (iter, key);
+        Ok(())
+    }
+
+    /// Returns an iterator over the entries whose keys fall within `bounds`, in key order.
+    fn range<R>(&self, bounds: R) -> TrieRangeIterator<Self::Iter<'_>>
+    where
+        R: RangeBounds<Vec<u8>>,
+        Self: Sized,
+    {
+        let start = bounds.start_bound().cloned();
+        let end = bounds.end_bound().cloned();
+
+        let mut inner = self.iter();
+        let seek_error = match start {
+            Bound::Included(ref key) | Bound::Excluded(ref key) => {
+                self.seek_iter(&mut inner, key).err()
+            }
+            Bound::Unbounded => None,
+        };
+
+        TrieRangeIterator {
+            inner,
+            start,
+            end,
+            seek_error,
+            done: false,
+        }
+    }
+}
+
+/// The write operations of a trie.
+pub trait TrieWrite<D: DB>: TrieRead<D> {
     /// Inserts value into trie and modifies it if it exists
     fn insert(&mut self, key: &[u8], value: &[u8]) -> TrieResult<()>;
 
     /// Removes any existing value for key from the trie.
     fn remove(&mut self, key: &[u8]) -> TrieResult<bool>;
 
+    /// Inserts value under the trie bytes of `key`, as given by `TrieKey`.
+    fn insert_keyed<K>(&mut self, key: &K, value: &[u8]) -> TrieResult<()>
+    where
+        K: TrieKey + ?Sized,
+        Self: Sized,
+    {
+        self.insert(&key.trie_key(), value)
+    }
+
+    /// Removes any existing value under the trie bytes of `key`, as given by `TrieKey`.
+    fn remove_keyed<K>(&mut self, key: &K) -> TrieResult<bool>
+    where
+        K: TrieKey + ?Sized,
+        Self: Sized,
+    {
+        self.remove(&key.trie_key())
+    }
+
     /// Saves all the nodes in the db, clears the cache data, recalculates the root.
     /// Returns the root hash of the trie.
     fn root_hash(&mut self) -> TrieResult<B256>;
@@ -42,24 +216,17 @@ pub trait Trie<D: DB> {
 
     /// Clears the whole trie from the database.
     fn clear_trie_from_db(&mut self) -> TrieResult<()>;
+}
 
-    /// Prove constructs a merkle proof for key. The result contains all encoded nodes
-    /// on the path to the value at key. The value itself is also included in the last
-    /// node and can be retrieved by verifying the proof.
-    ///
-    /// If the trie does not contain a value for key, the returned proof contains all
-    /// nodes of the longest existing prefix of the key (at least the root node), ending
-    /// with the node that proves the absence of the key.
-    // TODO refactor encode_raw() so that it doesn't need a &mut self
-    fn get_proof(&mut self, key: &[u8]) -> TrieResult<Vec<Vec<u8>>>;
+/// A trie that can be both read and written, implemented for every type that implements
+/// `TrieRead` and `TrieWrite`.
+pub trait Trie<D: DB>: TrieRead<D> + TrieWrite<D> {}
 
-    /// return value if key exists, None if key not exist, Error if proof is wrong
-    fn verify_proof(
-        &self,
-        root_hash: B256,
-        key: &[u8],
-        proof: Vec<Vec<u8>>,
-    ) -> TrieResult<Option<Vec<u8>>>;
+impl<D, T> Trie<D> for T
+where
+    D: DB,
+    T: TrieRead<D> + TrieWrite<D>,
+{
 }
 
 #[derive(Debug)]
@@ -67,15 +234,72 @@ pub struct EthTrie<D>
 where
     D: DB,
 {
-    root: Node,
-    root_hash: B256,
+    pub(crate) root: Node,
+    pub(crate) root_hash: B256,
+
+    // A copy of the root node as of the last commit, kept apart from `root` since nodes
+    // are updated in place.
+    committed_root: Node,
+    committed_leaf_count: Option<usize>,
 
     pub db: Arc<D>,
 
     // The batch of pending new nodes to write
     cache: HashMap<B256, Vec<u8>>,
+    pub(crate) passing_keys: HashSet<B256>,
+    gen_keys: HashSet<B256>,
+
+    // The number of leaves, if known. Tries opened at a root without a persisted count
+    // fall back to counting by iteration.
+    pub(crate) leaf_count: Option<usize>,
+
+    // Snapshots of the uncommitted state, one per outstanding checkpoint.
+    checkpoints: Vec<Snapshot>,
+
+    pub(crate) config: TrieConfig,
+    // The number of inserts and removals since the last commit, for `auto_flush`.
+    pub(crate) writes_since_commit: usize,
+    // The root and the nodes made stale by each of the last commits, oldest first, whose
+    // removal is deferred by `retention_window`.
+    deferred_removals: VecDeque<(Option<B256>, Vec<B256>)>,
+    // The channels of `subscribe`, told about every commit.
+    pub(crate) subscribers: Vec<Sender<CommitEvent>>,
+    // The view of the last committed root, replaced as a whole on every commit. Readers
+    // only hold the lock to clone the `Arc`, and the writer to swap it. `committed_root`
+    // must never be changed in place, since published views share its nodes.
+    pub(crate) published: Published<D>,
+}
+
+pub(crate) type Published<D> = Arc<parking_lot::RwLock<Arc<TrieView<D>>>>;
+
+/// Identifies a point in the uncommitted history of a trie that can be reverted to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Checkpoint(usize);
+
+// Options set through `EthTrieBuilder`.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct TrieConfig {
+    pub(crate) auto_flush: Option<usize>,
+    pub(crate) retain_stale_nodes: bool,
+    pub(crate) retention_window: Option<usize>,
+    pub(crate) epoch_pruning: Option<u64>,
+    pub(crate) strict: bool,
+    // The roots registry of an attached `RootManager`.
+    pub(crate) roots: Option<Arc<parking_lot::RwLock<RootRegistry>>>,
+    pub(crate) observers: Observers,
+    pub(crate) resolver: Resolver,
+    // The batch size of pipelined commits.
+    pub(crate) commit_batch_size: Option<usize>,
+}
+
+#[derive(Debug)]
+struct Snapshot {
+    root: Node,
+    cache: HashMap<B256, Vec<u8>>,
     passing_keys: HashSet<B256>,
     gen_keys: HashSet<B256>,
+    leaf_count: Option<usize>,
+    writes_since_commit: usize,
 }
 
 enum EncodedNode {
@@ -124,9 +348,12 @@ pub struct TrieIterator<'a, D>
 where
     D: DB,
 {
-    trie: &'a EthTrie<D>,
+    reader: NodeReader<'a, D>,
+    root: Node,
     nibble: Nibbles,
     nodes: Vec<TraceNode>,
+    // The key the next entry is at or after, `None` once the iterator is exhausted.
+    position: Option<Vec<u8>>,
 }
 
 impl<'a, D> Iterator for TrieIterator<'a, D>
@@ -136,6 +363,39 @@ where
     type Item = Result<(Vec<u8>, Vec<u8>), TrieError>;
 
     fn next(&mut self) -> Option<Self::Item> {
+        let node = match self.step() {
+            Some(Ok(node)) => node,
+            Some(Err(e)) => return Some(Err(e)),
+            None => {
+                self.position = None;
+                return None;
+            }
+        };
+        let value = match node {
+            Node::Leaf(leaf) => leaf.value.clone(),
+            Node::Branch(branch) => branch.read().value.clone().unwrap(),
+            _ => unreachable!(),
+        };
+        let key = self.nibble.encode_raw().0;
+        // The smallest key after this one is the key followed by a zero byte
+        let mut position = key.clone();
+        position.push(0);
+        self.position = Some(position);
+        Some(Ok((key, value)))
+    }
+}
+
+impl<'a, D> TrieIterator<'a, D>
+where
+    D: DB,
+{
+    pub(crate) fn nibble(&self) -> &Nibbles {
+        &self.nibble
+    }
+
+    // Moves on to the next node holding a value, which is either a leaf or a branch with
+    // a value of its own. The key of the entry is left in `self.nibble`.
+    pub(crate) fn step(&mut self) -> Option<TrieResult<Node>> {
         loop {
             let mut now = self.nodes.last().cloned();
             if let Some(ref mut now) = now {
@@ -151,8 +411,7 @@ where
 
                             Node::Extension(ref ext) => {
                                 let cur_len = self.nibble.len();
-                                self.nibble
-                                    .truncate(cur_len - ext.read().unwrap().prefix.len());
+                                self.nibble.truncate(cur_len - ext.read().prefix.len());
                             }
 
                             Node::Branch(_) => {
@@ -164,19 +423,18 @@ where
                     }
 
                     (TraceStatus::Doing, Node::Extension(ref ext)) => {
-                        self.nibble.extend(&ext.read().unwrap().prefix);
-                        self.nodes.push((ext.read().unwrap().node.clone()).into());
+                        self.nibble.extend(&ext.read().prefix);
+                        self.nodes.push((ext.read().node.clone()).into());
                     }
 
                     (TraceStatus::Doing, Node::Leaf(ref leaf)) => {
                         self.nibble.extend(&leaf.key);
-                        return Some(Ok((self.nibble.encode_raw().0, leaf.value.clone())));
+                        return Some(Ok(now.node.clone()));
                     }
 
                     (TraceStatus::Doing, Node::Branch(ref branch)) => {
-                        let value_option = branch.read().unwrap().value.clone();
-                        if let Some(value) = value_option {
-                            return Some(Ok((self.nibble.encode_raw().0, value)));
+                        if branch.read().value.is_some() {
+                            return Some(Ok(now.node.clone()));
                         } else {
                             continue;
                         }
@@ -184,7 +442,7 @@ where
 
                     (TraceStatus::Doing, Node::Hash(ref hash_node)) => {
                         let node_hash = hash_node.hash;
-                        match self.trie.recover_from_db(node_hash) {
+                        match self.reader.recover(node_hash) {
                             Ok(Some(node)) => {
                                 self.nodes.pop();
                                 self.nodes.push(node.into());
@@ -193,7 +451,7 @@ where
                                 return Some(Err(TrieError::MissingTrieNode {
                                     node_hash,
                                     traversed: Some(self.nibble.clone()),
-                                    root_hash: Some(self.trie.root_hash),
+                                    root_hash: Some(self.reader.root_hash),
                                     err_key: None,
                                 }));
                             }
@@ -211,7 +469,7 @@ where
                             self.nibble.push(i);
                         }
                         self.nodes
-                            .push((branch.read().unwrap().children[i as usize].clone()).into());
+                            .push((branch.read().children[i as usize].clone()).into());
                     }
 
                     (_, Node::Empty) => {
@@ -224,121 +482,271 @@ where
             }
         }
     }
-}
 
-impl<D> EthTrie<D>
-where
-    D: DB,
-{
-    pub fn iter(&self) -> TrieIterator<D> {
-        let nodes = vec![(self.root.clone()).into()];
-        TrieIterator {
-            trie: self,
-            nibble: Nibbles::from_raw(&[], false),
-            nodes,
+    /// Returns the position of the iterator, to continue from later with `resume`.
+    pub fn cursor(&self) -> IterCursor {
+        IterCursor {
+            root: self.reader.root_hash,
+            next: self.position.as_deref().map(Bytes::copy_from_slice),
         }
     }
-    pub fn new(db: Arc<D>) -> Self {
-        Self {
-            root: Node::Empty,
-            root_hash: KECCAK_NULL_RLP.as_fixed_bytes().into(),
 
-            cache: HashMap::new(),
-            passing_keys: HashSet::new(),
-            gen_keys: HashSet::new(),
+    /// Continues from a position returned by `cursor`. Fails with
+    /// `TrieError::InvalidStateRoot` if the cursor was taken on another root. Uncommitted
+    /// changes are not part of the root, so the trie should be committed while a scan is
+    /// chunked this way.
+    pub fn resume(&mut self, cursor: &IterCursor) -> TrieResult<()> {
+        if cursor.root != self.reader.root_hash {
+            return Err(TrieError::InvalidStateRoot);
+        }
+        match &cursor.next {
+            Some(key) => self.seek(key),
+            None => {
+                self.nibble = Nibbles::from_raw(&[], false);
+                self.nodes.clear();
+                self.position = None;
+                Ok(())
+            }
+        }
+    }
 
-            db,
+    /// Repositions the iterator so that the next item returned is the first entry
+    /// whose key is greater than or equal to `key`.
+    pub fn seek(&mut self, key: &[u8]) -> TrieResult<()> {
+        self.nibble = Nibbles::from_raw(&[], false);
+        self.nodes.clear();
+        self.position = Some(key.to_vec());
+
+        let path = Nibbles::from_raw(key, false);
+        let mut path_index = 0;
+        let mut node = self.root.clone();
+        loop {
+            let partial = path.offset(path_index);
+            match node {
+                Node::Empty => return Ok(()),
+                Node::Leaf(ref leaf) => {
+                    let leaf_key = &leaf.key.get_data()[..leaf.key.len() - 1];
+                    if leaf_key >= partial.get_data() {
+                        self.nodes.push(node.clone().into());
+                    } else {
+                        // Mark the leaf as already visited so it is skipped.
+                        self.nibble.extend(&leaf.key);
+                        self.nodes.push(TraceNode {
+                            node: node.clone(),
+                            status: TraceStatus::End,
+                        });
+                    }
+                    return Ok(());
+                }
+                Node::Extension(ref ext) => {
+                    let (prefix, child) = {
+                        let borrow_ext = ext.read();
+                        (borrow_ext.prefix.clone(), borrow_ext.node.clone())
+                    };
+                    let match_len = partial.common_prefix(&prefix);
+
+                    if match_len == prefix.len() {
+                        self.nibble.extend(&prefix);
+                        self.nodes.push(TraceNode {
+                            node: node.clone(),
+                            status: TraceStatus::End,
+                        });
+                        path_index += match_len;
+                        node = child;
+                        continue;
+                    }
+
+                    if match_len == partial.len() || prefix.at(match_len) > partial.at(match_len) {
+                        self.nodes.push(node.clone().into());
+                    } else {
+                        // Every key below this extension sorts before the seek key.
+                        self.nibble.extend(&prefix);
+                        self.nodes.push(TraceNode {
+                            node: node.clone(),
+                            status: TraceStatus::End,
+                        });
+                    }
+                    return Ok(());
+                }
+                Node::Branch(ref branch) => {
+                    if partial.is_empty() {
+                        self.nodes.push(node.clone().into());
+                        return Ok(());
+                    }
+
+                    // The branch value and the children before `index` sort before the seek
+                    // key, so resume the branch as if `index` had just been visited.
+                    let index = partial.at(0);
+                    let child = branch.read().children[index].clone();
+                    self.nibble.push(index as u8);
+                    let status = if index < 15 {
+                        TraceStatus::Child(index as u8 + 1)
+                    } else {
+                        TraceStatus::End
+                    };
+                    self.nodes.push(TraceNode {
+                        node: node.clone(),
+                        status,
+                    });
+                    path_index += 1;
+                    node = child;
+                }
+                Node::Hash(ref hash_node) => {
+                    let node_hash = hash_node.hash;
+                    node = self.reader.recover(node_hash)?.ok_or_else(|| {
+                        TrieError::MissingTrieNode {
+                            node_hash,
+                            traversed: Some(self.nibble.clone()),
+                            root_hash: Some(self.reader.root_hash),
+                            err_key: Some(key.to_vec()),
+                        }
+                    })?;
+                }
+            }
         }
     }
+}
 
-    pub fn from(db: Arc<D>, root: B256) -> TrieResult<Self> {
-        match db
-            .get(root.as_slice())
-            .map_err(|e| TrieError::DB(e.to_string()))?
-        {
-            Some(data) => {
-                let mut trie = Self {
-                    root: Node::Empty,
-                    root_hash: root,
+pub struct TrieRangeIterator<I> {
+    inner: I,
+    start: Bound<Vec<u8>>,
+    end: Bound<Vec<u8>>,
+    // An error raised while seeking to the start bound, reported on the first call to next.
+    seek_error: Option<TrieError>,
+    done: bool,
+}
 
-                    cache: HashMap::new(),
-                    passing_keys: HashSet::new(),
-                    gen_keys: HashSet::new(),
+impl<I> Iterator for TrieRangeIterator<I>
+where
+    I: Iterator<Item = TrieResult<(Vec<u8>, Vec<u8>)>>,
+{
+    type Item = Result<(Vec<u8>, Vec<u8>), TrieError>;
 
-                    db,
-                };
+    fn next(&mut self) -> Option<Self::Item> {
+        if let Some(err) = self.seek_error.take() {
+            self.done = true;
+            return Some(Err(err));
+        }
+        if self.done {
+            return None;
+        }
 
-                trie.root = EthTrie::<D>::decode_node(&mut data.as_slice())?;
-                Ok(trie)
+        loop {
+            let (key, value) = match self.inner.next()? {
+                Ok(item) => item,
+                Err(e) => return Some(Err(e)),
+            };
+
+            let before_start = match self.start {
+                Bound::Included(ref start) => &key < start,
+                Bound::Excluded(ref start) => &key <= start,
+                Bound::Unbounded => false,
+            };
+            if before_start {
+                continue;
             }
-            None => Err(TrieError::InvalidStateRoot),
+
+            let past_end = match self.end {
+                Bound::Included(ref end) => &key > end,
+                Bound::Excluded(ref end) => &key >= end,
+                Bound::Unbounded => false,
+            };
+            if past_end {
+                self.done = true;
+                return None;
+            }
+
+            return Some(Ok((key, value)));
         }
     }
 }
 
-impl<D> Trie<D> for EthTrie<D>
+impl<D> EthTrie<D>
 where
     D: DB,
 {
-    /// Returns the value for key stored in the trie.
-    fn get(&self, key: &[u8]) -> TrieResult<Option<Vec<u8>>> {
-        let path = &Nibbles::from_raw(key, true);
-        let result = self.get_at(&self.root, path, 0);
-        if let Err(TrieError::MissingTrieNode {
-            node_hash,
-            traversed,
-            root_hash,
-            err_key: _,
-        }) = result
-        {
-            Err(TrieError::MissingTrieNode {
-                node_hash,
-                traversed,
-                root_hash,
-                err_key: Some(key.to_vec()),
-            })
-        } else {
-            result
+    /// Returns the number of entries in the trie, including uncommitted changes.
+    ///
+    /// The count is maintained through inserts and removals and persisted with each
+    /// committed root. A trie opened at a root without a persisted count is counted by
+    /// iterating over it instead.
+    pub fn len(&self) -> TrieResult<usize> {
+        match self.leaf_count {
+            Some(leaf_count) => Ok(leaf_count),
+            None => self
+                .iter()
+                .try_fold(0, |count, item| item.map(|_| count + 1)),
         }
     }
 
-    /// Checks that the key is present in the trie
-    fn contains(&self, key: &[u8]) -> TrieResult<bool> {
-        let path = &Nibbles::from_raw(key, true);
-        Ok(self.get_at(&self.root, path, 0)?.map_or(false, |_| true))
+    /// Returns true if the trie holds no entries.
+    pub fn is_empty(&self) -> TrieResult<bool> {
+        Ok(matches!(self.root, Node::Empty))
     }
 
-    /// Inserts value into trie and modifies it if it exists
-    fn insert(&mut self, key: &[u8], value: &[u8]) -> TrieResult<()> {
-        if value.is_empty() {
-            self.remove(key)?;
-            return Ok(());
-        }
-        let root = self.root.clone();
-        let path = &Nibbles::from_raw(key, true);
-        let result = self.insert_at(root, path, 0, value.to_vec());
+    /// Records the current uncommitted state so that later changes can be rolled back
+    /// with `revert_to`.
+    ///
+    /// Checkpoints nest: reverting to or discarding a checkpoint also drops every
+    /// checkpoint taken after it. Committing the trie drops all of them.
+    pub fn checkpoint(&mut self) -> Checkpoint {
+        self.checkpoints.push(Snapshot {
+            root: copy_node(&self.root),
+            cache: self.cache.clone(),
+            passing_keys: self.passing_keys.clone(),
+            gen_keys: self.gen_keys.clone(),
+            leaf_count: self.leaf_count,
+            writes_since_commit: self.writes_since_commit,
+        });
+        Checkpoint(self.checkpoints.len() - 1)
+    }
 
-        if let Err(TrieError::MissingTrieNode {
-            node_hash,
-            traversed,
-            root_hash,
-            err_key: _,
-        }) = result
-        {
-            Err(TrieError::MissingTrieNode {
-                node_hash,
-                traversed,
-                root_hash,
-                err_key: Some(key.to_vec()),
-            })
-        } else {
-            self.root = result?;
-            Ok(())
-        }
+    // Returns true if `checkpoint` hasn't been dropped by a commit, or by reverting to or
+    // discarding an earlier checkpoint.
+    pub(crate) fn has_checkpoint(&self, checkpoint: Checkpoint) -> bool {
+        checkpoint.0 < self.checkpoints.len()
     }
 
-    /// Removes any existing value for key from the trie.
-    fn remove(&mut self, key: &[u8]) -> TrieResult<bool> {
+    /// Rolls back every change made since `checkpoint` was taken.
+    pub fn revert_to(&mut self, checkpoint: Checkpoint) {
+        assert!(
+            checkpoint.0 < self.checkpoints.len(),
+            "Reverting to a checkpoint that no longer exists"
+        );
+        let snapshot = self.checkpoints.drain(checkpoint.0..).next().unwrap();
+        self.root = snapshot.root;
+        self.cache = snapshot.cache;
+        self.passing_keys = snapshot.passing_keys;
+        self.gen_keys = snapshot.gen_keys;
+        self.leaf_count = snapshot.leaf_count;
+        self.writes_since_commit = snapshot.writes_since_commit;
+    }
+
+    /// Drops `checkpoint`, keeping the changes made since it was taken.
+    pub fn discard(&mut self, checkpoint: Checkpoint) {
+        assert!(
+            checkpoint.0 < self.checkpoints.len(),
+            "Discarding a checkpoint that no longer exists"
+        );
+        self.checkpoints.truncate(checkpoint.0);
+    }
+
+    /// Drops every uncommitted change, along with the checkpoints, and goes back to the
+    /// last committed root. Unlike `clear_trie_from_db`, nothing is removed from the
+    /// database.
+    pub fn clear_pending(&mut self) {
+        self.root = copy_node(&self.committed_root);
+        self.leaf_count = self.committed_leaf_count;
+        self.cache.clear();
+        self.passing_keys.clear();
+        self.gen_keys.clear();
+        self.checkpoints.clear();
+        self.writes_since_commit = 0;
+    }
+
+    /// Removes any existing value for key from the trie, like `remove`, and tells whether
+    /// the value was held by a leaf or by a branch node.
+    pub fn remove_checked(&mut self, key: &[u8]) -> TrieResult<RemoveOutcome> {
         let path = &Nibbles::from_raw(key, true);
         let result = self.delete_at(&self.root.clone(), path, 0);
 
@@ -356,81 +764,377 @@ where
                 err_key: Some(key.to_vec()),
             })
         } else {
-            let (n, removed) = result?;
+            let (n, outcome) = result?;
             self.root = n;
-            Ok(removed)
+            self.count_write()?;
+            Ok(outcome)
         }
     }
 
-    /// Saves all the nodes in the db, clears the cache data, recalculates the root.
-    /// Returns the root hash of the trie.
-    fn root_hash(&mut self) -> TrieResult<B256> {
-        self.commit(false)
-            .map(|root_with_trie_diff| root_with_trie_diff.root)
+    /// Returns a copy of the root node, including uncommitted changes. Children stored
+    /// apart from the root are left as `Node::Hash`.
+    pub fn root_node(&self) -> Node {
+        copy_node(&self.root)
     }
 
-    /// Saves all the nodes in the db, clears the cache data, recalculates the root.
-    /// Returns the root hash of the trie and updated nodes from the cache.
-    fn root_hash_with_changed_nodes(&mut self) -> TrieResult<RootWithTrieDiff> {
-        self.commit(true)
+    /// Returns a view of the last committed root, which can be read from other threads
+    /// while this trie takes new changes.
+    ///
+    /// Readers never wait on the writer: the view shares the committed nodes, which
+    /// commits replace rather than change, and a commit publishes a new view for later
+    /// snapshots instead of changing those handed out. A view outliving the commit after
+    /// it can still fail with `TrieError::MissingTrieNode` once the nodes it needs are
+    /// removed, unless stale nodes are retained.
+    pub fn snapshot(&self) -> Arc<TrieView<D>> {
+        self.published.read().clone()
     }
 
-    /// Clears the whole trie from the database.
-    fn clear_trie_from_db(&mut self) -> TrieResult<()> {
-        let mut stack = vec![self.root_hash];
+    // Replaces the published view with one of the last committed root.
+    pub(crate) fn publish(&self) {
+        let view =
+            TrieView::from_parts(self.db.clone(), self.committed_root.clone(), self.root_hash);
+        *self.published.write() = Arc::new(view);
+    }
 
-        while let Some(node_key) = stack.pop() {
-            let encoded_node = self
-                .db
-                .get(node_key.as_slice())
-                .map_err(|e| TrieError::DB(e.to_string()))?
-                .expect("Failed to clear trie from db");
-
-            self.db
-                .remove(node_key.as_slice())
-                .map_err(|e| TrieError::DB(e.to_string()))?;
-
-            let decoded_node = decode_node(&mut encoded_node.as_slice())
-                .expect("Should should only be passing valid encoded nodes");
-
-            match decoded_node {
-                Node::Extension(extension) => {
-                    let extension = extension.read().expect("Reading an extension should work");
-                    if let Node::Hash(hash_node) = &extension.node {
-                        stack.push(hash_node.hash);
-                    }
-                }
-                Node::Branch(branch) => {
-                    let branch = branch.read().expect("Reading a branch should work");
-                    for child in branch.children.iter() {
-                        if let Node::Hash(hash_node) = child {
-                            stack.push(hash_node.hash);
-                        }
-                    }
-                }
-                _ => {}
-            }
+    /// Returns true if the trie has changes that are not committed yet.
+    ///
+    /// Changes that cancel each other out, such as inserting a key and removing it again,
+    /// don't make the trie dirty.
+    pub fn is_dirty(&self) -> bool {
+        self.pending_root().0 != self.root_hash
+    }
+
+    /// Returns the number of nodes the next commit would write to the database.
+    pub fn pending_node_count(&self) -> usize {
+        match self.pending_root() {
+            (root_hash, _) if root_hash == self.root_hash => 0,
+            (_, count) => count,
         }
+    }
 
-        self.root = Node::Empty;
-        self.root_hash = KECCAK_NULL_RLP.as_fixed_bytes().into();
-        self.cache.clear();
-        self.passing_keys.clear();
-        self.gen_keys.clear();
+    /// Commits the trie like `root_hash`, and returns the keys the commit changed along
+    /// with the new root.
+    pub fn root_hash_with_key_changes(&mut self) -> TrieResult<RootWithKeyChanges> {
+        let changes = self.pending_changes().collect::<TrieResult<Vec<_>>>()?;
+        let root = self.commit(false)?.root;
+        Ok(RootWithKeyChanges { root, changes })
+    }
 
-        TrieResult::Ok(())
+    /// Returns an iterator over the keys changed since the last commit, in key order,
+    /// with their committed and their current value.
+    pub fn pending_changes(&self) -> DiffIterator<D> {
+        diff_nodes(
+            &self.db,
+            self.root_hash,
+            copy_node(&self.committed_root),
+            self.root.clone(),
+        )
     }
 
-    /// Prove constructs a merkle proof for key. The result contains all encoded nodes
+    /// Panics unless `other` holds the same entries as this trie, including uncommitted
+    /// changes, naming the first key whose value differs. Both tries must read their
+    /// nodes from the same database.
+    #[track_caller]
+    pub fn assert_equal(&self, other: &EthTrie<D>) {
+        if self == other {
+            return;
+        }
+        let mut changes = diff_nodes(
+            &self.db,
+            self.root_hash,
+            self.root.clone(),
+            other.root.clone(),
+        );
+        match changes.next() {
+            Some(Ok((key, left, right))) => panic!(
+                "tries differ at key 0x{}: left {:?}, right {:?}",
+                alloy_primitives::hex::encode(&key),
+                left.map(alloy_primitives::hex::encode),
+                right.map(alloy_primitives::hex::encode)
+            ),
+            Some(Err(e)) => panic!("tries differ, comparing them failed: {}", e),
+            None => unreachable!("tries holding the same entries have the same root"),
+        }
+    }
+
+    // The root hash of the trie including uncommitted changes, without writing anything.
+    fn current_root(&self) -> B256 {
+        if self.writes_since_commit == 0 {
+            self.root_hash
+        } else {
+            self.pending_root().0
+        }
+    }
+
+    // Works out the root hash the next commit would produce, along with the number of
+    // nodes it would write, without writing anything.
+    fn pending_root(&self) -> (B256, usize) {
+        let mut count = 0;
+        let root_hash = match encode_child(&self.root, &mut |_, _| count += 1) {
+            EncodedNode::Hash(hash) => hash,
+            EncodedNode::Inline(encoded) => {
+                count += 1;
+                keccak(&encoded).as_fixed_bytes().into()
+            }
+        };
+        (root_hash, count)
+    }
+
+    /// Creates an independent trie at the last committed root, sharing the database.
+    ///
+    /// The fork starts without the uncommitted changes of this trie, and neither trie
+    /// sees the changes made to the other afterwards. Since both may commit to the same
+    /// database, forks should only be used with a database that retains stale nodes,
+    /// otherwise a commit on one side can remove nodes the other still refers to.
+    pub fn fork(&self) -> Self {
+        Self {
+            root: copy_node(&self.committed_root),
+            root_hash: self.root_hash,
+
+            committed_root: copy_node(&self.committed_root),
+            committed_leaf_count: self.committed_leaf_count,
+
+            cache: HashMap::new(),
+            passing_keys: HashSet::new(),
+            gen_keys: HashSet::new(),
+
+            leaf_count: self.committed_leaf_count,
+            checkpoints: Vec::new(),
+
+            config: self.config.clone(),
+            writes_since_commit: 0,
+            deferred_removals: VecDeque::new(),
+            subscribers: Vec::new(),
+            published: publish(&self.db, &self.committed_root, self.root_hash),
+
+            db: self.db.clone(),
+        }
+    }
+
+    /// Inserts every key-value pair from `items`. As with `insert`, an empty value removes
+    /// the key.
+    pub fn insert_batch<I, K, V>(&mut self, items: I) -> TrieResult<()>
+    where
+        I: IntoIterator<Item = (K, V)>,
+        K: AsRef<[u8]>,
+        V: AsRef<[u8]>,
+    {
+        for (key, value) in items {
+            self.insert(key.as_ref(), value.as_ref())?;
+        }
+        Ok(())
+    }
+
+    pub fn new(db: Arc<D>) -> Self {
+        Self {
+            root: Node::Empty,
+            root_hash: KECCAK_NULL_RLP.as_fixed_bytes().into(),
+
+            committed_root: Node::Empty,
+            committed_leaf_count: Some(0),
+
+            cache: HashMap::new(),
+            passing_keys: HashSet::new(),
+            gen_keys: HashSet::new(),
+
+            leaf_count: Some(0),
+            checkpoints: Vec::new(),
+
+            config: TrieConfig::default(),
+            writes_since_commit: 0,
+            deferred_removals: VecDeque::new(),
+            subscribers: Vec::new(),
+            published: publish(&db, &Node::Empty, KECCAK_NULL_RLP.as_fixed_bytes().into()),
+
+            db,
+        }
+    }
+
+    pub fn from(db: Arc<D>, root: B256) -> TrieResult<Self> {
+        match db.get(root.as_slice()).map_err(TrieError::db)? {
+            Some(data) => {
+                let leaf_count = match db.get(&leaf_count_key(&root)).map_err(TrieError::db)? {
+                    Some(encoded) => Some(decode_leaf_count(&encoded)?),
+                    None => None,
+                };
+
+                let mut trie = Self {
+                    root: Node::Empty,
+                    root_hash: root,
+
+                    committed_root: Node::Empty,
+                    committed_leaf_count: leaf_count,
+
+                    cache: HashMap::new(),
+                    passing_keys: HashSet::new(),
+                    gen_keys: HashSet::new(),
+
+                    leaf_count,
+                    checkpoints: Vec::new(),
+
+                    config: TrieConfig::default(),
+                    writes_since_commit: 0,
+                    deferred_removals: VecDeque::new(),
+                    subscribers: Vec::new(),
+                    published: publish(&db, &Node::Empty, root),
+
+                    db,
+                };
+
+                trie.root = EthTrie::<D>::decode_node(&mut data.as_slice())?;
+                trie.committed_root = copy_node(&trie.root);
+                trie.publish();
+                Ok(trie)
+            }
+            None => Err(TrieError::InvalidStateRoot),
+        }
+    }
+
+    /// Creates a new trie holding the key-value pairs from `items`. Nothing is written to
+    /// the database until the trie is committed.
+    pub fn from_iter<I, K, V>(db: Arc<D>, items: I) -> TrieResult<Self>
+    where
+        I: IntoIterator<Item = (K, V)>,
+        K: AsRef<[u8]>,
+        V: AsRef<[u8]>,
+    {
+        let mut trie = Self::new(db);
+        trie.insert_batch(items)?;
+        Ok(trie)
+    }
+}
+
+impl<D, K, V> Extend<(K, V)> for EthTrie<D>
+where
+    D: DB,
+    K: AsRef<[u8]>,
+    V: AsRef<[u8]>,
+{
+    /// Inserts every key-value pair through `insert_batch`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if a node can't be loaded from the database. Use `insert_batch` to handle
+    /// the error instead.
+    fn extend<I: IntoIterator<Item = (K, V)>>(&mut self, iter: I) {
+        self.insert_batch(iter).expect("to insert into trie");
+    }
+}
+
+impl<D> TrieRead<D> for EthTrie<D>
+where
+    D: DB,
+{
+    type Iter<'a>
+        = TrieIterator<'a, D>
+    where
+        Self: 'a;
+
+    /// Returns the value for key stored in the trie.
+    fn get(&self, key: &[u8]) -> TrieResult<Option<Vec<u8>>> {
+        self.reader().get(&self.root, key, |value| value.to_vec())
+    }
+
+    fn get_with<R, F>(&self, key: &[u8], f: F) -> TrieResult<Option<R>>
+    where
+        F: FnOnce(&[u8]) -> R,
+    {
+        self.reader().get(&self.root, key, f)
+    }
+
+    /// Checks that the key is present in the trie
+    fn contains(&self, key: &[u8]) -> TrieResult<bool> {
+        self.reader().contains(&self.root, key)
+    }
+
+    /// Prove constructs a merkle proof for key. The result contains all encoded nodes
     /// on the path to the value at key. The value itself is also included in the last
     /// node and can be retrieved by verifying the proof.
     ///
     /// If the trie does not contain a value for key, the returned proof contains all
     /// nodes of the longest existing prefix of the key (at least the root node), ending
     /// with the node that proves the absence of the key.
-    fn get_proof(&mut self, key: &[u8]) -> TrieResult<Vec<Vec<u8>>> {
-        let key_path = &Nibbles::from_raw(key, true);
-        let result = self.get_path_at(&self.root, key_path, 0);
+    fn get_proof(&self, key: &[u8]) -> TrieResult<Vec<Vec<u8>>> {
+        self.reader().get_proof(&self.root, key)
+    }
+
+    /// return value if key exists, None if key not exist, Error if proof is wrong
+    fn verify_proof(
+        &self,
+        root_hash: B256,
+        key: &[u8],
+        proof: Vec<Vec<u8>>,
+    ) -> TrieResult<Option<Vec<u8>>> {
+        verify_proof(root_hash, key, proof)
+    }
+
+    fn iter(&self) -> Self::Iter<'_> {
+        self.reader().iter(self.root.clone())
+    }
+
+    fn seek_iter<'a>(&'a self, iter: &mut Self::Iter<'a>, key: &[u8]) -> TrieResult<()> {
+        iter.seek(key)
+    }
+}
+
+/// Tries are equal when they hold the same entries, including uncommitted changes. Tries
+/// without uncommitted writes are compared by their committed roots, others by the roots
+/// their next commits would produce.
+impl<D> PartialEq for EthTrie<D>
+where
+    D: DB,
+{
+    fn eq(&self, other: &Self) -> bool {
+        self.current_root() == other.current_root()
+    }
+}
+
+impl<D> Eq for EthTrie<D> where D: DB {}
+
+impl<'a, D> IntoIterator for &'a EthTrie<D>
+where
+    D: DB,
+{
+    type Item = TrieResult<(Vec<u8>, Vec<u8>)>;
+    type IntoIter = TrieIterator<'a, D>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+impl<D> IntoIterator for EthTrie<D>
+where
+    D: DB + 'static,
+{
+    type Item = TrieResult<(Vec<u8>, Vec<u8>)>;
+    type IntoIter = TrieIterator<'static, D>;
+
+    /// Consumes the trie, including its uncommitted changes, into an iterator over its
+    /// entries in key order. The in-memory nodes are handed over rather than copied.
+    fn into_iter(self) -> Self::IntoIter {
+        NodeReader::owned(self.db, self.root_hash)
+            .with_resolver(self.config.resolver.0)
+            .iter(self.root)
+    }
+}
+
+impl<D> TrieWrite<D> for EthTrie<D>
+where
+    D: DB,
+{
+    /// Inserts value into trie and modifies it if it exists
+    fn insert(&mut self, key: &[u8], value: &[u8]) -> TrieResult<()> {
+        if value.is_empty() {
+            if self.config.strict {
+                return Err(TrieError::InvalidData);
+            }
+            self.remove(key)?;
+            return Ok(());
+        }
+        let root = self.root.clone();
+        let path = &Nibbles::from_raw(key, true);
+        let result = self.insert_at(root, path, 0, value.to_vec());
 
         if let Err(TrieError::MissingTrieNode {
             node_hash,
@@ -446,96 +1150,92 @@ where
                 err_key: Some(key.to_vec()),
             })
         } else {
-            let mut path = result?;
-            match self.root {
-                Node::Empty => {}
-                _ => path.push(self.root.clone()),
-            }
-            Ok(path
-                .into_iter()
-                .rev()
-                .map(|n| self.encode_raw(&n))
-                .collect())
+            self.root = result?;
+            self.count_write()
         }
     }
 
-    /// return value if key exists, None if key not exist, Error if proof is wrong
-    fn verify_proof(
-        &self,
-        root_hash: B256,
-        key: &[u8],
-        proof: Vec<Vec<u8>>,
-    ) -> TrieResult<Option<Vec<u8>>> {
-        let proof_db = Arc::new(MemoryDB::new(true));
-        for node_encoded in proof.into_iter() {
-            let hash: B256 = keccak(&node_encoded).as_fixed_bytes().into();
+    /// Removes any existing value for key from the trie.
+    fn remove(&mut self, key: &[u8]) -> TrieResult<bool> {
+        self.remove_checked(key)
+            .map(|outcome| outcome != RemoveOutcome::NotFound)
+    }
 
-            if root_hash.eq(&hash) || node_encoded.len() >= HASHED_LENGTH {
-                proof_db.insert(hash.as_slice(), node_encoded).unwrap();
-            }
-        }
-        let trie = EthTrie::from(proof_db, root_hash).or(Err(TrieError::InvalidProof))?;
-        trie.get(key).or(Err(TrieError::InvalidProof))
+    /// Saves all the nodes in the db, clears the cache data, recalculates the root.
+    /// Returns the root hash of the trie.
+    fn root_hash(&mut self) -> TrieResult<B256> {
+        self.commit(false)
+            .map(|root_with_trie_diff| root_with_trie_diff.root)
     }
-}
 
-impl<D> EthTrie<D>
-where
-    D: DB,
-{
-    fn get_at(
-        &self,
-        source_node: &Node,
-        path: &Nibbles,
-        path_index: usize,
-    ) -> TrieResult<Option<Vec<u8>>> {
-        let partial = &path.offset(path_index);
-        match source_node {
-            Node::Empty => Ok(None),
-            Node::Leaf(leaf) => {
-                if &leaf.key == partial {
-                    Ok(Some(leaf.value.clone()))
-                } else {
-                    Ok(None)
-                }
-            }
-            Node::Branch(branch) => {
-                let borrow_branch = branch.read().unwrap();
+    /// Saves all the nodes in the db, clears the cache data, recalculates the root.
+    /// Returns the root hash of the trie and updated nodes from the cache.
+    fn root_hash_with_changed_nodes(&mut self) -> TrieResult<RootWithTrieDiff> {
+        self.commit(true)
+    }
 
-                if partial.is_empty() || partial.at(0) == 16 {
-                    Ok(borrow_branch.value.clone())
-                } else {
-                    let index = partial.at(0);
-                    self.get_at(&borrow_branch.children[index], path, path_index + 1)
-                }
-            }
-            Node::Extension(extension) => {
-                let extension = extension.read().unwrap();
+    /// Clears the whole trie from the database.
+    ///
+    /// Each node is visited once, so subtrees referred to from several places are only
+    /// removed once, and malformed data whose references form a cycle can't loop forever.
+    fn clear_trie_from_db(&mut self) -> TrieResult<()> {
+        let empty_root: B256 = KECCAK_NULL_RLP.as_fixed_bytes().into();
+        let mut stack = vec![self.root_hash];
+        let mut visited = HashSet::new();
 
-                let prefix = &extension.prefix;
-                let match_len = partial.common_prefix(prefix);
-                if match_len == prefix.len() {
-                    self.get_at(&extension.node, path, path_index + match_len)
-                } else {
-                    Ok(None)
+        while let Some(node_key) = stack.pop() {
+            if !visited.insert(node_key) {
+                continue;
+            }
+            let encoded_node = match self.db.get(node_key.as_slice()).map_err(TrieError::db)? {
+                Some(encoded_node) => encoded_node,
+                // An empty trie that was never committed has nothing stored
+                None if node_key == empty_root => continue,
+                None => {
+                    return Err(TrieError::MissingTrieNode {
+                        node_hash: node_key,
+                        traversed: None,
+                        root_hash: Some(self.root_hash),
+                        err_key: None,
+                    })
                 }
+            };
+
+            self.db.remove(node_key.as_slice()).map_err(TrieError::db)?;
+
+            let decoded_node = decode_node(&mut encoded_node.as_slice())?;
+            for (_, hash) in decoded_node.child_hashes() {
+                stack.push(hash);
             }
-            Node::Hash(hash_node) => {
-                let node_hash = hash_node.hash;
-                let node =
-                    self.recover_from_db(node_hash)?
-                        .ok_or_else(|| TrieError::MissingTrieNode {
-                            node_hash,
-                            traversed: Some(path.slice(0, path_index)),
-                            root_hash: Some(self.root_hash),
-                            err_key: None,
-                        })?;
-                self.get_at(&node, path, path_index)
+            if let Some(Node::Hash(hash_node)) = decoded_node.extension_child() {
+                stack.push(hash_node.hash);
             }
         }
+
+        self.db
+            .remove(&leaf_count_key(&self.root_hash))
+            .map_err(TrieError::db)?;
+
+        self.root = Node::Empty;
+        self.root_hash = KECCAK_NULL_RLP.as_fixed_bytes().into();
+        self.committed_root = Node::Empty;
+        self.committed_leaf_count = Some(0);
+        self.cache.clear();
+        self.passing_keys.clear();
+        self.gen_keys.clear();
+        self.leaf_count = Some(0);
+        self.checkpoints.clear();
+        self.publish();
+
+        TrieResult::Ok(())
     }
+}
 
-    fn insert_at(
+impl<D> EthTrie<D>
+where
+    D: DB,
+{
+    pub(crate) fn insert_at(
         &mut self,
         n: Node,
         path: &Nibbles,
@@ -544,7 +1244,10 @@ where
     ) -> TrieResult<Node> {
         let partial = path.offset(path_index);
         match n {
-            Node::Empty => Ok(Node::from_leaf(partial, value)),
+            Node::Empty => {
+                self.adjust_leaf_count(true);
+                Ok(Node::from_leaf(partial, value))
+            }
             Node::Leaf(leaf) => {
                 let old_partial = &leaf.key;
                 let match_index = partial.common_prefix(old_partial);
@@ -552,6 +1255,8 @@ where
                     return Ok(Node::from_leaf(leaf.key.clone(), value));
                 }
 
+                self.adjust_leaf_count(true);
+
                 let mut branch = BranchNode {
                     children: empty_children(),
                     value: None,
@@ -574,23 +1279,28 @@ where
                 ))
             }
             Node::Branch(branch) => {
-                let mut borrow_branch = branch.write().unwrap();
-
                 if partial.at(0) == 0x10 {
+                    let mut borrow_branch = branch.write();
+                    if borrow_branch.value.is_none() {
+                        self.adjust_leaf_count(true);
+                    }
                     borrow_branch.value = Some(value);
-                    return Ok(Node::Branch(branch.clone()));
+                    drop(borrow_branch);
+                    return Ok(Node::Branch(branch));
                 }
 
-                let child = borrow_branch.children[partial.at(0)].clone();
+                // The child is updated without holding the lock of the branch
+                let child = branch.read().children[partial.at(0)].clone();
                 let new_child = self.insert_at(child, path, path_index + 1, value)?;
-                borrow_branch.children[partial.at(0)] = new_child;
-                Ok(Node::Branch(branch.clone()))
+                branch.write().children[partial.at(0)] = new_child;
+                Ok(Node::Branch(branch))
             }
             Node::Extension(ext) => {
-                let mut borrow_ext = ext.write().unwrap();
-
-                let prefix = &borrow_ext.prefix;
-                let sub_node = borrow_ext.node.clone();
+                let (prefix, sub_node) = {
+                    let borrow_ext = ext.read();
+                    (borrow_ext.prefix.clone(), borrow_ext.node.clone())
+                };
+                let prefix = &prefix;
                 let match_index = partial.common_prefix(prefix);
 
                 if match_index == 0 {
@@ -619,108 +1329,122 @@ where
 
                 let new_ext = Node::from_extension(prefix.offset(match_index), sub_node);
                 let new_node = self.insert_at(new_ext, path, path_index + match_index, value)?;
+                let mut borrow_ext = ext.write();
                 borrow_ext.prefix = prefix.slice(0, match_index);
                 borrow_ext.node = new_node;
-                Ok(Node::Extension(ext.clone()))
+                drop(borrow_ext);
+                Ok(Node::Extension(ext))
             }
             Node::Hash(hash_node) => {
                 let node_hash = hash_node.hash;
                 self.passing_keys.insert(node_hash);
-                let node =
-                    self.recover_from_db(node_hash)?
-                        .ok_or_else(|| TrieError::MissingTrieNode {
-                            node_hash,
-                            traversed: Some(path.slice(0, path_index)),
-                            root_hash: Some(self.root_hash),
-                            err_key: None,
-                        })?;
+                let node = self
+                    .get_node(node_hash)?
+                    .ok_or_else(|| TrieError::MissingTrieNode {
+                        node_hash,
+                        traversed: Some(path.slice(0, path_index)),
+                        root_hash: Some(self.root_hash),
+                        err_key: None,
+                    })?;
                 self.insert_at(node, path, path_index, value)
             }
         }
     }
 
-    fn delete_at(
+    pub(crate) fn delete_at(
         &mut self,
         old_node: &Node,
         path: &Nibbles,
         path_index: usize,
-    ) -> TrieResult<(Node, bool)> {
+    ) -> TrieResult<(Node, RemoveOutcome)> {
         let partial = &path.offset(path_index);
-        let (new_node, deleted) = match old_node {
-            Node::Empty => Ok((Node::Empty, false)),
+        let (new_node, outcome) = match old_node {
+            Node::Empty => TrieResult::Ok((Node::Empty, RemoveOutcome::NotFound)),
             Node::Leaf(leaf) => {
                 if &leaf.key == partial {
-                    return Ok((Node::Empty, true));
+                    self.adjust_leaf_count(false);
+                    return Ok((Node::Empty, RemoveOutcome::Removed));
                 }
-                Ok((Node::Leaf(leaf.clone()), false))
+                Ok((Node::Leaf(leaf.clone()), RemoveOutcome::NotFound))
             }
             Node::Branch(branch) => {
-                let mut borrow_branch = branch.write().unwrap();
-
                 if partial.at(0) == 0x10 {
-                    borrow_branch.value = None;
-                    return Ok((Node::Branch(branch.clone()), true));
-                }
+                    // Fall through to `degenerate` below, the branch may be left with a
+                    // single child.
+                    let outcome = if branch.write().value.take().is_some() {
+                        self.adjust_leaf_count(false);
+                        RemoveOutcome::BranchValueCleared
+                    } else {
+                        RemoveOutcome::NotFound
+                    };
+                    Ok((Node::Branch(branch.clone()), outcome))
+                } else {
+                    // The child is updated without holding the lock of the branch
+                    let index = partial.at(0);
+                    let child = branch.read().children[index].clone();
 
-                let index = partial.at(0);
-                let child = &borrow_branch.children[index];
+                    let (new_child, outcome) = self.delete_at(&child, path, path_index + 1)?;
+                    if outcome != RemoveOutcome::NotFound {
+                        branch.write().children[index] = new_child;
+                    }
 
-                let (new_child, deleted) = self.delete_at(child, path, path_index + 1)?;
-                if deleted {
-                    borrow_branch.children[index] = new_child;
+                    Ok((Node::Branch(branch.clone()), outcome))
                 }
-
-                Ok((Node::Branch(branch.clone()), deleted))
             }
             Node::Extension(ext) => {
-                let mut borrow_ext = ext.write().unwrap();
-
-                let prefix = &borrow_ext.prefix;
-                let match_len = partial.common_prefix(prefix);
+                let (prefix, child) = {
+                    let borrow_ext = ext.read();
+                    (borrow_ext.prefix.clone(), borrow_ext.node.clone())
+                };
+                let match_len = partial.common_prefix(&prefix);
 
                 if match_len == prefix.len() {
-                    let (new_node, deleted) =
-                        self.delete_at(&borrow_ext.node, path, path_index + match_len)?;
+                    let (new_node, outcome) =
+                        self.delete_at(&child, path, path_index + match_len)?;
 
-                    if deleted {
-                        borrow_ext.node = new_node;
+                    if outcome != RemoveOutcome::NotFound {
+                        ext.write().node = new_node;
                     }
 
-                    Ok((Node::Extension(ext.clone()), deleted))
+                    Ok((Node::Extension(ext.clone()), outcome))
                 } else {
-                    Ok((Node::Extension(ext.clone()), false))
+                    Ok((Node::Extension(ext.clone()), RemoveOutcome::NotFound))
                 }
             }
             Node::Hash(hash_node) => {
                 let hash = hash_node.hash;
-                self.passing_keys.insert(hash);
-
-                let node =
-                    self.recover_from_db(hash)?
-                        .ok_or_else(|| TrieError::MissingTrieNode {
-                            node_hash: hash,
-                            traversed: Some(path.slice(0, path_index)),
-                            root_hash: Some(self.root_hash),
-                            err_key: None,
-                        })?;
-                self.delete_at(&node, path, path_index)
+                let node = self
+                    .get_node(hash)?
+                    .ok_or_else(|| TrieError::MissingTrieNode {
+                        node_hash: hash,
+                        traversed: Some(path.slice(0, path_index)),
+                        root_hash: Some(self.root_hash),
+                        err_key: None,
+                    })?;
+                let (new_node, outcome) = self.delete_at(&node, path, path_index)?;
+                // The parent keeps referring to the stored node if nothing was removed,
+                // so it must not be cleaned up on commit.
+                if outcome != RemoveOutcome::NotFound {
+                    self.passing_keys.insert(hash);
+                }
+                Ok((new_node, outcome))
             }
         }?;
 
-        if deleted {
-            Ok((self.degenerate(new_node)?, deleted))
+        if outcome != RemoveOutcome::NotFound {
+            Ok((self.degenerate(new_node)?, outcome))
         } else {
-            Ok((new_node, deleted))
+            Ok((new_node, outcome))
         }
     }
 
     // This refactors the trie after a node deletion, as necessary.
     // For example, if a deletion removes a child of a branch node, leaving only one child left, it
     // needs to be modified into an extension and maybe combined with its parent and/or child node.
-    fn degenerate(&mut self, n: Node) -> TrieResult<Node> {
+    pub(crate) fn degenerate(&mut self, n: Node) -> TrieResult<Node> {
         match n {
             Node::Branch(branch) => {
-                let borrow_branch = branch.read().unwrap();
+                let borrow_branch = branch.read();
 
                 let mut used_indexs = vec![];
                 for (index, node) in borrow_branch.children.iter().enumerate() {
@@ -739,6 +1463,7 @@ where
                 } else if used_indexs.len() == 1 && borrow_branch.value.is_none() {
                     let used_index = used_indexs[0];
                     let n = borrow_branch.children[used_index].clone();
+                    drop(borrow_branch);
 
                     let new_node = Node::from_extension(Nibbles::from_hex(&[used_index as u8]), n);
                     self.degenerate(new_node)
@@ -747,15 +1472,20 @@ where
                 }
             }
             Node::Extension(ext) => {
-                let borrow_ext = ext.read().unwrap();
-
-                let prefix = &borrow_ext.prefix;
-                match borrow_ext.node.clone() {
+                let (prefix, child) = {
+                    let borrow_ext = ext.read();
+                    (borrow_ext.prefix.clone(), borrow_ext.node.clone())
+                };
+                let prefix = &prefix;
+                match child {
                     Node::Extension(sub_ext) => {
-                        let borrow_sub_ext = sub_ext.read().unwrap();
+                        let (sub_prefix, sub_node) = {
+                            let borrow_sub_ext = sub_ext.read();
+                            (borrow_sub_ext.prefix.clone(), borrow_sub_ext.node.clone())
+                        };
 
-                        let new_prefix = prefix.join(&borrow_sub_ext.prefix);
-                        let new_n = Node::from_extension(new_prefix, borrow_sub_ext.node.clone());
+                        let new_prefix = prefix.join(&sub_prefix);
+                        let new_n = Node::from_extension(new_prefix, sub_node);
                         self.degenerate(new_n)
                     }
                     Node::Leaf(leaf) => {
@@ -768,7 +1498,7 @@ where
                         self.passing_keys.insert(node_hash);
 
                         let new_node =
-                            self.recover_from_db(node_hash)?
+                            self.get_node(node_hash)?
                                 .ok_or(TrieError::MissingTrieNode {
                                     node_hash,
                                     traversed: None,
@@ -776,7 +1506,7 @@ where
                                     err_key: None,
                                 })?;
 
-                        let n = Node::from_extension(borrow_ext.prefix.clone(), new_node);
+                        let n = Node::from_extension(prefix.clone(), new_node);
                         self.degenerate(n)
                     }
                     _ => Ok(Node::Extension(ext.clone())),
@@ -786,62 +1516,60 @@ where
         }
     }
 
-    // Get nodes path along the key, only the nodes whose encode length is greater than
-    // hash length are added.
-    // For embedded nodes whose data are already contained in their parent node, we don't need to
-    // add them in the path.
-    // In the code below, we only add the nodes get by `get_node_from_hash`, because they contains
-    // all data stored in db, including nodes whose encoded data is less than hash length.
-    fn get_path_at(
-        &self,
-        source_node: &Node,
-        path: &Nibbles,
-        path_index: usize,
-    ) -> TrieResult<Vec<Node>> {
-        let partial = &path.offset(path_index);
-        match source_node {
-            Node::Empty | Node::Leaf(_) => Ok(vec![]),
-            Node::Branch(branch) => {
-                let borrow_branch = branch.read().unwrap();
-
-                if partial.is_empty() || partial.at(0) == 16 {
-                    Ok(vec![])
-                } else {
-                    let node = &borrow_branch.children[partial.at(0)];
-                    self.get_path_at(node, path, path_index + 1)
-                }
-            }
-            Node::Extension(ext) => {
-                let borrow_ext = ext.read().unwrap();
-
-                let prefix = &borrow_ext.prefix;
-                let match_len = partial.common_prefix(prefix);
+    fn commit(&mut self, return_changed_nodes: bool) -> TrieResult<RootWithTrieDiff> {
+        let mut prepared = match self.config.commit_batch_size {
+            Some(batch_size) => self.prepare_commit_pipelined(return_changed_nodes, batch_size)?,
+            None => self.prepare_commit(return_changed_nodes)?,
+        };
 
-                if match_len == prefix.len() {
-                    self.get_path_at(&borrow_ext.node, path, path_index + match_len)
-                } else {
-                    Ok(vec![])
-                }
-            }
-            Node::Hash(hash_node) => {
-                let node_hash = hash_node.hash;
-                let n = self
-                    .recover_from_db(node_hash)?
-                    .ok_or(TrieError::MissingTrieNode {
-                        node_hash,
-                        traversed: None,
-                        root_hash: Some(self.root_hash),
-                        err_key: None,
-                    })?;
-                let mut rest = self.get_path_at(&n, path, path_index)?;
-                rest.push(n);
-                Ok(rest)
-            }
+        // Record the root with the attached manager, and keep the nodes it protects
+        let roots = self.config.roots.clone();
+        let mut registry = roots.as_ref().map(|roots| roots.write());
+        let pending_root = match registry.as_ref() {
+            Some(registry) => registry.record(&*self.db, prepared.root_hash)?,
+            None => None,
+        };
+        for (key, value) in pending_root.iter().flat_map(PendingRoot::entries) {
+            prepared.keys.push(key);
+            prepared.values.push(value);
         }
+        let keys = std::mem::take(&mut prepared.keys);
+        let values = std::mem::take(&mut prepared.values);
+        self.db.insert_batch(keys, values).map_err(TrieError::db)?;
+        if let (Some(registry), Some(pending)) = (registry.as_mut(), pending_root) {
+            registry.recorded(pending);
+        }
+        self.finish_commit(prepared, registry)
     }
 
-    fn commit(&mut self, return_changed_nodes: bool) -> TrieResult<RootWithTrieDiff> {
-        let root_hash = match self.write_node(&self.root.clone()) {
+    // Encodes the uncommitted nodes into the batch of keys and values a commit writes.
+    pub(crate) fn prepare_commit(
+        &mut self,
+        return_changed_nodes: bool,
+    ) -> TrieResult<PreparedCommit> {
+        self.prepare_commit_with(return_changed_nodes, &mut |_| {})
+    }
+
+    // Like `prepare_commit`, calling `spill` with the nodes encoded so far before each
+    // node is added to them. Nodes `spill` takes out are left out of the batch; the root
+    // node is added last, so it always stays in.
+    pub(crate) fn prepare_commit_with<F>(
+        &mut self,
+        return_changed_nodes: bool,
+        spill: &mut F,
+    ) -> TrieResult<PreparedCommit>
+    where
+        F: FnMut(&mut HashMap<B256, Vec<u8>>),
+    {
+        // Subscribers get the changed keys and nodes of every commit
+        let key_changes = if self.subscribers.is_empty() {
+            None
+        } else {
+            Some(self.pending_changes().collect::<TrieResult<Vec<_>>>()?)
+        };
+        let return_changed_nodes = return_changed_nodes || key_changes.is_some();
+
+        let root_hash = match self.write_node(&self.root.clone(), spill) {
             EncodedNode::Hash(hash) => hash,
             EncodedNode::Inline(encoded) => {
                 let hash: B256 = keccak(&encoded).as_fixed_bytes().into();
@@ -855,275 +1583,991 @@ where
             changed_nodes = self.cache.clone();
         }
 
-        let mut keys = Vec::with_capacity(self.cache.len());
-        let mut values = Vec::with_capacity(self.cache.len());
+        let nodes_written = self.cache.len();
+        let written: Vec<(B256, Vec<u8>)> = match self.config.observers.0.is_empty() {
+            true => vec![],
+            false => self.cache.iter().map(|(k, v)| (*k, v.clone())).collect(),
+        };
+
+        let mut keys = Vec::with_capacity(self.cache.len() + 1);
+        let mut values = Vec::with_capacity(self.cache.len() + 1);
         for (k, v) in self.cache.drain() {
             keys.push(k.to_vec());
             values.push(v);
         }
+        if let Some(leaf_count) = self.leaf_count {
+            keys.push(leaf_count_key(&root_hash));
+            values.push((leaf_count as u64).to_be_bytes().to_vec());
+        }
 
-        self.db
-            .insert_batch(keys, values)
-            .map_err(|e| TrieError::DB(e.to_string()))?;
+        Ok(PreparedCommit {
+            root_hash,
+            return_changed_nodes,
+            key_changes,
+            changed_nodes,
+            nodes_written,
+            written,
+            keys,
+            values,
+        })
+    }
 
-        let removed_keys: Vec<Vec<u8>> = self
+    // Returns the hashes of the nodes the previous root held that the prepared root
+    // `root_hash` doesn't.
+    pub(crate) fn stale_nodes(&self, root_hash: B256) -> HashSet<B256> {
+        let mut stale_nodes: HashSet<B256> = self
             .passing_keys
             .iter()
             .filter(|h| !self.gen_keys.contains(*h))
-            .map(|h| h.to_vec())
+            .copied()
             .collect();
+        // The root node is held decoded rather than by hash, so it never passes through
+        // `passing_keys`, and is left in the database.
+        let empty_root: B256 = KECCAK_NULL_RLP.as_fixed_bytes().into();
+        if self.root_hash != root_hash && self.root_hash != empty_root {
+            stale_nodes.insert(self.root_hash);
+        }
+        stale_nodes
+    }
 
-        self.db
-            .remove_batch(&removed_keys)
-            .map_err(|e| TrieError::DB(e.to_string()))?;
+    // Completes a commit once its batch is written: removes the stale nodes and moves
+    // the trie to the new root.
+    pub(crate) fn finish_commit(
+        &mut self,
+        prepared: PreparedCommit,
+        mut registry: Option<parking_lot::RwLockWriteGuard<'_, RootRegistry>>,
+    ) -> TrieResult<RootWithTrieDiff> {
+        let PreparedCommit {
+            root_hash,
+            return_changed_nodes,
+            key_changes,
+            changed_nodes,
+            nodes_written,
+            written,
+            ..
+        } = prepared;
+        let observers = self.config.observers.0.clone();
+        for observer in observers.iter() {
+            for (hash, encoded) in written.iter() {
+                observer.node_written(hash, encoded);
+            }
+        }
+
+        let stale: Vec<B256> = self
+            .passing_keys
+            .iter()
+            .filter(|h| !self.gen_keys.contains(*h))
+            .copied()
+            .collect();
+        let stale_nodes = match return_changed_nodes {
+            true => self.stale_nodes(root_hash),
+            false => HashSet::new(),
+        };
 
+        // Readers move to the new root before the nodes it made stale are removed, so that
+        // a read failing on a removed node finds a newer root to read at.
+        let previous_root = self.root_hash;
         self.root_hash = root_hash;
-        self.gen_keys.clear();
-        self.passing_keys.clear();
         self.root = self
-            .recover_from_db(root_hash)?
+            .get_node(root_hash)?
             .expect("The root that was just created is missing");
-        Ok(RootWithTrieDiff {
+        self.committed_root = copy_node(&self.root);
+        self.committed_leaf_count = self.leaf_count;
+        self.publish();
+
+        if let Some(registry) = registry.as_mut() {
+            registry.revive(&self.gen_keys);
+        }
+        let mut removed = vec![];
+        if !self.config.retain_stale_nodes {
+            // The leaf count of the previous root goes along with its stale nodes. Pinned
+            // roots lose theirs too, and are counted by iteration if opened again.
+            let stale_root = (previous_root != root_hash).then_some(previous_root);
+            let mut expired_tags = vec![];
+            let (expired, expired_roots) =
+                match (self.config.epoch_pruning, self.config.retention_window) {
+                    (Some(keep), _) => {
+                        let (expired, expired_roots) =
+                            advance_epoch(&*self.db, &self.gen_keys, stale, stale_root, keep)?;
+                        expired_tags = expired.iter().map(node_epoch_key).collect();
+                        (expired, expired_roots)
+                    }
+                    (None, None) => (stale, stale_root.into_iter().collect()),
+                    (None, Some(window)) => {
+                        // Nodes written again since they went stale are live once more
+                        for (root, batch) in self.deferred_removals.iter_mut() {
+                            batch.retain(|h| !self.gen_keys.contains(h));
+                            root.take_if(|root| self.gen_keys.contains(root));
+                        }
+                        self.deferred_removals.push_back((stale_root, stale));
+                        let expired = self.deferred_removals.len().saturating_sub(window);
+                        let (roots, batches): (Vec<_>, Vec<_>) =
+                            self.deferred_removals.drain(..expired).unzip();
+                        let roots = roots.into_iter().flatten().collect();
+                        (batches.into_iter().flatten().collect(), roots)
+                    }
+                };
+
+            removed = match registry.as_mut() {
+                Some(registry) => registry.filter_protected(expired),
+                None => expired,
+            };
+            let mut removed_keys: Vec<Vec<u8>> = removed.iter().map(|h| h.to_vec()).collect();
+            removed_keys.extend(expired_tags);
+            removed_keys.extend(expired_roots.iter().map(leaf_count_key));
+            self.db.remove_batch(&removed_keys).map_err(TrieError::db)?;
+        }
+        if let Some(registry) = registry.as_mut() {
+            registry.store_kept(&*self.db)?;
+        }
+        drop(registry);
+        for observer in observers.iter() {
+            for hash in removed.iter() {
+                observer.node_removed(hash);
+            }
+        }
+
+        self.gen_keys.clear();
+        self.passing_keys.clear();
+        self.checkpoints.clear();
+        self.writes_since_commit = 0;
+        let result = RootWithTrieDiff {
             root: root_hash,
+            previous_root,
             trie_diff: changed_nodes,
-        })
+            stale_nodes,
+        };
+        if let Some(changes) = key_changes {
+            self.notify(&result, changes);
+        }
+        let stats = CommitStats {
+            root: root_hash,
+            previous_root,
+            nodes_written,
+            nodes_removed: removed.len(),
+        };
+        for observer in observers.iter() {
+            observer.commit_finished(&stats);
+        }
+        Ok(result)
+    }
+
+    // Commits once the number of writes reaches the `auto_flush` threshold.
+    fn count_write(&mut self) -> TrieResult<()> {
+        self.count_writes(1)
     }
 
-    fn write_node(&mut self, to_encode: &Node) -> EncodedNode {
-        // Returns the hash value directly to avoid double counting.
-        if let Node::Hash(hash_node) = to_encode {
-            return EncodedNode::Hash(hash_node.hash);
+    pub(crate) fn count_writes(&mut self, writes: usize) -> TrieResult<()> {
+        self.writes_since_commit += writes;
+        match self.config.auto_flush {
+            Some(threshold) if self.writes_since_commit >= threshold => {
+                self.commit(false)?;
+                Ok(())
+            }
+            _ => Ok(()),
         }
+    }
 
-        let data = self.encode_raw(to_encode);
-        // Nodes smaller than 32 bytes are stored inside their parent,
-        // Nodes equal to 32 bytes are returned directly
-        if data.len() < HASHED_LENGTH {
-            EncodedNode::Inline(data)
-        } else {
-            let hash: B256 = keccak(&data).as_fixed_bytes().into();
-            self.cache.insert(hash, data);
+    fn write_node<F>(&mut self, to_encode: &Node, spill: &mut F) -> EncodedNode
+    where
+        F: FnMut(&mut HashMap<B256, Vec<u8>>),
+    {
+        let cache = &mut self.cache;
+        let gen_keys = &mut self.gen_keys;
+        encode_child(to_encode, &mut |hash, data| {
+            spill(cache);
+            cache.insert(hash, data);
+            gen_keys.insert(hash);
+        })
+    }
 
-            self.gen_keys.insert(hash);
-            EncodedNode::Hash(hash)
+    pub(crate) fn adjust_leaf_count(&mut self, added: bool) {
+        if let Some(leaf_count) = self.leaf_count.as_mut() {
+            if added {
+                *leaf_count += 1;
+            } else {
+                *leaf_count -= 1;
+            }
         }
     }
 
-    fn encode_raw(&mut self, node: &Node) -> Vec<u8> {
-        match node {
-            Node::Empty => vec![EMPTY_STRING_CODE],
-            Node::Leaf(leaf) => {
-                let mut buf = Vec::<u8>::new();
-                let mut list = Vec::<u8>::new();
-                leaf.key.encode_compact().as_slice().encode(&mut list);
-                leaf.value.as_slice().encode(&mut list);
-                let header = Header {
-                    list: true,
-                    payload_length: list.len(),
-                };
-                header.encode(&mut buf);
-                buf.extend_from_slice(&list);
-                buf
-            }
-            Node::Branch(branch) => {
-                let borrow_branch = branch.read().expect("to read branch node");
-                let mut buf = Vec::<u8>::new();
-                let mut list = Vec::<u8>::new();
-                for i in 0..16 {
-                    let n = &borrow_branch.children[i];
-                    match self.write_node(n) {
-                        EncodedNode::Hash(hash) => hash.as_slice().encode(&mut list),
-                        EncodedNode::Inline(data) => list.extend_from_slice(data.as_slice()),
-                    };
-                }
+    fn decode_node(data: &mut &[u8]) -> TrieResult<Node> {
+        decode_node(data)
+    }
+
+    /// Loads and decodes the node stored under `hash`, or returns `None` if the database
+    /// doesn't hold it. Uncommitted nodes are not stored yet and can't be found this way.
+    ///
+    /// Children that are stored apart from the node are returned as `Node::Hash`.
+    pub fn get_node(&self, hash: B256) -> TrieResult<Option<Node>> {
+        self.reader().recover(hash)
+    }
 
+    pub(crate) fn reader(&self) -> NodeReader<'_, D> {
+        NodeReader::new(&*self.db, self.root_hash).with_resolver(self.config.resolver.0.clone())
+    }
+}
+
+// Encodes a node, passing each child that is too large to be inlined to `store` along with
+// its hash.
+pub(crate) fn encode_node<F>(node: &Node, store: &mut F) -> Vec<u8>
+where
+    F: FnMut(B256, Vec<u8>),
+{
+    match node {
+        Node::Empty => vec![EMPTY_STRING_CODE],
+        Node::Leaf(leaf) => {
+            let mut buf = Vec::<u8>::new();
+            let mut list = Vec::<u8>::new();
+            leaf.key.encode_compact().as_slice().encode(&mut list);
+            leaf.value.as_slice().encode(&mut list);
+            let header = Header {
+                list: true,
+                payload_length: list.len(),
+            };
+            header.encode(&mut buf);
+            buf.extend_from_slice(&list);
+            buf
+        }
+        Node::Branch(branch) => {
+            // The children are encoded without holding the lock of the branch
+            let (children, value) = {
+                let borrow_branch = branch.read();
+                let mut value = Vec::<u8>::new();
                 match &borrow_branch.value {
-                    Some(v) => v.as_slice().encode(&mut list),
-                    None => list.put_u8(EMPTY_STRING_CODE),
-                };
-                let header = Header {
-                    list: true,
-                    payload_length: list.len(),
+                    Some(v) => v.as_slice().encode(&mut value),
+                    None => value.put_u8(EMPTY_STRING_CODE),
                 };
-                header.encode(&mut buf);
-                buf.extend_from_slice(&list);
-                buf
-            }
-            Node::Extension(ext) => {
-                let borrow_ext = ext.read().expect("to read extension node");
-                let mut buf = Vec::<u8>::new();
-                let mut list = Vec::<u8>::new();
-                borrow_ext
-                    .prefix
-                    .encode_compact()
-                    .as_slice()
-                    .encode(&mut list);
-                match self.write_node(&borrow_ext.node) {
+                (borrow_branch.children.clone(), value)
+            };
+            let mut buf = Vec::<u8>::new();
+            let mut list = Vec::<u8>::new();
+            for n in children.iter() {
+                match encode_child(n, store) {
                     EncodedNode::Hash(hash) => hash.as_slice().encode(&mut list),
                     EncodedNode::Inline(data) => list.extend_from_slice(data.as_slice()),
                 };
-                let header = Header {
-                    list: true,
-                    payload_length: list.len(),
-                };
-                header.encode(&mut buf);
-                buf.extend_from_slice(&list);
-                buf
             }
-            Node::Hash(_hash) => unreachable!(),
+            list.extend_from_slice(&value);
+            let header = Header {
+                list: true,
+                payload_length: list.len(),
+            };
+            header.encode(&mut buf);
+            buf.extend_from_slice(&list);
+            buf
+        }
+        Node::Extension(ext) => {
+            let (prefix, child) = {
+                let borrow_ext = ext.read();
+                (borrow_ext.prefix.encode_compact(), borrow_ext.node.clone())
+            };
+            let mut buf = Vec::<u8>::new();
+            let mut list = Vec::<u8>::new();
+            prefix.as_slice().encode(&mut list);
+            match encode_child(&child, store) {
+                EncodedNode::Hash(hash) => hash.as_slice().encode(&mut list),
+                EncodedNode::Inline(data) => list.extend_from_slice(data.as_slice()),
+            };
+            let header = Header {
+                list: true,
+                payload_length: list.len(),
+            };
+            header.encode(&mut buf);
+            buf.extend_from_slice(&list);
+            buf
         }
+        Node::Hash(_hash) => unreachable!(),
+    }
+}
+
+fn encode_child<F>(to_encode: &Node, store: &mut F) -> EncodedNode
+where
+    F: FnMut(B256, Vec<u8>),
+{
+    // Returns the hash value directly to avoid double counting.
+    if let Node::Hash(hash_node) = to_encode {
+        return EncodedNode::Hash(hash_node.hash);
     }
 
-    fn decode_node(data: &mut &[u8]) -> TrieResult<Node> {
-        decode_node(data)
+    let data = encode_node(to_encode, store);
+    // Nodes smaller than 32 bytes are stored inside their parent,
+    // Nodes equal to 32 bytes are returned directly
+    if data.len() < HASHED_LENGTH {
+        EncodedNode::Inline(data)
+    } else {
+        let hash: B256 = keccak(&data).as_fixed_bytes().into();
+        store(hash, data.clone());
+        EncodedNode::Hash(hash)
     }
+}
 
-    fn recover_from_db(&self, key: B256) -> TrieResult<Option<Node>> {
-        let node = match self
-            .db
-            .get(key.as_slice())
-            .map_err(|e| TrieError::DB(e.to_string()))?
-        {
-            Some(value) => Some(Self::decode_node(&mut value.as_slice())?),
-            None => None,
-        };
-        Ok(node)
+// Returns the length of the node's encoding, as `encode_node` would produce it, without
+// encoding it. A `Node::Hash` counts as the 33 byte reference a parent holds.
+pub(crate) fn encoded_len(node: &Node) -> usize {
+    let payload_length = match node {
+        Node::Empty => return 1,
+        Node::Hash(_) => return HASHED_LENGTH + 1,
+        Node::Leaf(leaf) => compact_len(&leaf.key) + leaf.value.as_slice().length(),
+        Node::Branch(branch) => {
+            let (children, value_len) = {
+                let branch = branch.read();
+                let value_len = branch.value.as_ref().map_or(1, |v| v.as_slice().length());
+                (branch.children.clone(), value_len)
+            };
+            children.iter().map(child_len).sum::<usize>() + value_len
+        }
+        Node::Extension(ext) => {
+            let (prefix_len, child) = {
+                let ext = ext.read();
+                (compact_len(&ext.prefix), ext.node.clone())
+            };
+            prefix_len + child_len(&child)
+        }
+    };
+    alloy_rlp::length_of_length(payload_length) + payload_length
+}
+
+// The length a child takes up in its parent, either inline or as a hash.
+fn child_len(node: &Node) -> usize {
+    let len = encoded_len(node);
+    if len < HASHED_LENGTH {
+        len
+    } else {
+        HASHED_LENGTH + 1
     }
 }
 
-fn length_of_length(payload_length: usize) -> usize {
-    if payload_length == 1 {
-        0
-    } else if payload_length < 56 {
+// The length of the RLP string holding the compact encoding of `nibbles`. An encoding of
+// a single byte is its flag, which is always below 0x80 and so needs no header.
+fn compact_len(nibbles: &Nibbles) -> usize {
+    let len = (nibbles.len() - nibbles.is_leaf() as usize) / 2 + 1;
+    if len == 1 {
         1
     } else {
-        1 + (usize::BITS as usize / 8) - payload_length.leading_zeros() as usize / 8
+        alloy_rlp::length_of_length(len) + len
     }
 }
 
-pub fn decode_node(data: &mut &[u8]) -> TrieResult<Node> {
-    let rlp_header = Header::decode(data)?;
-    match rlp_header.list {
-        true => {
-            let mut list: Vec<Bytes> = vec![];
-            let payload = &mut &data[..rlp_header.payload_length];
-            while !payload.is_empty() {
-                let other_header = Header::decode(payload)?;
-                let value = &mut &payload[..other_header.payload_length];
-                payload.advance(other_header.payload_length);
-                let mut buf = Vec::<u8>::new();
-                if !(value.len() == 1 && value[0] <= 127) {
-                    other_header.encode(&mut buf);
-                }
-                list.push(Bytes::copy_from_slice(&[buf, value.to_vec()].concat()));
-            }
-            if list.len() == 17 {
-                let mut nodes = empty_children();
-                #[allow(clippy::needless_range_loop)]
-                for i in 0..nodes.len() {
-                    let n = decode_node(&mut list[i].as_ref())?;
-                    nodes[i] = n;
-                }
+// Read access to the nodes of a trie, shared by `EthTrie` and `TrieView`.
+pub(crate) struct NodeReader<'a, D>
+where
+    D: DB,
+{
+    db: DbHandle<'a, D>,
+    root_hash: B256,
+    resolver: Option<Arc<dyn NodeResolver>>,
+}
 
-                // The last element is a value node.
-                let value_header = Header::decode(&mut list[16].as_ref())?;
-                let value_rlp = list[16][length_of_length(value_header.payload_length)..].to_vec();
-                let value = if value_rlp.is_empty() {
-                    None
-                } else {
-                    Some(value_rlp)
-                };
+// The database a reader loads nodes from. It is owned by the reader when the trie it
+// reads has been consumed, as with `EthTrie::into_iter`.
+enum DbHandle<'a, D> {
+    Borrowed(&'a D),
+    Owned(Arc<D>),
+}
 
-                Ok(Node::from_branch(nodes, value))
-            } else if list.len() == 2 {
-                let value_header = Header::decode(&mut list[0].as_ref())?;
-                let key = Nibbles::from_compact(
-                    &list[0][length_of_length(value_header.payload_length)..],
-                );
-
-                if key.is_leaf() {
-                    let value_header = Header::decode(&mut list[1].as_ref())?;
-                    Ok(Node::from_leaf(
-                        key,
-                        list[1][length_of_length(value_header.payload_length)..].to_vec(),
-                    ))
-                } else {
-                    let n = decode_node(&mut list[1].as_ref())?;
-                    Ok(Node::from_extension(key, n))
-                }
-            } else {
-                Err(TrieError::InvalidData)
-            }
-        }
-        false => {
-            if rlp_header.payload_length == HASHED_LENGTH {
-                Ok(Node::from_hash(B256::from_slice(data)))
-            } else if rlp_header.payload_length == 0 {
-                Ok(Node::Empty)
-            } else {
-                Err(TrieError::InvalidData)
-            }
+impl<D> Deref for DbHandle<'_, D> {
+    type Target = D;
+
+    fn deref(&self) -> &D {
+        match self {
+            DbHandle::Borrowed(db) => db,
+            DbHandle::Owned(db) => db,
         }
     }
 }
 
-#[cfg(test)]
-mod tests {
-    use alloy_primitives::B256;
-    use alloy_rlp::EMPTY_STRING_CODE;
-    use rand::distributions::Alphanumeric;
-    use rand::seq::SliceRandom;
-    use rand::{thread_rng, Rng};
-    use std::collections::{HashMap, HashSet};
-    use std::sync::Arc;
-
-    use keccak_hash::KECCAK_NULL_RLP;
-
-    use super::{EthTrie, Trie};
-    use crate::db::{MemoryDB, DB};
-    use crate::errors::TrieError;
-    use crate::nibbles::Nibbles;
+impl<'a, D> NodeReader<'a, D>
+where
+    D: DB,
+{
+    pub(crate) fn new(db: &'a D, root_hash: B256) -> Self {
+        Self {
+            db: DbHandle::Borrowed(db),
+            root_hash,
+            resolver: None,
+        }
+    }
 
-    #[test]
-    fn test_trie_insert() {
-        let memdb = Arc::new(MemoryDB::new(true));
-        let mut trie = EthTrie::new(memdb);
-        trie.insert(b"test", b"test").unwrap();
+    pub(crate) fn owned(db: Arc<D>, root_hash: B256) -> Self {
+        Self {
+            db: DbHandle::Owned(db),
+            root_hash,
+            resolver: None,
+        }
     }
 
-    #[test]
-    fn test_trie_get() {
-        let memdb = Arc::new(MemoryDB::new(true));
-        let mut trie = EthTrie::new(memdb);
-        trie.insert(b"test", b"test").unwrap();
-        let v = trie.get(b"test").unwrap();
+    // Sets the resolver asked for the nodes missing from the database.
+    pub(crate) fn with_resolver(mut self, resolver: Option<Arc<dyn NodeResolver>>) -> Self {
+        self.resolver = resolver;
+        self
+    }
 
-        assert_eq!(Some(b"test".to_vec()), v)
+    pub(crate) fn root_hash(&self) -> B256 {
+        self.root_hash
     }
 
-    #[test]
-    fn test_trie_get_missing() {
-        let memdb = Arc::new(MemoryDB::new(true));
-        let mut trie = EthTrie::new(memdb);
-        trie.insert(b"test", b"test").unwrap();
-        let v = trie.get(b"no-val").unwrap();
+    pub(crate) fn get<R, F>(&self, root: &Node, key: &[u8], f: F) -> TrieResult<Option<R>>
+    where
+        F: FnOnce(&[u8]) -> R,
+    {
+        let path = &Nibbles::from_raw(key, true);
+        let result = self.get_at(root, path, 0, f);
+        if let Err(TrieError::MissingTrieNode {
+            node_hash,
+            traversed,
+            root_hash,
+            err_key: _,
+        }) = result
+        {
+            Err(TrieError::MissingTrieNode {
+                node_hash,
+                traversed,
+                root_hash,
+                err_key: Some(key.to_vec()),
+            })
+        } else {
+            result
+        }
+    }
 
-        assert_eq!(None, v)
+    pub(crate) fn contains(&self, root: &Node, key: &[u8]) -> TrieResult<bool> {
+        let path = &Nibbles::from_raw(key, true);
+        Ok(self.get_at(root, path, 0, |_| ())?.is_some())
     }
 
-    fn corrupt_trie() -> (EthTrie<MemoryDB>, B256, B256) {
-        let memdb = Arc::new(MemoryDB::new(true));
-        let corruptor_db = memdb.clone();
-        let mut trie = EthTrie::new(memdb);
-        trie.insert(b"test1-key", b"really-long-value1-to-prevent-inlining")
-            .unwrap();
-        trie.insert(b"test2-key", b"really-long-value2-to-prevent-inlining")
-            .unwrap();
-        let actual_root_hash = trie.root_hash().unwrap();
+    pub(crate) fn get_proof(&self, root: &Node, key: &[u8]) -> TrieResult<Vec<Vec<u8>>> {
+        let key_path = &Nibbles::from_raw(key, true);
+        let result = self.get_path_at(root, key_path, 0);
 
-        // Manually corrupt the database by removing a trie node
-        // This is the hash for the leaf node for test2-key
-        let node_hash_to_delete = b"\xcb\x15v%j\r\x1e\te_TvQ\x8d\x93\x80\xd1\xa2\xd1\xde\xfb\xa5\xc3hJ\x8c\x9d\xb93I-\xbd";
+        if let Err(TrieError::MissingTrieNode {
+            node_hash,
+            traversed,
+            root_hash,
+            err_key: _,
+        }) = result
+        {
+            Err(TrieError::MissingTrieNode {
+                node_hash,
+                traversed,
+                root_hash,
+                err_key: Some(key.to_vec()),
+            })
+        } else {
+            let mut path = result?;
+            match root {
+                Node::Empty => {}
+                _ => path.push(root.clone()),
+            }
+            Ok(path
+                .into_iter()
+                .rev()
+                .map(|n| encode_node(&n, &mut |_, _| {}))
+                .collect())
+        }
+    }
+
+    pub(crate) fn iter(self, root: Node) -> TrieIterator<'a, D> {
+        let nodes = vec![(root.clone()).into()];
+        TrieIterator {
+            reader: self,
+            root,
+            nibble: Nibbles::from_raw(&[], false),
+            nodes,
+            position: Some(vec![]),
+        }
+    }
+
+    pub(crate) fn recover(&self, key: B256) -> TrieResult<Option<Node>> {
+        let node = match self.db.get(key.as_slice()).map_err(TrieError::db)? {
+            Some(value) => Some(decode_node(&mut value.as_slice())?),
+            None => match self.resolve(key)? {
+                Some(value) => Some(decode_node(&mut value.as_slice())?),
+                None => None,
+            },
+        };
+        Ok(node)
+    }
+
+    // Fetches a node missing from the database from the resolver, if any, and writes it
+    // to the database.
+    fn resolve(&self, key: B256) -> TrieResult<Option<Vec<u8>>> {
+        let Some(resolver) = &self.resolver else {
+            return Ok(None);
+        };
+        let Some(value) = resolver.resolve(key).map_err(TrieError::DB)? else {
+            return Ok(None);
+        };
+        if keccak(&value).as_bytes() != key.as_slice() {
+            return Err(TrieError::InvalidData);
+        }
+        self.db
+            .insert(key.as_slice(), value.clone())
+            .map_err(TrieError::db)?;
+        Ok(Some(value))
+    }
+
+    fn get_at<R, F>(
+        &self,
+        source_node: &Node,
+        path: &Nibbles,
+        path_index: usize,
+        f: F,
+    ) -> TrieResult<Option<R>>
+    where
+        F: FnOnce(&[u8]) -> R,
+    {
+        let partial = &path.offset(path_index);
+        match source_node {
+            Node::Empty => Ok(None),
+            Node::Leaf(leaf) => {
+                if &leaf.key == partial {
+                    Ok(Some(f(&leaf.value)))
+                } else {
+                    Ok(None)
+                }
+            }
+            Node::Branch(branch) => {
+                if partial.is_empty() || partial.at(0) == 16 {
+                    Ok(branch.read().value.as_deref().map(f))
+                } else {
+                    let index = partial.at(0);
+                    let child = branch.read().children[index].clone();
+                    self.get_at(&child, path, path_index + 1, f)
+                }
+            }
+            Node::Extension(extension) => {
+                let (match_len, prefix_len, child) = {
+                    let extension = extension.read();
+                    let match_len = partial.common_prefix(&extension.prefix);
+                    (match_len, extension.prefix.len(), extension.node.clone())
+                };
+                if match_len == prefix_len {
+                    self.get_at(&child, path, path_index + match_len, f)
+                } else {
+                    Ok(None)
+                }
+            }
+            Node::Hash(hash_node) => {
+                let node_hash = hash_node.hash;
+                let node = self
+                    .recover(node_hash)?
+                    .ok_or_else(|| TrieError::MissingTrieNode {
+                        node_hash,
+                        traversed: Some(path.slice(0, path_index)),
+                        root_hash: Some(self.root_hash),
+                        err_key: None,
+                    })?;
+                self.get_at(&node, path, path_index, f)
+            }
+        }
+    }
+
+    // Get nodes path along the key, only the nodes whose encode length is greater than
+    // hash length are added.
+    // For embedded nodes whose data are already contained in their parent node, we don't need to
+    // add them in the path.
+    // In the code below, we only add the nodes get by `get_node_from_hash`, because they contains
+    // all data stored in db, including nodes whose encoded data is less than hash length.
+    fn get_path_at(
+        &self,
+        source_node: &Node,
+        path: &Nibbles,
+        path_index: usize,
+    ) -> TrieResult<Vec<Node>> {
+        let partial = &path.offset(path_index);
+        match source_node {
+            Node::Empty | Node::Leaf(_) => Ok(vec![]),
+            Node::Branch(branch) => {
+                if partial.is_empty() || partial.at(0) == 16 {
+                    Ok(vec![])
+                } else {
+                    let node = branch.read().children[partial.at(0)].clone();
+                    self.get_path_at(&node, path, path_index + 1)
+                }
+            }
+            Node::Extension(ext) => {
+                let (match_len, prefix_len, child) = {
+                    let borrow_ext = ext.read();
+                    let match_len = partial.common_prefix(&borrow_ext.prefix);
+                    (match_len, borrow_ext.prefix.len(), borrow_ext.node.clone())
+                };
+
+                if match_len == prefix_len {
+                    self.get_path_at(&child, path, path_index + match_len)
+                } else {
+                    Ok(vec![])
+                }
+            }
+            Node::Hash(hash_node) => {
+                let node_hash = hash_node.hash;
+                let n = self.recover(node_hash)?.ok_or(TrieError::MissingTrieNode {
+                    node_hash,
+                    traversed: None,
+                    root_hash: Some(self.root_hash),
+                    err_key: None,
+                })?;
+                let mut rest = self.get_path_at(&n, path, path_index)?;
+                rest.push(n);
+                Ok(rest)
+            }
+        }
+    }
+}
+
+pub(crate) fn verify_proof(
+    root_hash: B256,
+    key: &[u8],
+    proof: Vec<Vec<u8>>,
+) -> TrieResult<Option<Vec<u8>>> {
+    // Nothing is needed to prove a key absent from the empty trie.
+    if root_hash.as_slice() == KECCAK_NULL_RLP.as_bytes() {
+        return Ok(None);
+    }
+    let proof_db = Arc::new(MemoryDB::new(true));
+    for node_encoded in proof.into_iter() {
+        // A node can only be proven under the one encoding its hash commits to.
+        decode_node_strict(&node_encoded).or(Err(TrieError::InvalidProof))?;
+        let hash: B256 = keccak(&node_encoded).as_fixed_bytes().into();
+
+        if root_hash.eq(&hash) || node_encoded.len() >= HASHED_LENGTH {
+            proof_db.insert(hash.as_slice(), node_encoded).unwrap();
+        }
+    }
+    let trie = TrieView::new(proof_db, root_hash).or(Err(TrieError::InvalidProof))?;
+    trie.get(key).or(Err(TrieError::InvalidProof))
+}
+
+// Creates the slot holding the view of the last committed root.
+fn publish<D: DB>(db: &Arc<D>, committed_root: &Node, root_hash: B256) -> Published<D> {
+    let view = TrieView::from_parts(db.clone(), committed_root.clone(), root_hash);
+    Arc::new(parking_lot::RwLock::new(Arc::new(view)))
+}
+
+// Copies the in-memory part of the trie, since branches and extensions are updated in
+// place. Leaves and hash nodes are never mutated and stay shared.
+fn copy_node(node: &Node) -> Node {
+    match node {
+        Node::Branch(branch) => {
+            let (mut children, value) = {
+                let branch = branch.read();
+                (branch.children.clone(), branch.value.clone())
+            };
+            for child in children.iter_mut() {
+                *child = copy_node(child);
+            }
+            Node::from_branch(children, value)
+        }
+        Node::Extension(ext) => {
+            let (prefix, child) = {
+                let ext = ext.read();
+                (ext.prefix.clone(), ext.node.clone())
+            };
+            Node::from_extension(prefix, copy_node(&child))
+        }
+        _ => node.clone(),
+    }
+}
+
+pub(crate) fn leaf_count_key(root: &B256) -> Vec<u8> {
+    [LEAF_COUNT_KEY_PREFIX, root.as_slice()].concat()
+}
+
+fn decode_leaf_count(encoded: &[u8]) -> TrieResult<usize> {
+    let bytes: [u8; 8] = encoded.try_into().map_err(|_| TrieError::InvalidData)?;
+    Ok(u64::from_be_bytes(bytes) as usize)
+}
+
+/// The deepest nesting of inline nodes `decode_node` accepts. Every level of a real trie
+/// consumes at least one nibble of a 32 byte key, so valid nodes stay far below it.
+pub const MAX_DECODE_DEPTH: usize = 128;
+
+/// Decodes a node from the start of `data` and advances `data` past it. Children stored
+/// apart from the node are returned as `Node::Hash`.
+pub fn decode_node(data: &mut &[u8]) -> TrieResult<Node> {
+    decode_node_with_max_depth(data, MAX_DECODE_DEPTH)
+}
+
+/// Decodes a node like `decode_node`, failing with `TrieError::DepthLimitExceeded` if
+/// more than `max_depth` branch and extension nodes are nested in one another, the
+/// outermost included.
+///
+/// Nodes are decoded without recursion, so untrusted input can't exhaust the stack.
+pub fn decode_node_with_max_depth(data: &mut &[u8], max_depth: usize) -> TrieResult<Node> {
+    let input = *data;
+    let mut stack: Vec<PartialNode<'_>> = Vec::new();
+    let mut item = next_item(data, ItemAt::START)?;
+    'items: loop {
+        let at = ItemAt {
+            offset: item.as_ptr() as usize - input.as_ptr() as usize,
+            slot: stack.last().and_then(PartialNode::next_slot),
+        };
+        // Only nodes shorter than a hash are embedded in their parent, larger ones are
+        // stored apart and referred to by hash.
+        if !stack.is_empty() && item[0] >= EMPTY_LIST_CODE && item.len() >= HASHED_LENGTH {
+            return Err(at.error(NodeDecodeErrorKind::InlineTooLarge { len: item.len() }));
+        }
+        let mut node = match open_item(item, at)? {
+            OpenedItem::Node(node) => node,
+            OpenedItem::Partial(mut partial) => {
+                if stack.len() == max_depth {
+                    return Err(TrieError::DepthLimitExceeded { max_depth });
+                }
+                item = partial.pending.pop().expect("partial nodes have children");
+                stack.push(partial);
+                continue;
+            }
+        };
+
+        // Hand the finished node to its parent, completing every parent that has all of
+        // its children.
+        loop {
+            let Some(parent) = stack.last_mut() else {
+                return Ok(node);
+            };
+            parent.children.push(node);
+            if let Some(next) = parent.pending.pop() {
+                item = next;
+                continue 'items;
+            }
+            node = stack.pop().unwrap().finish();
+        }
+    }
+}
+
+/// Decodes a node, accepting only its canonical encoding: `data` must hold the node and
+/// nothing else, and encoding the decoded node must give back `data` exactly. Inline
+/// children that should have been stored by hash are rejected as well.
+///
+/// Used to check proofs, where any byte string that decodes to the same node as the
+/// canonical one would otherwise be accepted in its place.
+pub fn decode_node_strict(data: &[u8]) -> TrieResult<Node> {
+    let mut rest = data;
+    let node = decode_node(&mut rest)?;
+    let (kind, offset) = if !rest.is_empty() {
+        (NodeDecodeErrorKind::TrailingBytes, data.len() - rest.len())
+    } else if NodeCodec::encode(&node) != data {
+        (NodeDecodeErrorKind::NonCanonical, 0)
+    } else {
+        return Ok(node);
+    };
+    Err(TrieError::InvalidNode(NodeDecodeError { kind, offset }))
+}
+
+// A branch or extension node whose children are still being decoded.
+struct PartialNode<'a> {
+    kind: PartialKind,
+    // The encodings of the children left to decode, last child first.
+    pending: Vec<&'a [u8]>,
+    children: Vec<Node>,
+}
+
+enum PartialKind {
+    Branch(Option<Vec<u8>>),
+    Extension(Nibbles),
+}
+
+impl PartialNode<'_> {
+    // The branch slot of the next child to decode, or `None` below an extension.
+    fn next_slot(&self) -> Option<u8> {
+        match self.kind {
+            PartialKind::Branch(_) => Some(self.children.len() as u8),
+            PartialKind::Extension(_) => None,
+        }
+    }
+
+    fn finish(self) -> Node {
+        match self.kind {
+            PartialKind::Branch(value) => {
+                let mut children = empty_children();
+                for (slot, child) in children.iter_mut().zip(self.children) {
+                    *slot = child;
+                }
+                Node::from_branch(children, value)
+            }
+            PartialKind::Extension(prefix) => {
+                Node::from_extension(prefix, self.children.into_iter().next().unwrap())
+            }
+        }
+    }
+}
+
+// Where an item being decoded sits in the input, for error reporting.
+#[derive(Clone, Copy)]
+struct ItemAt {
+    offset: usize,
+    slot: Option<u8>,
+}
+
+impl ItemAt {
+    const START: Self = Self {
+        offset: 0,
+        slot: None,
+    };
+
+    fn error(self, kind: NodeDecodeErrorKind) -> TrieError {
+        TrieError::InvalidNode(NodeDecodeError {
+            kind,
+            offset: self.offset,
+        })
+    }
+
+    // The position of one of the item's own elements.
+    fn element(self, item: &[u8], element: &[u8]) -> Self {
+        Self {
+            offset: self.offset + (element.as_ptr() as usize - item.as_ptr() as usize),
+            slot: None,
+        }
+    }
+}
+
+enum OpenedItem<'a> {
+    Node(Node),
+    Partial(PartialNode<'a>),
+}
+
+// Decodes the parts of a node that need no further nesting. Branches and extensions are
+// returned with the encodings of their children.
+fn open_item(item: &[u8], at: ItemAt) -> TrieResult<OpenedItem<'_>> {
+    let mut payload = item;
+    let header =
+        Header::decode(&mut payload).map_err(|err| at.error(NodeDecodeErrorKind::Rlp(err)))?;
+    if !header.list {
+        return match header.payload_length {
+            0 => Ok(OpenedItem::Node(Node::Empty)),
+            HASHED_LENGTH => Ok(OpenedItem::Node(Node::from_hash(B256::from_slice(
+                &payload[..HASHED_LENGTH],
+            )))),
+            _ => Err(at.error(NodeDecodeErrorKind::ChildSlot { slot: at.slot })),
+        };
+    }
+
+    let mut items = Vec::with_capacity(17);
+    while !payload.is_empty() {
+        let element = at.element(item, payload);
+        items.push(next_item(&mut payload, element)?);
+    }
+    match items.len() {
+        17 => {
+            let value = decode_value(item, items[16], at)?;
+            let value = (!value.is_empty()).then(|| value.to_vec());
+            items.truncate(16);
+            items.reverse();
+            Ok(OpenedItem::Partial(PartialNode {
+                kind: PartialKind::Branch(value),
+                pending: items,
+                children: Vec::with_capacity(16),
+            }))
+        }
+        2 => {
+            let key = Header::decode_bytes(&mut &items[0][..], false)
+                .ok()
+                .and_then(|compact| Nibbles::try_from_compact(compact).ok())
+                .ok_or_else(|| {
+                    at.element(item, items[0])
+                        .error(NodeDecodeErrorKind::CompactPrefix)
+                })?;
+            if key.is_leaf() {
+                let value = decode_value(item, items[1], at)?;
+                Ok(OpenedItem::Node(Node::from_leaf(key, value.to_vec())))
+            } else {
+                Ok(OpenedItem::Partial(PartialNode {
+                    kind: PartialKind::Extension(key),
+                    pending: vec![items[1]],
+                    children: Vec::with_capacity(1),
+                }))
+            }
+        }
+        len => Err(at.error(NodeDecodeErrorKind::ListArity { items: len })),
+    }
+}
+
+fn decode_value<'a>(item: &[u8], value: &'a [u8], at: ItemAt) -> TrieResult<&'a [u8]> {
+    Header::decode_bytes(&mut &value[..], false)
+        .map_err(|_| at.element(item, value).error(NodeDecodeErrorKind::Value))
+}
+
+// Splits the RLP item at the start of `buf`, which is at `at` in the input, off it,
+// header included.
+fn next_item<'a>(buf: &mut &'a [u8], at: ItemAt) -> TrieResult<&'a [u8]> {
+    let mut payload = *buf;
+    let header =
+        Header::decode(&mut payload).map_err(|err| at.error(NodeDecodeErrorKind::Rlp(err)))?;
+    let len = buf.len() - payload.len() + header.payload_length;
+    if len > buf.len() {
+        let err = alloy_rlp::Error::InputTooShort;
+        return Err(at.error(NodeDecodeErrorKind::Rlp(err)));
+    }
+    let (item, rest) = buf.split_at(len);
+    *buf = rest;
+    Ok(item)
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use alloy_primitives::B256;
+    use alloy_rlp::EMPTY_STRING_CODE;
+    use rand::distributions::Alphanumeric;
+    use rand::seq::SliceRandom;
+    use rand::{thread_rng, Rng};
+    use std::collections::{BTreeMap, HashMap, HashSet};
+    use std::ops::Bound;
+    use std::sync::Arc;
+
+    use keccak_hash::KECCAK_NULL_RLP;
+
+    use alloy_rlp::Header;
+
+    use super::{
+        decode_node, decode_node_strict, decode_node_with_max_depth, leaf_count_key, EthTrie,
+        RemoveOutcome, TrieRead, TrieWrite, LEAF_COUNT_KEY_PREFIX,
+    };
+    use crate::codec::NodeCodec;
+    use crate::db::{IterableDB, MemoryDB, DB};
+    use crate::errors::{NodeDecodeError, NodeDecodeErrorKind, TrieError};
+    use crate::nibbles::Nibbles;
+    use crate::node::{empty_children, Node};
+
+    #[test]
+    fn test_trie_insert() {
+        let memdb = Arc::new(MemoryDB::new(true));
+        let mut trie = EthTrie::new(memdb);
+        trie.insert(b"test", b"test").unwrap();
+    }
+
+    #[test]
+    fn test_trie_get() {
+        let memdb = Arc::new(MemoryDB::new(true));
+        let mut trie = EthTrie::new(memdb);
+        trie.insert(b"test", b"test").unwrap();
+        let v = trie.get(b"test").unwrap();
+
+        assert_eq!(Some(b"test".to_vec()), v)
+    }
+
+    #[test]
+    fn test_trie_get_missing() {
+        let memdb = Arc::new(MemoryDB::new(true));
+        let mut trie = EthTrie::new(memdb);
+        trie.insert(b"test", b"test").unwrap();
+        let v = trie.get(b"no-val").unwrap();
+
+        assert_eq!(None, v)
+    }
+
+    fn corrupt_trie() -> (EthTrie<MemoryDB>, B256, B256) {
+        let memdb = Arc::new(MemoryDB::new(true));
+        let corruptor_db = memdb.clone();
+        let mut trie = EthTrie::new(memdb);
+        trie.insert(b"test1-key", b"really-long-value1-to-prevent-inlining")
+            .unwrap();
+        trie.insert(b"test2-key", b"really-long-value2-to-prevent-inlining")
+            .unwrap();
+        let actual_root_hash = trie.root_hash().unwrap();
+
+        // Manually corrupt the database by removing a trie node
+        // This is the hash for the leaf node for test2-key
+        let node_hash_to_delete = b"\xcb\x15v%j\r\x1e\te_TvQ\x8d\x93\x80\xd1\xa2\xd1\xde\xfb\xa5\xc3hJ\x8c\x9d\xb93I-\xbd";
         assert_ne!(corruptor_db.get(node_hash_to_delete).unwrap(), None);
         corruptor_db.remove(node_hash_to_delete).unwrap();
         assert_eq!(corruptor_db.get(node_hash_to_delete).unwrap(), None);
@@ -1210,7 +2654,7 @@ mod tests {
     #[test]
     /// When a database entry is missing, get_proof returns a MissingTrieNode error
     fn test_trie_get_proof_corrupt() {
-        let (mut trie, actual_root_hash, deleted_node_hash) = corrupt_trie();
+        let (trie, actual_root_hash, deleted_node_hash) = corrupt_trie();
 
         let result = trie.get_proof(b"test2-key");
 
@@ -1275,11 +2719,31 @@ mod tests {
     }
 
     #[test]
-    fn test_trie_contains() {
+    fn test_trie_get_with() {
         let memdb = Arc::new(MemoryDB::new(true));
-        let mut trie = EthTrie::new(memdb);
+        let mut trie = EthTrie::new(memdb.clone());
         trie.insert(b"test", b"test").unwrap();
-        assert!(trie.contains(b"test").unwrap());
+        trie.insert(b"test1", &[7; 40]).unwrap();
+        let root = trie.root_hash().unwrap();
+
+        let trie = EthTrie::from(memdb, root).unwrap();
+        assert_eq!(
+            trie.get_with(b"test1", |value| value.len()).unwrap(),
+            Some(40)
+        );
+        assert_eq!(
+            trie.get_with(b"test", |value| value[0]).unwrap(),
+            Some(b't')
+        );
+        assert_eq!(trie.get_with(b"test2", |value| value.len()).unwrap(), None);
+    }
+
+    #[test]
+    fn test_trie_contains() {
+        let memdb = Arc::new(MemoryDB::new(true));
+        let mut trie = EthTrie::new(memdb);
+        trie.insert(b"test", b"test").unwrap();
+        assert!(trie.contains(b"test").unwrap());
         assert!(!trie.contains(b"test2").unwrap());
     }
 
@@ -1576,4 +3040,887 @@ mod tests {
         // Previous trie was not modified
         assert_eq!(empty_trie.get(b"pretty-long-key").unwrap(), None);
     }
+
+    pub(crate) fn random_trie(count: usize) -> (EthTrie<MemoryDB>, BTreeMap<Vec<u8>, Vec<u8>>) {
+        let memdb = Arc::new(MemoryDB::new(true));
+        let mut trie = EthTrie::new(memdb);
+        let mut kv = BTreeMap::new();
+
+        let mut rng = rand::thread_rng();
+        for _ in 0..count {
+            let key: Vec<u8> = (0..rng.gen_range(1..6))
+                .map(|_| rng.gen_range(0..4u8))
+                .collect();
+            let value: Vec<u8> = (0..rng.gen_range(2..40)).map(|_| rng.gen()).collect();
+            trie.insert(&key, &value).unwrap();
+            kv.insert(key, value);
+        }
+        trie.root_hash().unwrap();
+        (trie, kv)
+    }
+
+    #[test]
+    fn test_iterator_seek() {
+        let (trie, kv) = random_trie(200);
+
+        for _ in 0..50 {
+            let seek_key: Vec<u8> = (0..thread_rng().gen_range(0..6))
+                .map(|_| thread_rng().gen_range(0..4u8))
+                .collect();
+            let mut iter = trie.iter();
+            iter.seek(&seek_key).unwrap();
+
+            let found: Vec<(Vec<u8>, Vec<u8>)> = iter.map(|item| item.unwrap()).collect();
+            let expected: Vec<(Vec<u8>, Vec<u8>)> = kv
+                .range(seek_key.clone()..)
+                .map(|(k, v)| (k.clone(), v.clone()))
+                .collect();
+            assert_eq!(found, expected, "seek to {:?}", seek_key);
+        }
+    }
+
+    #[test]
+    fn test_trie_range() {
+        let (trie, kv) = random_trie(200);
+
+        let bound = |rng: &mut rand::rngs::ThreadRng| {
+            let key: Vec<u8> = (0..rng.gen_range(0..6))
+                .map(|_| rng.gen_range(0..4u8))
+                .collect();
+            match rng.gen_range(0..3) {
+                0 => Bound::Included(key),
+                1 => Bound::Excluded(key),
+                _ => Bound::Unbounded,
+            }
+        };
+
+        let mut rng = thread_rng();
+        for _ in 0..100 {
+            let bounds = (bound(&mut rng), bound(&mut rng));
+            let found: Vec<(Vec<u8>, Vec<u8>)> = trie
+                .range(bounds.clone())
+                .map(|item| item.unwrap())
+                .collect();
+
+            let expected: Vec<(Vec<u8>, Vec<u8>)> = kv
+                .iter()
+                .filter(|(k, _)| std::ops::RangeBounds::contains(&bounds, *k))
+                .map(|(k, v)| (k.clone(), v.clone()))
+                .collect();
+            assert_eq!(found, expected, "range {:?}", bounds);
+        }
+    }
+
+    #[test]
+    fn test_trie_range_uncommitted() {
+        let memdb = Arc::new(MemoryDB::new(true));
+        let mut trie = EthTrie::new(memdb);
+        for key in [b"do".as_ref(), b"dog", b"doge", b"horse", b"house"] {
+            trie.insert(key, key).unwrap();
+        }
+
+        let keys: Vec<Vec<u8>> = trie
+            .range(b"dog".to_vec()..b"horse".to_vec())
+            .map(|item| item.unwrap().0)
+            .collect();
+        assert_eq!(keys, vec![b"dog".to_vec(), b"doge".to_vec()]);
+
+        let keys: Vec<Vec<u8>> = trie
+            .range(b"doge".to_vec()..=b"horse".to_vec())
+            .map(|item| item.unwrap().0)
+            .collect();
+        assert_eq!(keys, vec![b"doge".to_vec(), b"horse".to_vec()]);
+    }
+
+    #[test]
+    fn test_trie_len() {
+        let memdb = Arc::new(MemoryDB::new(true));
+        let mut trie = EthTrie::new(memdb.clone());
+        assert_eq!(trie.len().unwrap(), 0);
+        assert!(trie.is_empty().unwrap());
+
+        trie.insert(b"test", b"test").unwrap();
+        trie.insert(b"test1", b"test").unwrap();
+        trie.insert(b"test2", b"test").unwrap();
+        trie.insert(b"test23", b"test").unwrap();
+        // Overwriting an existing key doesn't change the count
+        trie.insert(b"test", b"test-overwritten").unwrap();
+        assert_eq!(trie.len().unwrap(), 4);
+
+        trie.remove(b"test1").unwrap();
+        trie.remove(b"missing").unwrap();
+        // Removing the value of a branch without one doesn't change the count
+        trie.remove(b"test2").unwrap();
+        trie.remove(b"test2").unwrap();
+        assert_eq!(trie.len().unwrap(), 2);
+        assert!(!trie.is_empty().unwrap());
+
+        let root = trie.root_hash().unwrap();
+        let mut trie = EthTrie::from(memdb, root).unwrap();
+        assert_eq!(trie.len().unwrap(), 2);
+
+        trie.remove(b"test").unwrap();
+        trie.remove(b"test23").unwrap();
+        assert_eq!(trie.len().unwrap(), 0);
+        assert!(trie.is_empty().unwrap());
+    }
+
+    #[test]
+    fn test_trie_len_without_persisted_count() {
+        let (mut trie, kv) = random_trie(200);
+        let root = trie.root_hash().unwrap();
+
+        trie.db.remove(&leaf_count_key(&root)).unwrap();
+
+        let trie = EthTrie::from(trie.db.clone(), root).unwrap();
+        assert_eq!(trie.len().unwrap(), kv.len());
+    }
+
+    #[test]
+    fn test_stale_leaf_counts_removed() {
+        let count_keys = |memdb: &MemoryDB| {
+            let keys = memdb.keys().unwrap().into_iter();
+            keys.filter(|key| key.starts_with(LEAF_COUNT_KEY_PREFIX))
+                .count()
+        };
+        for (window, epochs) in [(None, None), (Some(3), None), (None, Some(3))] {
+            let memdb = Arc::new(MemoryDB::new(true));
+            let mut builder = EthTrie::builder(memdb.clone());
+            if let Some(window) = window {
+                builder = builder.retention_window(window);
+            }
+            if let Some(keep) = epochs {
+                builder = builder.epoch_pruning(keep);
+            }
+            let mut trie = builder.build().unwrap();
+            for round in 0..50u8 {
+                trie.insert(b"test", &[round; 40]).unwrap();
+                trie.insert(&[round], &[round; 40]).unwrap();
+                trie.root_hash().unwrap();
+                // The counts of the roots still readable, and no others
+                assert!(count_keys(&memdb) <= 4);
+            }
+            assert_eq!(trie.len().unwrap(), 51);
+        }
+    }
+
+    #[test]
+    fn test_remove_branch_value_restructures_trie() {
+        let memdb = Arc::new(MemoryDB::new(true));
+        let mut trie = EthTrie::new(memdb.clone());
+        trie.insert(b"test", b"test").unwrap();
+        trie.insert(b"test2", b"test").unwrap();
+        trie.insert(b"test23", b"test").unwrap();
+        trie.remove(b"test2").unwrap();
+
+        let mut expected = EthTrie::new(memdb);
+        expected.insert(b"test", b"test").unwrap();
+        expected.insert(b"test23", b"test").unwrap();
+        assert_eq!(trie.root_hash().unwrap(), expected.root_hash().unwrap());
+    }
+
+    #[test]
+    fn test_remove_branch_value_matches_fresh_trie() {
+        let memdb = Arc::new(MemoryDB::new(true));
+        let mut trie = EthTrie::new(memdb.clone());
+        trie.insert(b"do", b"verb").unwrap();
+        trie.insert(b"dog", b"puppy").unwrap();
+        trie.root_hash().unwrap();
+        trie.remove(b"do").unwrap();
+
+        let mut expected = EthTrie::new(memdb);
+        expected.insert(b"dog", b"puppy").unwrap();
+        assert_eq!(trie.root_hash().unwrap(), expected.root_hash().unwrap());
+    }
+
+    #[test]
+    fn test_remove_missing_key_keeps_stored_nodes() {
+        let memdb = Arc::new(MemoryDB::new(true));
+        let mut trie = EthTrie::new(memdb.clone());
+        trie.insert(b"test", b"test").unwrap();
+        trie.insert(b"test1", &[1; 40]).unwrap();
+        trie.insert(b"test2", &[2; 40]).unwrap();
+        let root = trie.root_hash().unwrap();
+
+        // The walk goes through stored nodes without finding anything to remove
+        let mut trie = EthTrie::from(memdb.clone(), root).unwrap();
+        assert!(!trie.remove(b"test3").unwrap());
+        assert_eq!(trie.root_hash().unwrap(), root);
+
+        let trie = EthTrie::from(memdb, root).unwrap();
+        assert_eq!(trie.get(b"test1").unwrap(), Some(vec![1; 40]));
+        assert_eq!(trie.get(b"test2").unwrap(), Some(vec![2; 40]));
+    }
+
+    #[test]
+    fn test_checkpoint_revert() {
+        let memdb = Arc::new(MemoryDB::new(true));
+        let mut trie = EthTrie::new(memdb.clone());
+        trie.insert(b"test", b"test").unwrap();
+        trie.insert(b"test1", b"test").unwrap();
+        let root = trie.root_hash().unwrap();
+
+        let mut trie = EthTrie::from(memdb, root).unwrap();
+        let outer = trie.checkpoint();
+        trie.insert(b"test2", b"test").unwrap();
+        trie.remove(b"test1").unwrap();
+
+        let inner = trie.checkpoint();
+        trie.insert(b"test23", b"test").unwrap();
+        trie.revert_to(inner);
+        assert_eq!(trie.get(b"test23").unwrap(), None);
+        assert_eq!(trie.get(b"test2").unwrap(), Some(b"test".to_vec()));
+        assert_eq!(trie.len().unwrap(), 2);
+
+        trie.revert_to(outer);
+        assert_eq!(trie.get(b"test1").unwrap(), Some(b"test".to_vec()));
+        assert_eq!(trie.get(b"test2").unwrap(), None);
+        assert_eq!(trie.len().unwrap(), 2);
+        assert_eq!(trie.root_hash().unwrap(), root);
+
+        // Reverted writes don't count towards `auto_flush`
+        let mut trie = EthTrie::builder(Arc::new(MemoryDB::new(true)))
+            .auto_flush(4)
+            .build()
+            .unwrap();
+        trie.insert(b"test", b"test").unwrap();
+        let checkpoint = trie.checkpoint();
+        trie.insert(b"test1", b"test").unwrap();
+        trie.insert(b"test2", b"test").unwrap();
+        trie.revert_to(checkpoint);
+        trie.insert(b"test3", b"test").unwrap();
+        trie.insert(b"test4", b"test").unwrap();
+        assert!(trie.is_dirty());
+        trie.insert(b"test5", b"test").unwrap();
+        assert!(!trie.is_dirty());
+    }
+
+    #[test]
+    fn test_checkpoint_discard() {
+        let memdb = Arc::new(MemoryDB::new(true));
+        let mut trie = EthTrie::new(memdb);
+        trie.insert(b"test", b"test").unwrap();
+
+        let outer = trie.checkpoint();
+        trie.insert(b"test1", b"test").unwrap();
+        let inner = trie.checkpoint();
+        trie.insert(b"test2", b"test").unwrap();
+
+        // Discarding keeps the changes, which are then covered by the outer checkpoint
+        trie.discard(inner);
+        assert_eq!(trie.get(b"test2").unwrap(), Some(b"test".to_vec()));
+
+        trie.revert_to(outer);
+        assert_eq!(trie.get(b"test1").unwrap(), None);
+        assert_eq!(trie.get(b"test2").unwrap(), None);
+        assert_eq!(trie.get(b"test").unwrap(), Some(b"test".to_vec()));
+    }
+
+    #[test]
+    #[should_panic(expected = "no longer exists")]
+    fn test_checkpoint_dropped_on_commit() {
+        let memdb = Arc::new(MemoryDB::new(true));
+        let mut trie = EthTrie::new(memdb);
+        let checkpoint = trie.checkpoint();
+        trie.insert(b"test", b"test").unwrap();
+        trie.root_hash().unwrap();
+        trie.revert_to(checkpoint);
+    }
+
+    #[test]
+    fn test_clear_pending() {
+        let (mut trie, kv) = random_trie(200);
+        let root = trie.root_hash;
+
+        trie.checkpoint();
+        for key in kv.keys().step_by(2) {
+            trie.remove(key).unwrap();
+        }
+        trie.insert(&[9, 9], b"test").unwrap();
+        trie.clear_pending();
+        assert!(!trie.is_dirty());
+        assert_eq!(trie.len().unwrap(), kv.len());
+        assert_eq!(trie.get(&[9, 9]).unwrap(), None);
+
+        // Nothing the discarded changes passed through is removed on the next commit
+        trie.insert(&[9, 9], b"test").unwrap();
+        assert_ne!(trie.root_hash().unwrap(), root);
+        for (key, value) in kv.iter() {
+            assert_eq!(trie.get(key).unwrap().as_ref(), Some(value));
+        }
+    }
+
+    #[test]
+    fn test_trie_across_threads() {
+        let (mut trie, kv) = random_trie(200);
+
+        // Readers borrow the trie from scoped threads
+        std::thread::scope(|scope| {
+            let readers: Vec<_> = (0..4)
+                .map(|_| scope.spawn(|| trie.iter().count()))
+                .collect();
+            for reader in readers {
+                assert_eq!(reader.join().unwrap(), kv.len());
+            }
+        });
+
+        trie.insert(&[9, 9], b"test").unwrap();
+        let db = trie.db.clone();
+        let root = std::thread::spawn(move || trie.root_hash().unwrap())
+            .join()
+            .unwrap();
+        let trie = EthTrie::from(db, root).unwrap();
+        assert_eq!(trie.get(&[9, 9]).unwrap(), Some(b"test".to_vec()));
+    }
+
+    #[test]
+    fn test_snapshot_reads_while_writing() {
+        let memdb = Arc::new(MemoryDB::new(true));
+        let mut trie = EthTrie::new(memdb);
+        for i in 0..100u8 {
+            trie.insert(&[i], &[i; 40]).unwrap();
+        }
+        let root = trie.root_hash().unwrap();
+        let snapshot = trie.snapshot();
+        assert_eq!(snapshot.root_hash(), root);
+
+        // Readers see the committed version while the writer changes the trie
+        std::thread::scope(|scope| {
+            let readers: Vec<_> = (0..4)
+                .map(|_| {
+                    let snapshot = snapshot.clone();
+                    scope.spawn(move || {
+                        for i in 0..100u8 {
+                            assert_eq!(snapshot.get(&[i]).unwrap(), Some(vec![i; 40]));
+                            assert!(!snapshot.get_proof(&[i]).unwrap().is_empty());
+                        }
+                    })
+                })
+                .collect();
+            for i in 0..100u8 {
+                trie.insert(&[i], &[0xff; 40]).unwrap();
+            }
+            for reader in readers {
+                reader.join().unwrap();
+            }
+        });
+        assert_eq!(trie.snapshot().get(&[7]).unwrap(), Some(vec![7; 40]));
+
+        let root = trie.root_hash().unwrap();
+        let snapshot = trie.snapshot();
+        assert_eq!(snapshot.root_hash(), root);
+        assert_eq!(snapshot.get(&[7]).unwrap(), Some(vec![0xff; 40]));
+    }
+
+    #[test]
+    fn test_committed_nodes_shared_across_threads() {
+        let (mut trie, kv) = random_trie(300);
+        trie.root_hash().unwrap();
+        let snapshot = trie.snapshot();
+
+        // Snapshots share the committed nodes, which the writer keeps reading from
+        std::thread::scope(|scope| {
+            let readers: Vec<_> = (0..4)
+                .map(|_| {
+                    let snapshot = snapshot.clone();
+                    let kv = &kv;
+                    scope.spawn(move || {
+                        for _ in 0..5 {
+                            for (key, value) in kv.iter() {
+                                assert_eq!(snapshot.get(key).unwrap().as_ref(), Some(value));
+                                snapshot.get_proof(key).unwrap();
+                            }
+                            assert_eq!(snapshot.iter().count(), kv.len());
+                        }
+                    })
+                })
+                .collect();
+            for (i, key) in kv.keys().enumerate() {
+                match i % 2 {
+                    0 => trie.insert(key, b"changed").unwrap(),
+                    _ => assert!(trie.remove(key).unwrap()),
+                }
+                if i % 50 == 0 {
+                    assert_eq!(trie.pending_changes().count(), i + 1);
+                    trie.fork();
+                }
+            }
+            for reader in readers {
+                reader.join().unwrap();
+            }
+        });
+        assert_eq!(snapshot.iter().count(), kv.len());
+    }
+
+    #[test]
+    fn test_node_lock_not_poisoned_by_panic() {
+        let memdb = Arc::new(MemoryDB::new(true));
+        let mut trie = EthTrie::new(memdb);
+        for i in 0..16u8 {
+            trie.insert(&[i << 4], &[i; 40]).unwrap();
+        }
+        trie.root_hash().unwrap();
+
+        let Node::Branch(root) = trie.root.clone() else {
+            panic!("the root is a branch");
+        };
+        let panicked = std::thread::spawn(move || {
+            let _guard = root.write();
+            panic!("panicking while holding the lock of the root");
+        })
+        .join();
+        assert!(panicked.is_err());
+
+        // The trie stays usable through the lock held when the thread panicked
+        for i in 0..16u8 {
+            assert_eq!(trie.get(&[i << 4]).unwrap(), Some(vec![i; 40]));
+        }
+        trie.insert(b"after", b"panic").unwrap();
+        trie.root_hash().unwrap();
+    }
+
+    #[test]
+    fn test_trie_into_iter() {
+        let (mut trie, kv) = random_trie(200);
+        let expected: Vec<_> = kv.into_iter().collect();
+
+        let mut borrowed = vec![];
+        for item in &trie {
+            borrowed.push(item.unwrap());
+        }
+        assert_eq!(borrowed, expected);
+
+        // Uncommitted changes are part of the owned iteration
+        trie.insert(&[9, 9], b"test").unwrap();
+        let owned: Vec<_> = trie.into_iter().map(|item| item.unwrap()).collect();
+        assert_eq!(owned.len(), expected.len() + 1);
+        assert_eq!(owned.last().unwrap(), &(vec![9, 9], b"test".to_vec()));
+    }
+
+    #[test]
+    fn test_trie_from_iter_and_extend() {
+        let (mut trie, entries) = random_trie(200);
+        let expected = trie.root_hash().unwrap();
+
+        let memdb = Arc::new(MemoryDB::new(true));
+        let mut from_iter = EthTrie::from_iter(memdb.clone(), entries.iter()).unwrap();
+        assert_eq!(from_iter.root_hash().unwrap(), expected);
+        assert_eq!(from_iter.len().unwrap(), entries.len());
+
+        let mut extended = EthTrie::new(memdb);
+        extended.extend(entries.into_iter().rev());
+        assert_eq!(extended.root_hash().unwrap(), expected);
+    }
+
+    #[test]
+    fn test_pending_changes() {
+        let memdb = Arc::new(MemoryDB::new(true));
+        let mut trie = EthTrie::new(memdb);
+        assert!(!trie.is_dirty());
+        trie.insert(b"test", b"test").unwrap();
+        trie.insert(b"test1", &[1; 40]).unwrap();
+        assert!(trie.is_dirty());
+        assert_eq!(trie.pending_node_count(), 3);
+        trie.root_hash().unwrap();
+        assert!(!trie.is_dirty());
+        assert_eq!(trie.pending_node_count(), 0);
+        assert_eq!(trie.pending_changes().count(), 0);
+
+        trie.insert(b"test", b"changed").unwrap();
+        trie.insert(b"test2", b"test2").unwrap();
+        trie.remove(b"test1").unwrap();
+        let changes: Vec<_> = trie.pending_changes().map(|c| c.unwrap()).collect();
+        assert_eq!(
+            changes,
+            vec![
+                (
+                    b"test".to_vec(),
+                    Some(b"test".to_vec()),
+                    Some(b"changed".to_vec())
+                ),
+                (b"test1".to_vec(), Some(vec![1; 40]), None),
+                (b"test2".to_vec(), None, Some(b"test2".to_vec())),
+            ]
+        );
+
+        // Undoing the changes leaves nothing to commit
+        trie.insert(b"test", b"test").unwrap();
+        trie.insert(b"test1", &[1; 40]).unwrap();
+        trie.remove(b"test2").unwrap();
+        assert!(!trie.is_dirty());
+        assert_eq!(trie.pending_changes().count(), 0);
+    }
+
+    #[test]
+    fn test_fork() {
+        let memdb = Arc::new(MemoryDB::new(false));
+        let mut trie = EthTrie::new(memdb);
+        trie.insert(b"test", b"test").unwrap();
+        trie.insert(b"test1", b"test").unwrap();
+        let root = trie.root_hash().unwrap();
+
+        // Uncommitted changes are left behind
+        trie.insert(b"test2", b"test").unwrap();
+        let mut fork = trie.fork();
+        assert_eq!(fork.get(b"test2").unwrap(), None);
+        assert_eq!(fork.len().unwrap(), 2);
+
+        fork.insert(b"test1", b"forked").unwrap();
+        fork.insert(b"test3", b"forked").unwrap();
+        assert_eq!(trie.get(b"test1").unwrap(), Some(b"test".to_vec()));
+        assert_eq!(trie.get(b"test3").unwrap(), None);
+
+        let trie_root = trie.root_hash().unwrap();
+        let fork_root = fork.root_hash().unwrap();
+        assert_ne!(trie_root, fork_root);
+        assert_ne!(fork_root, root);
+
+        // Both versions stay readable from the shared database
+        let trie = EthTrie::from(trie.db.clone(), trie_root).unwrap();
+        assert_eq!(trie.get(b"test2").unwrap(), Some(b"test".to_vec()));
+        assert_eq!(fork.get(b"test1").unwrap(), Some(b"forked".to_vec()));
+        assert_eq!(fork.get(b"test2").unwrap(), None);
+    }
+
+    #[test]
+    fn test_get_node() {
+        let memdb = Arc::new(MemoryDB::new(true));
+        let mut trie = EthTrie::new(memdb);
+        for i in 0..50u8 {
+            trie.insert(&[i, i], &[i; 40]).unwrap();
+        }
+        let root = trie.root_hash().unwrap();
+
+        let root_node = trie.get_node(root).unwrap().unwrap();
+        assert_eq!(NodeCodec::hash(&root_node), root);
+        assert_eq!(
+            NodeCodec::encode(&root_node),
+            NodeCodec::encode(&trie.root_node())
+        );
+        for (_, hash) in root_node.child_hashes() {
+            let child = trie.get_node(hash).unwrap().unwrap();
+            assert_eq!(NodeCodec::hash(&child), hash);
+        }
+        assert!(trie.get_node(B256::repeat_byte(1)).unwrap().is_none());
+    }
+
+    #[test]
+    fn test_decode_node_depth_limit() {
+        // Extensions nested inline far deeper than any real trie
+        fn nested(depth: usize) -> Vec<u8> {
+            let leaf = Node::from_leaf(Nibbles::from_hex(&[1, 16]), vec![1, 2]);
+            let mut encoded = NodeCodec::encode(&leaf);
+            for _ in 0..depth {
+                let mut payload = vec![0x11];
+                payload.extend_from_slice(&encoded);
+                let mut node = vec![];
+                Header {
+                    list: true,
+                    payload_length: payload.len(),
+                }
+                .encode(&mut node);
+                node.extend_from_slice(&payload);
+                encoded = node;
+            }
+            encoded
+        }
+
+        let node = decode_node_with_max_depth(&mut nested(3).as_slice(), 3).unwrap();
+        assert_eq!(node.prefix(), Some(Nibbles::from_hex(&[1])));
+        assert_eq!(
+            decode_node_with_max_depth(&mut nested(4).as_slice(), 3).unwrap_err(),
+            TrieError::DepthLimitExceeded { max_depth: 3 }
+        );
+        // Deep nesting is cut off without recursing, here by the inline size rule since
+        // the nested nodes outgrow it long before the depth limit
+        assert!(matches!(
+            decode_node(&mut nested(10_000).as_slice()).unwrap_err(),
+            TrieError::InvalidNode(NodeDecodeError {
+                kind: NodeDecodeErrorKind::InlineTooLarge { .. },
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn test_decode_node_single_byte_values() {
+        for value in [vec![0x05], vec![0x80], vec![0xff], vec![]] {
+            let mut children = empty_children();
+            children[3] = Node::from_leaf(Nibbles::from_hex(&[1, 16]), value.clone());
+            let branch = Node::from_branch(children, Some(value.clone()));
+            let encoded = NodeCodec::encode(&branch);
+
+            let mut data = encoded.as_slice();
+            let decoded = decode_node(&mut data).unwrap();
+            assert!(data.is_empty());
+            assert_eq!(NodeCodec::encode(&decoded), encoded);
+            assert_eq!(decoded.children()[0].1.value(), Some(value));
+        }
+    }
+
+    #[test]
+    fn test_decode_node_strict() {
+        let (trie, _) = random_trie(100);
+        for encoded in trie.export().unwrap().nodes {
+            let node = decode_node_strict(&encoded).unwrap();
+            assert_eq!(NodeCodec::encode(&node), encoded.to_vec());
+
+            let mut trailing = encoded.to_vec();
+            trailing.push(0x80);
+            assert!(decode_node_strict(&trailing).is_err());
+            assert!(decode_node(&mut trailing.as_slice()).is_ok());
+        }
+
+        // A long string header for a short string
+        assert!(decode_node_strict(&[0xc5, 0x82, 0x20, 0x01, 0xb8, 0x01, 0x02]).is_err());
+        // A key flag with a non-zero padding nibble
+        assert!(decode_node_strict(&[0xc4, 0x82, 0x25, 0x01, 0x02]).is_err());
+        assert!(decode_node_strict(&[0xc4, 0x82, 0x20, 0x01, 0x02]).is_ok());
+    }
+
+    #[test]
+    fn test_decode_node_inline_size() {
+        // A child of 32 bytes or more inlined instead of hashed
+        let leaf = Node::from_leaf(Nibbles::from_hex(&[1, 16]), vec![0xaa; 40]);
+        let inline = NodeCodec::encode(&leaf);
+        let mut payload = vec![0x11];
+        payload.extend_from_slice(&inline);
+        let mut encoded = vec![];
+        Header {
+            list: true,
+            payload_length: payload.len(),
+        }
+        .encode(&mut encoded);
+        encoded.extend_from_slice(&payload);
+        assert_eq!(
+            decode_node(&mut encoded.as_slice()).unwrap_err(),
+            TrieError::InvalidNode(NodeDecodeError {
+                kind: NodeDecodeErrorKind::InlineTooLarge { len: inline.len() },
+                offset: 2,
+            })
+        );
+
+        // A string child that is neither empty nor a hash
+        let mut children = vec![0x80; 17];
+        children[4] = 0x05;
+        let mut encoded = vec![0xd1];
+        encoded.extend_from_slice(&children);
+        assert_eq!(
+            decode_node(&mut encoded.as_slice()).unwrap_err(),
+            TrieError::InvalidNode(NodeDecodeError {
+                kind: NodeDecodeErrorKind::ChildSlot { slot: Some(4) },
+                offset: 5,
+            })
+        );
+        encoded[5] = 0x80;
+        assert!(decode_node(&mut encoded.as_slice()).is_ok());
+    }
+
+    #[test]
+    fn test_decode_node_errors() {
+        let error = |data: &[u8]| match decode_node(&mut &data[..]) {
+            Err(TrieError::InvalidNode(err)) => (err.kind, err.offset),
+            other => panic!("unexpected result {:?}", other),
+        };
+
+        assert_eq!(
+            error(&[0xc3, 0x80, 0x80, 0x80]),
+            (NodeDecodeErrorKind::ListArity { items: 3 }, 0)
+        );
+        assert_eq!(
+            error(&[0xc4, 0x82, 0x45, 0x01, 0x02]),
+            (NodeDecodeErrorKind::CompactPrefix, 1)
+        );
+        assert_eq!(
+            error(&[0xc3, 0x20, 0xc1, 0x80]),
+            (NodeDecodeErrorKind::Value, 2)
+        );
+        assert_eq!(
+            error(&[0x83, 0x01, 0x02, 0x03]),
+            (NodeDecodeErrorKind::ChildSlot { slot: None }, 0)
+        );
+
+        // The offset points into the inline child, below an extension
+        assert_eq!(
+            error(&[0xc6, 0x11, 0xc4, 0x82, 0x25, 0x01, 0x02]),
+            (NodeDecodeErrorKind::CompactPrefix, 3)
+        );
+
+        // Invalid RLP headers, of the node and of its items
+        let too_short = NodeDecodeErrorKind::Rlp(alloy_rlp::Error::InputTooShort);
+        assert_eq!(error(&[0xc5, 0x80]), (too_short, 0));
+        assert_eq!(error(&[0xc3, 0x20, 0x83, 0x01]), (too_short, 2));
+        assert_eq!(
+            error(&[0xc5, 0x11, 0xc3, 0x20, 0x81, 0x01]),
+            (
+                NodeDecodeErrorKind::Rlp(alloy_rlp::Error::NonCanonicalSingleByte),
+                4
+            )
+        );
+        assert_eq!(
+            decode_node_strict(&[0xc4, 0x20, 0x82, 0x01, 0x02, 0x80]).unwrap_err(),
+            TrieError::InvalidNode(NodeDecodeError {
+                kind: NodeDecodeErrorKind::TrailingBytes,
+                offset: 5,
+            })
+        );
+    }
+
+    #[test]
+    fn test_clear_trie_from_db_shared_nodes() {
+        let memdb = Arc::new(MemoryDB::new(true));
+        let mut trie = EthTrie::new(memdb.clone());
+        // Identical subtrees under different keys are stored once
+        let value = [0xaa; 40];
+        for prefix in [0x10u8, 0x20] {
+            for i in 0..4u8 {
+                trie.insert(&[prefix, i], &value).unwrap();
+            }
+        }
+        trie.root_hash().unwrap();
+        assert!(memdb.len().unwrap() > 0);
+
+        trie.clear_trie_from_db().unwrap();
+        assert_eq!(memdb.len().unwrap(), 0);
+        assert_eq!(trie.get(&[0x10, 0]).unwrap(), None);
+
+        // A branch whose child refers back to the branch itself
+        let memdb = Arc::new(MemoryDB::new(true));
+        let root = B256::repeat_byte(7);
+        let mut children = empty_children();
+        children[0] = Node::from_hash(root);
+        let branch = NodeCodec::encode(&Node::from_branch(children, None));
+        memdb.insert(root.as_slice(), branch).unwrap();
+        let mut trie = EthTrie {
+            root_hash: root,
+            ..EthTrie::new(memdb.clone())
+        };
+        trie.clear_trie_from_db().unwrap();
+        assert_eq!(memdb.len().unwrap(), 0);
+    }
+
+    #[test]
+    fn test_remove_checked() {
+        let memdb = Arc::new(MemoryDB::new(true));
+        let mut trie = EthTrie::new(memdb);
+        trie.insert(b"dog", b"puppy").unwrap();
+        trie.insert(b"do\x10", b"other").unwrap();
+        let root = trie.root_hash().unwrap();
+
+        // "do" ends at the branch holding both keys, which has no value of its own
+        assert_eq!(trie.remove_checked(b"do").unwrap(), RemoveOutcome::NotFound);
+        assert!(!trie.remove(b"do").unwrap());
+        assert_eq!(trie.root_hash().unwrap(), root);
+
+        trie.insert(b"do", b"verb").unwrap();
+        assert_eq!(
+            trie.remove_checked(b"do").unwrap(),
+            RemoveOutcome::BranchValueCleared
+        );
+        assert_eq!(trie.remove_checked(b"dog").unwrap(), RemoveOutcome::Removed);
+        assert_eq!(
+            trie.remove_checked(b"dog").unwrap(),
+            RemoveOutcome::NotFound
+        );
+        assert_eq!(trie.get(b"do\x10").unwrap(), Some(b"other".to_vec()));
+        assert_eq!(trie.len().unwrap(), 1);
+    }
+
+    #[test]
+    fn test_trie_eq() {
+        let memdb = Arc::new(MemoryDB::new(false));
+        let mut a = EthTrie::new(memdb.clone());
+        let mut b = EthTrie::new(memdb.clone());
+        assert_eq!(a, b);
+
+        a.insert(b"dog", b"puppy").unwrap();
+        a.insert(b"horse", b"stallion").unwrap();
+        b.insert(b"horse", b"stallion").unwrap();
+        assert_ne!(a, b);
+        b.insert(b"dog", b"puppy").unwrap();
+        assert_eq!(a, b);
+
+        // Committed and uncommitted tries with the same entries are equal
+        let root = a.root_hash().unwrap();
+        assert_eq!(a, b);
+        assert_eq!(a, EthTrie::from(memdb, root).unwrap());
+        a.assert_equal(&b);
+    }
+
+    #[test]
+    #[should_panic(
+        expected = "tries differ at key 0x646f67: left Some(\"7075707079\"), right None"
+    )]
+    fn test_assert_equal_reports_key() {
+        let memdb = Arc::new(MemoryDB::new(false));
+        let mut a = EthTrie::new(memdb.clone());
+        let mut b = EthTrie::new(memdb);
+        a.insert(b"dog", b"puppy").unwrap();
+        a.insert(b"horse", b"stallion").unwrap();
+        b.insert(b"horse", b"stallion").unwrap();
+        b.root_hash().unwrap();
+        a.assert_equal(&b);
+    }
+
+    #[test]
+    fn test_root_with_trie_diff_stale_nodes() {
+        let memdb = Arc::new(MemoryDB::new(false));
+        let mut trie = EthTrie::new(memdb.clone());
+        for i in 0..200u8 {
+            trie.insert(&[i], &[i; 40]).unwrap();
+        }
+        let previous = trie.root_hash().unwrap();
+
+        for i in 0..200u8 {
+            if i % 17 == 0 {
+                trie.remove(&[i]).unwrap();
+            } else if i % 5 == 0 {
+                trie.insert(&[i], &[0xff; 40]).unwrap();
+            }
+        }
+        let diff = trie.root_hash_with_changed_nodes().unwrap();
+        assert_eq!(diff.previous_root, previous);
+
+        // Exactly the nodes of the previous trie the new one lacks
+        let expected: hashbrown::HashSet<B256> =
+            crate::diff::node_diff(&memdb, diff.root, previous)
+                .map(|change| change.unwrap().1)
+                .collect();
+        assert_eq!(diff.stale_nodes, expected);
+        assert!(diff.trie_diff.keys().all(|hash| !expected.contains(hash)));
+    }
+
+    #[test]
+    fn test_root_hash_with_key_changes() {
+        let memdb = Arc::new(MemoryDB::new(true));
+        let mut trie = EthTrie::new(memdb);
+        trie.insert(b"dog", b"puppy").unwrap();
+        trie.insert(b"horse", b"stallion").unwrap();
+        trie.root_hash().unwrap();
+
+        trie.insert(b"dog", b"hound").unwrap();
+        trie.insert(b"cat", b"kitten").unwrap();
+        trie.remove(b"horse").unwrap();
+        trie.insert(b"fox", b"kit").unwrap();
+        trie.remove(b"fox").unwrap();
+        let committed = trie.root_hash_with_key_changes().unwrap();
+        assert_eq!(committed.root, trie.root_hash().unwrap());
+        assert_eq!(
+            committed.changes,
+            vec![
+                (b"cat".to_vec(), None, Some(b"kitten".to_vec())),
+                (
+                    b"dog".to_vec(),
+                    Some(b"puppy".to_vec()),
+                    Some(b"hound".to_vec())
+                ),
+                (b"horse".to_vec(), Some(b"stallion".to_vec()), None),
+            ]
+        );
+        assert!(trie
+            .root_hash_with_key_changes()
+            .unwrap()
+            .changes
+            .is_empty());
+    }
 }
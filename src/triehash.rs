@@ -0,0 +1,104 @@
+//! Root computation matching the free functions of the `triehash` crate, for code that only
+//! needs the root of a set of entries. Unlike `triehash`, the hash is always keccak, and
+//! entries with an empty value are left out, as they are by `EthTrie`.
+
+use std::sync::Arc;
+
+use alloy_primitives::B256;
+use keccak_hash::keccak;
+
+use crate::db::MemoryDB;
+use crate::trie::{EthTrie, TrieWrite};
+
+/// Returns the root of a trie holding the given entries. Later entries for a key replace
+/// earlier ones.
+///
+/// ```
+/// use eth_trie::trie_root;
+///
+/// let root = trie_root(vec![("doe", "reindeer"), ("dog", "puppy"), ("dogglesworth", "cat")]);
+/// assert_eq!(
+///     format!("{:x}", root),
+///     "8aad789dff2f538bca5d8ea56e8abe10f4c7ba3a5dea95fea4cd6e7c3a1168d3"
+/// );
+/// ```
+pub fn trie_root<I, A, B>(input: I) -> B256
+where
+    I: IntoIterator<Item = (A, B)>,
+    A: AsRef<[u8]> + Ord,
+    B: AsRef<[u8]>,
+{
+    let mut trie =
+        EthTrie::from_iter(Arc::new(MemoryDB::new(true)), input).expect("MemoryDB never fails");
+    trie.root_hash().expect("MemoryDB never fails")
+}
+
+/// Returns the root of a secure trie holding the given entries, where each key is stored
+/// under its keccak hash, as accounts and storage slots are.
+pub fn sec_trie_root<I, A, B>(input: I) -> B256
+where
+    I: IntoIterator<Item = (A, B)>,
+    A: AsRef<[u8]>,
+    B: AsRef<[u8]>,
+{
+    trie_root(input.into_iter().map(|(key, value)| (keccak(key).0, value)))
+}
+
+/// Returns the root of a trie holding the given values keyed by the RLP encoding of their
+/// index, as transactions, receipts and withdrawals are.
+pub fn ordered_trie_root<I>(input: I) -> B256
+where
+    I: IntoIterator,
+    I::Item: AsRef<[u8]>,
+{
+    trie_root(
+        input
+            .into_iter()
+            .enumerate()
+            .map(|(i, value)| (alloy_rlp::encode(i), value)),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use keccak_hash::{keccak, KECCAK_NULL_RLP};
+
+    use super::{ordered_trie_root, sec_trie_root, trie_root};
+    use crate::db::MemoryDB;
+    use crate::trie::{EthTrie, TrieWrite};
+
+    #[test]
+    fn test_triehash_roots() {
+        let empty = trie_root(Vec::<(Vec<u8>, Vec<u8>)>::new());
+        assert_eq!(empty.as_slice(), KECCAK_NULL_RLP.as_bytes());
+        assert_eq!(ordered_trie_root(Vec::<Vec<u8>>::new()), empty);
+
+        let entries = vec![
+            (b"foo".to_vec(), b"bar".to_vec()),
+            (b"food".to_vec(), b"bass".to_vec()),
+        ];
+        assert_eq!(
+            format!("{:x}", trie_root(entries.clone())),
+            "17beaa1648bafa633cda809c90c04af50fc8aed3cb40d16efbddee6fdf63c4c3"
+        );
+        // The last entry for a key wins
+        let mut repeated = entries.clone();
+        repeated.insert(0, (b"foo".to_vec(), b"baz".to_vec()));
+        assert_eq!(trie_root(repeated), trie_root(entries.clone()));
+
+        let hashed: Vec<_> = entries
+            .iter()
+            .map(|(k, v)| (keccak(k).0, v.clone()))
+            .collect();
+        assert_eq!(sec_trie_root(entries), trie_root(hashed));
+
+        let values: Vec<Vec<u8>> = (0..200u8).map(|i| vec![i; 40]).collect();
+        let mut trie = EthTrie::new(Arc::new(MemoryDB::new(true)));
+        for (i, value) in values.iter().enumerate() {
+            trie.insert(&alloy_rlp::encode(i), value).unwrap();
+        }
+        assert_eq!(ordered_trie_root(&values), trie.root_hash().unwrap());
+    }
+}
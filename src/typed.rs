@@ -0,0 +1,246 @@
+use std::marker::PhantomData;
+
+use alloy_primitives::B256;
+use alloy_rlp::{Decodable, Encodable};
+
+use crate::db::DB;
+use crate::key::TrieKey;
+use crate::trie::{EthTrie, TrieIterator, TrieRead, TrieResult, TrieWrite};
+use crate::view::TrieView;
+
+/// Wraps an `EthTrie` whose values are all of type `V`, RLP encoding them on insert and
+/// decoding them on read. Keys are mapped to their trie bytes through `TrieKey`.
+#[derive(Debug)]
+pub struct TypedTrie<D, V>
+where
+    D: DB,
+{
+    trie: EthTrie<D>,
+    // Values are only held encoded, so the trie is `Send` and `Sync` whatever `V` is
+    _value: PhantomData<fn() -> V>,
+}
+
+impl<D, V> TypedTrie<D, V>
+where
+    D: DB,
+    V: Encodable + Decodable,
+{
+    pub fn new(trie: EthTrie<D>) -> Self {
+        Self {
+            trie,
+            _value: PhantomData,
+        }
+    }
+
+    /// Returns the decoded value for key, or None if the key is not present.
+    pub fn get<K: TrieKey + ?Sized>(&self, key: &K) -> TrieResult<Option<V>> {
+        let value = self
+            .trie
+            .get_with(&key.trie_key(), |value| alloy_rlp::decode_exact(value))?;
+        Ok(value.transpose()?)
+    }
+
+    pub fn contains<K: TrieKey + ?Sized>(&self, key: &K) -> TrieResult<bool> {
+        self.trie.contains(&key.trie_key())
+    }
+
+    /// Inserts the encoded value, replacing any existing value for key.
+    pub fn insert<K: TrieKey + ?Sized>(&mut self, key: &K, value: &V) -> TrieResult<()> {
+        self.trie.insert(&key.trie_key(), &alloy_rlp::encode(value))
+    }
+
+    pub fn remove<K: TrieKey + ?Sized>(&mut self, key: &K) -> TrieResult<bool> {
+        self.trie.remove(&key.trie_key())
+    }
+
+    pub fn root_hash(&mut self) -> TrieResult<B256> {
+        self.trie.root_hash()
+    }
+
+    /// Iterates over the entries in key order, decoding each value.
+    pub fn iter(&self) -> DecodedIterator<'_, D, V> {
+        self.trie.iter_decoded()
+    }
+
+    pub fn inner(&self) -> &EthTrie<D> {
+        &self.trie
+    }
+
+    pub fn inner_mut(&mut self) -> &mut EthTrie<D> {
+        &mut self.trie
+    }
+
+    pub fn into_inner(self) -> EthTrie<D> {
+        self.trie
+    }
+}
+
+/// Iterates over the entries of a trie in key order, RLP decoding each value as a `V`.
+/// A value that fails to decode is returned as `TrieError::Decoder`, and iteration
+/// continues with the next entry. Created by `EthTrie::iter_decoded` and
+/// `TrieView::iter_decoded`.
+pub struct DecodedIterator<'a, D, V>
+where
+    D: DB,
+{
+    inner: TrieIterator<'a, D>,
+    _value: PhantomData<fn() -> V>,
+}
+
+impl<'a, D, V> DecodedIterator<'a, D, V>
+where
+    D: DB,
+{
+    /// Repositions the iterator so that the next item returned is the first entry
+    /// whose key is greater than or equal to `key`.
+    pub fn seek(&mut self, key: &[u8]) -> TrieResult<()> {
+        self.inner.seek(key)
+    }
+}
+
+impl<D, V> Iterator for DecodedIterator<'_, D, V>
+where
+    D: DB,
+    V: Decodable,
+{
+    type Item = TrieResult<(Vec<u8>, V)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let item = self.inner.next()?;
+        Some(item.and_then(|(key, encoded)| Ok((key, alloy_rlp::decode_exact(encoded)?))))
+    }
+}
+
+impl<D> EthTrie<D>
+where
+    D: DB,
+{
+    /// Returns an iterator over the entries of the trie, including uncommitted changes,
+    /// with each value decoded as a `V`.
+    pub fn iter_decoded<V: Decodable>(&self) -> DecodedIterator<'_, D, V> {
+        DecodedIterator {
+            inner: self.iter(),
+            _value: PhantomData,
+        }
+    }
+}
+
+impl<D> TrieView<D>
+where
+    D: DB,
+{
+    /// Returns an iterator over the entries of the trie with each value decoded as a `V`.
+    pub fn iter_decoded<V: Decodable>(&self) -> DecodedIterator<'_, D, V> {
+        DecodedIterator {
+            inner: self.iter(),
+            _value: PhantomData,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use alloy_primitives::{B256, U256};
+    use alloy_rlp::{RlpDecodable, RlpEncodable};
+
+    use super::TypedTrie;
+    use crate::db::MemoryDB;
+    use crate::errors::TrieError;
+    use crate::trie::{EthTrie, TrieWrite};
+    use crate::view::TrieView;
+
+    #[derive(Debug, Clone, PartialEq, Eq, RlpEncodable, RlpDecodable)]
+    struct Account {
+        nonce: u64,
+        balance: U256,
+        storage_root: B256,
+        code_hash: B256,
+    }
+
+    #[test]
+    fn test_typed_trie() {
+        let memdb = Arc::new(MemoryDB::new(true));
+        let mut trie = TypedTrie::new(EthTrie::new(memdb));
+        let account = Account {
+            nonce: 1,
+            balance: U256::from(1000),
+            storage_root: B256::repeat_byte(1),
+            code_hash: B256::repeat_byte(2),
+        };
+        trie.insert(b"alice", &account).unwrap();
+        trie.insert(
+            b"bob",
+            &Account {
+                nonce: 2,
+                ..account.clone()
+            },
+        )
+        .unwrap();
+
+        assert_eq!(trie.get(b"alice").unwrap(), Some(account.clone()));
+        assert_eq!(trie.get(b"carol").unwrap(), None);
+        assert_eq!(trie.iter().count(), 2);
+
+        assert!(trie.remove(b"bob").unwrap());
+        let root = trie.root_hash().unwrap();
+
+        let mut plain = EthTrie::new(Arc::new(MemoryDB::new(true)));
+        plain
+            .insert(b"alice", &alloy_rlp::encode(&account))
+            .unwrap();
+        assert_eq!(plain.root_hash().unwrap(), root);
+    }
+
+    #[test]
+    fn test_typed_trie_index_keys() {
+        let memdb = Arc::new(MemoryDB::new(true));
+        let mut trie = TypedTrie::new(EthTrie::new(memdb));
+        for i in 0..200u64 {
+            trie.insert(&i, &U256::from(i)).unwrap();
+        }
+        assert_eq!(trie.get(&128u64).unwrap(), Some(U256::from(128)));
+        assert_eq!(trie.get(&[0x81, 0x80]).unwrap(), Some(U256::from(128)));
+        assert!(!trie.contains(&200u64).unwrap());
+    }
+
+    #[test]
+    fn test_typed_trie_invalid_value() {
+        let memdb = Arc::new(MemoryDB::new(true));
+        let mut trie = EthTrie::new(memdb);
+        trie.insert(b"key", b"not an account").unwrap();
+
+        let trie = TypedTrie::<_, Account>::new(trie);
+        assert!(matches!(trie.get(b"key"), Err(TrieError::Decoder(_))));
+    }
+
+    #[test]
+    fn test_iter_decoded() {
+        let memdb = Arc::new(MemoryDB::new(true));
+        let mut trie = EthTrie::new(memdb);
+        for i in 0..5u64 {
+            trie.insert(&[i as u8], &alloy_rlp::encode(U256::from(i)))
+                .unwrap();
+        }
+        trie.insert(&[2, 0], b"not rlp").unwrap();
+
+        let items: Vec<_> = trie.iter_decoded::<U256>().collect();
+        assert_eq!(items.len(), 6);
+        assert!(matches!(items[3], Err(TrieError::Decoder(_))));
+        assert_eq!(
+            items
+                .into_iter()
+                .filter_map(Result::ok)
+                .map(|(_, value)| value)
+                .collect::<Vec<_>>(),
+            (0..5).map(U256::from).collect::<Vec<_>>()
+        );
+
+        let root = trie.root_hash().unwrap();
+        let view = TrieView::new(trie.db.clone(), root).unwrap();
+        let mut iter = view.iter_decoded::<U256>();
+        iter.seek(&[3]).unwrap();
+        assert_eq!(iter.next().unwrap().unwrap(), (vec![3], U256::from(3)));
+    }
+}
@@ -0,0 +1,164 @@
+use std::ops::Deref;
+use std::sync::Arc;
+
+use alloy_primitives::B256;
+
+use crate::db::DB;
+use crate::root_manager::RootManager;
+use crate::trie::{EthTrie, TrieResult};
+use crate::view::TrieView;
+
+/// A trie with read access to its past versions.
+///
+/// Commits go through the head trie, which records every root it commits in the
+/// `RootManager` of the database. Views can be opened at any of those roots whose nodes
+/// are still stored, and while a view is open, no commit removes the nodes it reads:
+/// opening a view protects every node reachable from its root, the same way pinning
+/// does, until the view is dropped.
+///
+/// Only the stale node removal of commits made through tries attached to the same
+/// manager is held back; a `Pruner` run on the database must be given the roots of the
+/// open views to retain.
+#[derive(Debug)]
+pub struct VersionedTrie<D>
+where
+    D: DB,
+{
+    manager: RootManager<D>,
+    head: EthTrie<D>,
+}
+
+impl<D> VersionedTrie<D>
+where
+    D: DB,
+{
+    /// Opens the trie stored in `db`, with its head at the last root it recorded.
+    pub fn open(db: Arc<D>) -> TrieResult<Self> {
+        let manager = RootManager::open(db.clone())?;
+        let mut builder = EthTrie::builder(db).root_manager(&manager);
+        if let Some(root) = manager.last_root() {
+            builder = builder.root(root);
+        }
+        Ok(Self {
+            head: builder.build()?,
+            manager,
+        })
+    }
+
+    pub fn head(&self) -> &EthTrie<D> {
+        &self.head
+    }
+
+    /// Returns the head trie, to write to and commit.
+    pub fn head_mut(&mut self) -> &mut EthTrie<D> {
+        &mut self.head
+    }
+
+    pub fn manager(&self) -> &RootManager<D> {
+        &self.manager
+    }
+
+    /// Returns the committed roots, oldest first. Views can be opened at those whose
+    /// nodes haven't been removed since, which includes the pinned ones.
+    pub fn roots(&self) -> TrieResult<Vec<B256>> {
+        self.manager.roots()
+    }
+
+    /// Opens a view at `root`, whose nodes are kept until the view is dropped. Fails with
+    /// `TrieError::MissingTrieNode` if some of them were already removed, or
+    /// `TrieError::InvalidStateRoot` if the root node was.
+    pub fn view_at(&self, root: B256) -> TrieResult<VersionedView<D>> {
+        self.manager.lease(root)?;
+        match TrieView::new(self.head.db.clone(), root) {
+            Ok(view) => Ok(VersionedView {
+                view,
+                manager: self.manager.clone(),
+            }),
+            Err(err) => {
+                self.manager.release(root);
+                Err(err)
+            }
+        }
+    }
+}
+
+/// A view opened by `VersionedTrie::view_at`, which keeps the nodes it reads from being
+/// removed until it is dropped.
+#[derive(Debug)]
+pub struct VersionedView<D>
+where
+    D: DB,
+{
+    view: TrieView<D>,
+    manager: RootManager<D>,
+}
+
+impl<D> Deref for VersionedView<D>
+where
+    D: DB,
+{
+    type Target = TrieView<D>;
+
+    fn deref(&self) -> &TrieView<D> {
+        &self.view
+    }
+}
+
+impl<D> Drop for VersionedView<D>
+where
+    D: DB,
+{
+    fn drop(&mut self) {
+        self.manager.release(self.view.root_hash());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::VersionedTrie;
+    use crate::db::MemoryDB;
+    use crate::errors::TrieError;
+    use crate::trie::{TrieRead, TrieWrite};
+
+    #[test]
+    fn test_versioned_views() {
+        let memdb = Arc::new(MemoryDB::new(true));
+        let mut trie = VersionedTrie::open(memdb.clone()).unwrap();
+        let mut roots = vec![];
+        for round in 0..2u8 {
+            for i in 0..50u8 {
+                trie.head_mut().insert(&[i], &[round; 40]).unwrap();
+            }
+            roots.push(trie.head_mut().root_hash().unwrap());
+        }
+        assert_eq!(trie.roots().unwrap(), roots);
+
+        let view = trie.view_at(roots[1]).unwrap();
+        let view_again = trie.view_at(roots[1]).unwrap();
+        for round in 2..4u8 {
+            for i in 0..50u8 {
+                trie.head_mut().insert(&[i], &[round; 40]).unwrap();
+            }
+            roots.push(trie.head_mut().root_hash().unwrap());
+        }
+        assert_eq!(view.get(&[7]).unwrap(), Some(vec![1; 40]));
+        assert!(matches!(
+            trie.view_at(roots[2]).map(|v| v.get(&[7])),
+            Err(TrieError::MissingTrieNode { .. })
+        ));
+
+        drop(view);
+        assert_eq!(view_again.get(&[7]).unwrap(), Some(vec![1; 40]));
+        drop(view_again);
+        for i in 0..50u8 {
+            trie.head_mut().insert(&[i], &[4; 40]).unwrap();
+        }
+        trie.head_mut().root_hash().unwrap();
+        assert!(trie.view_at(roots[1]).is_err());
+
+        let reopened = VersionedTrie::open(memdb).unwrap();
+        assert_eq!(reopened.head().get(&[7]).unwrap(), Some(vec![4; 40]));
+    }
+}
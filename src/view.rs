@@ -0,0 +1,160 @@
+use std::sync::Arc;
+
+use alloy_primitives::B256;
+
+use crate::db::DB;
+use crate::errors::TrieError;
+use crate::node::Node;
+use crate::trie::{decode_node, verify_proof, NodeReader, TrieIterator, TrieRead, TrieResult};
+
+/// A read-only view of a committed trie.
+///
+/// Unlike `EthTrie`, a view carries no state for pending changes, which makes it cheap to
+/// create and clone, and safe to hand out to code that should only read the trie.
+#[derive(Debug)]
+pub struct TrieView<D>
+where
+    D: DB,
+{
+    db: Arc<D>,
+    pub(crate) root: Node,
+    root_hash: B256,
+}
+
+impl<D> TrieView<D>
+where
+    D: DB,
+{
+    pub fn new(db: Arc<D>, root_hash: B256) -> TrieResult<Self> {
+        match db.get(root_hash.as_slice()).map_err(TrieError::db)? {
+            Some(data) => Ok(Self {
+                root: decode_node(&mut data.as_slice())?,
+                root_hash,
+                db,
+            }),
+            None => Err(TrieError::InvalidStateRoot),
+        }
+    }
+
+    pub(crate) fn from_parts(db: Arc<D>, root: Node, root_hash: B256) -> Self {
+        Self {
+            db,
+            root,
+            root_hash,
+        }
+    }
+
+    /// Returns the root hash the view was opened at.
+    pub fn root_hash(&self) -> B256 {
+        self.root_hash
+    }
+
+    /// Loads and decodes the node stored under `hash`, like `EthTrie::get_node`.
+    pub fn get_node(&self, hash: B256) -> TrieResult<Option<Node>> {
+        self.reader().recover(hash)
+    }
+
+    pub(crate) fn reader(&self) -> NodeReader<'_, D> {
+        NodeReader::new(&*self.db, self.root_hash)
+    }
+}
+
+impl<D> Clone for TrieView<D>
+where
+    D: DB,
+{
+    fn clone(&self) -> Self {
+        Self {
+            db: self.db.clone(),
+            root: self.root.clone(),
+            root_hash: self.root_hash,
+        }
+    }
+}
+
+impl<D> TrieRead<D> for TrieView<D>
+where
+    D: DB,
+{
+    type Iter<'a>
+        = TrieIterator<'a, D>
+    where
+        Self: 'a;
+
+    fn get(&self, key: &[u8]) -> TrieResult<Option<Vec<u8>>> {
+        self.reader().get(&self.root, key, |value| value.to_vec())
+    }
+
+    fn get_with<R, F>(&self, key: &[u8], f: F) -> TrieResult<Option<R>>
+    where
+        F: FnOnce(&[u8]) -> R,
+    {
+        self.reader().get(&self.root, key, f)
+    }
+
+    fn contains(&self, key: &[u8]) -> TrieResult<bool> {
+        self.reader().contains(&self.root, key)
+    }
+
+    fn get_proof(&self, key: &[u8]) -> TrieResult<Vec<Vec<u8>>> {
+        self.reader().get_proof(&self.root, key)
+    }
+
+    fn verify_proof(
+        &self,
+        root_hash: B256,
+        key: &[u8],
+        proof: Vec<Vec<u8>>,
+    ) -> TrieResult<Option<Vec<u8>>> {
+        verify_proof(root_hash, key, proof)
+    }
+
+    fn iter(&self) -> Self::Iter<'_> {
+        self.reader().iter(self.root.clone())
+    }
+
+    fn seek_iter<'a>(&'a self, iter: &mut Self::Iter<'a>, key: &[u8]) -> TrieResult<()> {
+        iter.seek(key)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use alloy_primitives::B256;
+
+    use super::TrieView;
+    use crate::db::MemoryDB;
+    use crate::errors::TrieError;
+    use crate::trie::{EthTrie, TrieRead, TrieWrite};
+
+    #[test]
+    fn test_view_reads_committed_trie() {
+        let memdb = Arc::new(MemoryDB::new(true));
+        let mut trie = EthTrie::new(memdb.clone());
+        trie.insert(b"test", b"test").unwrap();
+        trie.insert(b"test1", b"test1").unwrap();
+        let root = trie.root_hash().unwrap();
+
+        // Uncommitted changes are not visible through the view
+        trie.insert(b"test2", b"test2").unwrap();
+
+        let view = TrieView::new(memdb, root).unwrap();
+        assert_eq!(view.root_hash(), root);
+        assert_eq!(view.get(b"test1").unwrap(), Some(b"test1".to_vec()));
+        assert!(!view.contains(b"test2").unwrap());
+        assert_eq!(view.iter().count(), 2);
+
+        let proof = view.get_proof(b"test").unwrap();
+        let value = view.verify_proof(root, b"test", proof).unwrap();
+        assert_eq!(value, Some(b"test".to_vec()));
+    }
+
+    #[test]
+    fn test_view_missing_root() {
+        let memdb = Arc::new(MemoryDB::new(true));
+        let result = TrieView::new(memdb, B256::repeat_byte(1));
+        assert_eq!(result.unwrap_err(), TrieError::InvalidStateRoot);
+    }
+}
@@ -0,0 +1,201 @@
+use std::collections::BTreeMap;
+
+use alloy_primitives::{Bytes, B256};
+use keccak_hash::KECCAK_NULL_RLP;
+
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+use crate::db::DB;
+use crate::debug::resolve;
+use crate::nibbles::Nibbles;
+use crate::node::Node;
+use crate::trie::{verify_proof, EthTrie, NodeReader, TrieResult};
+use crate::view::TrieView;
+
+/// The nodes proving a set of keys against a root, laid out the same way every time.
+///
+/// Each stored node on the path to one of the keys appears once in `nodes`. Nodes are
+/// ordered by their path from the root, in key order, with a node before every node
+/// below it, so the root comes first. The layout depends only on the trie and the set of
+/// keys, not on the order the keys were given in.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Witness {
+    pub root: B256,
+    /// The encoded nodes.
+    pub nodes: Vec<Bytes>,
+    /// For each key, the positions in `nodes` of its proof, from the root down.
+    pub index: BTreeMap<Bytes, Vec<usize>>,
+}
+
+impl Witness {
+    /// Returns the proof of `key`, as `TrieRead::get_proof` would, if the key is part of
+    /// the witness.
+    pub fn proof(&self, key: &[u8]) -> Option<Vec<Vec<u8>>> {
+        let positions = self.index.get(key)?;
+        Some(positions.iter().map(|i| self.nodes[*i].to_vec()).collect())
+    }
+
+    /// Checks the proof of `key` against the root and returns its value. Fails with
+    /// `TrieError::InvalidProof` if the key is not part of the witness.
+    pub fn verify(&self, key: &[u8]) -> TrieResult<Option<Vec<u8>>> {
+        let proof = self.proof(key).unwrap_or_default();
+        verify_proof(self.root, key, proof)
+    }
+}
+
+impl<D> EthTrie<D>
+where
+    D: DB,
+{
+    /// Builds the witness of `keys`, including uncommitted changes. Keys without a value
+    /// are proven absent.
+    pub fn witness<I>(&self, keys: I) -> TrieResult<Witness>
+    where
+        I: IntoIterator,
+        I::Item: AsRef<[u8]>,
+    {
+        build_witness(&self.reader(), &self.root, keys)
+    }
+}
+
+impl<D> TrieView<D>
+where
+    D: DB,
+{
+    /// Builds the witness of `keys`. Keys without a value are proven absent.
+    pub fn witness<I>(&self, keys: I) -> TrieResult<Witness>
+    where
+        I: IntoIterator,
+        I::Item: AsRef<[u8]>,
+    {
+        build_witness(&self.reader(), &self.root, keys)
+    }
+}
+
+fn build_witness<D, I>(reader: &NodeReader<'_, D>, root: &Node, keys: I) -> TrieResult<Witness>
+where
+    D: DB,
+    I: IntoIterator,
+    I::Item: AsRef<[u8]>,
+{
+    let mut root_hash: B256 = KECCAK_NULL_RLP.as_fixed_bytes().into();
+    // Nodes by path, which sorts parents before their children
+    let mut nodes: BTreeMap<Nibbles, Bytes> = BTreeMap::new();
+    let mut paths: BTreeMap<Bytes, Vec<Nibbles>> = BTreeMap::new();
+
+    for key in keys {
+        let key = key.as_ref();
+        let target = Nibbles::from_raw(key, false);
+        let mut proof = Vec::new();
+        let mut node = root.clone();
+        let mut path = Nibbles::from_hex(&[]);
+
+        loop {
+            let (resolved, encoded, hash) = resolve(reader, &node, &path, path.is_empty())?;
+            if let Some(hash) = hash {
+                if path.is_empty() {
+                    root_hash = hash;
+                }
+                nodes.entry(path.clone()).or_insert_with(|| encoded.into());
+                proof.push(path.clone());
+            }
+
+            let partial = target.offset(path.len());
+            let next = match &resolved {
+                Node::Branch(branch) if !partial.is_empty() => {
+                    let nibble = partial.at(0);
+                    path.push(nibble as u8);
+                    branch.read().children[nibble].clone()
+                }
+                Node::Extension(ext) => {
+                    let ext = ext.read();
+                    if partial.common_prefix(&ext.prefix) < ext.prefix.len() {
+                        break;
+                    }
+                    path = path.join(&ext.prefix);
+                    ext.node.clone()
+                }
+                _ => break,
+            };
+            if matches!(next, Node::Empty) {
+                break;
+            }
+            node = next;
+        }
+        paths.insert(Bytes::copy_from_slice(key), proof);
+    }
+
+    let positions: BTreeMap<&Nibbles, usize> = nodes
+        .keys()
+        .enumerate()
+        .map(|(i, path)| (path, i))
+        .collect();
+    let index = paths
+        .iter()
+        .map(|(key, proof)| {
+            let spans = proof.iter().map(|path| positions[path]).collect();
+            (key.clone(), spans)
+        })
+        .collect();
+
+    Ok(Witness {
+        root: root_hash,
+        nodes: nodes.into_values().collect(),
+        index,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::trie::tests::random_trie;
+    use crate::trie::{TrieRead, TrieWrite};
+
+    #[test]
+    fn test_witness_layout() {
+        let (mut trie, kv) = random_trie(200);
+        let root = trie.root_hash().unwrap();
+        let mut keys: Vec<Vec<u8>> = kv.keys().step_by(7).cloned().collect();
+        keys.push(b"missing key".to_vec());
+
+        let witness = trie.witness(&keys).unwrap();
+        assert_eq!(witness.root, root);
+        assert_eq!(witness.index.len(), keys.len());
+
+        // The same keys in another order give the same witness
+        keys.reverse();
+        assert_eq!(trie.witness(&keys).unwrap(), witness);
+
+        // Nodes are shared rather than repeated
+        let mut unique = witness.nodes.clone();
+        unique.sort();
+        unique.dedup();
+        assert_eq!(unique.len(), witness.nodes.len());
+
+        for key in keys.iter() {
+            assert_eq!(
+                witness.proof(key).unwrap(),
+                trie.get_proof(key).unwrap(),
+                "{:?}",
+                key
+            );
+            assert_eq!(witness.verify(key).unwrap(), kv.get(key).cloned());
+            assert_eq!(witness.index[key.as_slice()][0], 0);
+        }
+        assert!(witness.verify(b"not in witness").is_err());
+    }
+
+    #[test]
+    fn test_witness_empty_trie() {
+        let (mut trie, _) = random_trie(0);
+        let root = trie.root_hash().unwrap();
+        let witness = trie.witness([b"key"]).unwrap();
+        assert_eq!(witness.root, root);
+        assert!(witness.nodes.is_empty());
+        assert_eq!(
+            witness.proof(b"key").unwrap(),
+            trie.get_proof(b"key").unwrap()
+        );
+    }
+}